@@ -18,4 +18,7 @@ fn main() {
     fs::copy(manifest_src, &manifest_dest).expect("Failed to copy manifest.xml");
 
     println!("cargo:rerun-if-changed={}", manifest_src);
+
+    // Embedded by sqlx::migrate! in main.rs
+    println!("cargo:rerun-if-changed=migrations");
 }
@@ -0,0 +1,20 @@
+-- Append-only record of administrative actions. Every row carries the hash of
+-- the previous row, so editing or deleting an entry breaks the chain.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor_email TEXT,
+    actor_role TEXT,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    before_state JSONB,
+    after_state JSONB,
+    ip_address TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    prev_hash TEXT NOT NULL,
+    hash TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_target_idx ON audit_log (target_type, target_id);
+CREATE INDEX IF NOT EXISTS audit_log_actor_idx ON audit_log (actor_email);
+CREATE INDEX IF NOT EXISTS audit_log_created_at_idx ON audit_log (created_at);
@@ -0,0 +1,83 @@
+-- Machines are tracked independently of the people using them. `machine_id`
+-- is the stable hardware identity reported by the agent (/etc/machine-id,
+-- the Windows MachineGuid, or a hostname/MAC digest when neither exists).
+CREATE TABLE IF NOT EXISTS devices (
+    device_id BIGSERIAL PRIMARY KEY,
+    machine_id TEXT NOT NULL UNIQUE,
+    company_name TEXT,
+    hostname TEXT,
+    mac_address TEXT,
+    -- SystemAssignment.new_system_id of the asset record for this machine
+    system_id TEXT UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS device_owners (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    owner_email TEXT NOT NULL,
+    owner_role TEXT NOT NULL,
+    assigned_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    released_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS device_owners_device_idx ON device_owners (device_id);
+CREATE UNIQUE INDEX IF NOT EXISTS device_owners_current_idx ON device_owners (device_id) WHERE released_at IS NULL;
+
+-- Each user used to get one random metrics_id standing in for "their machine".
+-- Turn every one of those into a device so existing metrics keep their history.
+CREATE TEMPORARY TABLE legacy_metrics_owners AS
+    SELECT metrics_id, company_name, email, 'SubAdmin' AS role, created_at FROM sub_admin WHERE metrics_id IS NOT NULL
+    UNION ALL
+    SELECT metrics_id, company_affiliated_to, email, 'Staff', created_at FROM staff WHERE metrics_id IS NOT NULL;
+
+INSERT INTO devices (machine_id, company_name, hostname, created_at)
+SELECT
+    'legacy-metrics-' || o.metrics_id,
+    o.company_name,
+    (SELECT s.hostname FROM systeminfo_metrics s
+        WHERE s.sub_admin_metrics_id = o.metrics_id OR s.staff_metrics_id = o.metrics_id
+        ORDER BY s.updated_at DESC NULLS LAST LIMIT 1),
+    COALESCE(o.created_at, now())
+FROM legacy_metrics_owners o
+ON CONFLICT (machine_id) DO NOTHING;
+
+INSERT INTO device_owners (device_id, owner_email, owner_role, assigned_at)
+SELECT d.device_id, o.email, o.role, d.created_at
+FROM legacy_metrics_owners o
+JOIN devices d ON d.machine_id = 'legacy-metrics-' || o.metrics_id;
+
+DROP TABLE legacy_metrics_owners;
+
+DO $$
+DECLARE
+    metrics_table TEXT;
+BEGIN
+    FOREACH metrics_table IN ARRAY ARRAY[
+        'cpu_metrics', 'disk_metrics', 'memory_metrics', 'network_metrics', 'systeminfo_metrics',
+        'filesystem_metrics', 'ip_location_metrics', 'process_metrics', 'service_status_metrics', 'uptime_metrics'
+    ] LOOP
+        EXECUTE format('ALTER TABLE %I ADD COLUMN device_id BIGINT REFERENCES devices (device_id) ON DELETE CASCADE', metrics_table);
+        EXECUTE format(
+            'UPDATE %I m SET device_id = d.device_id FROM devices d
+             WHERE d.machine_id = ''legacy-metrics-'' || COALESCE(m.sub_admin_metrics_id, m.staff_metrics_id)',
+            metrics_table
+        );
+        EXECUTE format('ALTER TABLE %I DROP COLUMN sub_admin_metrics_id, DROP COLUMN staff_metrics_id', metrics_table);
+        EXECUTE format('CREATE INDEX ON %I (device_id)', metrics_table);
+    END LOOP;
+END $$;
+
+-- These two tables are upserted per device; drop duplicates left over from the
+-- old keys before enforcing that.
+DELETE FROM cpu_metrics a USING cpu_metrics b
+    WHERE a.device_id = b.device_id AND a.ctid < b.ctid;
+ALTER TABLE cpu_metrics ADD CONSTRAINT cpu_metrics_device_id_key UNIQUE (device_id);
+
+DELETE FROM service_status_metrics a USING service_status_metrics b
+    WHERE a.device_id = b.device_id AND a.service_name = b.service_name AND a.ctid < b.ctid;
+ALTER TABLE service_status_metrics ADD CONSTRAINT service_status_metrics_device_service_key UNIQUE (device_id, service_name);
+
+ALTER TABLE sub_admin DROP COLUMN metrics_id;
+ALTER TABLE staff DROP COLUMN metrics_id;
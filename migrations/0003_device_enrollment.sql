@@ -0,0 +1,27 @@
+-- One-time tokens a sub-admin hands to an agent so it can enroll its device.
+-- Only the SHA-256 of the token is stored.
+CREATE TABLE IF NOT EXISTS enrollment_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    company_name TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    used_by_device_id BIGINT REFERENCES devices (device_id) ON DELETE SET NULL
+);
+
+-- API keys agents use to push data for their device. A device has at most one
+-- active key; rotating revokes the old one.
+CREATE TABLE IF NOT EXISTS device_credentials (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    company_name TEXT NOT NULL,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS device_credentials_device_idx ON device_credentials (device_id);
@@ -0,0 +1,47 @@
+-- User and maintenance request ids used to be random positive i32s picked by
+-- the application, which collide long before the table is large. Ids are now
+-- issued by a sequence per table.
+--
+-- Existing ids are kept as they are so that tokens, bookmarks and audit
+-- entries referring to them stay valid. Because the random ids are spread over
+-- the whole i32 range, the columns are widened to BIGINT and each sequence
+-- starts above the largest id already issued. Rows that never got an id (super
+-- admins and technicians were inserted with whatever the client sent) are
+-- numbered from the sequence. Adding the primary key fails loudly if two rows
+-- already share an id; resolve those by hand before re-running.
+DO $$
+DECLARE
+    target RECORD;
+    sequence_name TEXT;
+BEGIN
+    FOR target IN
+        SELECT * FROM (VALUES
+            ('super_admin', 'id'),
+            ('sub_admin', 'id'),
+            ('staff', 'id'),
+            ('technician', 'id'),
+            ('maintenance_requests', 'maintenance_id')
+        ) AS t (table_name, column_name)
+    LOOP
+        sequence_name := target.table_name || '_' || target.column_name || '_seq';
+
+        EXECUTE format('ALTER TABLE %I ALTER COLUMN %I TYPE BIGINT', target.table_name, target.column_name);
+        EXECUTE format('CREATE SEQUENCE IF NOT EXISTS %I AS BIGINT OWNED BY %I.%I', sequence_name, target.table_name, target.column_name);
+        EXECUTE format(
+            'SELECT setval(%L, COALESCE((SELECT MAX(%I) FROM %I), 0) + 1, false)',
+            sequence_name, target.column_name, target.table_name
+        );
+        EXECUTE format('ALTER TABLE %I ALTER COLUMN %I SET DEFAULT nextval(%L)', target.table_name, target.column_name, sequence_name);
+        EXECUTE format('UPDATE %I SET %I = DEFAULT WHERE %I IS NULL', target.table_name, target.column_name, target.column_name);
+        EXECUTE format('ALTER TABLE %I ALTER COLUMN %I SET NOT NULL', target.table_name, target.column_name);
+
+        IF NOT EXISTS (
+            SELECT 1 FROM pg_constraint WHERE conrelid = target.table_name::regclass AND contype = 'p'
+        ) THEN
+            EXECUTE format('ALTER TABLE %I ADD PRIMARY KEY (%I)', target.table_name, target.column_name);
+        END IF;
+    END LOOP;
+END $$;
+
+ALTER TABLE maintenance_requests ALTER COLUMN reported_by_sub_admin_id TYPE BIGINT;
+ALTER TABLE maintenance_requests ALTER COLUMN reported_by_staff_id TYPE BIGINT;
@@ -0,0 +1,51 @@
+CREATE TYPE maintenance_status AS ENUM ('Pending', 'Triaged', 'Ongoing', 'OnHold', 'Resolved', 'Closed', 'Rejected');
+CREATE TYPE maintenance_priority AS ENUM ('Low', 'Medium', 'High', 'Critical');
+
+-- Status and priority were free text; fold the spellings seen in the wild onto
+-- the new values and fall back to the defaults for anything else.
+ALTER TABLE maintenance_requests
+    ALTER COLUMN status TYPE maintenance_status USING (
+        CASE regexp_replace(lower(COALESCE(status, '')), '[^a-z]', '', 'g')
+            WHEN 'triaged' THEN 'Triaged'
+            WHEN 'ongoing' THEN 'Ongoing'
+            WHEN 'inprogress' THEN 'Ongoing'
+            WHEN 'onhold' THEN 'OnHold'
+            WHEN 'resolved' THEN 'Resolved'
+            WHEN 'completed' THEN 'Resolved'
+            WHEN 'done' THEN 'Resolved'
+            WHEN 'closed' THEN 'Closed'
+            WHEN 'rejected' THEN 'Rejected'
+            ELSE 'Pending'
+        END
+    )::maintenance_status,
+    ALTER COLUMN status SET DEFAULT 'Pending',
+    ALTER COLUMN status SET NOT NULL,
+    ALTER COLUMN priority TYPE maintenance_priority USING (
+        CASE lower(COALESCE(priority, ''))
+            WHEN 'low' THEN 'Low'
+            WHEN 'high' THEN 'High'
+            WHEN 'critical' THEN 'Critical'
+            WHEN 'urgent' THEN 'Critical'
+            ELSE 'Medium'
+        END
+    )::maintenance_priority,
+    ALTER COLUMN priority SET DEFAULT 'Medium',
+    ALTER COLUMN priority SET NOT NULL;
+
+CREATE TABLE IF NOT EXISTS maintenance_status_history (
+    id BIGSERIAL PRIMARY KEY,
+    maintenance_id BIGINT NOT NULL REFERENCES maintenance_requests (maintenance_id) ON DELETE CASCADE,
+    -- NULL for the entry written when the request is filed
+    from_status maintenance_status,
+    to_status maintenance_status NOT NULL,
+    actor_email TEXT,
+    actor_role TEXT,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS maintenance_status_history_request_idx ON maintenance_status_history (maintenance_id, created_at);
+
+-- Give existing requests a starting point so every history begins with the filing
+INSERT INTO maintenance_status_history (maintenance_id, from_status, to_status, created_at)
+SELECT maintenance_id, NULL, status, COALESCE(created_at, now()) FROM maintenance_requests;
@@ -0,0 +1,23 @@
+ALTER TABLE technician ADD COLUMN IF NOT EXISTS skills TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE maintenance_requests
+    ADD COLUMN IF NOT EXISTS assigned_technician_id BIGINT REFERENCES technician (id) ON DELETE SET NULL,
+    -- Skills a technician must have for auto-dispatch to pick them
+    ADD COLUMN IF NOT EXISTS required_skills TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS maintenance_requests_technician_idx ON maintenance_requests (assigned_technician_id);
+
+-- One row per (re)assignment; the current one has unassigned_at NULL
+CREATE TABLE IF NOT EXISTS maintenance_assignments (
+    id BIGSERIAL PRIMARY KEY,
+    maintenance_id BIGINT NOT NULL REFERENCES maintenance_requests (maintenance_id) ON DELETE CASCADE,
+    technician_id BIGINT NOT NULL REFERENCES technician (id) ON DELETE CASCADE,
+    assigned_by TEXT,
+    -- 'manual' or 'auto'
+    method TEXT NOT NULL,
+    assigned_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    unassigned_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS maintenance_assignments_request_idx ON maintenance_assignments (maintenance_id);
+CREATE UNIQUE INDEX IF NOT EXISTS maintenance_assignments_current_idx ON maintenance_assignments (maintenance_id) WHERE unassigned_at IS NULL;
@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS maintenance_comments (
+    id BIGSERIAL PRIMARY KEY,
+    maintenance_id BIGINT NOT NULL REFERENCES maintenance_requests (maintenance_id) ON DELETE CASCADE,
+    -- Comment this one replies to, NULL for top-level comments
+    parent_id BIGINT REFERENCES maintenance_comments (id) ON DELETE CASCADE,
+    author_email TEXT NOT NULL,
+    author_role TEXT NOT NULL,
+    body TEXT NOT NULL,
+    -- Internal notes are hidden from staff
+    internal BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS maintenance_comments_request_idx ON maintenance_comments (maintenance_id, created_at);
+
+CREATE TABLE IF NOT EXISTS maintenance_attachments (
+    id BIGSERIAL PRIMARY KEY,
+    maintenance_id BIGINT NOT NULL REFERENCES maintenance_requests (maintenance_id) ON DELETE CASCADE,
+    -- Attachments on an internal comment are internal too
+    comment_id BIGINT REFERENCES maintenance_comments (id) ON DELETE CASCADE,
+    uploaded_by TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    sha256 TEXT NOT NULL,
+    -- Key of the content in the blob store
+    storage_key TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS maintenance_attachments_request_idx ON maintenance_attachments (maintenance_id);
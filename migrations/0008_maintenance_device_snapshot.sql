@@ -0,0 +1,17 @@
+-- These metric tables are append-only but never recorded when a row was
+-- taken, so "latest" had no meaning. Existing rows get the migration time.
+ALTER TABLE memory_metrics ADD COLUMN IF NOT EXISTS recorded_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE disk_metrics ADD COLUMN IF NOT EXISTS recorded_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE process_metrics ADD COLUMN IF NOT EXISTS recorded_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE service_status_metrics ADD COLUMN IF NOT EXISTS recorded_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS memory_metrics_device_recorded_idx ON memory_metrics (device_id, recorded_at);
+CREATE INDEX IF NOT EXISTS disk_metrics_device_recorded_idx ON disk_metrics (device_id, recorded_at);
+CREATE INDEX IF NOT EXISTS process_metrics_device_recorded_idx ON process_metrics (device_id, recorded_at);
+
+ALTER TABLE maintenance_requests
+    ADD COLUMN IF NOT EXISTS device_id BIGINT REFERENCES devices (device_id) ON DELETE SET NULL,
+    -- State of the device's metrics when the request was filed
+    ADD COLUMN IF NOT EXISTS device_snapshot JSONB;
+
+CREATE INDEX IF NOT EXISTS maintenance_requests_device_idx ON maintenance_requests (device_id);
@@ -0,0 +1,62 @@
+CREATE TABLE IF NOT EXISTS sla_policies (
+    id BIGSERIAL PRIMARY KEY,
+    company_name TEXT NOT NULL,
+    priority maintenance_priority NOT NULL,
+    -- Business minutes allowed until the first response and until resolution
+    response_minutes INTEGER NOT NULL CHECK (response_minutes > 0),
+    resolve_minutes INTEGER NOT NULL CHECK (resolve_minutes > 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ,
+    UNIQUE (company_name, priority)
+);
+
+-- Companies without a calendar are on the clock around the hour
+CREATE TABLE IF NOT EXISTS sla_calendars (
+    company_name TEXT PRIMARY KEY,
+    utc_offset_minutes INTEGER NOT NULL DEFAULT 0,
+    -- ISO weekdays, 1 = Monday
+    workdays SMALLINT[] NOT NULL DEFAULT '{1,2,3,4,5}',
+    day_start TIME NOT NULL DEFAULT '09:00',
+    day_end TIME NOT NULL DEFAULT '17:00',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (day_start < day_end)
+);
+
+CREATE TABLE IF NOT EXISTS sla_holidays (
+    company_name TEXT NOT NULL REFERENCES sla_calendars (company_name) ON DELETE CASCADE,
+    holiday DATE NOT NULL,
+    PRIMARY KEY (company_name, holiday)
+);
+
+ALTER TABLE maintenance_requests
+    ADD COLUMN IF NOT EXISTS company_name TEXT,
+    ADD COLUMN IF NOT EXISTS first_response_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS resolved_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS response_due_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS resolve_due_at TIMESTAMPTZ,
+    -- The resolution clock stops while a request is on hold
+    ADD COLUMN IF NOT EXISTS sla_paused_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS sla_paused_minutes BIGINT NOT NULL DEFAULT 0;
+
+UPDATE maintenance_requests m SET company_name = COALESCE(
+    (SELECT s.company_name FROM sub_admin s WHERE s.id = m.reported_by_sub_admin_id),
+    (SELECT s.company_affiliated_to FROM staff s WHERE s.id = m.reported_by_staff_id)
+)
+WHERE company_name IS NULL;
+
+UPDATE maintenance_requests SET sla_paused_at = COALESCE(updated_at, now()) WHERE status = 'OnHold';
+UPDATE maintenance_requests SET resolved_at = COALESCE(updated_at, created_at, now())
+WHERE status IN ('Resolved', 'Closed', 'Rejected');
+
+CREATE INDEX IF NOT EXISTS maintenance_requests_company_idx ON maintenance_requests (company_name);
+
+-- One row per missed target, written by the breach monitor
+CREATE TABLE IF NOT EXISTS sla_breaches (
+    id BIGSERIAL PRIMARY KEY,
+    maintenance_id BIGINT NOT NULL REFERENCES maintenance_requests (maintenance_id) ON DELETE CASCADE,
+    -- 'response' or 'resolve'
+    kind TEXT NOT NULL,
+    due_at TIMESTAMPTZ NOT NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (maintenance_id, kind)
+);
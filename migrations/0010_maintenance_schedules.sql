@@ -0,0 +1,57 @@
+-- Preventive maintenance: requests raised on a timetable instead of by a reporter
+CREATE TABLE IF NOT EXISTS maintenance_schedules (
+    id BIGSERIAL PRIMARY KEY,
+    company_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- 'device' (one entry in device_ids), 'group' (several) or 'company' (every device of the company)
+    scope TEXT NOT NULL CHECK (scope IN ('device', 'group', 'company')),
+    device_ids BIGINT[] NOT NULL DEFAULT '{}',
+    -- Exactly one of: a five-field cron expression in the company's SLA calendar
+    -- time zone, or a fixed number of days after starts_at
+    cron TEXT,
+    interval_days INTEGER CHECK (interval_days > 0),
+    starts_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    next_run_at TIMESTAMPTZ,
+    last_run_at TIMESTAMPTZ,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Template of the requests it raises
+    title TEXT NOT NULL,
+    description TEXT,
+    priority maintenance_priority NOT NULL DEFAULT 'Medium',
+    required_skills TEXT[] NOT NULL DEFAULT '{}',
+    checklist TEXT[] NOT NULL DEFAULT '{}',
+    created_by TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ,
+    CHECK ((cron IS NULL) <> (interval_days IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS maintenance_schedules_due_idx ON maintenance_schedules (next_run_at) WHERE active;
+
+ALTER TABLE maintenance_requests
+    ADD COLUMN IF NOT EXISTS schedule_id BIGINT REFERENCES maintenance_schedules (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS maintenance_requests_schedule_idx ON maintenance_requests (schedule_id, device_id);
+
+-- What happened to each device on each due date
+CREATE TABLE IF NOT EXISTS maintenance_schedule_runs (
+    id BIGSERIAL PRIMARY KEY,
+    schedule_id BIGINT NOT NULL REFERENCES maintenance_schedules (id) ON DELETE CASCADE,
+    device_id BIGINT REFERENCES devices (device_id) ON DELETE SET NULL,
+    due_at TIMESTAMPTZ NOT NULL,
+    -- 'created' or 'skipped_open'
+    outcome TEXT NOT NULL,
+    maintenance_id BIGINT REFERENCES maintenance_requests (maintenance_id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (schedule_id, device_id, due_at)
+);
+
+CREATE TABLE IF NOT EXISTS maintenance_checklist_items (
+    id BIGSERIAL PRIMARY KEY,
+    maintenance_id BIGINT NOT NULL REFERENCES maintenance_requests (maintenance_id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    done_at TIMESTAMPTZ,
+    done_by TEXT,
+    UNIQUE (maintenance_id, position)
+);
@@ -0,0 +1,53 @@
+-- Conditions over collected metrics that raise maintenance requests by themselves
+CREATE TABLE IF NOT EXISTS alert_rules (
+    id BIGSERIAL PRIMARY KEY,
+    company_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- 'disk_free_percent', 'memory_used_percent', 'uptime_hours' or 'service_not_running'
+    metric TEXT NOT NULL,
+    -- Threshold comparison for numeric metrics; unused for service_not_running
+    comparator TEXT CHECK (comparator IN ('<', '<=', '>', '>=')),
+    threshold DOUBLE PRECISION,
+    service_name TEXT,
+    -- How long the condition must hold before a request is opened
+    duration_minutes INTEGER NOT NULL DEFAULT 0 CHECK (duration_minutes >= 0),
+    -- Empty means every device of the company
+    device_ids BIGINT[] NOT NULL DEFAULT '{}',
+    priority maintenance_priority NOT NULL DEFAULT 'Medium',
+    required_skills TEXT[] NOT NULL DEFAULT '{}',
+    auto_resolve BOOLEAN NOT NULL DEFAULT TRUE,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS alert_rules_company_idx ON alert_rules (company_name);
+
+-- Where each rule stands on each device. A row exists only while the condition holds.
+CREATE TABLE IF NOT EXISTS alert_states (
+    rule_id BIGINT NOT NULL REFERENCES alert_rules (id) ON DELETE CASCADE,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    pending_since TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_value DOUBLE PRECISION,
+    last_checked_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    fired_at TIMESTAMPTZ,
+    maintenance_id BIGINT REFERENCES maintenance_requests (maintenance_id) ON DELETE SET NULL,
+    PRIMARY KEY (rule_id, device_id)
+);
+
+CREATE INDEX IF NOT EXISTS alert_states_maintenance_idx ON alert_states (maintenance_id);
+
+-- Firing and clearing, kept after the state row is gone
+CREATE TABLE IF NOT EXISTS alert_events (
+    id BIGSERIAL PRIMARY KEY,
+    rule_id BIGINT NOT NULL REFERENCES alert_rules (id) ON DELETE CASCADE,
+    device_id BIGINT REFERENCES devices (device_id) ON DELETE SET NULL,
+    -- 'fired' or 'cleared'
+    kind TEXT NOT NULL,
+    value DOUBLE PRECISION,
+    maintenance_id BIGINT REFERENCES maintenance_requests (maintenance_id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS alert_events_rule_idx ON alert_events (rule_id, created_at);
@@ -0,0 +1,79 @@
+-- return_date was free text; keep whatever starts with a real calendar date.
+-- Anything else, or anything more than the date, is set aside as it was.
+CREATE FUNCTION pg_temp.parse_legacy_date(value TEXT) RETURNS DATE AS $$
+BEGIN
+    IF value !~ '^\s*\d{4}-\d{2}-\d{2}' THEN
+        RETURN NULL;
+    END IF;
+    RETURN to_date(substring(trim(value) FROM 1 FOR 10), 'YYYY-MM-DD');
+EXCEPTION WHEN others THEN
+    -- Such as 2024-02-30
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+ALTER TABLE system_assignments ADD COLUMN IF NOT EXISTS legacy_return_date_text TEXT;
+
+UPDATE system_assignments SET legacy_return_date_text = return_date
+WHERE trim(return_date) <> ''
+    AND pg_temp.parse_legacy_date(return_date)::text IS DISTINCT FROM trim(return_date);
+
+ALTER TABLE system_assignments ALTER COLUMN return_date TYPE DATE USING pg_temp.parse_legacy_date(return_date);
+
+DROP FUNCTION pg_temp.parse_legacy_date(TEXT);
+
+-- One row per period a system spent with someone. Rows are written at checkout
+-- and completed at return, never rewritten to name a different holder.
+CREATE TABLE IF NOT EXISTS system_checkouts (
+    id BIGSERIAL PRIMARY KEY,
+    new_system_id TEXT NOT NULL,
+    staff_id_email TEXT NOT NULL,
+    staff_full_name TEXT,
+    staff_department TEXT,
+    staff_role_and_position TEXT,
+    purpose TEXT,
+    checked_out_by TEXT,
+    checked_out_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    due_back_on DATE,
+    returned_at TIMESTAMPTZ,
+    returned_by TEXT,
+    return_notes TEXT,
+    last_reminded_at TIMESTAMPTZ,
+    reminder_count INTEGER NOT NULL DEFAULT 0,
+    CHECK (returned_at IS NULL OR returned_at >= checked_out_at)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS system_checkouts_open_idx ON system_checkouts (new_system_id) WHERE returned_at IS NULL;
+CREATE INDEX IF NOT EXISTS system_checkouts_system_idx ON system_checkouts (new_system_id, checked_out_at);
+CREATE INDEX IF NOT EXISTS system_checkouts_staff_idx ON system_checkouts (staff_id_email);
+
+CREATE OR REPLACE FUNCTION system_checkouts_guard() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        RAISE EXCEPTION 'system checkout history cannot be deleted';
+    END IF;
+    IF OLD.returned_at IS NOT NULL THEN
+        RAISE EXCEPTION 'checkout % has been returned and can no longer change', OLD.id;
+    END IF;
+    IF NEW.new_system_id IS DISTINCT FROM OLD.new_system_id
+        OR NEW.staff_id_email IS DISTINCT FROM OLD.staff_id_email
+        OR NEW.checked_out_at IS DISTINCT FROM OLD.checked_out_at
+        OR NEW.checked_out_by IS DISTINCT FROM OLD.checked_out_by THEN
+        RAISE EXCEPTION 'checkout % cannot be reassigned', OLD.id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS system_checkouts_guard ON system_checkouts;
+CREATE TRIGGER system_checkouts_guard BEFORE UPDATE OR DELETE ON system_checkouts
+    FOR EACH ROW EXECUTE FUNCTION system_checkouts_guard();
+
+-- Whoever an assignment names today is holding the system since it was created
+INSERT INTO system_checkouts (new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by, checked_out_at, due_back_on)
+SELECT DISTINCT ON (new_system_id)
+    new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, assigned_by,
+    COALESCE(created_at, now()), return_date
+FROM system_assignments
+WHERE new_system_id IS NOT NULL AND staff_id_email IS NOT NULL
+ORDER BY new_system_id, updated_at DESC NULLS LAST, created_at DESC NULLS LAST;
@@ -0,0 +1,42 @@
+-- Custody terms a staff member accepts when a system is checked out to them.
+-- Each company publishes numbered versions; a published version never changes.
+CREATE TABLE IF NOT EXISTS system_assignment_terms (
+    id BIGSERIAL PRIMARY KEY,
+    company_name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    body TEXT NOT NULL,
+    published_by TEXT,
+    published_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (company_name, version)
+);
+
+CREATE INDEX IF NOT EXISTS system_assignment_terms_company_idx ON system_assignment_terms (company_name, published_at);
+
+-- One acceptance per checkout, by the staff member it was checked out to
+CREATE TABLE IF NOT EXISTS system_acknowledgements (
+    id BIGSERIAL PRIMARY KEY,
+    checkout_id BIGINT NOT NULL UNIQUE REFERENCES system_checkouts (id),
+    new_system_id TEXT NOT NULL,
+    staff_id_email TEXT NOT NULL,
+    terms_id BIGINT NOT NULL REFERENCES system_assignment_terms (id),
+    terms_version TEXT NOT NULL,
+    accepted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ip_address TEXT,
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS system_acknowledgements_system_idx ON system_acknowledgements (new_system_id, accepted_at);
+
+CREATE OR REPLACE FUNCTION system_acknowledgements_guard() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION '% rows cannot be changed once written', TG_TABLE_NAME;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS system_assignment_terms_guard ON system_assignment_terms;
+CREATE TRIGGER system_assignment_terms_guard BEFORE UPDATE OR DELETE ON system_assignment_terms
+    FOR EACH ROW EXECUTE FUNCTION system_acknowledgements_guard();
+
+DROP TRIGGER IF EXISTS system_acknowledgements_guard ON system_acknowledgements;
+CREATE TRIGGER system_acknowledgements_guard BEFORE UPDATE OR DELETE ON system_acknowledgements
+    FOR EACH ROW EXECUTE FUNCTION system_acknowledgements_guard();
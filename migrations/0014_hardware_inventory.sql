@@ -0,0 +1,15 @@
+-- What a device is made of. A new version is written only when the collected
+-- document differs from the latest one; otherwise last_seen_at moves forward.
+CREATE TABLE IF NOT EXISTS hardware_inventories (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    document JSONB NOT NULL,
+    -- SHA-256 of the document, to tell versions apart without comparing JSON
+    document_hash TEXT NOT NULL,
+    -- Fields that differ from the previous version: [{field, before, after}]
+    changes JSONB NOT NULL DEFAULT '[]',
+    collected_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (device_id, version)
+);
@@ -0,0 +1,16 @@
+-- Packages installed on each device, as last reported. A collection replaces
+-- the device's list; first_seen_at survives for packages still installed.
+CREATE TABLE IF NOT EXISTS software_packages (
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    -- 'dpkg', 'rpm', 'flatpak' or 'snap'
+    source TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- Empty when the source does not report one
+    architecture TEXT NOT NULL DEFAULT '',
+    version TEXT NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (device_id, source, name, architecture)
+);
+
+CREATE INDEX IF NOT EXISTS software_packages_name_idx ON software_packages (name);
@@ -0,0 +1,24 @@
+-- One row per collection; its processes are the process_metrics rows that
+-- point back to it. Older process_metrics rows predate snapshots and have none.
+CREATE TABLE IF NOT EXISTS process_snapshots (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    captured_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- How long CPU time was measured over
+    sample_interval_ms INTEGER NOT NULL,
+    process_count INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS process_snapshots_device_idx ON process_snapshots (device_id, captured_at);
+
+ALTER TABLE process_metrics
+    ADD COLUMN IF NOT EXISTS snapshot_id BIGINT REFERENCES process_snapshots (id) ON DELETE CASCADE,
+    ADD COLUMN IF NOT EXISTS ppid INTEGER,
+    ADD COLUMN IF NOT EXISTS cmdline TEXT,
+    ADD COLUMN IF NOT EXISTS user_name TEXT,
+    ADD COLUMN IF NOT EXISTS started_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS rss_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS virtual_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS state TEXT;
+
+CREATE INDEX IF NOT EXISTS process_metrics_snapshot_idx ON process_metrics (snapshot_id);
@@ -0,0 +1,44 @@
+-- Per-company rules over the processes found in each snapshot. Deny entries
+-- flag matching processes; allow entries exempt processes from the deny entries.
+CREATE TABLE IF NOT EXISTS process_policies (
+    id BIGSERIAL PRIMARY KEY,
+    company_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    effect TEXT NOT NULL CHECK (effect IN ('allow', 'deny')),
+    -- name and exe take a case-insensitive glob (* and ?); sha256 an exact hex digest
+    match_field TEXT NOT NULL CHECK (match_field IN ('name', 'exe', 'sha256')),
+    pattern TEXT NOT NULL,
+    raise_request BOOLEAN NOT NULL DEFAULT FALSE,
+    priority maintenance_priority NOT NULL DEFAULT 'High',
+    required_skills TEXT[] NOT NULL DEFAULT '{}',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS process_policies_company_idx ON process_policies (company_name);
+
+-- A program running against a deny entry on a device, from the first snapshot
+-- that showed it until the first one that did not
+CREATE TABLE IF NOT EXISTS process_policy_violations (
+    id BIGSERIAL PRIMARY KEY,
+    policy_id BIGINT NOT NULL REFERENCES process_policies (id) ON DELETE CASCADE,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    process_name TEXT NOT NULL,
+    exe TEXT NOT NULL DEFAULT '',
+    sha256 TEXT,
+    pid INTEGER,
+    user_name TEXT,
+    cmdline TEXT,
+    first_snapshot_id BIGINT REFERENCES process_snapshots (id) ON DELETE SET NULL,
+    last_snapshot_id BIGINT REFERENCES process_snapshots (id) ON DELETE SET NULL,
+    detected_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMPTZ,
+    maintenance_id BIGINT REFERENCES maintenance_requests (maintenance_id) ON DELETE SET NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS process_policy_violations_open_idx
+    ON process_policy_violations (policy_id, device_id, process_name, exe) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS process_policy_violations_device_idx ON process_policy_violations (device_id, detected_at);
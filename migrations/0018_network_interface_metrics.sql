@@ -0,0 +1,32 @@
+-- Device totals were summed into 32-bit columns and overflowed past 2 GiB
+ALTER TABLE network_metrics
+    ALTER COLUMN total_received TYPE BIGINT,
+    ALTER COLUMN total_transmitted TYPE BIGINT,
+    ADD COLUMN IF NOT EXISTS recorded_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- One row per interface per collection. Counters are cumulative as the
+-- kernel reports them; rates are against the interface's previous row.
+CREATE TABLE IF NOT EXISTS network_interface_metrics (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    interface_name TEXT NOT NULL,
+    mac_address TEXT,
+    addresses TEXT[] NOT NULL DEFAULT '{}',
+    bytes_received BIGINT NOT NULL,
+    bytes_transmitted BIGINT NOT NULL,
+    packets_received BIGINT NOT NULL,
+    packets_transmitted BIGINT NOT NULL,
+    errors_received BIGINT NOT NULL,
+    errors_transmitted BIGINT NOT NULL,
+    drops_received BIGINT,
+    drops_transmitted BIGINT,
+    receive_bytes_per_sec DOUBLE PRECISION,
+    transmit_bytes_per_sec DOUBLE PRECISION,
+    -- A counter went backwards since the previous row (reboot, driver reload
+    -- or wrap), so no rate could be computed
+    counter_reset BOOLEAN NOT NULL DEFAULT false,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS network_interface_metrics_device_idx
+    ON network_interface_metrics (device_id, interface_name, recorded_at);
@@ -0,0 +1,29 @@
+-- One row per mounted filesystem per collection. I/O counters belong to the
+-- block device behind the mount and are cumulative as /proc/diskstats
+-- reports them; rates are against the mount's previous row.
+CREATE TABLE IF NOT EXISTS disk_volume_metrics (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    mount_point TEXT NOT NULL,
+    file_system TEXT,
+    -- SSD, HDD or Unknown
+    kind TEXT NOT NULL,
+    removable BOOLEAN NOT NULL,
+    total_bytes BIGINT NOT NULL,
+    available_bytes BIGINT NOT NULL,
+    -- Filesystems with no block device (tmpfs, overlay, network mounts) have no I/O counters
+    read_bytes BIGINT,
+    written_bytes BIGINT,
+    reads_completed BIGINT,
+    writes_completed BIGINT,
+    read_bytes_per_sec DOUBLE PRECISION,
+    write_bytes_per_sec DOUBLE PRECISION,
+    read_iops DOUBLE PRECISION,
+    write_iops DOUBLE PRECISION,
+    counter_reset BOOLEAN NOT NULL DEFAULT false,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS disk_volume_metrics_device_idx
+    ON disk_volume_metrics (device_id, mount_point, recorded_at);
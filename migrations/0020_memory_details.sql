@@ -0,0 +1,17 @@
+-- total_memory and used_memory stay in MiB for existing readers; the new
+-- columns are bytes. Pressure columns are NULL on kernels without PSI.
+ALTER TABLE memory_metrics
+    ADD COLUMN IF NOT EXISTS total_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS used_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS available_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS free_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS cached_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS buffers_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS swap_total_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS swap_used_bytes BIGINT,
+    ADD COLUMN IF NOT EXISTS pressure_some_avg10 DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS pressure_some_avg60 DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS pressure_some_avg300 DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS pressure_full_avg10 DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS pressure_full_avg60 DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS pressure_full_avg300 DOUBLE PRECISION;
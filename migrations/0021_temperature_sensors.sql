@@ -0,0 +1,17 @@
+-- One row per sensor per collection, all sharing the collection's
+-- recorded_at. A device without sensors simply has no rows.
+CREATE TABLE IF NOT EXISTS temperature_sensor_metrics (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    label TEXT NOT NULL,
+    -- 'sysinfo', or 'hwmon' when read from /sys/class/hwmon directly
+    source TEXT NOT NULL,
+    temperature_celsius DOUBLE PRECISION NOT NULL,
+    -- Highest reading the sensor reports, where it keeps one
+    max_celsius DOUBLE PRECISION,
+    critical_celsius DOUBLE PRECISION,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS temperature_sensor_metrics_device_idx
+    ON temperature_sensor_metrics (device_id, recorded_at);
@@ -0,0 +1,32 @@
+-- Uptime is now measured from boot_time. The old downtime column was never
+-- meaningful and is no longer written; downtime comes from heartbeat gaps.
+ALTER TABLE uptime_metrics ADD COLUMN IF NOT EXISTS boot_time TIMESTAMPTZ;
+
+-- Every sign of life from a device's agent. Gaps between them are downtime.
+CREATE TABLE IF NOT EXISTS device_heartbeats (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    boot_time TIMESTAMPTZ,
+    -- What the agent was sending, e.g. 'uptime'
+    source TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS device_heartbeats_device_idx ON device_heartbeats (device_id, received_at);
+
+-- One row per boot of a device, from agent reports or the machine's wtmp.
+CREATE TABLE IF NOT EXISTS device_boots (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    boot_time TIMESTAMPTZ NOT NULL,
+    -- 'agent' when first reported by the agent, 'wtmp' when only known from wtmp
+    source TEXT NOT NULL CHECK (source IN ('agent', 'wtmp')),
+    -- First and last agent report during this boot; unset for wtmp-only boots
+    first_seen_at TIMESTAMPTZ,
+    last_seen_at TIMESTAMPTZ,
+    shutdown_at TIMESTAMPTZ,
+    -- 'wtmp' for a recorded shutdown, 'last_seen' when estimated from the
+    -- last report before the next boot
+    shutdown_source TEXT CHECK (shutdown_source IN ('wtmp', 'last_seen')),
+    UNIQUE (device_id, boot_time)
+);
@@ -0,0 +1,22 @@
+-- Whether a device's agent is alive, from how long ago it last checked in
+CREATE TYPE device_status AS ENUM ('unknown', 'online', 'stale', 'offline');
+
+ALTER TABLE devices
+    ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS status device_status NOT NULL DEFAULT 'unknown',
+    ADD COLUMN IF NOT EXISTS status_changed_at TIMESTAMPTZ;
+
+UPDATE devices d SET last_seen_at = h.last_seen_at
+FROM (SELECT device_id, max(received_at) AS last_seen_at FROM device_heartbeats GROUP BY device_id) h
+WHERE h.device_id = d.device_id;
+
+CREATE TABLE IF NOT EXISTS device_status_events (
+    id BIGSERIAL PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES devices (device_id) ON DELETE CASCADE,
+    from_status device_status NOT NULL,
+    to_status device_status NOT NULL,
+    last_seen_at TIMESTAMPTZ,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS device_status_events_device_idx ON device_status_events (device_id, changed_at);
@@ -0,0 +1,2 @@
+-- Technicians without a company work for every company
+ALTER TABLE technician ADD COLUMN IF NOT EXISTS company_name TEXT;
@@ -0,0 +1,333 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use log::error;
+use crate::auth::claims::Claims;
+use crate::user::users::UserRole;
+
+// prev_hash of the very first entry in the chain
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// Entries read per query when verifying the chain
+const VERIFY_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor_email: Option<String>,
+    pub actor_role: Option<String>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: String,
+    pub before_state: Option<Value>,
+    pub after_state: Option<Value>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    // Everything except `id` and `hash` itself feeds the hash. The fields are
+    // encoded as a JSON array so that no two entries can serialize the same way.
+    fn compute_hash(&self) -> String {
+        let payload = json!([
+            self.prev_hash,
+            self.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.actor_email,
+            self.actor_role,
+            self.action,
+            self.target_type,
+            self.target_id,
+            self.before_state,
+            self.after_state,
+            self.ip_address,
+        ]);
+        format!("{:x}", Sha256::digest(payload.to_string().as_bytes()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub actor_email: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditExportQuery {
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditChainStatus {
+    pub valid: bool,
+    pub entries_checked: usize,
+    pub first_broken_id: Option<i64>,
+}
+
+/// Serializes a record for the audit trail, dropping the password hash.
+pub fn snapshot<T: Serialize>(value: &T) -> Option<Value> {
+    let mut value = serde_json::to_value(value).ok()?;
+    if let Value::Object(fields) = &mut value {
+        fields.remove("password");
+    }
+    Some(value)
+}
+
+/// Who made an audited change and where from.
+pub struct AuditActor {
+    email: String,
+    role: String,
+    ip_address: Option<String>,
+}
+
+impl AuditActor {
+    /// The signed-in user behind `req`.
+    pub fn new(req: &HttpRequest, claims: &Claims) -> Self {
+        Self::named(req, claims.email(), &format!("{:?}", claims.role()))
+    }
+
+    /// For changes made without a session, such as an agent redeeming an
+    /// enrollment token on behalf of whoever issued it.
+    pub fn named(req: &HttpRequest, email: &str, role: &str) -> Self {
+        Self {
+            email: email.to_string(),
+            role: role.to_string(),
+            ip_address: req.peer_addr().map(|addr| addr.ip().to_string()),
+        }
+    }
+}
+
+/// Appends an entry for a change made in `tx`, so the change and its entry are
+/// committed together or not at all. Callers must fail the request on error.
+pub async fn record(
+    tx: &mut Transaction<'_, Postgres>,
+    actor: &AuditActor,
+    action: &str,
+    target_type: &str,
+    target_id: &str,
+    before_state: Option<Value>,
+    after_state: Option<Value>,
+) -> Result<(), sqlx::Error> {
+    let mut entry = AuditEntry {
+        id: 0,
+        actor_email: Some(actor.email.clone()),
+        actor_role: Some(actor.role.clone()),
+        action: action.to_string(),
+        target_type: target_type.to_string(),
+        target_id: target_id.to_string(),
+        before_state,
+        after_state,
+        ip_address: actor.ip_address.clone(),
+        // Postgres keeps microseconds; truncate so the stored value hashes the same
+        created_at: Utc::now().trunc_subsecs(6),
+        prev_hash: String::new(),
+        hash: String::new(),
+    };
+
+    // Serialize writers so two entries never link to the same predecessor. The
+    // lock is held until the caller's transaction ends.
+    sqlx::query!("LOCK TABLE audit_log IN EXCLUSIVE MODE")
+        .execute(&mut **tx)
+        .await?;
+
+    entry.prev_hash = sqlx::query!("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1")
+        .fetch_optional(&mut **tx)
+        .await?
+        .map(|row| row.hash)
+        .unwrap_or_else(|| GENESIS_HASH.to_string());
+    entry.hash = entry.compute_hash();
+
+    sqlx::query!(
+        "INSERT INTO audit_log (actor_email, actor_role, action, target_type, target_id, before_state, after_state, ip_address, created_at, prev_hash, hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+        entry.actor_email,
+        entry.actor_role,
+        entry.action,
+        entry.target_type,
+        entry.target_id,
+        entry.before_state,
+        entry.after_state,
+        entry.ip_address,
+        entry.created_at,
+        entry.prev_hash,
+        entry.hash,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn fetch_audit_entries(pool: &PgPool, query: &AuditQuery, limit: Option<i64>) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditEntry,
+        "SELECT id, actor_email, actor_role, action, target_type, target_id, before_state, after_state, ip_address, created_at, prev_hash, hash
+        FROM audit_log
+        WHERE ($1::text IS NULL OR actor_email = $1)
+        AND ($2::text IS NULL OR action = $2)
+        AND ($3::text IS NULL OR target_type = $3)
+        AND ($4::text IS NULL OR target_id = $4)
+        AND ($5::timestamptz IS NULL OR created_at >= $5)
+        AND ($6::timestamptz IS NULL OR created_at < $6)
+        ORDER BY id
+        LIMIT $7 OFFSET $8",
+        query.actor_email,
+        query.action,
+        query.target_type,
+        query.target_id,
+        query.from,
+        query.to,
+        limit,
+        query.offset.unwrap_or(0),
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Walks the chain in id order a page at a time, so the whole log never has
+/// to sit in memory at once.
+async fn verify_audit_chain_in_database(pool: &PgPool) -> Result<AuditChainStatus, sqlx::Error> {
+    let mut expected_prev = GENESIS_HASH.to_string();
+    let mut entries_checked = 0;
+    let mut last_id = 0;
+
+    loop {
+        let page = sqlx::query_as!(
+            AuditEntry,
+            "SELECT id, actor_email, actor_role, action, target_type, target_id, before_state, after_state, ip_address, created_at, prev_hash, hash
+            FROM audit_log WHERE id > $1 ORDER BY id LIMIT $2",
+            last_id,
+            VERIFY_PAGE_SIZE,
+        )
+        .fetch_all(pool)
+        .await?;
+        if page.is_empty() {
+            break;
+        }
+
+        for entry in page {
+            entries_checked += 1;
+            if entry.prev_hash != expected_prev || entry.compute_hash() != entry.hash {
+                return Ok(AuditChainStatus {
+                    valid: false,
+                    entries_checked,
+                    first_broken_id: Some(entry.id),
+                });
+            }
+            last_id = entry.id;
+            expected_prev = entry.hash;
+        }
+    }
+
+    Ok(AuditChainStatus {
+        valid: true,
+        entries_checked,
+        first_broken_id: None,
+    })
+}
+
+pub async fn get_audit_log(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AuditQuery>,
+) -> impl Responder {
+    if claims.role() != UserRole::SuperAdmin {
+        return HttpResponse::Forbidden().body("Only super admins can read the audit log");
+    }
+
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    match fetch_audit_entries(&pool, &query, Some(limit)).await {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => {
+            error!("Failed to fetch audit log: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch audit log")
+        }
+    }
+}
+
+pub async fn export_audit_log(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AuditQuery>,
+    export: web::Query<AuditExportQuery>,
+) -> impl Responder {
+    if claims.role() != UserRole::SuperAdmin {
+        return HttpResponse::Forbidden().body("Only super admins can export the audit log");
+    }
+
+    let entries = match fetch_audit_entries(&pool, &query, query.limit).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to export audit log: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to export audit log");
+        }
+    };
+
+    match export.format.as_deref().unwrap_or("json") {
+        "json" => HttpResponse::Ok()
+            .insert_header(("Content-Disposition", "attachment; filename=\"audit_log.json\""))
+            .json(entries),
+        "csv" => HttpResponse::Ok()
+            .content_type("text/csv")
+            .insert_header(("Content-Disposition", "attachment; filename=\"audit_log.csv\""))
+            .body(entries_to_csv(&entries)),
+        other => HttpResponse::BadRequest().body(format!("Unsupported export format: {}", other)),
+    }
+}
+
+pub async fn verify_audit_chain(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+) -> impl Responder {
+    if claims.role() != UserRole::SuperAdmin {
+        return HttpResponse::Forbidden().body("Only super admins can verify the audit log");
+    }
+
+    match verify_audit_chain_in_database(&pool).await {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(e) => {
+            error!("Failed to verify audit log: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to verify audit log")
+        }
+    }
+}
+
+fn entries_to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from("id,created_at,actor_email,actor_role,action,target_type,target_id,ip_address,before_state,after_state,prev_hash,hash\n");
+    for entry in entries {
+        let fields = [
+            entry.id.to_string(),
+            entry.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            entry.actor_email.clone().unwrap_or_default(),
+            entry.actor_role.clone().unwrap_or_default(),
+            entry.action.clone(),
+            entry.target_type.clone(),
+            entry.target_id.clone(),
+            entry.ip_address.clone().unwrap_or_default(),
+            entry.before_state.as_ref().map(Value::to_string).unwrap_or_default(),
+            entry.after_state.as_ref().map(Value::to_string).unwrap_or_default(),
+            entry.prev_hash.clone(),
+            entry.hash.clone(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_escape(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
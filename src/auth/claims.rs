@@ -1,17 +1,54 @@
 use serde::{Deserialize, Serialize};
 use chrono::{Utc, Duration};
+use actix_web::{dev::Payload, error::ErrorUnauthorized, Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{ready, Ready};
+use dotenvy::dotenv;
+use std::env;
+use crate::user::users::UserRole;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     sub: String,
+    role: UserRole,
     exp: usize,
 }
 
 impl Claims {
-    pub fn with_email(email: &str) -> Self {
+    pub fn for_user(email: &str, role: UserRole) -> Self {
         Self {
             sub: email.to_owned(),
+            role,
             exp: (Utc::now() + Duration::hours(24)).timestamp() as usize,
         }
     }
-}
\ No newline at end of file
+
+    pub fn email(&self) -> &str {
+        &self.sub
+    }
+
+    pub fn role(&self) -> UserRole {
+        self.role
+    }
+}
+
+// Handlers that need to know who is calling take `Claims` as an argument;
+// the middleware stores them in the request extensions once the token checks out.
+impl FromRequest for Claims {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Claims>()
+                .cloned()
+                .ok_or_else(|| ErrorUnauthorized("Missing or invalid token")),
+        )
+    }
+}
+
+pub fn load_secret() -> String {
+    dotenv().ok();
+    env::var("JWT_SECRET_KEY")
+        .expect("JWT_SECRET_KEY must be set in the environment")
+}
@@ -1,10 +1,10 @@
-use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
 use actix_service::{Service, Transform};
 use futures::future::{ok, Ready};
 use jsonwebtoken::{decode, Validation, DecodingKey};
 use std::task::{Context, Poll};
 use std::pin::Pin;
-use crate::auth::claims::Claims;
+use crate::auth::claims::{load_secret, Claims};
 
 pub struct AuthMiddleware;
 
@@ -52,8 +52,9 @@ where
     
         // Check if the token is valid
         if let Some(token) = token {
-            if decode::<Claims>(&token, &DecodingKey::from_secret("secret".as_ref()), &Validation::default()).is_ok() {
-                // If token is valid, call the inner service and return its result
+            if let Ok(data) = decode::<Claims>(&token, &DecodingKey::from_secret(load_secret().as_ref()), &Validation::default()) {
+                // If token is valid, expose the claims to handlers and call the inner service
+                req.extensions_mut().insert(data.claims);
                 let fut = self.service.call(req);
                 return Box::pin(async move {
                     let res = fut.await?;
@@ -0,0 +1,465 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, FromRow, PgConnection, PgPool};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::process::Command;
+use sysinfo::{Networks, System};
+use log::error;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::user::users::{fetch_company_of_user, fetch_user_company, resolve_company_scope, UserRole};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Device {
+    pub device_id: i64,
+    pub machine_id: String,
+    pub company_name: Option<String>,
+    pub hostname: Option<String>,
+    pub mac_address: Option<String>,
+    pub system_id: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub updated_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DeviceOwner {
+    pub id: i64,
+    pub device_id: i64,
+    pub owner_email: String,
+    pub owner_role: String,
+    pub assigned_at: chrono::DateTime<Utc>,
+    pub released_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// What a machine reports about itself. `machine_id` is what ties metrics from
+/// the same hardware together across reinstalls of the agent and new owners.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceIdentity {
+    pub machine_id: String,
+    pub hostname: Option<String>,
+    pub mac_address: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceListQuery {
+    pub company_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateDevice {
+    // Moving a device to another company is left to super admins
+    pub company_name: Option<String>,
+    pub system_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferDeviceOwner {
+    pub owner_email: String,
+    pub owner_role: UserRole,
+}
+
+pub fn local_device_identity() -> DeviceIdentity {
+    let hostname = System::host_name();
+    let mac_address = primary_mac_address();
+    let machine_id = read_machine_id().unwrap_or_else(|| {
+        // No OS-provided id; fall back to something that at least survives reboots
+        let seed = format!("{}|{}", hostname.clone().unwrap_or_default(), mac_address.clone().unwrap_or_default());
+        format!("derived-{:x}", Sha256::digest(seed.as_bytes()))
+    });
+
+    DeviceIdentity {
+        machine_id,
+        hostname,
+        mac_address,
+    }
+}
+
+fn read_machine_id() -> Option<String> {
+    for path in ["/etc/machine-id", "/var/lib/dbus/machine-id"] {
+        if let Ok(contents) = fs::read_to_string(path) {
+            let id = contents.trim();
+            if !id.is_empty() {
+                return Some(id.to_string());
+            }
+        }
+    }
+
+    // Windows keeps a per-install GUID in the registry
+    let output = Command::new("reg")
+        .args(["query", r"HKLM\SOFTWARE\Microsoft\Cryptography", "/v", "MachineGuid"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains("MachineGuid"))
+        .and_then(|line| line.split_whitespace().last())
+        .map(|guid| guid.to_lowercase())
+}
+
+// The lowest-named interface with a real MAC, so the choice is stable between runs
+fn primary_mac_address() -> Option<String> {
+    let networks = Networks::new_with_refreshed_list();
+    let mut interfaces: Vec<_> = networks
+        .iter()
+        .map(|(name, data)| (name.clone(), data.mac_address()))
+        .filter(|(_, mac)| !mac.is_unspecified())
+        .collect();
+    interfaces.sort_by(|a, b| a.0.cmp(&b.0));
+    interfaces.first().map(|(_, mac)| mac.to_string())
+}
+
+/// Creates the device on first sight and refreshes its hostname/MAC afterwards.
+/// A device keeps the company it was first registered to; moving it to another
+/// company is an explicit update.
+pub async fn register_device(
+    conn: &mut PgConnection,
+    identity: &DeviceIdentity,
+    company_name: Option<&str>,
+) -> Result<Device, sqlx::Error> {
+    sqlx::query_as!(
+        Device,
+        "INSERT INTO devices (machine_id, company_name, hostname, mac_address, created_at) VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (machine_id) DO UPDATE SET
+            company_name = COALESCE(devices.company_name, EXCLUDED.company_name),
+            hostname = EXCLUDED.hostname,
+            mac_address = EXCLUDED.mac_address,
+            updated_at = EXCLUDED.created_at
+        RETURNING device_id, machine_id, company_name, hostname, mac_address, system_id, created_at, updated_at",
+        identity.machine_id,
+        company_name,
+        identity.hostname,
+        identity.mac_address,
+        Utc::now(),
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Makes `owner_email` the current owner, closing the previous ownership period.
+/// Re-assigning the current owner is a no-op so their period is not split.
+pub async fn assign_device_owner(
+    conn: &mut PgConnection,
+    device_id: i64,
+    owner_email: &str,
+    owner_role: UserRole,
+) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let now = Utc::now();
+
+    let current = sqlx::query!(
+        "SELECT owner_email FROM device_owners WHERE device_id = $1 AND released_at IS NULL FOR UPDATE",
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if current.as_ref().map(|row| row.owner_email.as_str()) == Some(owner_email) {
+        return tx.commit().await;
+    }
+
+    sqlx::query!(
+        "UPDATE device_owners SET released_at = $1 WHERE device_id = $2 AND released_at IS NULL",
+        now,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO device_owners (device_id, owner_email, owner_role, assigned_at) VALUES ($1, $2, $3, $4)",
+        device_id,
+        owner_email,
+        format!("{:?}", owner_role),
+        now,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// Hands the device linked to a system assignment over to the assigned staff member.
+pub async fn assign_device_owner_by_system_id(
+    conn: &mut PgConnection,
+    system_id: &str,
+    owner_email: &str,
+) -> Result<(), sqlx::Error> {
+    match fetch_device_by_system_id(&mut *conn, system_id).await? {
+        Some(device) => assign_device_owner(conn, device.device_id, owner_email, UserRole::Staff).await,
+        None => Ok(()),
+    }
+}
+
+/// Ends the current ownership period of the device linked to a system
+/// assignment, leaving it without an owner.
+pub async fn release_device_owner_by_system_id<'e, E>(executor: E, system_id: &str) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "UPDATE device_owners SET released_at = now()
+        WHERE released_at IS NULL AND device_id = (SELECT device_id FROM devices WHERE system_id = $1)",
+        system_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+pub async fn fetch_device_by_id<'e, E>(executor: E, device_id: i64) -> Result<Device, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        Device,
+        "SELECT device_id, machine_id, company_name, hostname, mac_address, system_id, created_at, updated_at FROM devices WHERE device_id = $1",
+        device_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+async fn fetch_device_by_system_id<'e, E>(executor: E, system_id: &str) -> Result<Option<Device>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        Device,
+        "SELECT device_id, machine_id, company_name, hostname, mac_address, system_id, created_at, updated_at FROM devices WHERE system_id = $1",
+        system_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+async fn fetch_devices(pool: &PgPool, company_name: Option<&str>) -> Result<Vec<Device>, sqlx::Error> {
+    sqlx::query_as!(
+        Device,
+        "SELECT device_id, machine_id, company_name, hostname, mac_address, system_id, created_at, updated_at
+        FROM devices
+        WHERE ($1::text IS NULL OR company_name = $1)
+        ORDER BY device_id",
+        company_name
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_device_owners(pool: &PgPool, device_id: i64) -> Result<Vec<DeviceOwner>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceOwner,
+        "SELECT id, device_id, owner_email, owner_role, assigned_at, released_at FROM device_owners WHERE device_id = $1 ORDER BY assigned_at",
+        device_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn update_device_in_database<'e, E>(executor: E, device_id: i64, update: &UpdateDevice) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "UPDATE devices SET company_name = COALESCE($1, company_name), system_id = COALESCE($2, system_id), updated_at = $3 WHERE device_id = $4",
+        update.company_name,
+        update.system_id,
+        Utc::now(),
+        device_id,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Super admins manage every device; sub-admins only those of their own company.
+pub async fn can_manage_device(pool: &PgPool, claims: &Claims, device: &Device) -> Result<bool, sqlx::Error> {
+    match claims.role() {
+        UserRole::SuperAdmin => Ok(true),
+        UserRole::SubAdmin => {
+            let company = fetch_user_company(pool, claims).await?;
+            Ok(company.is_some() && company == device.company_name)
+        }
+        UserRole::Staff | UserRole::Technician => Ok(false),
+    }
+}
+
+/// Loads the device and checks the caller may administer it.
+pub async fn load_managed_device(pool: &PgPool, claims: &Claims, device_id: i64) -> Result<Device, HttpResponse> {
+    let device = fetch_device_by_id(pool, device_id)
+        .await
+        .map_err(|_| HttpResponse::NotFound().body("Device not found"))?;
+
+    match can_manage_device(pool, claims, &device).await {
+        Ok(true) => Ok(device),
+        Ok(false) => Err(HttpResponse::Forbidden().body("Not allowed to manage this device")),
+        Err(e) => {
+            error!("Failed to check device permissions: {:?}", e);
+            Err(HttpResponse::InternalServerError().body("Failed to check device permissions"))
+        }
+    }
+}
+
+/// Device currently handed to `email`; the most recent one if they hold several.
+pub async fn fetch_current_device_for_owner(pool: &PgPool, email: &str) -> Result<Option<Device>, sqlx::Error> {
+    sqlx::query_as!(
+        Device,
+        "SELECT d.device_id, d.machine_id, d.company_name, d.hostname, d.mac_address, d.system_id, d.created_at, d.updated_at
+        FROM devices d
+        JOIN device_owners o ON o.device_id = d.device_id
+        WHERE o.owner_email = $1 AND o.released_at IS NULL
+        ORDER BY o.assigned_at DESC
+        LIMIT 1",
+        email
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Loads a device the caller may file maintenance requests for: one they
+/// currently hold, or one they administer.
+pub async fn load_reportable_device(pool: &PgPool, claims: &Claims, device_id: i64) -> Result<Device, HttpResponse> {
+    let is_owner = sqlx::query!(
+        "SELECT id FROM device_owners WHERE device_id = $1 AND owner_email = $2 AND released_at IS NULL",
+        device_id,
+        claims.email()
+    )
+    .fetch_optional(pool)
+    .await;
+
+    match is_owner {
+        Ok(Some(_)) => fetch_device_by_id(pool, device_id)
+            .await
+            .map_err(|_| HttpResponse::NotFound().body("Device not found")),
+        Ok(None) => load_managed_device(pool, claims, device_id).await,
+        Err(e) => {
+            error!("Failed to check device owner: {:?}", e);
+            Err(HttpResponse::InternalServerError().body("Failed to check device permissions"))
+        }
+    }
+}
+
+pub async fn list_devices(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<DeviceListQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_devices(&pool, company_name.as_deref()).await {
+        Ok(devices) => HttpResponse::Ok().json(devices),
+        Err(e) => {
+            error!("Failed to fetch devices: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch devices")
+        }
+    }
+}
+
+pub async fn get_device(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    match load_managed_device(&pool, &claims, device_id.into_inner()).await {
+        Ok(device) => HttpResponse::Ok().json(device),
+        Err(response) => response,
+    }
+}
+
+pub async fn get_device_owners(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device = match load_managed_device(&pool, &claims, device_id.into_inner()).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    match fetch_device_owners(&pool, device.device_id).await {
+        Ok(owners) => HttpResponse::Ok().json(owners),
+        Err(e) => {
+            error!("Failed to fetch device owners: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch device owners")
+        }
+    }
+}
+
+pub async fn update_device(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    update: web::Json<UpdateDevice>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    let before = match load_managed_device(&pool, &claims, device_id).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+    let moves_company = update.company_name.is_some() && update.company_name != before.company_name;
+    if moves_company && claims.role() != UserRole::SuperAdmin {
+        return HttpResponse::Forbidden().body("Only super admins can move a device to another company");
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        update_device_in_database(&mut *tx, device_id, &update).await?;
+        let after = fetch_device_by_id(&mut *tx, device_id).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "update", "device", &device_id.to_string(), snapshot(&before), snapshot(&after)).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("Device updated"),
+        Err(e) => {
+            error!("Failed to update device: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update device")
+        }
+    }
+}
+
+pub async fn transfer_device_owner(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    transfer: web::Json<TransferDeviceOwner>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    let device = match load_managed_device(&pool, &claims, device_id).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    match fetch_company_of_user(&pool, &transfer.owner_email, transfer.owner_role).await {
+        Ok(Some(company_name)) if company_name == device.company_name => {}
+        Ok(Some(_)) => return HttpResponse::BadRequest().body("The new owner does not belong to the device's company"),
+        Ok(None) => return HttpResponse::NotFound().body(format!("No {:?} with that email", transfer.owner_role)),
+        Err(e) => {
+            error!("Failed to fetch new owner of device {}: {:?}", device_id, e);
+            return HttpResponse::InternalServerError().body("Failed to transfer device owner");
+        }
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        assign_device_owner(&mut tx, device_id, &transfer.owner_email, transfer.owner_role).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "transfer_owner", "device", &device_id.to_string(), None, snapshot(&*transfer)).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("Device owner updated"),
+        Err(e) => {
+            error!("Failed to transfer device owner: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to transfer device owner")
+        }
+    }
+}
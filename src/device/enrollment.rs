@@ -0,0 +1,425 @@
+use actix_web::{dev::Payload, error::{ErrorInternalServerError, ErrorUnauthorized}, web, Error, FromRequest, HttpRequest, HttpResponse, Responder};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use chrono::{Duration, Utc};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use log::error;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::device::devices::{load_managed_device, local_device_identity, register_device, DeviceIdentity};
+use crate::user::users::{fetch_user_company, UserRole};
+
+/// Header agents send their device key in on ingest requests.
+pub const DEVICE_KEY_HEADER: &str = "X-Device-Key";
+
+const DEFAULT_TOKEN_LIFETIME_HOURS: i64 = 24;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DeviceCredential {
+    pub id: i64,
+    pub device_id: i64,
+    pub company_name: String,
+    pub key_prefix: String,
+    pub created_at: chrono::DateTime<Utc>,
+    pub last_used_at: Option<chrono::DateTime<Utc>>,
+    pub revoked_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateEnrollmentToken {
+    pub expires_in_hours: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnrollmentTokenResponse {
+    pub token: String,
+    pub company_name: String,
+    pub expires_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnrollRequest {
+    pub token: String,
+    // Defaults to the machine this server runs on
+    pub identity: Option<DeviceIdentity>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceKeyResponse {
+    pub device_id: i64,
+    pub credential_id: i64,
+    pub api_key: String,
+}
+
+/// The device behind a valid `X-Device-Key`. Ingest handlers take this instead
+/// of a device id so an agent can only ever write its own data.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedDevice {
+    pub device_id: i64,
+}
+
+impl FromRequest for AuthenticatedDevice {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let key = req
+            .headers()
+            .get(DEVICE_KEY_HEADER)
+            .and_then(|header| header.to_str().ok())
+            .map(|key| key.to_owned());
+
+        Box::pin(async move {
+            let pool = pool.ok_or_else(|| ErrorInternalServerError("Database pool not configured"))?;
+            let key = key.ok_or_else(|| ErrorUnauthorized("Missing device key"))?;
+            match authenticate_device_key(&pool, &key).await {
+                Ok(Some(device)) => Ok(device),
+                Ok(None) => Err(ErrorUnauthorized("Invalid or revoked device key")),
+                Err(e) => {
+                    error!("Failed to authenticate device key: {:?}", e);
+                    Err(ErrorInternalServerError("Failed to authenticate device"))
+                }
+            }
+        })
+    }
+}
+
+fn generate_secret(prefix: &str) -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}_{}", prefix, hex)
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+async fn authenticate_device_key(pool: &PgPool, key: &str) -> Result<Option<AuthenticatedDevice>, sqlx::Error> {
+    let credential = sqlx::query!(
+        "UPDATE device_credentials SET last_used_at = $1 WHERE key_hash = $2 AND revoked_at IS NULL RETURNING device_id",
+        Utc::now(),
+        hash_secret(key),
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(credential.map(|row| AuthenticatedDevice {
+        device_id: row.device_id,
+    }))
+}
+
+/// Revokes any active key for the device and issues a new one. The plaintext key
+/// is only ever returned here. Run it in a transaction so the device is never
+/// left without a key.
+async fn issue_device_credential(conn: &mut PgConnection, device_id: i64, company_name: &str) -> Result<DeviceKeyResponse, sqlx::Error> {
+    let api_key = generate_secret("dk");
+    let now = Utc::now();
+
+    sqlx::query!(
+        "UPDATE device_credentials SET revoked_at = $1 WHERE device_id = $2 AND revoked_at IS NULL",
+        now,
+        device_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let credential_id = sqlx::query!(
+        "INSERT INTO device_credentials (device_id, company_name, key_prefix, key_hash, created_at) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        device_id,
+        company_name,
+        &api_key[..11],
+        hash_secret(&api_key),
+        now,
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .id;
+
+    Ok(DeviceKeyResponse {
+        device_id,
+        credential_id,
+        api_key,
+    })
+}
+
+async fn fetch_device_credentials(pool: &PgPool, device_id: i64) -> Result<Vec<DeviceCredential>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceCredential,
+        "SELECT id, device_id, company_name, key_prefix, created_at, last_used_at, revoked_at FROM device_credentials WHERE device_id = $1 ORDER BY created_at",
+        device_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn revoke_device_credential_in_database<'e, E>(executor: E, device_id: i64, credential_id: i64) -> Result<bool, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let result = sqlx::query!(
+        "UPDATE device_credentials SET revoked_at = $1 WHERE id = $2 AND device_id = $3 AND revoked_at IS NULL",
+        Utc::now(),
+        credential_id,
+        device_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn create_enrollment_token(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    request: web::Json<CreateEnrollmentToken>,
+) -> impl Responder {
+    if claims.role() != UserRole::SubAdmin {
+        return HttpResponse::Forbidden().body("Only sub admins can create enrollment tokens");
+    }
+
+    let company_name = match fetch_user_company(&pool, &claims).await {
+        Ok(Some(company)) => company,
+        Ok(None) => return HttpResponse::BadRequest().body("Sub-admin must have a company name"),
+        Err(e) => {
+            error!("Failed to look up company: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to create enrollment token");
+        }
+    };
+
+    let token = generate_secret("enr");
+    let lifetime = request.expires_in_hours.unwrap_or(DEFAULT_TOKEN_LIFETIME_HOURS).clamp(1, 24 * 30);
+    let expires_at = Utc::now() + Duration::hours(lifetime);
+
+    let saved = async {
+        let mut tx = pool.begin().await?;
+        let row = sqlx::query!(
+            "INSERT INTO enrollment_tokens (token_hash, company_name, created_by, created_at, expires_at) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            hash_secret(&token),
+            company_name,
+            claims.email(),
+            Utc::now(),
+            expires_at,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "enrollment_token", &row.id.to_string(), None, None).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match saved {
+        Ok(_) => HttpResponse::Created().json(EnrollmentTokenResponse {
+            token,
+            company_name,
+            expires_at,
+        }),
+        Err(e) => {
+            error!("Failed to create enrollment token: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to create enrollment token")
+        }
+    }
+}
+
+pub async fn enroll_device(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    request: web::Json<EnrollRequest>,
+) -> impl Responder {
+    let request = request.into_inner();
+    let now = Utc::now();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to start enrollment: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to enroll device");
+        }
+    };
+
+    // Claiming the token inside the transaction keeps it unused if anything below fails
+    let token = sqlx::query!(
+        "UPDATE enrollment_tokens SET used_at = $1 WHERE token_hash = $2 AND used_at IS NULL AND expires_at > $1 RETURNING id, company_name, created_by",
+        now,
+        hash_secret(&request.token),
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+
+    let token = match token {
+        Ok(Some(token)) => token,
+        Ok(None) => return HttpResponse::Unauthorized().body("Enrollment token is invalid, expired or already used"),
+        Err(e) => {
+            error!("Failed to redeem enrollment token: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to enroll device");
+        }
+    };
+
+    let local = local_device_identity();
+    let identity = request.identity.unwrap_or_else(|| local.clone());
+
+    // Identities come from the caller, so one naming some other machine may
+    // only register a new device, never take over a row that exists
+    let registered = sqlx::query_scalar!("SELECT device_id FROM devices WHERE machine_id = $1", identity.machine_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    match registered {
+        Ok(Some(_)) if identity.machine_id != local.machine_id => {
+            return HttpResponse::Conflict().body("A device is already registered under this identity");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to look up device {}: {:?}", identity.machine_id, e);
+            return HttpResponse::InternalServerError().body("Failed to enroll device");
+        }
+    }
+
+    let device = match register_device(&mut tx, &identity, Some(&token.company_name)).await {
+        Ok(device) => device,
+        Err(e) => {
+            error!("Failed to register device: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to enroll device");
+        }
+    };
+
+    if device.company_name.as_deref() != Some(token.company_name.as_str()) {
+        return HttpResponse::Conflict().body("Device is already registered to another company");
+    }
+
+    // Enrolling again would revoke the running agent's key; admins rotate it instead
+    let keyed = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM device_credentials WHERE device_id = $1 AND revoked_at IS NULL) as "keyed!""#,
+        device.device_id
+    )
+    .fetch_one(&mut *tx)
+    .await;
+    match keyed {
+        Ok(false) => {}
+        Ok(true) => return HttpResponse::Conflict().body("Device is already enrolled; an admin can rotate its key"),
+        Err(e) => {
+            error!("Failed to check credentials of device {}: {:?}", device.device_id, e);
+            return HttpResponse::InternalServerError().body("Failed to enroll device");
+        }
+    }
+
+    let issued = match issue_device_credential(&mut tx, device.device_id, &token.company_name).await {
+        Ok(issued) => issued,
+        Err(e) => {
+            error!("Failed to issue device credential: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to enroll device");
+        }
+    };
+
+    let linked = sqlx::query!(
+        "UPDATE enrollment_tokens SET used_by_device_id = $1 WHERE id = $2",
+        device.device_id,
+        token.id
+    )
+    .execute(&mut *tx)
+    .await;
+
+    // Agents enroll without a session; the entry goes to whoever issued the token
+    let actor = AuditActor::named(&req, &token.created_by, "EnrollmentToken");
+    let completed = match linked {
+        Ok(_) => match record(&mut tx, &actor, "enroll", "device", &device.device_id.to_string(), None, snapshot(&device)).await {
+            Ok(_) => tx.commit().await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = completed {
+        error!("Failed to complete enrollment: {:?}", e);
+        return HttpResponse::InternalServerError().body("Failed to enroll device");
+    }
+
+    HttpResponse::Created().json(issued)
+}
+
+pub async fn get_device_credentials(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device = match load_managed_device(&pool, &claims, device_id.into_inner()).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    match fetch_device_credentials(&pool, device.device_id).await {
+        Ok(credentials) => HttpResponse::Ok().json(credentials),
+        Err(e) => {
+            error!("Failed to fetch device credentials: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch device credentials")
+        }
+    }
+}
+
+pub async fn rotate_device_credential(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device = match load_managed_device(&pool, &claims, device_id.into_inner()).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let company_name = match &device.company_name {
+        Some(company) => company,
+        None => return HttpResponse::BadRequest().body("Device is not bound to a company"),
+    };
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let issued = issue_device_credential(&mut tx, device.device_id, company_name).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "rotate_credential", "device", &device.device_id.to_string(), None, None).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(issued)
+    }
+    .await;
+
+    match result {
+        Ok(issued) => HttpResponse::Created().json(issued),
+        Err(e) => {
+            error!("Failed to rotate device credential: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to rotate device credential")
+        }
+    }
+}
+
+pub async fn revoke_device_credential(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    path: web::Path<(i64, i64)>,
+) -> impl Responder {
+    let (device_id, credential_id) = path.into_inner();
+    let device = match load_managed_device(&pool, &claims, device_id).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let revoked = revoke_device_credential_in_database(&mut *tx, device.device_id, credential_id).await?;
+        if revoked {
+            record(&mut tx, &AuditActor::new(&req, &claims), "revoke_credential", "device", &device.device_id.to_string(), None, None).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(revoked)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().body("Device credential revoked"),
+        Ok(false) => HttpResponse::NotFound().body("Active credential not found"),
+        Err(e) => {
+            error!("Failed to revoke device credential: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to revoke device credential")
+        }
+    }
+}
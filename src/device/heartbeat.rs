@@ -0,0 +1,328 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use sysinfo::System;
+use chrono::{DateTime, Duration, Utc};
+use dotenvy::dotenv;
+use log::{error, warn};
+use std::env;
+use tokio::time::interval;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
+use crate::user::users::resolve_company_scope;
+
+const DEFAULT_EVENT_LIMIT: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "device_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceStatus {
+    /// Never checked in
+    Unknown,
+    Online,
+    /// Missed check-ins but not for long enough to count as offline
+    Stale,
+    Offline,
+}
+
+/// How long a device may stay silent before it is stale, then offline.
+#[derive(Debug, Clone, Copy)]
+pub struct StatusThresholds {
+    pub stale_after: Duration,
+    pub offline_after: Duration,
+}
+
+impl StatusThresholds {
+    // DEVICE_STALE_AFTER_SECS (5 minutes) and DEVICE_OFFLINE_AFTER_SECS (15 minutes)
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        let seconds = |name: &str, default: i64| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let stale_after = Duration::seconds(seconds("DEVICE_STALE_AFTER_SECS", 300));
+        let offline_after = Duration::seconds(seconds("DEVICE_OFFLINE_AFTER_SECS", 900)).max(stale_after);
+        Self { stale_after, offline_after }
+    }
+
+    pub fn status_of(&self, last_seen_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> DeviceStatus {
+        match last_seen_at {
+            None => DeviceStatus::Unknown,
+            Some(last_seen_at) if now - last_seen_at >= self.offline_after => DeviceStatus::Offline,
+            Some(last_seen_at) if now - last_seen_at >= self.stale_after => DeviceStatus::Stale,
+            Some(_) => DeviceStatus::Online,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DeviceStatusView {
+    pub device_id: i64,
+    pub hostname: Option<String>,
+    pub status: DeviceStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub status_changed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DeviceStatusEvent {
+    pub id: i64,
+    pub device_id: i64,
+    pub from_status: DeviceStatus,
+    pub to_status: DeviceStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Devices of one company in each state.
+#[derive(Debug, Serialize, FromRow)]
+pub struct FleetStatusCount {
+    pub company_name: Option<String>,
+    pub total: i64,
+    pub online: i64,
+    pub stale: i64,
+    pub offline: i64,
+    pub unknown: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FleetStatusQuery {
+    pub company_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusEventQuery {
+    pub limit: Option<i64>,
+}
+
+/// Records a sign of life from a device's agent and brings it back online.
+pub async fn record_heartbeat(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: i64,
+    boot_time: Option<DateTime<Utc>>,
+    source: &str,
+    received_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO device_heartbeats (device_id, received_at, boot_time, source) VALUES ($1, $2, $3, $4)",
+        device_id,
+        received_at,
+        boot_time,
+        source,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // The event is written only when the device was not online already
+    sqlx::query!(
+        r#"WITH previous AS (
+            SELECT device_id, status FROM devices WHERE device_id = $1 FOR UPDATE
+        ), updated AS (
+            UPDATE devices d SET last_seen_at = GREATEST(d.last_seen_at, $2),
+                status = 'online',
+                status_changed_at = CASE WHEN p.status = 'online' THEN d.status_changed_at ELSE $2 END
+            FROM previous p
+            WHERE d.device_id = p.device_id
+            RETURNING d.device_id, p.status as from_status
+        )
+        INSERT INTO device_status_events (device_id, from_status, to_status, last_seen_at, changed_at)
+        SELECT device_id, from_status, 'online', $2, $2 FROM updated WHERE from_status <> 'online'"#,
+        device_id,
+        received_at,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Lightweight check-in for agents between full collections.
+pub async fn get_heartbeat_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let device_id = device.device_id;
+    let boot_time = DateTime::from_timestamp(System::boot_time() as i64, 0);
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        record_heartbeat(&mut tx, device_id, boot_time, "heartbeat", Utc::now()).await?;
+        tx.commit().await?;
+        fetch_device_status(&pool, device_id).await
+    }
+    .await;
+
+    match result {
+        Ok(Some(status)) => HttpResponse::Ok().json(status),
+        Ok(None) => HttpResponse::NotFound().body("Device not found"),
+        Err(e) => {
+            error!("Failed to record heartbeat of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to record heartbeat")
+        }
+    }
+}
+
+/// Moves devices that went silent to stale or offline, writing an event for
+/// each change. Returns the changes made.
+pub async fn update_device_statuses(pool: &PgPool, thresholds: StatusThresholds) -> Result<Vec<DeviceStatusEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceStatusEvent,
+        r#"WITH silent AS (
+            SELECT device_id, status, last_seen_at,
+                CASE WHEN last_seen_at <= now() - make_interval(secs => $2) THEN 'offline' ELSE 'stale' END::device_status as to_status
+            FROM devices
+            WHERE last_seen_at <= now() - make_interval(secs => $1)
+            FOR UPDATE
+        ), updated AS (
+            UPDATE devices d SET status = s.to_status, status_changed_at = now()
+            FROM silent s
+            WHERE d.device_id = s.device_id AND d.status <> s.to_status
+            RETURNING d.device_id, s.status as from_status, s.to_status, s.last_seen_at
+        )
+        INSERT INTO device_status_events (device_id, from_status, to_status, last_seen_at)
+        SELECT device_id, from_status, to_status, last_seen_at FROM updated
+        RETURNING id, device_id, from_status as "from_status: DeviceStatus", to_status as "to_status: DeviceStatus", last_seen_at, changed_at"#,
+        thresholds.stale_after.num_seconds() as f64,
+        thresholds.offline_after.num_seconds() as f64,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Runs forever, checking every DEVICE_STATUS_CHECK_INTERVAL_SECS (60 by
+/// default) for devices that stopped checking in.
+pub async fn run_device_status_monitor(pool: PgPool) {
+    dotenv().ok();
+    let seconds = env::var("DEVICE_STATUS_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60);
+    let thresholds = StatusThresholds::from_env();
+    let mut interval = interval(std::time::Duration::from_secs(seconds));
+
+    loop {
+        interval.tick().await;
+        match update_device_statuses(&pool, thresholds).await {
+            Ok(events) => {
+                for event in events.iter().filter(|event| event.to_status == DeviceStatus::Offline) {
+                    warn!(
+                        "Device {} went offline, last seen {}",
+                        event.device_id,
+                        event.last_seen_at.map(|at| at.to_rfc3339()).unwrap_or_default()
+                    );
+                }
+            }
+            Err(e) => error!("Failed to update device statuses: {:?}", e),
+        }
+    }
+}
+
+async fn fetch_device_status(pool: &PgPool, device_id: i64) -> Result<Option<DeviceStatusView>, sqlx::Error> {
+    let device = sqlx::query_as!(
+        DeviceStatusView,
+        r#"SELECT device_id, hostname, status as "status: DeviceStatus", last_seen_at, status_changed_at
+        FROM devices WHERE device_id = $1"#,
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    // The monitor only runs every so often, so report what the thresholds say now
+    Ok(device.map(|mut device| {
+        device.status = StatusThresholds::from_env().status_of(device.last_seen_at, Utc::now());
+        device
+    }))
+}
+
+pub async fn get_device_status(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_device_status(&pool, device_id).await {
+        Ok(Some(status)) => HttpResponse::Ok().json(status),
+        Ok(None) => HttpResponse::NotFound().body("Device not found"),
+        Err(e) => {
+            error!("Failed to fetch status of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch device status")
+        }
+    }
+}
+
+/// The device's status changes, newest first.
+pub async fn get_device_status_events(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<StatusEventQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    let events = sqlx::query_as!(
+        DeviceStatusEvent,
+        r#"SELECT id, device_id, from_status as "from_status: DeviceStatus", to_status as "to_status: DeviceStatus", last_seen_at, changed_at
+        FROM device_status_events WHERE device_id = $1
+        ORDER BY changed_at DESC, id DESC
+        LIMIT $2"#,
+        device_id,
+        query.limit.unwrap_or(DEFAULT_EVENT_LIMIT).clamp(1, 1000),
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match events {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(e) => {
+            error!("Failed to fetch status events of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch device status")
+        }
+    }
+}
+
+/// Online, stale, offline and never-seen device counts per company.
+pub async fn get_fleet_status(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<FleetStatusQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+    let thresholds = StatusThresholds::from_env();
+
+    let counts = sqlx::query_as!(
+        FleetStatusCount,
+        r#"SELECT company_name,
+            COUNT(*) as "total!",
+            COUNT(*) FILTER (WHERE last_seen_at > now() - make_interval(secs => $2)) as "online!",
+            COUNT(*) FILTER (WHERE last_seen_at <= now() - make_interval(secs => $2) AND last_seen_at > now() - make_interval(secs => $3)) as "stale!",
+            COUNT(*) FILTER (WHERE last_seen_at <= now() - make_interval(secs => $3)) as "offline!",
+            COUNT(*) FILTER (WHERE last_seen_at IS NULL) as "unknown!"
+        FROM devices
+        WHERE ($1::text IS NULL OR company_name = $1)
+        GROUP BY company_name
+        ORDER BY company_name"#,
+        company_name,
+        thresholds.stale_after.num_seconds() as f64,
+        thresholds.offline_after.num_seconds() as f64,
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match counts {
+        Ok(counts) => HttpResponse::Ok().json(counts),
+        Err(e) => {
+            error!("Failed to fetch fleet status: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch device status")
+        }
+    }
+}
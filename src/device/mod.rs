@@ -0,0 +1,4 @@
+pub mod devices;
+pub mod enrollment;
+pub mod heartbeat;
+pub mod snapshot;
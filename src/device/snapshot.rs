@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use chrono::Utc;
+
+// How many of the busiest processes go into a snapshot
+const TOP_PROCESS_COUNT: i64 = 10;
+
+/// The latest metrics a device reported, frozen at one point in time. Each
+/// section is the stored row as JSON, or null if the device never sent it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceSnapshot {
+    pub device_id: i64,
+    pub captured_at: chrono::DateTime<Utc>,
+    pub cpu: Option<Value>,
+    pub memory: Option<Value>,
+    pub disk: Option<Value>,
+    pub services: Value,
+    pub uptime: Option<Value>,
+    pub top_processes: Value,
+}
+
+pub async fn capture_device_snapshot(pool: &PgPool, device_id: i64) -> Result<DeviceSnapshot, sqlx::Error> {
+    let cpu = sqlx::query_scalar!(
+        "SELECT row_to_json(m) FROM cpu_metrics m WHERE device_id = $1",
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let memory = sqlx::query_scalar!(
+        "SELECT row_to_json(m) FROM memory_metrics m WHERE device_id = $1 ORDER BY recorded_at DESC LIMIT 1",
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let disk = sqlx::query_scalar!(
+        "SELECT row_to_json(m) FROM disk_metrics m WHERE device_id = $1 ORDER BY recorded_at DESC LIMIT 1",
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let services = sqlx::query_scalar!(
+        "SELECT COALESCE(json_agg(row_to_json(m) ORDER BY m.service_name), '[]'::json) FROM service_status_metrics m WHERE device_id = $1",
+        device_id
+    )
+    .fetch_one(pool)
+    .await?
+    .unwrap_or_else(|| Value::Array(Vec::new()));
+
+    let uptime = sqlx::query_scalar!(
+        "SELECT row_to_json(m) FROM uptime_metrics m WHERE device_id = $1 ORDER BY COALESCE(m.updated_at, m.created_at) DESC NULLS LAST LIMIT 1",
+        device_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    // Busiest processes of the latest snapshot
+    let top_processes = sqlx::query_scalar!(
+        "SELECT COALESCE(json_agg(row_to_json(p)), '[]'::json) FROM (
+            SELECT * FROM process_metrics
+            WHERE snapshot_id = (SELECT id FROM process_snapshots WHERE device_id = $1 ORDER BY captured_at DESC, id DESC LIMIT 1)
+            ORDER BY cpu_usage DESC NULLS LAST, memory DESC NULLS LAST
+            LIMIT $2
+        ) p",
+        device_id,
+        TOP_PROCESS_COUNT
+    )
+    .fetch_one(pool)
+    .await?
+    .unwrap_or_else(|| Value::Array(Vec::new()));
+
+    Ok(DeviceSnapshot {
+        device_id,
+        captured_at: Utc::now(),
+        cpu,
+        memory,
+        disk,
+        services,
+        uptime,
+        top_processes,
+    })
+}
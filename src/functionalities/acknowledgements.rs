@@ -0,0 +1,434 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::functionalities::assign::{check_system_company, SystemCheckout};
+use crate::user::users::{fetch_user_company, resolve_company_scope, UserRole};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct AssignmentTerms {
+    pub id: i64,
+    pub company_name: String,
+    pub version: String,
+    pub body: String,
+    pub published_by: Option<String>,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PublishTermsRequest {
+    pub company_name: Option<String>,
+    pub version: String,
+    pub body: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct SystemAcknowledgement {
+    pub id: i64,
+    pub checkout_id: i64,
+    pub new_system_id: String,
+    pub staff_id_email: String,
+    pub terms_id: i64,
+    pub terms_version: String,
+    pub accepted_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcknowledgeRequest {
+    pub terms_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TermsQuery {
+    pub company_name: Option<String>,
+}
+
+/// A system currently checked out to the caller and whether they accepted it.
+#[derive(Debug, Serialize)]
+pub struct HeldSystem {
+    pub checkout: SystemCheckout,
+    pub acknowledgement: Option<SystemAcknowledgement>,
+}
+
+async fn fetch_current_terms(pool: &PgPool, company_name: &str) -> Result<Option<AssignmentTerms>, sqlx::Error> {
+    sqlx::query_as!(
+        AssignmentTerms,
+        "SELECT id, company_name, version, body, published_by, published_at
+        FROM system_assignment_terms WHERE company_name = $1
+        ORDER BY published_at DESC, id DESC
+        LIMIT 1",
+        company_name
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_terms_history(pool: &PgPool, company_name: Option<&str>) -> Result<Vec<AssignmentTerms>, sqlx::Error> {
+    sqlx::query_as!(
+        AssignmentTerms,
+        "SELECT id, company_name, version, body, published_by, published_at
+        FROM system_assignment_terms WHERE ($1::text IS NULL OR company_name = $1)
+        ORDER BY company_name, published_at DESC, id DESC",
+        company_name
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn save_terms_to_database<'e, E>(
+    executor: E,
+    company_name: &str,
+    terms: &PublishTermsRequest,
+    published_by: &str,
+) -> Result<Option<AssignmentTerms>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        AssignmentTerms,
+        "INSERT INTO system_assignment_terms (company_name, version, body, published_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (company_name, version) DO NOTHING
+        RETURNING id, company_name, version, body, published_by, published_at",
+        company_name,
+        terms.version.trim(),
+        terms.body,
+        published_by,
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+async fn fetch_open_checkout_of(pool: &PgPool, new_system_id: &str, staff_id_email: &str) -> Result<Option<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "SELECT id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count
+        FROM system_checkouts
+        WHERE new_system_id = $1 AND staff_id_email = $2 AND returned_at IS NULL",
+        new_system_id,
+        staff_id_email,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_open_checkouts_of(pool: &PgPool, staff_id_email: &str) -> Result<Vec<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "SELECT id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count
+        FROM system_checkouts
+        WHERE staff_id_email = $1 AND returned_at IS NULL
+        ORDER BY checked_out_at",
+        staff_id_email
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_acknowledgements_of_checkouts(pool: &PgPool, checkout_ids: &[i64]) -> Result<Vec<SystemAcknowledgement>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemAcknowledgement,
+        "SELECT id, checkout_id, new_system_id, staff_id_email, terms_id, terms_version, accepted_at, ip_address, user_agent
+        FROM system_acknowledgements WHERE checkout_id = ANY($1)",
+        checkout_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_acknowledgements_of_system(pool: &PgPool, new_system_id: &str) -> Result<Vec<SystemAcknowledgement>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemAcknowledgement,
+        "SELECT id, checkout_id, new_system_id, staff_id_email, terms_id, terms_version, accepted_at, ip_address, user_agent
+        FROM system_acknowledgements WHERE new_system_id = $1
+        ORDER BY accepted_at DESC",
+        new_system_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A checkout is accepted once; a second acceptance of the same checkout is refused
+async fn save_acknowledgement_to_database<'e, E>(
+    executor: E,
+    checkout: &SystemCheckout,
+    terms: &AssignmentTerms,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+) -> Result<Option<SystemAcknowledgement>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        SystemAcknowledgement,
+        "INSERT INTO system_acknowledgements (checkout_id, new_system_id, staff_id_email, terms_id, terms_version, ip_address, user_agent)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (checkout_id) DO NOTHING
+        RETURNING id, checkout_id, new_system_id, staff_id_email, terms_id, terms_version, accepted_at, ip_address, user_agent",
+        checkout.id,
+        checkout.new_system_id,
+        checkout.staff_id_email,
+        terms.id,
+        terms.version,
+        ip_address,
+        user_agent,
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Same company matching as the overdue report: through the device or the assigning sub admin
+async fn fetch_unacknowledged_checkouts(pool: &PgPool, company_name: Option<&str>) -> Result<Vec<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "SELECT c.id, c.new_system_id, c.staff_id_email, c.staff_full_name, c.staff_department, c.staff_role_and_position, c.purpose,
+            c.checked_out_by, c.checked_out_at, c.due_back_on, c.returned_at, c.returned_by, c.return_notes, c.last_reminded_at, c.reminder_count
+        FROM system_checkouts c
+        WHERE c.returned_at IS NULL
+        AND NOT EXISTS (SELECT 1 FROM system_acknowledgements k WHERE k.checkout_id = c.id)
+        AND ($1::text IS NULL
+            OR EXISTS (SELECT 1 FROM devices d WHERE d.system_id = c.new_system_id AND d.company_name = $1)
+            OR EXISTS (SELECT 1 FROM system_assignments a JOIN sub_admin s ON s.email = a.sub_admin_id_email
+                WHERE a.new_system_id = c.new_system_id AND s.company_name = $1))
+        ORDER BY c.checked_out_at, c.id",
+        company_name
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The company whose terms apply to the caller: staff use their own, admins
+/// name one (sub-admins are held to theirs).
+async fn resolve_terms_company(pool: &PgPool, claims: &Claims, requested: Option<&str>) -> Result<String, HttpResponse> {
+    let company_name = if claims.role() == UserRole::Staff {
+        fetch_user_company(pool, claims).await.map_err(|e| {
+            error!("Failed to fetch company of {}: {:?}", claims.email(), e);
+            HttpResponse::InternalServerError().body("Failed to fetch company")
+        })?
+    } else {
+        resolve_company_scope(pool, claims, requested).await?
+    };
+    company_name.ok_or_else(|| HttpResponse::BadRequest().body("company_name is required"))
+}
+
+pub async fn publish_assignment_terms(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    terms: web::Json<PublishTermsRequest>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, terms.company_name.as_deref()).await {
+        Ok(Some(company_name)) => company_name,
+        Ok(None) => return HttpResponse::BadRequest().body("company_name is required"),
+        Err(response) => return response,
+    };
+    if terms.version.trim().is_empty() || terms.body.trim().is_empty() {
+        return HttpResponse::BadRequest().body("version and body are required");
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let published = save_terms_to_database(&mut *tx, &company_name, &terms, claims.email()).await?;
+        if let Some(published) = &published {
+            record(&mut tx, &AuditActor::new(&req, &claims), "publish", "assignment_terms", &published.id.to_string(), None, snapshot(published)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(published)
+    }
+    .await;
+
+    match result {
+        Ok(Some(published)) => HttpResponse::Created().json(published),
+        Ok(None) => HttpResponse::Conflict().body("This terms version has already been published"),
+        Err(e) => {
+            error!("Failed to publish assignment terms: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to publish assignment terms")
+        }
+    }
+}
+
+pub async fn get_assignment_terms(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<TermsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_terms_history(&pool, company_name.as_deref()).await {
+        Ok(terms) => HttpResponse::Ok().json(terms),
+        Err(e) => {
+            error!("Failed to fetch assignment terms: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch assignment terms")
+        }
+    }
+}
+
+/// The version staff accept today.
+pub async fn get_current_assignment_terms(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<TermsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_terms_company(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_current_terms(&pool, &company_name).await {
+        Ok(Some(terms)) => HttpResponse::Ok().json(terms),
+        Ok(None) => HttpResponse::NotFound().body("No assignment terms have been published"),
+        Err(e) => {
+            error!("Failed to fetch assignment terms of {}: {:?}", company_name, e);
+            HttpResponse::InternalServerError().body("Failed to fetch assignment terms")
+        }
+    }
+}
+
+/// Systems checked out to the caller, with their acceptance if given.
+pub async fn get_my_systems(pool: web::Data<PgPool>, claims: Claims) -> impl Responder {
+    let checkouts = match fetch_open_checkouts_of(&pool, claims.email()).await {
+        Ok(checkouts) => checkouts,
+        Err(e) => {
+            error!("Failed to fetch systems of {}: {:?}", claims.email(), e);
+            return HttpResponse::InternalServerError().body("Failed to fetch your systems");
+        }
+    };
+    let checkout_ids: Vec<i64> = checkouts.iter().map(|checkout| checkout.id).collect();
+    let mut acknowledgements = match fetch_acknowledgements_of_checkouts(&pool, &checkout_ids).await {
+        Ok(acknowledgements) => acknowledgements,
+        Err(e) => {
+            error!("Failed to fetch acknowledgements of {}: {:?}", claims.email(), e);
+            return HttpResponse::InternalServerError().body("Failed to fetch your systems");
+        }
+    };
+
+    let held: Vec<HeldSystem> = checkouts
+        .into_iter()
+        .map(|checkout| {
+            let acknowledgement = acknowledgements
+                .iter()
+                .position(|acknowledgement| acknowledgement.checkout_id == checkout.id)
+                .map(|index| acknowledgements.swap_remove(index));
+            HeldSystem { checkout, acknowledgement }
+        })
+        .collect();
+    HttpResponse::Ok().json(held)
+}
+
+/// Accepts custody of a system checked out to the caller. The version sent
+/// must be the one currently published, so nobody accepts terms they were
+/// not shown.
+pub async fn acknowledge_system(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    new_system_id: web::Path<String>,
+    body: web::Json<AcknowledgeRequest>,
+) -> impl Responder {
+    if claims.role() != UserRole::Staff {
+        return HttpResponse::Forbidden().body("Only the staff member holding the system can accept it");
+    }
+    let new_system_id = new_system_id.into_inner();
+
+    let checkout = match fetch_open_checkout_of(&pool, &new_system_id, claims.email()).await {
+        Ok(Some(checkout)) => checkout,
+        Ok(None) => return HttpResponse::NotFound().body("This system is not checked out to you"),
+        Err(e) => {
+            error!("Failed to fetch checkout of system {}: {:?}", new_system_id, e);
+            return HttpResponse::InternalServerError().body("Failed to acknowledge system");
+        }
+    };
+    let company_name = match resolve_terms_company(&pool, &claims, None).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+    let terms = match fetch_current_terms(&pool, &company_name).await {
+        Ok(Some(terms)) => terms,
+        Ok(None) => return HttpResponse::Conflict().body("No assignment terms have been published"),
+        Err(e) => {
+            error!("Failed to fetch assignment terms of {}: {:?}", company_name, e);
+            return HttpResponse::InternalServerError().body("Failed to acknowledge system");
+        }
+    };
+    if terms.version != body.terms_version.trim() {
+        return HttpResponse::Conflict().body(format!("The current terms version is {}", terms.version));
+    }
+
+    let ip_address = req.peer_addr().map(|addr| addr.ip().to_string());
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let acknowledgement = save_acknowledgement_to_database(&mut *tx, &checkout, &terms, ip_address, user_agent).await?;
+        if let Some(acknowledgement) = &acknowledgement {
+            record(&mut tx, &AuditActor::new(&req, &claims), "acknowledge", "system_assignment", &new_system_id, None, snapshot(acknowledgement)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(acknowledgement)
+    }
+    .await;
+
+    match result {
+        Ok(Some(acknowledgement)) => HttpResponse::Created().json(acknowledgement),
+        Ok(None) => HttpResponse::Conflict().body("You have already accepted this system"),
+        Err(e) => {
+            error!("Failed to acknowledge system {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to acknowledge system")
+        }
+    }
+}
+
+pub async fn get_system_acknowledgements(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    new_system_id: web::Path<String>,
+) -> impl Responder {
+    if !matches!(claims.role(), UserRole::SuperAdmin | UserRole::SubAdmin) {
+        return HttpResponse::Forbidden().body("Only admins can view acknowledgements");
+    }
+    let new_system_id = new_system_id.into_inner();
+    if let Err(response) = check_system_company(&pool, &claims, &new_system_id).await {
+        return response;
+    }
+    match fetch_acknowledgements_of_system(&pool, &new_system_id).await {
+        Ok(acknowledgements) => HttpResponse::Ok().json(acknowledgements),
+        Err(e) => {
+            error!("Failed to fetch acknowledgements of system {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch acknowledgements")
+        }
+    }
+}
+
+/// Systems out with someone who has not accepted them yet.
+pub async fn get_pending_acknowledgements(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<TermsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_unacknowledged_checkouts(&pool, company_name.as_deref()).await {
+        Ok(pending) => HttpResponse::Ok().json(pending),
+        Err(e) => {
+            error!("Failed to fetch pending acknowledgements: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch pending acknowledgements")
+        }
+    }
+}
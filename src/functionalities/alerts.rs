@@ -0,0 +1,692 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Duration, Utc};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use dotenvy::dotenv;
+use log::{error, warn};
+use std::env;
+use tokio::time::interval;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::device::devices::Device;
+use crate::functionalities::maintenance::{
+    open_maintenance_request, settle_maintenance_request, snapshot_device_for_request, MaintenancePriority, MaintenanceRequest,
+};
+use crate::user::users::resolve_company_scope;
+
+const METRICS: [&str; 6] = [
+    "disk_free_percent",
+    "memory_used_percent",
+    "uptime_hours",
+    "service_not_running",
+    "temperature_celsius",
+    "temperature_critical_margin",
+];
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct AlertRule {
+    pub id: i64,
+    pub company_name: String,
+    pub name: String,
+    pub metric: String,
+    pub comparator: Option<String>,
+    pub threshold: Option<f64>,
+    pub service_name: Option<String>,
+    pub duration_minutes: i32,
+    pub device_ids: Vec<i64>,
+    pub priority: MaintenancePriority,
+    pub required_skills: Vec<String>,
+    pub auto_resolve: bool,
+    pub active: bool,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewAlertRule {
+    pub company_name: Option<String>,
+    pub name: String,
+    pub metric: String,
+    pub comparator: Option<String>,
+    pub threshold: Option<f64>,
+    pub service_name: Option<String>,
+    pub duration_minutes: Option<i32>,
+    #[serde(default)]
+    pub device_ids: Vec<i64>,
+    pub priority: Option<MaintenancePriority>,
+    #[serde(default)]
+    pub required_skills: Vec<String>,
+    pub auto_resolve: Option<bool>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertQuery {
+    pub company_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ActiveAlert {
+    pub rule_id: i64,
+    pub rule_name: String,
+    pub company_name: String,
+    pub device_id: i64,
+    pub hostname: Option<String>,
+    pub pending_since: DateTime<Utc>,
+    pub last_value: Option<f64>,
+    pub last_checked_at: DateTime<Utc>,
+    // Unset while the condition has not held for the rule's duration yet
+    pub fired_at: Option<DateTime<Utc>>,
+    pub maintenance_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AlertEvent {
+    pub id: i64,
+    pub rule_id: i64,
+    pub device_id: Option<i64>,
+    pub kind: String,
+    pub value: Option<f64>,
+    pub maintenance_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+// The latest reading of a rule's metric on one device
+struct Observation {
+    device: Device,
+    value: Option<f64>,
+}
+
+impl AlertRule {
+    fn is_violated_by(&self, value: f64) -> bool {
+        if self.metric == "service_not_running" {
+            return value > 0.0;
+        }
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        match self.comparator.as_deref() {
+            Some("<") => value < threshold,
+            Some("<=") => value <= threshold,
+            Some(">") => value > threshold,
+            Some(">=") => value >= threshold,
+            _ => false,
+        }
+    }
+
+    fn describe(&self, value: f64) -> String {
+        match (self.metric.as_str(), &self.service_name) {
+            ("service_not_running", Some(service_name)) => format!("Service {} is not running", service_name),
+            _ => format!(
+                "{} is {:.2} ({} {})",
+                self.metric,
+                value,
+                self.comparator.as_deref().unwrap_or("?"),
+                self.threshold.unwrap_or_default()
+            ),
+        }
+    }
+}
+
+fn validate_alert_rule(rule: &NewAlertRule) -> Result<(), String> {
+    if rule.name.trim().is_empty() {
+        return Err("Alert rules need a name".to_string());
+    }
+    if !METRICS.contains(&rule.metric.as_str()) {
+        return Err(format!("metric must be one of {}", METRICS.join(", ")));
+    }
+    if rule.metric == "service_not_running" {
+        if rule.service_name.as_deref().is_none_or(|name| name.trim().is_empty()) {
+            return Err("service_not_running rules need a service_name".to_string());
+        }
+    } else {
+        if !matches!(rule.comparator.as_deref(), Some("<" | "<=" | ">" | ">=")) {
+            return Err("comparator must be one of <, <=, >, >=".to_string());
+        }
+        if !rule.threshold.is_some_and(f64::is_finite) {
+            return Err("threshold is required".to_string());
+        }
+    }
+    if rule.duration_minutes.is_some_and(|minutes| minutes < 0) {
+        return Err("duration_minutes cannot be negative".to_string());
+    }
+    Ok(())
+}
+
+async fn fetch_alert_rule(pool: &PgPool, rule_id: i64) -> Result<Option<AlertRule>, sqlx::Error> {
+    sqlx::query_as!(
+        AlertRule,
+        r#"SELECT id, company_name, name, metric, comparator, threshold, service_name, duration_minutes, device_ids,
+            priority as "priority: MaintenancePriority", required_skills, auto_resolve, active, created_by, created_at, updated_at
+        FROM alert_rules WHERE id = $1"#,
+        rule_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_alert_rules(pool: &PgPool, company_name: Option<&str>, active_only: bool) -> Result<Vec<AlertRule>, sqlx::Error> {
+    sqlx::query_as!(
+        AlertRule,
+        r#"SELECT id, company_name, name, metric, comparator, threshold, service_name, duration_minutes, device_ids,
+            priority as "priority: MaintenancePriority", required_skills, auto_resolve, active, created_by, created_at, updated_at
+        FROM alert_rules
+        WHERE ($1::text IS NULL OR company_name = $1) AND (active OR NOT $2)
+        ORDER BY company_name, id"#,
+        company_name,
+        active_only,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn save_alert_rule_to_database<'e, E>(executor: E, company_name: &str, rule: &NewAlertRule, created_by: &str) -> Result<AlertRule, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        AlertRule,
+        r#"INSERT INTO alert_rules
+            (company_name, name, metric, comparator, threshold, service_name, duration_minutes, device_ids, priority, required_skills, auto_resolve, active, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        RETURNING id, company_name, name, metric, comparator, threshold, service_name, duration_minutes, device_ids,
+            priority as "priority: MaintenancePriority", required_skills, auto_resolve, active, created_by, created_at, updated_at"#,
+        company_name,
+        rule.name.trim(),
+        rule.metric,
+        rule.comparator,
+        rule.threshold,
+        rule.service_name.as_deref().map(str::trim),
+        rule.duration_minutes.unwrap_or(0),
+        &rule.device_ids,
+        rule.priority.unwrap_or(MaintenancePriority::Medium) as MaintenancePriority,
+        &rule.required_skills,
+        rule.auto_resolve.unwrap_or(true),
+        rule.active.unwrap_or(true),
+        created_by,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+async fn update_alert_rule_in_database<'e, E>(executor: E, rule_id: i64, rule: &NewAlertRule) -> Result<Option<AlertRule>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        AlertRule,
+        r#"UPDATE alert_rules SET
+            name = $2, metric = $3, comparator = $4, threshold = $5, service_name = $6, duration_minutes = $7, device_ids = $8,
+            priority = $9, required_skills = $10, auto_resolve = $11, active = $12, updated_at = now()
+        WHERE id = $1
+        RETURNING id, company_name, name, metric, comparator, threshold, service_name, duration_minutes, device_ids,
+            priority as "priority: MaintenancePriority", required_skills, auto_resolve, active, created_by, created_at, updated_at"#,
+        rule_id,
+        rule.name.trim(),
+        rule.metric,
+        rule.comparator,
+        rule.threshold,
+        rule.service_name.as_deref().map(str::trim),
+        rule.duration_minutes.unwrap_or(0),
+        &rule.device_ids,
+        rule.priority.unwrap_or(MaintenancePriority::Medium) as MaintenancePriority,
+        &rule.required_skills,
+        rule.auto_resolve.unwrap_or(true),
+        rule.active.unwrap_or(true),
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+async fn check_rule_devices(pool: &PgPool, company_name: &str, device_ids: &[i64]) -> Result<bool, sqlx::Error> {
+    if device_ids.is_empty() {
+        return Ok(true);
+    }
+    let mut wanted = device_ids.to_vec();
+    wanted.sort_unstable();
+    wanted.dedup();
+    let known = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM devices WHERE device_id = ANY($1) AND company_name = $2"#,
+        &wanted,
+        company_name,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(known == wanted.len() as i64)
+}
+
+/// The latest reading of the rule's metric on every device it watches, if it
+/// was taken since `fresh_since`. Service rules read 1 when the service is not
+/// running and 0 when it is.
+async fn observe_rule(pool: &PgPool, rule: &AlertRule, fresh_since: DateTime<Utc>) -> Result<Vec<Observation>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT d.device_id, d.machine_id, d.company_name, d.hostname, d.mac_address, d.system_id, d.created_at, d.updated_at,
+            CASE $3
+                WHEN 'disk_free_percent' THEN (SELECT 100 * m.available_space / NULLIF(m.total_space, 0) FROM disk_metrics m
+                    WHERE m.device_id = d.device_id AND m.recorded_at >= $5 ORDER BY m.recorded_at DESC LIMIT 1)
+                WHEN 'memory_used_percent' THEN (SELECT 100 * m.used_memory / NULLIF(m.total_memory, 0) FROM memory_metrics m
+                    WHERE m.device_id = d.device_id AND m.recorded_at >= $5 ORDER BY m.recorded_at DESC LIMIT 1)
+                WHEN 'uptime_hours' THEN (SELECT m.uptime FROM uptime_metrics m
+                    WHERE m.device_id = d.device_id AND m.updated_at >= $5 ORDER BY m.updated_at DESC LIMIT 1)
+                -- Hottest sensor of the latest collection
+                WHEN 'temperature_celsius' THEN (SELECT max(m.temperature_celsius) FROM temperature_sensor_metrics m
+                    WHERE m.device_id = d.device_id AND m.recorded_at >= $5
+                        AND m.recorded_at = (SELECT max(recorded_at) FROM temperature_sensor_metrics WHERE device_id = d.device_id))
+                -- Degrees left before the sensor closest to its critical threshold reaches it
+                WHEN 'temperature_critical_margin' THEN (SELECT min(m.critical_celsius - m.temperature_celsius) FROM temperature_sensor_metrics m
+                    WHERE m.device_id = d.device_id AND m.recorded_at >= $5
+                        AND m.recorded_at = (SELECT max(recorded_at) FROM temperature_sensor_metrics WHERE device_id = d.device_id))
+                WHEN 'service_not_running' THEN (SELECT CASE WHEN m.status ILIKE '%running%' OR lower(m.status) = 'active' THEN 0 ELSE 1 END
+                    FROM service_status_metrics m WHERE m.device_id = d.device_id AND m.service_name = $4 AND m.recorded_at >= $5)
+            END::float8 as value
+        FROM devices d
+        WHERE d.company_name = $1 AND (cardinality($2::bigint[]) = 0 OR d.device_id = ANY($2))
+        ORDER BY d.device_id"#,
+        rule.company_name,
+        &rule.device_ids,
+        rule.metric,
+        rule.service_name,
+        fresh_since,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Observation {
+            device: Device {
+                device_id: row.device_id,
+                machine_id: row.machine_id,
+                company_name: row.company_name,
+                hostname: row.hostname,
+                mac_address: row.mac_address,
+                system_id: row.system_id,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            },
+            value: row.value,
+        })
+        .collect())
+}
+
+async fn record_alert_event(
+    pool: &PgPool,
+    rule_id: i64,
+    device_id: i64,
+    kind: &str,
+    value: Option<f64>,
+    maintenance_id: Option<i64>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO alert_events (rule_id, device_id, kind, value, maintenance_id) VALUES ($1, $2, $3, $4, $5)",
+        rule_id,
+        device_id,
+        kind,
+        value,
+        maintenance_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Opens a request once the condition has held for the rule's duration. A rule
+// fires at most once per device until the condition clears, so a request that
+// was closed while the problem persists is not raised again.
+async fn handle_violation(pool: &PgPool, rule: &AlertRule, device: &Device, value: f64) -> Result<(), sqlx::Error> {
+    let state = sqlx::query!(
+        "INSERT INTO alert_states (rule_id, device_id, last_value) VALUES ($1, $2, $3)
+        ON CONFLICT (rule_id, device_id) DO UPDATE SET last_value = EXCLUDED.last_value, last_checked_at = now()
+        RETURNING pending_since, fired_at",
+        rule.id,
+        device.device_id,
+        value,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if state.fired_at.is_some() || Utc::now() - state.pending_since < Duration::minutes(rule.duration_minutes as i64) {
+        return Ok(());
+    }
+
+    // Only the evaluation that marks the state as fired raises the request
+    let claimed = sqlx::query!(
+        "UPDATE alert_states SET fired_at = now() WHERE rule_id = $1 AND device_id = $2 AND fired_at IS NULL",
+        rule.id,
+        device.device_id,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected() == 1;
+    if !claimed {
+        return Ok(());
+    }
+
+    let description = format!(
+        "{} on {} since {}.",
+        rule.describe(value),
+        device.hostname.as_deref().unwrap_or("unknown host"),
+        state.pending_since.format("%Y-%m-%d %H:%M UTC")
+    );
+    let mut request = MaintenanceRequest::raised_for_device(
+        device,
+        rule.name.clone(),
+        Some(description),
+        rule.priority,
+        rule.required_skills.clone(),
+    );
+    let device_snapshot = snapshot_device_for_request(pool, device.device_id).await;
+    let mut tx = pool.begin().await?;
+    let maintenance_id = open_maintenance_request(&mut tx, &mut request, device_snapshot, None, Some(format!("Raised by alert rule {}", rule.id))).await?;
+
+    sqlx::query!(
+        "UPDATE alert_states SET maintenance_id = $3 WHERE rule_id = $1 AND device_id = $2",
+        rule.id,
+        device.device_id,
+        maintenance_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    record_alert_event(pool, rule.id, device.device_id, "fired", Some(value), Some(maintenance_id)).await?;
+    warn!("Alert rule {} fired on device {}, opened maintenance request {}", rule.id, device.device_id, maintenance_id);
+    Ok(())
+}
+
+async fn handle_recovery(pool: &PgPool, rule: &AlertRule, device: &Device, value: f64) -> Result<(), sqlx::Error> {
+    let cleared = sqlx::query!(
+        "DELETE FROM alert_states WHERE rule_id = $1 AND device_id = $2 RETURNING fired_at, maintenance_id",
+        rule.id,
+        device.device_id,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let cleared = match cleared {
+        Some(cleared) if cleared.fired_at.is_some() => cleared,
+        _ => return Ok(()),
+    };
+    record_alert_event(pool, rule.id, device.device_id, "cleared", Some(value), cleared.maintenance_id).await?;
+
+    if let (true, Some(maintenance_id)) = (rule.auto_resolve, cleared.maintenance_id) {
+        let reason = format!("Alert cleared: {}", rule.describe(value));
+        settle_maintenance_request(pool, maintenance_id, reason).await?;
+    }
+    Ok(())
+}
+
+/// Readings older than the rule's duration, or than one check when that is
+/// longer, no longer describe the device.
+fn freshness_window(rule: &AlertRule, check_interval: Duration) -> Duration {
+    Duration::minutes(rule.duration_minutes as i64).max(check_interval)
+}
+
+async fn evaluate_alert_rule(pool: &PgPool, rule: &AlertRule, check_interval: Duration) -> Result<(), sqlx::Error> {
+    let fresh_since = Utc::now() - freshness_window(rule, check_interval);
+    let observations = observe_rule(pool, rule, fresh_since).await?;
+
+    // Devices the rule no longer watches drop their state without resolving anything
+    let watched: Vec<i64> = observations.iter().map(|observation| observation.device.device_id).collect();
+    sqlx::query!(
+        "DELETE FROM alert_states WHERE rule_id = $1 AND device_id <> ALL($2)",
+        rule.id,
+        &watched,
+    )
+    .execute(pool)
+    .await?;
+
+    for observation in observations {
+        // No fresh data says nothing either way
+        let value = match observation.value {
+            Some(value) => value,
+            None => continue,
+        };
+        if rule.is_violated_by(value) {
+            handle_violation(pool, rule, &observation.device, value).await?;
+        } else {
+            handle_recovery(pool, rule, &observation.device, value).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Background job evaluating every active rule every ALERT_CHECK_INTERVAL_SECS (60 by default).
+pub async fn run_alert_evaluator(pool: PgPool) {
+    dotenv().ok();
+    let seconds = env::var("ALERT_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60);
+    let mut interval = interval(std::time::Duration::from_secs(seconds));
+
+    loop {
+        interval.tick().await;
+        let rules = match fetch_alert_rules(&pool, None, true).await {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Failed to fetch alert rules: {:?}", e);
+                continue;
+            }
+        };
+        for rule in rules {
+            if let Err(e) = evaluate_alert_rule(&pool, &rule, Duration::seconds(seconds as i64)).await {
+                error!("Failed to evaluate alert rule {}: {:?}", rule.id, e);
+            }
+        }
+    }
+}
+
+async fn load_managed_alert_rule(pool: &PgPool, claims: &Claims, rule_id: i64) -> Result<AlertRule, HttpResponse> {
+    let rule = match fetch_alert_rule(pool, rule_id).await {
+        Ok(Some(rule)) => rule,
+        Ok(None) => return Err(HttpResponse::NotFound().body("Alert rule not found")),
+        Err(e) => {
+            error!("Failed to fetch alert rule {}: {:?}", rule_id, e);
+            return Err(HttpResponse::InternalServerError().body("Failed to fetch alert rule"));
+        }
+    };
+    resolve_company_scope(pool, claims, Some(&rule.company_name)).await?;
+    Ok(rule)
+}
+
+pub async fn get_alert_rules(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AlertQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_alert_rules(&pool, company_name.as_deref(), false).await {
+        Ok(rules) => HttpResponse::Ok().json(rules),
+        Err(e) => {
+            error!("Failed to fetch alert rules: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch alert rules")
+        }
+    }
+}
+
+pub async fn create_alert_rule(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rule: web::Json<NewAlertRule>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, rule.company_name.as_deref()).await {
+        Ok(Some(company_name)) => company_name,
+        Ok(None) => return HttpResponse::BadRequest().body("company_name is required"),
+        Err(response) => return response,
+    };
+    if let Err(message) = validate_alert_rule(&rule) {
+        return HttpResponse::BadRequest().body(message);
+    }
+    match check_rule_devices(&pool, &company_name, &rule.device_ids).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Every device must exist and belong to the rule's company"),
+        Err(e) => {
+            error!("Failed to check devices of alert rule: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to save alert rule");
+        }
+    }
+
+    let saved = async {
+        let mut tx = pool.begin().await?;
+        let saved = save_alert_rule_to_database(&mut *tx, &company_name, &rule, claims.email()).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "alert_rule", &saved.id.to_string(), None, snapshot(&saved)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match saved {
+        Ok(saved) => HttpResponse::Created().json(saved),
+        Err(e) => {
+            error!("Failed to save alert rule: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save alert rule")
+        }
+    }
+}
+
+pub async fn update_alert_rule(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rule_id: web::Path<i64>,
+    rule: web::Json<NewAlertRule>,
+) -> impl Responder {
+    let rule_id = rule_id.into_inner();
+    let before = match load_managed_alert_rule(&pool, &claims, rule_id).await {
+        Ok(before) => before,
+        Err(response) => return response,
+    };
+    if rule.company_name.as_deref().is_some_and(|company_name| company_name != before.company_name) {
+        return HttpResponse::BadRequest().body("Alert rules cannot move between companies");
+    }
+    if let Err(message) = validate_alert_rule(&rule) {
+        return HttpResponse::BadRequest().body(message);
+    }
+    match check_rule_devices(&pool, &before.company_name, &rule.device_ids).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::BadRequest().body("Every device must exist and belong to the rule's company"),
+        Err(e) => {
+            error!("Failed to check devices of alert rule: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to update alert rule");
+        }
+    }
+
+    let saved = async {
+        let mut tx = pool.begin().await?;
+        let saved = update_alert_rule_in_database(&mut *tx, rule_id, &rule).await?;
+        if let Some(saved) = &saved {
+            record(&mut tx, &AuditActor::new(&req, &claims), "update", "alert_rule", &rule_id.to_string(), snapshot(&before), snapshot(saved)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match saved {
+        Ok(Some(saved)) => HttpResponse::Ok().json(saved),
+        Ok(None) => HttpResponse::NotFound().body("Alert rule not found"),
+        Err(e) => {
+            error!("Failed to update alert rule {}: {:?}", rule_id, e);
+            HttpResponse::InternalServerError().body("Failed to update alert rule")
+        }
+    }
+}
+
+pub async fn delete_alert_rule(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rule_id: web::Path<i64>,
+) -> impl Responder {
+    let rule_id = rule_id.into_inner();
+    let rule = match load_managed_alert_rule(&pool, &claims, rule_id).await {
+        Ok(rule) => rule,
+        Err(response) => return response,
+    };
+
+    // Requests the rule opened stay open for a person to close
+    let deleted = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM alert_rules WHERE id = $1", rule_id).execute(&mut *tx).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "delete", "alert_rule", &rule_id.to_string(), snapshot(&rule), None).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match deleted {
+        Ok(_) => HttpResponse::Ok().body("Alert rule deleted"),
+        Err(e) => {
+            error!("Failed to delete alert rule {}: {:?}", rule_id, e);
+            HttpResponse::InternalServerError().body("Failed to delete alert rule")
+        }
+    }
+}
+
+pub async fn get_alert_rule_events(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rule_id: web::Path<i64>,
+) -> impl Responder {
+    let rule_id = rule_id.into_inner();
+    if let Err(response) = load_managed_alert_rule(&pool, &claims, rule_id).await {
+        return response;
+    }
+
+    match sqlx::query_as!(
+        AlertEvent,
+        "SELECT id, rule_id, device_id, kind, value, maintenance_id, created_at
+        FROM alert_events WHERE rule_id = $1
+        ORDER BY created_at DESC, id DESC
+        LIMIT 500",
+        rule_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(e) => {
+            error!("Failed to fetch events of alert rule {}: {:?}", rule_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch alert events")
+        }
+    }
+}
+
+/// Conditions currently holding, whether or not they have fired yet.
+pub async fn get_active_alerts(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AlertQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as!(
+        ActiveAlert,
+        "SELECT s.rule_id, r.name as rule_name, r.company_name, s.device_id, d.hostname,
+            s.pending_since, s.last_value, s.last_checked_at, s.fired_at, s.maintenance_id
+        FROM alert_states s
+        JOIN alert_rules r ON r.id = s.rule_id
+        JOIN devices d ON d.device_id = s.device_id
+        WHERE r.active AND ($1::text IS NULL OR r.company_name = $1)
+        ORDER BY s.fired_at DESC NULLS LAST, s.pending_since",
+        company_name
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(alerts) => HttpResponse::Ok().json(alerts),
+        Err(e) => {
+            error!("Failed to fetch active alerts: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch active alerts")
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, NaiveDate, Utc};
+use actix_web::{web, HttpResponse, Responder};
+use log::error;
+use crate::auth::claims::Claims;
+use crate::functionalities::maintenance::{MaintenancePriority, MaintenanceStatus};
+use crate::user::users::resolve_company_scope;
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsQuery {
+    pub company_name: Option<String>,
+    // Only requests created in [from, to)
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub weeks: Option<i32>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct BacklogRow {
+    pub status: MaintenanceStatus,
+    pub priority: MaintenancePriority,
+    pub count: i64,
+}
+
+/// Mean and median times in minutes. The row with no priority covers all of them.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ResponseTimeRow {
+    pub priority: Option<MaintenancePriority>,
+    pub requests: i64,
+    pub acknowledged: i64,
+    pub resolved: i64,
+    pub mean_minutes_to_acknowledge: Option<f64>,
+    pub median_minutes_to_acknowledge: Option<f64>,
+    pub mean_minutes_to_resolve: Option<f64>,
+    pub median_minutes_to_resolve: Option<f64>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DeviceRequestsRow {
+    pub device_id: i64,
+    pub hostname: Option<String>,
+    pub total: i64,
+    pub open: i64,
+    pub last_reported_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DepartmentRequestsRow {
+    pub department: String,
+    pub total: i64,
+    pub open: i64,
+    pub mean_minutes_to_resolve: Option<f64>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct WeeklyTrendRow {
+    pub week_start: NaiveDate,
+    pub opened: i64,
+    pub resolved: i64,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TitleCountRow {
+    pub title: String,
+    pub count: i64,
+    pub last_reported_at: Option<DateTime<Utc>>,
+}
+
+async fn fetch_backlog(pool: &PgPool, company_name: Option<&str>, query: &AnalyticsQuery) -> Result<Vec<BacklogRow>, sqlx::Error> {
+    sqlx::query_as!(
+        BacklogRow,
+        r#"SELECT status as "status: MaintenanceStatus", priority as "priority: MaintenancePriority", COUNT(*) as "count!"
+        FROM maintenance_requests
+        WHERE status NOT IN ('Resolved', 'Closed', 'Rejected')
+        AND ($1::text IS NULL OR company_name = $1)
+        AND ($2::timestamptz IS NULL OR created_at >= $2)
+        AND ($3::timestamptz IS NULL OR created_at < $3)
+        GROUP BY status, priority
+        ORDER BY status, priority DESC"#,
+        company_name,
+        query.from,
+        query.to,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_response_times(pool: &PgPool, company_name: Option<&str>, query: &AnalyticsQuery) -> Result<Vec<ResponseTimeRow>, sqlx::Error> {
+    sqlx::query_as!(
+        ResponseTimeRow,
+        r#"SELECT
+            priority as "priority?: MaintenancePriority",
+            COUNT(*) as "requests!",
+            COUNT(first_response_at) as "acknowledged!",
+            COUNT(resolved_at) as "resolved!",
+            AVG(EXTRACT(EPOCH FROM first_response_at - created_at) / 60)::float8 as mean_minutes_to_acknowledge,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM first_response_at - created_at) / 60))::float8 as median_minutes_to_acknowledge,
+            AVG(EXTRACT(EPOCH FROM resolved_at - created_at) / 60)::float8 as mean_minutes_to_resolve,
+            (PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM resolved_at - created_at) / 60))::float8 as median_minutes_to_resolve
+        FROM maintenance_requests
+        WHERE created_at IS NOT NULL
+        AND ($1::text IS NULL OR company_name = $1)
+        AND ($2::timestamptz IS NULL OR created_at >= $2)
+        AND ($3::timestamptz IS NULL OR created_at < $3)
+        GROUP BY GROUPING SETS ((priority), ())
+        ORDER BY priority DESC NULLS FIRST"#,
+        company_name,
+        query.from,
+        query.to,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_requests_per_device(pool: &PgPool, company_name: Option<&str>, query: &AnalyticsQuery) -> Result<Vec<DeviceRequestsRow>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceRequestsRow,
+        r#"SELECT
+            d.device_id,
+            d.hostname,
+            COUNT(*) as "total!",
+            COUNT(*) FILTER (WHERE m.status NOT IN ('Resolved', 'Closed', 'Rejected')) as "open!",
+            MAX(m.created_at) as last_reported_at
+        FROM maintenance_requests m
+        JOIN devices d ON d.device_id = m.device_id
+        WHERE ($1::text IS NULL OR m.company_name = $1)
+        AND ($2::timestamptz IS NULL OR m.created_at >= $2)
+        AND ($3::timestamptz IS NULL OR m.created_at < $3)
+        GROUP BY d.device_id, d.hostname
+        ORDER BY 3 DESC, d.device_id
+        LIMIT $4"#,
+        company_name,
+        query.from,
+        query.to,
+        query.limit.unwrap_or(50).clamp(1, 500),
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Departments live on system assignments: go through the asset record of the
+// request's device, falling back to the reporter's own assignment.
+async fn fetch_requests_per_department(pool: &PgPool, company_name: Option<&str>, query: &AnalyticsQuery) -> Result<Vec<DepartmentRequestsRow>, sqlx::Error> {
+    sqlx::query_as!(
+        DepartmentRequestsRow,
+        r#"SELECT
+            COALESCE(
+                (SELECT a.staff_department FROM devices d
+                    JOIN system_assignments a ON a.new_system_id = d.system_id
+                    WHERE d.device_id = m.device_id AND a.staff_department IS NOT NULL
+                    LIMIT 1),
+                (SELECT a.staff_department FROM staff s
+                    JOIN system_assignments a ON a.staff_id_email = s.email
+                    WHERE s.id = m.reported_by_staff_id AND a.staff_department IS NOT NULL
+                    ORDER BY a.created_at DESC NULLS LAST
+                    LIMIT 1),
+                'Unknown'
+            ) as "department!",
+            COUNT(*) as "total!",
+            COUNT(*) FILTER (WHERE m.status NOT IN ('Resolved', 'Closed', 'Rejected')) as "open!",
+            AVG(EXTRACT(EPOCH FROM m.resolved_at - m.created_at) / 60)::float8 as mean_minutes_to_resolve
+        FROM maintenance_requests m
+        WHERE ($1::text IS NULL OR m.company_name = $1)
+        AND ($2::timestamptz IS NULL OR m.created_at >= $2)
+        AND ($3::timestamptz IS NULL OR m.created_at < $3)
+        GROUP BY 1
+        ORDER BY 2 DESC, 1"#,
+        company_name,
+        query.from,
+        query.to,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Weeks start on Monday; weeks without activity are reported as zeroes
+async fn fetch_weekly_trend(pool: &PgPool, company_name: Option<&str>, weeks: i32) -> Result<Vec<WeeklyTrendRow>, sqlx::Error> {
+    sqlx::query_as!(
+        WeeklyTrendRow,
+        r#"WITH weeks AS (
+            SELECT generate_series(
+                date_trunc('week', now()) - ($2::int - 1) * interval '1 week',
+                date_trunc('week', now()),
+                interval '1 week'
+            ) as week_start
+        )
+        SELECT
+            w.week_start::date as "week_start!",
+            (SELECT COUNT(*) FROM maintenance_requests m
+                WHERE m.created_at >= w.week_start AND m.created_at < w.week_start + interval '1 week'
+                AND ($1::text IS NULL OR m.company_name = $1)) as "opened!",
+            (SELECT COUNT(*) FROM maintenance_requests m
+                WHERE m.resolved_at >= w.week_start AND m.resolved_at < w.week_start + interval '1 week'
+                AND ($1::text IS NULL OR m.company_name = $1)) as "resolved!"
+        FROM weeks w
+        ORDER BY w.week_start"#,
+        company_name,
+        weeks,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Titles are compared case- and whitespace-insensitively
+async fn fetch_top_titles(pool: &PgPool, company_name: Option<&str>, query: &AnalyticsQuery) -> Result<Vec<TitleCountRow>, sqlx::Error> {
+    sqlx::query_as!(
+        TitleCountRow,
+        r#"SELECT
+            lower(regexp_replace(trim(title), '\s+', ' ', 'g')) as "title!",
+            COUNT(*) as "count!",
+            MAX(created_at) as last_reported_at
+        FROM maintenance_requests
+        WHERE title IS NOT NULL AND trim(title) <> ''
+        AND ($1::text IS NULL OR company_name = $1)
+        AND ($2::timestamptz IS NULL OR created_at >= $2)
+        AND ($3::timestamptz IS NULL OR created_at < $3)
+        GROUP BY 1
+        ORDER BY 2 DESC, 3 DESC NULLS LAST
+        LIMIT $4"#,
+        company_name,
+        query.from,
+        query.to,
+        query.limit.unwrap_or(10).clamp(1, 100),
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_maintenance_backlog(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AnalyticsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_backlog(&pool, company_name.as_deref(), &query).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(e) => {
+            error!("Failed to fetch maintenance backlog: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance backlog")
+        }
+    }
+}
+
+pub async fn get_maintenance_response_times(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AnalyticsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_response_times(&pool, company_name.as_deref(), &query).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(e) => {
+            error!("Failed to fetch maintenance response times: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance response times")
+        }
+    }
+}
+
+pub async fn get_maintenance_by_device(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AnalyticsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_requests_per_device(&pool, company_name.as_deref(), &query).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(e) => {
+            error!("Failed to fetch maintenance requests per device: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance requests per device")
+        }
+    }
+}
+
+pub async fn get_maintenance_by_department(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AnalyticsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_requests_per_department(&pool, company_name.as_deref(), &query).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(e) => {
+            error!("Failed to fetch maintenance requests per department: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance requests per department")
+        }
+    }
+}
+
+pub async fn get_maintenance_weekly_trend(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AnalyticsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    let weeks = query.weeks.unwrap_or(12).clamp(1, 104);
+    match fetch_weekly_trend(&pool, company_name.as_deref(), weeks).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(e) => {
+            error!("Failed to fetch maintenance trend: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance trend")
+        }
+    }
+}
+
+pub async fn get_maintenance_top_titles(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<AnalyticsQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_top_titles(&pool, company_name.as_deref(), &query).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(e) => {
+            error!("Failed to fetch top maintenance titles: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch top maintenance titles")
+        }
+    }
+}
@@ -1,8 +1,16 @@
 // use log::info;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
-use chrono::Utc;
-use actix_web::{web, HttpResponse, Responder};
+use sqlx::{Connection, FromRow, PgConnection, PgPool};
+use chrono::{DateTime, NaiveDate, Utc};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use dotenvy::dotenv;
+use std::env;
+use tokio::time::interval;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::device::devices::{assign_device_owner_by_system_id, release_device_owner_by_system_id};
+use crate::user::users::{resolve_company_scope, UserRole};
+use log::{error, warn};
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct SystemAssignment {
@@ -12,7 +20,7 @@ pub struct SystemAssignment {
     pub system_name: Option<String>,
     pub new_system_id: Option<String>,
     pub operating_system: Option<String>,
-    pub return_date: Option<String>,
+    pub return_date: Option<NaiveDate>,
     pub assigned_by: Option<String>,
     pub purpose: Option<String>,
     pub sub_admin_id_email: Option<String>,
@@ -22,9 +30,14 @@ pub struct SystemAssignment {
 }
 
 pub async fn create_system_assignment(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
+    claims: Claims,
     assignment: web::Json<SystemAssignment>,
 ) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can assign systems");
+    }
     let new_assignment = assignment.into_inner();
 
     let system_assignment = SystemAssignment {
@@ -33,16 +46,34 @@ pub async fn create_system_assignment(
         ..new_assignment
     };
 
-    match save_system_assignment_to_database(&pool, &system_assignment).await {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        save_system_assignment_to_database(&mut *tx, &system_assignment).await?;
+        let target_id = system_assignment.new_system_id.clone().unwrap_or_default();
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "system_assignment", &target_id, None, snapshot(&system_assignment)).await?;
+        if let Some(checkout) = SystemCheckoutRequest::from_assignment(&system_assignment) {
+            hand_over_system(&mut tx, &target_id, &checkout, system_assignment.assigned_by.as_deref()).await?;
+        }
+        tx.commit().await
+    }
+    .await;
+
+    match result {
         Ok(_) => HttpResponse::Created().json(system_assignment),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to create system assignment"),
+        Err(e) => {
+            error!("Failed to create system assignment: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to create system assignment")
+        }
     }
 }
 
-async fn save_system_assignment_to_database(
-    pool: &PgPool,
+async fn save_system_assignment_to_database<'e, E>(
+    executor: E,
     assignment: &SystemAssignment,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     sqlx::query!(
         "INSERT INTO system_assignments (staff_full_name, staff_department, staff_role_and_position, system_name, new_system_id, operating_system, return_date, assigned_by, purpose, sub_admin_id_email, staff_id_email, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
         assignment.staff_full_name,
@@ -59,22 +90,27 @@ async fn save_system_assignment_to_database(
         assignment.created_at,
         assignment.updated_at,
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
-async fn fetch_system_assignment_by_id(
-    pool: &PgPool,
+async fn fetch_system_assignment_by_id<'e, E>(
+    executor: E,
     new_system_id: &str,
-) -> Result<SystemAssignment, sqlx::Error> {
+) -> Result<SystemAssignment, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     let assignment = sqlx::query_as!(
         SystemAssignment,
-        "SELECT * FROM system_assignments WHERE new_system_id = $1",
+        "SELECT staff_full_name, staff_department, staff_role_and_position, system_name, new_system_id, operating_system,
+            return_date, assigned_by, purpose, sub_admin_id_email, staff_id_email, created_at, updated_at
+        FROM system_assignments WHERE new_system_id = $1",
         new_system_id
     )
-    .fetch_one(pool)
+    .fetch_one(executor)
     .await?;
 
     Ok(assignment)
@@ -86,7 +122,7 @@ pub async fn get_system_assignment(
     new_system_id: web::Path<String>,
 ) -> impl Responder {
     let new_system_id = new_system_id.into_inner();
-    match fetch_system_assignment_by_id(&pool, &new_system_id).await {
+    match fetch_system_assignment_by_id(pool.get_ref(), &new_system_id).await {
         Ok(assignment) => HttpResponse::Ok().json(assignment),
         Err(_) => HttpResponse::NotFound().body("System assignment not found"),
     }
@@ -100,7 +136,7 @@ pub struct UpdateSystemAssignment {
     pub staff_role_and_position: Option<String>,
     pub system_name: Option<String>,
     pub operating_system: Option<String>,
-    pub return_date: Option<String>,
+    pub return_date: Option<NaiveDate>,
     pub assigned_by: Option<String>,
     pub purpose: Option<String>,
     pub sub_admin_id_email: Option<String>,
@@ -109,26 +145,64 @@ pub struct UpdateSystemAssignment {
 }
 
 pub async fn update_system_assignment(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
+    claims: Claims,
     new_system_id: web::Path<String>,
     update_assignment: web::Json<UpdateSystemAssignment>,
 ) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can change system assignments");
+    }
     let new_system_id = new_system_id.into_inner();
     let update_assignment = update_assignment.into_inner();
     let updated_at = Some(Utc::now());
 
-    match update_system_assignment_in_database(&pool, &new_system_id, &update_assignment, updated_at).await {
-        Ok(_) => HttpResponse::Ok().body("System assignment updated"),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to update system assignment"),
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let before = match fetch_system_assignment_by_id(&mut *tx, &new_system_id).await {
+            Ok(before) => before,
+            Err(sqlx::Error::RowNotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        update_system_assignment_in_database(&mut *tx, &new_system_id, &update_assignment, updated_at).await?;
+        let after = fetch_system_assignment_by_id(&mut *tx, &new_system_id).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "update", "system_assignment", &new_system_id, snapshot(&before), snapshot(&after)).await?;
+        // A new holder is a handover, not an edit of the current checkout
+        match (&update_assignment.staff_id_email, SystemCheckoutRequest::from_assignment(&after)) {
+            (Some(_), Some(checkout)) => {
+                hand_over_system(&mut tx, &new_system_id, &checkout, update_assignment.assigned_by.as_deref()).await?;
+            }
+            _ => {
+                if let Some(due_back_on) = update_assignment.return_date {
+                    update_due_date_in_database(&mut *tx, &new_system_id, due_back_on).await?;
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().body("System assignment updated"),
+        Ok(false) => HttpResponse::NotFound().body("System assignment not found"),
+        Err(e) => {
+            error!("Failed to update system assignment {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to update system assignment")
+        }
     }
 }
 
-async fn update_system_assignment_in_database(
-    pool: &PgPool,
+async fn update_system_assignment_in_database<'e, E>(
+    executor: E,
     new_system_id: &str,
     update_assignment: &UpdateSystemAssignment,
     updated_at: Option<chrono::DateTime<Utc>>,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     sqlx::query!(
         "UPDATE system_assignments SET staff_full_name = COALESCE($1, staff_full_name), staff_department = COALESCE($2, staff_department), staff_role_and_position = COALESCE($3, staff_role_and_position), system_name = COALESCE($4, system_name), operating_system = COALESCE($5, operating_system), return_date = COALESCE($6, return_date), assigned_by = COALESCE($7, assigned_by), purpose = COALESCE($8, purpose), sub_admin_id_email = COALESCE($9, sub_admin_id_email), staff_id_email = COALESCE($10, staff_id_email), updated_at = $11 WHERE new_system_id = $12",
         update_assignment.staff_full_name,
@@ -144,38 +218,65 @@ async fn update_system_assignment_in_database(
         updated_at,
         new_system_id,
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
 pub async fn delete_system_assignment(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
+    claims: Claims,
     new_system_id: web::Path<String>,
 ) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can delete system assignments");
+    }
     let new_system_id = new_system_id.into_inner();
 
-    match delete_system_assignment_from_database(&pool, &new_system_id).await {
-        Ok(_) => HttpResponse::Ok().body("System assignment deleted"),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to delete system assignment"),
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let before = match fetch_system_assignment_by_id(&mut *tx, &new_system_id).await {
+            Ok(before) => before,
+            Err(sqlx::Error::RowNotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        delete_system_assignment_from_database(&mut *tx, &new_system_id).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "delete", "system_assignment", &new_system_id, snapshot(&before), None).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().body("System assignment deleted"),
+        Ok(false) => HttpResponse::NotFound().body("System assignment not found"),
+        Err(e) => {
+            error!("Failed to delete system assignment {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to delete system assignment")
+        }
     }
 }
 
-async fn delete_system_assignment_from_database(
-    pool: &PgPool,
+async fn delete_system_assignment_from_database<'e, E>(
+    executor: E,
     new_system_id: &str,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     sqlx::query!(
         "DELETE FROM system_assignments WHERE new_system_id = $1",
         new_system_id
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
+/// Number of assignments a sub admin made or a staff member is named on.
 pub async fn get_system_assignment_count(
     pool: web::Data<PgPool>,
     email: web::Path<String>,
@@ -202,3 +303,651 @@ async fn fetch_system_assignment_count_by_email(
 
     Ok(count)
 }
+
+/// Filters shared by the assignment list and counts. `overdue` means the
+/// system is still with someone after its return date.
+#[derive(Debug, Deserialize)]
+pub struct SystemAssignmentQuery {
+    pub company_name: Option<String>,
+    pub staff_id_email: Option<String>,
+    pub staff_department: Option<String>,
+    pub assigned_by: Option<String>,
+    pub operating_system: Option<String>,
+    pub overdue: Option<bool>,
+    pub group_by: Option<AssignmentGrouping>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentGrouping {
+    StaffIdEmail,
+    StaffDepartment,
+    AssignedBy,
+    OperatingSystem,
+    Overdue,
+}
+
+impl AssignmentGrouping {
+    fn as_str(self) -> &'static str {
+        match self {
+            AssignmentGrouping::StaffIdEmail => "staff_id_email",
+            AssignmentGrouping::StaffDepartment => "staff_department",
+            AssignmentGrouping::AssignedBy => "assigned_by",
+            AssignmentGrouping::OperatingSystem => "operating_system",
+            AssignmentGrouping::Overdue => "overdue",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SystemAssignmentCount {
+    pub key: Option<String>,
+    pub count: i64,
+}
+
+// A system belongs to a company through the sub admin who assigned it or its device
+async fn fetch_system_assignments(
+    pool: &PgPool,
+    company_name: Option<&str>,
+    query: &SystemAssignmentQuery,
+) -> Result<Vec<SystemAssignment>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemAssignment,
+        "SELECT a.staff_full_name, a.staff_department, a.staff_role_and_position, a.system_name, a.new_system_id, a.operating_system,
+            a.return_date, a.assigned_by, a.purpose, a.sub_admin_id_email, a.staff_id_email, a.created_at, a.updated_at
+        FROM system_assignments a
+        WHERE ($1::text IS NULL
+            OR EXISTS (SELECT 1 FROM sub_admin s WHERE s.email = a.sub_admin_id_email AND s.company_name = $1)
+            OR EXISTS (SELECT 1 FROM devices d WHERE d.system_id = a.new_system_id AND d.company_name = $1))
+        AND ($2::text IS NULL OR a.staff_id_email = $2)
+        AND ($3::text IS NULL OR a.staff_department = $3)
+        AND ($4::text IS NULL OR a.assigned_by = $4)
+        AND ($5::text IS NULL OR a.operating_system = $5)
+        AND ($6::bool IS NULL OR (a.staff_id_email IS NOT NULL AND COALESCE(a.return_date < CURRENT_DATE, false)) = $6)
+        ORDER BY a.created_at DESC NULLS LAST, a.new_system_id",
+        company_name,
+        query.staff_id_email,
+        query.staff_department,
+        query.assigned_by,
+        query.operating_system,
+        query.overdue,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Without a grouping everything falls into a single row with no key
+async fn count_system_assignments(
+    pool: &PgPool,
+    company_name: Option<&str>,
+    query: &SystemAssignmentQuery,
+) -> Result<Vec<SystemAssignmentCount>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemAssignmentCount,
+        r#"SELECT grouped.key, COUNT(*) as "count!"
+        FROM (
+            SELECT CASE $7::text
+                WHEN 'staff_id_email' THEN a.staff_id_email
+                WHEN 'staff_department' THEN a.staff_department
+                WHEN 'assigned_by' THEN a.assigned_by
+                WHEN 'operating_system' THEN a.operating_system
+                WHEN 'overdue' THEN (a.staff_id_email IS NOT NULL AND COALESCE(a.return_date < CURRENT_DATE, false))::text
+            END as key
+            FROM system_assignments a
+            WHERE ($1::text IS NULL
+                OR EXISTS (SELECT 1 FROM sub_admin s WHERE s.email = a.sub_admin_id_email AND s.company_name = $1)
+                OR EXISTS (SELECT 1 FROM devices d WHERE d.system_id = a.new_system_id AND d.company_name = $1))
+            AND ($2::text IS NULL OR a.staff_id_email = $2)
+            AND ($3::text IS NULL OR a.staff_department = $3)
+            AND ($4::text IS NULL OR a.assigned_by = $4)
+            AND ($5::text IS NULL OR a.operating_system = $5)
+            AND ($6::bool IS NULL OR (a.staff_id_email IS NOT NULL AND COALESCE(a.return_date < CURRENT_DATE, false)) = $6)
+        ) grouped
+        GROUP BY grouped.key
+        ORDER BY 2 DESC, 1"#,
+        company_name,
+        query.staff_id_email,
+        query.staff_department,
+        query.assigned_by,
+        query.operating_system,
+        query.overdue,
+        query.group_by.map(AssignmentGrouping::as_str),
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_system_assignments(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<SystemAssignmentQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_system_assignments(&pool, company_name.as_deref(), &query).await {
+        Ok(assignments) => HttpResponse::Ok().json(assignments),
+        Err(e) => {
+            error!("Failed to fetch system assignments: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch system assignments")
+        }
+    }
+}
+
+/// Assignment counts matching the list filters, optionally per `group_by`.
+pub async fn get_system_assignment_counts(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<SystemAssignmentQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match count_system_assignments(&pool, company_name.as_deref(), &query).await {
+        Ok(counts) => HttpResponse::Ok().json(counts),
+        Err(e) => {
+            error!("Failed to count system assignments: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to count system assignments")
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct SystemCheckout {
+    pub id: i64,
+    pub new_system_id: String,
+    pub staff_id_email: String,
+    pub staff_full_name: Option<String>,
+    pub staff_department: Option<String>,
+    pub staff_role_and_position: Option<String>,
+    pub purpose: Option<String>,
+    pub checked_out_by: Option<String>,
+    pub checked_out_at: DateTime<Utc>,
+    pub due_back_on: Option<NaiveDate>,
+    pub returned_at: Option<DateTime<Utc>>,
+    pub returned_by: Option<String>,
+    pub return_notes: Option<String>,
+    pub last_reminded_at: Option<DateTime<Utc>>,
+    pub reminder_count: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SystemCheckoutRequest {
+    pub staff_id_email: String,
+    pub staff_full_name: Option<String>,
+    pub staff_department: Option<String>,
+    pub staff_role_and_position: Option<String>,
+    pub purpose: Option<String>,
+    pub due_back_on: Option<NaiveDate>,
+}
+
+impl SystemCheckoutRequest {
+    fn from_assignment(assignment: &SystemAssignment) -> Option<Self> {
+        Some(Self {
+            staff_id_email: assignment.staff_id_email.clone()?,
+            staff_full_name: assignment.staff_full_name.clone(),
+            staff_department: assignment.staff_department.clone(),
+            staff_role_and_position: assignment.staff_role_and_position.clone(),
+            purpose: assignment.purpose.clone(),
+            due_back_on: assignment.return_date,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SystemReturnRequest {
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HolderQuery {
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OverdueQuery {
+    pub company_name: Option<String>,
+}
+
+enum CheckoutError {
+    UnknownSystem,
+    AlreadyCheckedOut(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CheckoutError {
+    fn from(e: sqlx::Error) -> Self {
+        CheckoutError::Database(e)
+    }
+}
+
+fn can_manage_systems(claims: &Claims) -> bool {
+    matches!(claims.role(), UserRole::SuperAdmin | UserRole::SubAdmin)
+}
+
+// A system belongs to a company through its device or the sub admin who assigned it
+async fn fetch_system_company(pool: &PgPool, new_system_id: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT COALESCE(
+            (SELECT d.company_name FROM devices d WHERE d.system_id = $1 AND d.company_name IS NOT NULL ORDER BY d.device_id LIMIT 1),
+            (SELECT s.company_name FROM system_assignments a JOIN sub_admin s ON s.email = a.sub_admin_id_email
+                WHERE a.new_system_id = $1 AND s.company_name IS NOT NULL LIMIT 1)
+        )",
+        new_system_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// Checks the caller may manage systems of the company `new_system_id` belongs to.
+// Systems of no company are left to super admins.
+pub async fn check_system_company(pool: &PgPool, claims: &Claims, new_system_id: &str) -> Result<(), HttpResponse> {
+    if claims.role() == UserRole::SuperAdmin {
+        return Ok(());
+    }
+    match fetch_system_company(pool, new_system_id).await {
+        Ok(Some(company_name)) => resolve_company_scope(pool, claims, Some(&company_name)).await.map(|_| ()),
+        Ok(None) => Err(HttpResponse::Forbidden().body("Not allowed to access data of this company")),
+        Err(e) => {
+            error!("Failed to fetch company of system {}: {:?}", new_system_id, e);
+            Err(HttpResponse::InternalServerError().body("Failed to fetch system"))
+        }
+    }
+}
+
+/// Checks `new_system_id` out to `checkout.staff_id_email`. With `handover`,
+/// whoever holds it now returns it first; without, a held system is refused.
+/// Checking a system out to its current holder changes nothing.
+async fn check_out_system_in_database(
+    conn: &mut PgConnection,
+    new_system_id: &str,
+    checkout: &SystemCheckoutRequest,
+    checked_out_by: Option<&str>,
+    handover: bool,
+) -> Result<SystemCheckout, CheckoutError> {
+    let mut tx = conn.begin().await?;
+
+    let known = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM system_assignments WHERE new_system_id = $1"#,
+        new_system_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if known == 0 {
+        return Err(CheckoutError::UnknownSystem);
+    }
+
+    let current = sqlx::query_as!(
+        SystemCheckout,
+        "SELECT id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count
+        FROM system_checkouts WHERE new_system_id = $1 AND returned_at IS NULL FOR UPDATE",
+        new_system_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(current) = current {
+        if current.staff_id_email == checkout.staff_id_email {
+            tx.commit().await?;
+            return Ok(current);
+        }
+        if !handover {
+            return Err(CheckoutError::AlreadyCheckedOut(current.staff_id_email));
+        }
+        sqlx::query!(
+            "UPDATE system_checkouts SET returned_at = now(), returned_by = $2, return_notes = $3 WHERE id = $1",
+            current.id,
+            checked_out_by,
+            format!("Handed over to {}", checkout.staff_id_email),
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let opened = sqlx::query_as!(
+        SystemCheckout,
+        "INSERT INTO system_checkouts (new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by, due_back_on)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count",
+        new_system_id,
+        checkout.staff_id_email.trim(),
+        checkout.staff_full_name,
+        checkout.staff_department,
+        checkout.staff_role_and_position,
+        checkout.purpose,
+        checked_out_by,
+        checkout.due_back_on,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // The assignment row keeps describing whoever holds the system now
+    sqlx::query!(
+        "UPDATE system_assignments SET staff_id_email = $2, staff_full_name = $3, staff_department = $4, staff_role_and_position = $5,
+            purpose = COALESCE($6, purpose), return_date = $7, assigned_by = COALESCE($8, assigned_by), updated_at = now()
+        WHERE new_system_id = $1",
+        new_system_id,
+        opened.staff_id_email,
+        opened.staff_full_name,
+        opened.staff_department,
+        opened.staff_role_and_position,
+        opened.purpose,
+        opened.due_back_on,
+        checked_out_by,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(opened)
+}
+
+// Used by the assignment endpoints, which predate checkouts and name the new
+// holder directly
+async fn hand_over_system(conn: &mut PgConnection, new_system_id: &str, checkout: &SystemCheckoutRequest, checked_out_by: Option<&str>) -> Result<(), sqlx::Error> {
+    match check_out_system_in_database(conn, new_system_id, checkout, checked_out_by, true).await {
+        Ok(_) => assign_device_owner_by_system_id(conn, new_system_id, &checkout.staff_id_email).await,
+        Err(CheckoutError::Database(e)) => Err(e),
+        Err(_) => Ok(()),
+    }
+}
+
+async fn return_system_in_database(
+    conn: &mut PgConnection,
+    new_system_id: &str,
+    returned_by: &str,
+    notes: Option<&str>,
+) -> Result<Option<SystemCheckout>, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+
+    let returned = sqlx::query_as!(
+        SystemCheckout,
+        "UPDATE system_checkouts SET returned_at = now(), returned_by = $2, return_notes = $3
+        WHERE new_system_id = $1 AND returned_at IS NULL
+        RETURNING id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count",
+        new_system_id,
+        returned_by,
+        notes,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if returned.is_some() {
+        sqlx::query!(
+            "UPDATE system_assignments SET staff_id_email = NULL, staff_full_name = NULL, staff_department = NULL,
+                staff_role_and_position = NULL, return_date = NULL, updated_at = now()
+            WHERE new_system_id = $1",
+            new_system_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(returned)
+}
+
+async fn update_due_date_in_database<'e, E>(executor: E, new_system_id: &str, due_back_on: NaiveDate) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "UPDATE system_checkouts SET due_back_on = $2 WHERE new_system_id = $1 AND returned_at IS NULL",
+        new_system_id,
+        due_back_on,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_checkout_history(pool: &PgPool, new_system_id: &str) -> Result<Vec<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "SELECT id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count
+        FROM system_checkouts WHERE new_system_id = $1
+        ORDER BY checked_out_at DESC, id DESC",
+        new_system_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_holder_at(pool: &PgPool, new_system_id: &str, at: DateTime<Utc>) -> Result<Option<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "SELECT id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count
+        FROM system_checkouts
+        WHERE new_system_id = $1 AND checked_out_at <= $2 AND (returned_at IS NULL OR returned_at > $2)
+        ORDER BY checked_out_at DESC
+        LIMIT 1",
+        new_system_id,
+        at,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// A system belongs to a company through its device or the sub admin who assigned it
+async fn fetch_overdue_checkouts(pool: &PgPool, company_name: Option<&str>) -> Result<Vec<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "SELECT c.id, c.new_system_id, c.staff_id_email, c.staff_full_name, c.staff_department, c.staff_role_and_position, c.purpose,
+            c.checked_out_by, c.checked_out_at, c.due_back_on, c.returned_at, c.returned_by, c.return_notes, c.last_reminded_at, c.reminder_count
+        FROM system_checkouts c
+        WHERE c.returned_at IS NULL AND c.due_back_on < CURRENT_DATE
+        AND ($1::text IS NULL
+            OR EXISTS (SELECT 1 FROM devices d WHERE d.system_id = c.new_system_id AND d.company_name = $1)
+            OR EXISTS (SELECT 1 FROM system_assignments a JOIN sub_admin s ON s.email = a.sub_admin_id_email
+                WHERE a.new_system_id = c.new_system_id AND s.company_name = $1))
+        ORDER BY c.due_back_on, c.id",
+        company_name
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Marks overdue checkouts as reminded, at most once per `every_hours`, and
+/// returns the ones that were due a reminder.
+async fn remind_overdue_checkouts(pool: &PgPool, every_hours: i32) -> Result<Vec<SystemCheckout>, sqlx::Error> {
+    sqlx::query_as!(
+        SystemCheckout,
+        "UPDATE system_checkouts SET last_reminded_at = now(), reminder_count = reminder_count + 1
+        WHERE returned_at IS NULL AND due_back_on < CURRENT_DATE
+        AND (last_reminded_at IS NULL OR last_reminded_at <= now() - make_interval(hours => $1))
+        RETURNING id, new_system_id, staff_id_email, staff_full_name, staff_department, staff_role_and_position, purpose, checked_out_by,
+            checked_out_at, due_back_on, returned_at, returned_by, return_notes, last_reminded_at, reminder_count",
+        every_hours
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Background job reminding holders of overdue systems, checking every
+/// SYSTEM_RETURN_CHECK_INTERVAL_SECS (3600 by default) and reminding each
+/// holder every SYSTEM_RETURN_REMINDER_HOURS (24 by default).
+pub async fn run_system_return_monitor(pool: PgPool) {
+    dotenv().ok();
+    let seconds = env::var("SYSTEM_RETURN_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3600);
+    let every_hours = env::var("SYSTEM_RETURN_REMINDER_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24);
+    let mut interval = interval(std::time::Duration::from_secs(seconds));
+
+    loop {
+        interval.tick().await;
+        match remind_overdue_checkouts(&pool, every_hours).await {
+            Ok(reminded) => {
+                for checkout in reminded {
+                    warn!(
+                        "Reminder {}: {} was due to return system {} on {}",
+                        checkout.reminder_count,
+                        checkout.staff_id_email,
+                        checkout.new_system_id,
+                        checkout.due_back_on.map(|date| date.to_string()).unwrap_or_default()
+                    );
+                }
+            }
+            Err(e) => error!("Failed to check overdue systems: {:?}", e),
+        }
+    }
+}
+
+pub async fn check_out_system(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    new_system_id: web::Path<String>,
+    checkout: web::Json<SystemCheckoutRequest>,
+) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can check systems out");
+    }
+    let new_system_id = new_system_id.into_inner();
+    if let Err(response) = check_system_company(&pool, &claims, &new_system_id).await {
+        return response;
+    }
+    if checkout.staff_id_email.trim().is_empty() {
+        return HttpResponse::BadRequest().body("staff_id_email is required");
+    }
+    if checkout.due_back_on.is_some_and(|due_back_on| due_back_on < Utc::now().date_naive()) {
+        return HttpResponse::BadRequest().body("due_back_on cannot be in the past");
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let opened = check_out_system_in_database(&mut tx, &new_system_id, &checkout, Some(claims.email()), false).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "checkout", "system_assignment", &new_system_id, None, snapshot(&opened)).await?;
+        assign_device_owner_by_system_id(&mut tx, &new_system_id, &opened.staff_id_email).await?;
+        tx.commit().await?;
+        Ok::<_, CheckoutError>(opened)
+    }
+    .await;
+
+    match result {
+        Ok(opened) => HttpResponse::Created().json(opened),
+        Err(CheckoutError::UnknownSystem) => HttpResponse::NotFound().body("System assignment not found"),
+        Err(CheckoutError::AlreadyCheckedOut(holder)) => {
+            HttpResponse::Conflict().body(format!("System is checked out to {}; return it first", holder))
+        }
+        Err(CheckoutError::Database(e)) => {
+            error!("Failed to check out system {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to check out system")
+        }
+    }
+}
+
+pub async fn return_system(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    new_system_id: web::Path<String>,
+    body: Option<web::Json<SystemReturnRequest>>,
+) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can take systems back");
+    }
+    let new_system_id = new_system_id.into_inner();
+    if let Err(response) = check_system_company(&pool, &claims, &new_system_id).await {
+        return response;
+    }
+    let notes = body.and_then(|body| body.into_inner().notes);
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let returned = return_system_in_database(&mut tx, &new_system_id, claims.email(), notes.as_deref()).await?;
+        if let Some(returned) = &returned {
+            record(&mut tx, &AuditActor::new(&req, &claims), "return", "system_assignment", &new_system_id, None, snapshot(returned)).await?;
+            release_device_owner_by_system_id(&mut *tx, &new_system_id).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(returned)
+    }
+    .await;
+
+    match result {
+        Ok(Some(returned)) => HttpResponse::Ok().json(returned),
+        Ok(None) => HttpResponse::Conflict().body("System is not checked out"),
+        Err(e) => {
+            error!("Failed to return system {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to return system")
+        }
+    }
+}
+
+pub async fn get_system_checkout_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    new_system_id: web::Path<String>,
+) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can view system history");
+    }
+    let new_system_id = new_system_id.into_inner();
+    if let Err(response) = check_system_company(&pool, &claims, &new_system_id).await {
+        return response;
+    }
+    match fetch_checkout_history(&pool, &new_system_id).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch history of system {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch system history")
+        }
+    }
+}
+
+/// Who had the system at `?at=`.
+pub async fn get_system_holder_at(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    new_system_id: web::Path<String>,
+    query: web::Query<HolderQuery>,
+) -> impl Responder {
+    if !can_manage_systems(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can view system history");
+    }
+    let new_system_id = new_system_id.into_inner();
+    if let Err(response) = check_system_company(&pool, &claims, &new_system_id).await {
+        return response;
+    }
+    match fetch_holder_at(&pool, &new_system_id, query.at).await {
+        Ok(Some(checkout)) => HttpResponse::Ok().json(checkout),
+        Ok(None) => HttpResponse::NotFound().body("Nobody had this system at that time"),
+        Err(e) => {
+            error!("Failed to fetch holder of system {}: {:?}", new_system_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch system holder")
+        }
+    }
+}
+
+pub async fn get_overdue_systems(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<OverdueQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_overdue_checkouts(&pool, company_name.as_deref()).await {
+        Ok(overdue) => HttpResponse::Ok().json(overdue),
+        Err(e) => {
+            error!("Failed to fetch overdue systems: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch overdue systems")
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::Utc;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use dotenvy::dotenv;
+use log::error;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::env;
+use crate::auth::claims::Claims;
+use crate::functionalities::comments::{fetch_comment, sees_internal_notes};
+use crate::functionalities::maintenance::load_visible_maintenance_request;
+use crate::storage::blob::BlobStore;
+use crate::user::users::UserRole;
+
+const DEFAULT_MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+
+// Content types accepted for upload, with the leading bytes binary formats must
+// start with. Text types only need to be valid UTF-8.
+const ALLOWED_CONTENT_TYPES: &[(&str, &[&[u8]])] = &[
+    ("image/png", &[b"\x89PNG\r\n\x1a\n"]),
+    ("image/jpeg", &[b"\xff\xd8\xff"]),
+    ("image/gif", &[b"GIF87a", b"GIF89a"]),
+    ("image/webp", &[b"RIFF"]),
+    ("application/pdf", &[b"%PDF-"]),
+    ("application/zip", &[b"PK\x03\x04"]),
+    ("application/gzip", &[b"\x1f\x8b"]),
+    ("text/plain", &[]),
+    ("text/csv", &[]),
+    ("application/json", &[]),
+];
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct MaintenanceAttachment {
+    pub id: i64,
+    pub maintenance_id: i64,
+    pub comment_id: Option<i64>,
+    pub uploaded_by: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    #[serde(skip)]
+    pub storage_key: String,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    pub file_name: String,
+    pub comment_id: Option<i64>,
+}
+
+/// Largest upload accepted, from MAX_ATTACHMENT_BYTES (10 MiB by default).
+pub fn max_attachment_bytes() -> usize {
+    dotenv().ok();
+    env::var("MAX_ATTACHMENT_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_ATTACHMENT_BYTES)
+}
+
+/// Checks the declared type is one we accept and that the content looks like it.
+fn check_content_type(content_type: &str, data: &[u8]) -> Result<(), String> {
+    let (_, signatures) = ALLOWED_CONTENT_TYPES
+        .iter()
+        .find(|(allowed, _)| *allowed == content_type)
+        .ok_or_else(|| format!("Unsupported content type: {}", content_type))?;
+
+    let matches = if signatures.is_empty() {
+        std::str::from_utf8(data).is_ok()
+    } else if content_type == "image/webp" {
+        data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP".as_slice())
+    } else {
+        signatures.iter().any(|signature| data.starts_with(signature))
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("File content does not match {}", content_type))
+    }
+}
+
+// Keep only the last path segment and drop characters that would need quoting
+// in a Content-Disposition header.
+fn clean_file_name(file_name: &str) -> Option<String> {
+    let name: String = file_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(255)
+        .collect();
+    let name = name.trim().to_string();
+    (!name.is_empty() && name != "." && name != "..").then_some(name)
+}
+
+fn new_storage_key(maintenance_id: i64) -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let suffix: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("maintenance/{}/{}", maintenance_id, suffix)
+}
+
+async fn save_attachment_to_database(pool: &PgPool, attachment: &MaintenanceAttachment) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        "INSERT INTO maintenance_attachments (maintenance_id, comment_id, uploaded_by, file_name, content_type, size_bytes, sha256, storage_key, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+        attachment.maintenance_id,
+        attachment.comment_id,
+        attachment.uploaded_by,
+        attachment.file_name,
+        attachment.content_type,
+        attachment.size_bytes,
+        attachment.sha256,
+        attachment.storage_key,
+        attachment.created_at,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.id)
+}
+
+async fn fetch_attachments(pool: &PgPool, maintenance_id: i64, include_internal: bool) -> Result<Vec<MaintenanceAttachment>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceAttachment,
+        "SELECT a.id, a.maintenance_id, a.comment_id, a.uploaded_by, a.file_name, a.content_type, a.size_bytes, a.sha256, a.storage_key, a.created_at
+        FROM maintenance_attachments a
+        LEFT JOIN maintenance_comments c ON c.id = a.comment_id
+        WHERE a.maintenance_id = $1 AND ($2 OR NOT COALESCE(c.internal, FALSE))
+        ORDER BY a.created_at, a.id",
+        maintenance_id,
+        include_internal
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_attachment(
+    pool: &PgPool,
+    maintenance_id: i64,
+    attachment_id: i64,
+    include_internal: bool,
+) -> Result<Option<MaintenanceAttachment>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceAttachment,
+        "SELECT a.id, a.maintenance_id, a.comment_id, a.uploaded_by, a.file_name, a.content_type, a.size_bytes, a.sha256, a.storage_key, a.created_at
+        FROM maintenance_attachments a
+        LEFT JOIN maintenance_comments c ON c.id = a.comment_id
+        WHERE a.maintenance_id = $1 AND a.id = $2 AND ($3 OR NOT COALESCE(c.internal, FALSE))",
+        maintenance_id,
+        attachment_id,
+        include_internal
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Storage keys of every attachment on a request, so the blobs can be removed
+/// along with it.
+pub async fn fetch_attachment_keys<'e, E>(executor: E, maintenance_id: i64) -> Result<Vec<String>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let rows = sqlx::query!(
+        "SELECT storage_key FROM maintenance_attachments WHERE maintenance_id = $1",
+        maintenance_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.storage_key).collect())
+}
+
+pub async fn upload_attachment(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    store: web::Data<dyn BlobStore>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+    query: web::Query<UploadQuery>,
+    body: web::Bytes,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    if body.is_empty() {
+        return HttpResponse::BadRequest().body("Attachment is empty");
+    }
+    if body.len() > max_attachment_bytes() {
+        return HttpResponse::PayloadTooLarge().body(format!("Attachments are limited to {} bytes", max_attachment_bytes()));
+    }
+
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if let Err(message) = check_content_type(&content_type, &body) {
+        return HttpResponse::UnsupportedMediaType().body(message);
+    }
+
+    let file_name = match clean_file_name(&query.file_name) {
+        Some(file_name) => file_name,
+        None => return HttpResponse::BadRequest().body("Invalid file name"),
+    };
+
+    if let Some(comment_id) = query.comment_id {
+        match fetch_comment(&pool, maintenance_id, comment_id).await {
+            Ok(Some(comment)) if !comment.internal || sees_internal_notes(claims.role()) => {}
+            Ok(_) => return HttpResponse::NotFound().body("Comment not found"),
+            Err(e) => {
+                error!("Failed to fetch comment {}: {:?}", comment_id, e);
+                return HttpResponse::InternalServerError().body("Failed to upload attachment");
+            }
+        }
+    }
+
+    let mut attachment = MaintenanceAttachment {
+        id: 0,
+        maintenance_id,
+        comment_id: query.comment_id,
+        uploaded_by: claims.email().to_string(),
+        file_name,
+        content_type,
+        size_bytes: body.len() as i64,
+        sha256: format!("{:x}", Sha256::digest(&body)),
+        storage_key: new_storage_key(maintenance_id),
+        created_at: Utc::now(),
+    };
+
+    if let Err(e) = store.put(&attachment.storage_key, &body).await {
+        error!("Failed to store attachment {}: {:?}", attachment.storage_key, e);
+        return HttpResponse::InternalServerError().body("Failed to upload attachment");
+    }
+
+    match save_attachment_to_database(&pool, &attachment).await {
+        Ok(id) => {
+            attachment.id = id;
+            HttpResponse::Created().json(attachment)
+        }
+        Err(e) => {
+            error!("Failed to save attachment: {:?}", e);
+            if let Err(e) = store.delete(&attachment.storage_key).await {
+                error!("Failed to clean up attachment {}: {:?}", attachment.storage_key, e);
+            }
+            HttpResponse::InternalServerError().body("Failed to upload attachment")
+        }
+    }
+}
+
+pub async fn get_attachments(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    match fetch_attachments(&pool, maintenance_id, sees_internal_notes(claims.role())).await {
+        Ok(attachments) => HttpResponse::Ok().json(attachments),
+        Err(e) => {
+            error!("Failed to fetch attachments: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachments")
+        }
+    }
+}
+
+pub async fn download_attachment(
+    pool: web::Data<PgPool>,
+    store: web::Data<dyn BlobStore>,
+    claims: Claims,
+    path: web::Path<(i64, i64)>,
+) -> impl Responder {
+    let (maintenance_id, attachment_id) = path.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    let attachment = match fetch_attachment(&pool, maintenance_id, attachment_id, sees_internal_notes(claims.role())).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => {
+            error!("Failed to fetch attachment {}: {:?}", attachment_id, e);
+            return HttpResponse::InternalServerError().body("Failed to fetch attachment");
+        }
+    };
+
+    match store.get(&attachment.storage_key).await {
+        Ok(data) => HttpResponse::Ok()
+            .content_type(attachment.content_type)
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", attachment.file_name)))
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            .body(data),
+        Err(e) => {
+            error!("Failed to read attachment {}: {:?}", attachment.storage_key, e);
+            HttpResponse::InternalServerError().body("Failed to fetch attachment")
+        }
+    }
+}
+
+pub async fn delete_attachment(
+    pool: web::Data<PgPool>,
+    store: web::Data<dyn BlobStore>,
+    claims: Claims,
+    path: web::Path<(i64, i64)>,
+) -> impl Responder {
+    let (maintenance_id, attachment_id) = path.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    let attachment = match fetch_attachment(&pool, maintenance_id, attachment_id, sees_internal_notes(claims.role())).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return HttpResponse::NotFound().body("Attachment not found"),
+        Err(e) => {
+            error!("Failed to fetch attachment {}: {:?}", attachment_id, e);
+            return HttpResponse::InternalServerError().body("Failed to delete attachment");
+        }
+    };
+
+    let is_admin = matches!(claims.role(), UserRole::SuperAdmin | UserRole::SubAdmin);
+    if !is_admin && attachment.uploaded_by != claims.email() {
+        return HttpResponse::Forbidden().body("Only the uploader or an admin can delete an attachment");
+    }
+
+    if let Err(e) = sqlx::query!("DELETE FROM maintenance_attachments WHERE id = $1", attachment.id)
+        .execute(pool.get_ref())
+        .await
+    {
+        error!("Failed to delete attachment {}: {:?}", attachment.id, e);
+        return HttpResponse::InternalServerError().body("Failed to delete attachment");
+    }
+
+    if let Err(e) = store.delete(&attachment.storage_key).await {
+        error!("Failed to remove attachment {}: {:?}", attachment.storage_key, e);
+    }
+    HttpResponse::Ok().body("Attachment deleted")
+}
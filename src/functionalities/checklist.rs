@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::Utc;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::functionalities::maintenance::load_visible_maintenance_request;
+use crate::user::users::UserRole;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ChecklistItem {
+    pub id: i64,
+    pub maintenance_id: i64,
+    pub position: i32,
+    pub label: String,
+    pub done_at: Option<chrono::DateTime<Utc>>,
+    pub done_by: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateChecklistItem {
+    pub done: bool,
+}
+
+/// Copies checklist labels onto a request, in order.
+pub async fn save_checklist_to_database<'e, E>(executor: E, maintenance_id: i64, labels: &[String]) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "INSERT INTO maintenance_checklist_items (maintenance_id, position, label)
+        SELECT $1, item.position::int, item.label
+        FROM unnest($2::text[]) WITH ORDINALITY AS item(label, position)",
+        maintenance_id,
+        labels,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_checklist(pool: &PgPool, maintenance_id: i64) -> Result<Vec<ChecklistItem>, sqlx::Error> {
+    sqlx::query_as!(
+        ChecklistItem,
+        "SELECT id, maintenance_id, position, label, done_at, done_by
+        FROM maintenance_checklist_items WHERE maintenance_id = $1 ORDER BY position",
+        maintenance_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn update_checklist_item_in_database<'e, E>(
+    executor: E,
+    maintenance_id: i64,
+    item_id: i64,
+    done_by: Option<&str>,
+) -> Result<Option<ChecklistItem>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        ChecklistItem,
+        "UPDATE maintenance_checklist_items
+        SET done_at = CASE WHEN $3::text IS NULL THEN NULL ELSE COALESCE(done_at, now()) END,
+            done_by = CASE WHEN $3::text IS NULL THEN NULL ELSE COALESCE(done_by, $3) END
+        WHERE maintenance_id = $1 AND id = $2
+        RETURNING id, maintenance_id, position, label, done_at, done_by",
+        maintenance_id,
+        item_id,
+        done_by,
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+pub async fn get_checklist(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    match fetch_checklist(&pool, maintenance_id).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(e) => {
+            error!("Failed to fetch checklist of maintenance request {}: {:?}", maintenance_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch checklist")
+        }
+    }
+}
+
+pub async fn update_checklist_item(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    path: web::Path<(i64, i64)>,
+    update: web::Json<UpdateChecklistItem>,
+) -> impl Responder {
+    let (maintenance_id, item_id) = path.into_inner();
+    if claims.role() == UserRole::Staff {
+        return HttpResponse::Forbidden().body("Only technicians and admins can tick off checklist items");
+    }
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    let done_by = update.done.then(|| claims.email());
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let item = update_checklist_item_in_database(&mut *tx, maintenance_id, item_id, done_by).await?;
+        if let Some(item) = &item {
+            record(&mut tx, &AuditActor::new(&req, &claims), "update", "maintenance_checklist_item", &item_id.to_string(), None, snapshot(item)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(item)
+    }
+    .await;
+
+    match result {
+        Ok(Some(item)) => HttpResponse::Ok().json(item),
+        Ok(None) => HttpResponse::NotFound().body("Checklist item not found"),
+        Err(e) => {
+            error!("Failed to update checklist item {}: {:?}", item_id, e);
+            HttpResponse::InternalServerError().body("Failed to update checklist item")
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::Utc;
+use actix_web::{web, HttpResponse, Responder};
+use log::error;
+use std::collections::HashMap;
+use crate::auth::claims::Claims;
+use crate::functionalities::maintenance::load_visible_maintenance_request;
+use crate::functionalities::sla::record_first_response;
+use crate::user::users::UserRole;
+
+const MAX_COMMENT_LENGTH: usize = 10_000;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct MaintenanceComment {
+    pub id: i64,
+    pub maintenance_id: i64,
+    pub parent_id: Option<i64>,
+    pub author_email: String,
+    pub author_role: String,
+    pub body: String,
+    pub internal: bool,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewComment {
+    pub body: String,
+    pub parent_id: Option<i64>,
+    pub internal: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommentThread {
+    #[serde(flatten)]
+    pub comment: MaintenanceComment,
+    pub replies: Vec<CommentThread>,
+}
+
+/// Internal notes are for the people working the request, not the reporter.
+pub fn sees_internal_notes(role: UserRole) -> bool {
+    role != UserRole::Staff
+}
+
+async fn save_comment_to_database(
+    pool: &PgPool,
+    maintenance_id: i64,
+    claims: &Claims,
+    comment: &NewComment,
+    internal: bool,
+) -> Result<MaintenanceComment, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceComment,
+        "INSERT INTO maintenance_comments (maintenance_id, parent_id, author_email, author_role, body, internal)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, maintenance_id, parent_id, author_email, author_role, body, internal, created_at",
+        maintenance_id,
+        comment.parent_id,
+        claims.email(),
+        format!("{:?}", claims.role()),
+        comment.body.trim(),
+        internal,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn fetch_comment(pool: &PgPool, maintenance_id: i64, comment_id: i64) -> Result<Option<MaintenanceComment>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceComment,
+        "SELECT id, maintenance_id, parent_id, author_email, author_role, body, internal, created_at
+        FROM maintenance_comments WHERE maintenance_id = $1 AND id = $2",
+        maintenance_id,
+        comment_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_comments(pool: &PgPool, maintenance_id: i64, include_internal: bool) -> Result<Vec<MaintenanceComment>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceComment,
+        "SELECT id, maintenance_id, parent_id, author_email, author_role, body, internal, created_at
+        FROM maintenance_comments
+        WHERE maintenance_id = $1 AND ($2 OR NOT internal)
+        ORDER BY created_at, id",
+        maintenance_id,
+        include_internal
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Comments come in creation order, so every parent is seen before its replies.
+// Replies to a comment the caller cannot see are dropped along with it.
+fn build_threads(comments: Vec<MaintenanceComment>) -> Vec<CommentThread> {
+    let mut children: HashMap<Option<i64>, Vec<MaintenanceComment>> = HashMap::new();
+    for comment in comments {
+        children.entry(comment.parent_id).or_default().push(comment);
+    }
+
+    fn attach(parent_id: Option<i64>, children: &mut HashMap<Option<i64>, Vec<MaintenanceComment>>) -> Vec<CommentThread> {
+        children
+            .remove(&parent_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|comment| {
+                let replies = attach(Some(comment.id), children);
+                CommentThread { comment, replies }
+            })
+            .collect()
+    }
+
+    attach(None, &mut children)
+}
+
+pub async fn create_comment(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+    comment: web::Json<NewComment>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    let body = comment.body.trim();
+    if body.is_empty() || body.chars().count() > MAX_COMMENT_LENGTH {
+        return HttpResponse::BadRequest().body(format!("Comment must be between 1 and {} characters", MAX_COMMENT_LENGTH));
+    }
+
+    let mut internal = comment.internal.unwrap_or(false);
+    if internal && !sees_internal_notes(claims.role()) {
+        return HttpResponse::Forbidden().body("Staff cannot write internal notes");
+    }
+
+    if let Some(parent_id) = comment.parent_id {
+        match fetch_comment(&pool, maintenance_id, parent_id).await {
+            Ok(Some(parent)) if parent.internal && !sees_internal_notes(claims.role()) => {
+                return HttpResponse::NotFound().body("Parent comment not found");
+            }
+            // A reply to an internal note stays internal
+            Ok(Some(parent)) => internal |= parent.internal,
+            Ok(None) => return HttpResponse::NotFound().body("Parent comment not found"),
+            Err(e) => {
+                error!("Failed to fetch comment {}: {:?}", parent_id, e);
+                return HttpResponse::InternalServerError().body("Failed to create comment");
+            }
+        }
+    }
+
+    match save_comment_to_database(&pool, maintenance_id, &claims, &comment, internal).await {
+        Ok(saved) => {
+            // A reply from whoever works the request counts as the first response
+            if claims.role() != UserRole::Staff && !saved.internal {
+                if let Err(e) = record_first_response(pool.get_ref(), maintenance_id).await {
+                    error!("Failed to record first response of maintenance request {}: {:?}", maintenance_id, e);
+                }
+            }
+            HttpResponse::Created().json(saved)
+        }
+        Err(e) => {
+            error!("Failed to create comment: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to create comment")
+        }
+    }
+}
+
+pub async fn get_comments(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    match fetch_comments(&pool, maintenance_id, sees_internal_notes(claims.role())).await {
+        Ok(comments) => HttpResponse::Ok().json(build_threads(comments)),
+        Err(e) => {
+            error!("Failed to fetch comments: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch comments")
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, FromRow, PgConnection, PgPool};
+use chrono::Utc;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use dotenvy::dotenv;
+use log::error;
+use std::env;
+use crate::audit::trail::{record, AuditActor};
+use crate::auth::claims::Claims;
+use crate::functionalities::maintenance::{load_visible_maintenance_request, MaintenancePriority, MaintenanceRequest, MaintenanceStatus};
+use crate::user::users::{resolve_company_scope, UserRole};
+
+#[derive(Debug, Deserialize)]
+pub struct AssignTechnician {
+    // Leave out to let auto-dispatch pick the technician
+    pub technician_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTechnicianSkills {
+    pub skills: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueueQuery {
+    pub status: Option<MaintenanceStatus>,
+    pub priority: Option<MaintenancePriority>,
+    // Resolved, closed and rejected requests are left out unless asked for
+    pub include_closed: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Assignment {
+    pub maintenance_id: i64,
+    pub technician_id: i64,
+    pub previous_technician_id: Option<i64>,
+    pub method: String,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AssignmentHistoryEntry {
+    pub id: i64,
+    pub maintenance_id: i64,
+    pub technician_id: i64,
+    pub assigned_by: Option<String>,
+    pub method: String,
+    pub assigned_at: chrono::DateTime<Utc>,
+    pub unassigned_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DispatchSummary {
+    pub assigned: Vec<Assignment>,
+    // Open requests no technician has the skills for
+    pub unassigned: Vec<i64>,
+}
+
+enum DispatchError {
+    NotFound,
+    TechnicianNotFound,
+    NotOpen(MaintenanceStatus),
+    NoTechnicianAvailable,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for DispatchError {
+    fn from(e: sqlx::Error) -> Self {
+        DispatchError::Database(e)
+    }
+}
+
+impl DispatchError {
+    fn into_response(self, maintenance_id: i64) -> HttpResponse {
+        match self {
+            DispatchError::NotFound => HttpResponse::NotFound().body("Maintenance request not found"),
+            DispatchError::TechnicianNotFound => HttpResponse::NotFound().body("Technician not found"),
+            DispatchError::NotOpen(status) => HttpResponse::Conflict().body(format!("Cannot assign a {:?} maintenance request", status)),
+            DispatchError::NoTechnicianAvailable => HttpResponse::Conflict().body("No technician has the skills this request needs"),
+            DispatchError::Database(e) => {
+                error!("Failed to assign maintenance request {}: {:?}", maintenance_id, e);
+                HttpResponse::InternalServerError().body("Failed to assign maintenance request")
+            }
+        }
+    }
+}
+
+fn is_open(status: MaintenanceStatus) -> bool {
+    !matches!(status, MaintenanceStatus::Resolved | MaintenanceStatus::Closed | MaintenanceStatus::Rejected)
+}
+
+fn can_dispatch(claims: &Claims) -> bool {
+    matches!(claims.role(), UserRole::SuperAdmin | UserRole::SubAdmin)
+}
+
+/// New maintenance requests are handed to a technician straight away when
+/// MAINTENANCE_AUTO_DISPATCH is set to true.
+pub fn auto_dispatch_enabled() -> bool {
+    dotenv().ok();
+    env::var("MAINTENANCE_AUTO_DISPATCH")
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Assigns a maintenance request to `technician_id`, or when that is `None`
+/// to the technician with the required skills and the fewest open requests.
+/// Ties go to whoever was assigned work least recently. Only technicians
+/// working for the request's company are considered, and with a `company`
+/// scope only that company's requests can be assigned.
+async fn assign_maintenance_request_in_database(
+    conn: &mut PgConnection,
+    maintenance_id: i64,
+    technician_id: Option<i64>,
+    assigned_by: Option<&str>,
+    company: Option<&str>,
+) -> Result<Assignment, DispatchError> {
+    let mut tx = conn.begin().await?;
+
+    if technician_id.is_none() {
+        // Keep concurrent auto-dispatches from reading the same loads
+        sqlx::query!("LOCK TABLE maintenance_assignments IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let request = sqlx::query!(
+        r#"SELECT status as "status: MaintenanceStatus", assigned_technician_id, required_skills, company_name FROM maintenance_requests
+        WHERE maintenance_id = $1 AND ($2::text IS NULL OR company_name = $2) FOR UPDATE"#,
+        maintenance_id,
+        company,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(DispatchError::NotFound)?;
+
+    if !is_open(request.status) {
+        return Err(DispatchError::NotOpen(request.status));
+    }
+
+    let (technician_id, method) = match technician_id {
+        Some(technician_id) => {
+            sqlx::query!(
+                "SELECT id FROM technician WHERE id = $1 AND (company_name IS NULL OR company_name = $2)",
+                technician_id,
+                request.company_name,
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(DispatchError::TechnicianNotFound)?;
+            (technician_id, "manual")
+        }
+        None => {
+            let picked = sqlx::query!(
+                "SELECT t.id FROM technician t
+                WHERE t.skills @> $1 AND (t.company_name IS NULL OR t.company_name = $2)
+                ORDER BY
+                    (SELECT COUNT(*) FROM maintenance_requests m
+                        WHERE m.assigned_technician_id = t.id
+                        AND m.status NOT IN ('Resolved', 'Closed', 'Rejected')),
+                    (SELECT MAX(a.assigned_at) FROM maintenance_assignments a WHERE a.technician_id = t.id) NULLS FIRST,
+                    t.id
+                LIMIT 1",
+                &request.required_skills,
+                request.company_name,
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(DispatchError::NoTechnicianAvailable)?;
+            (picked.id, "auto")
+        }
+    };
+
+    let assignment = Assignment {
+        maintenance_id,
+        technician_id,
+        previous_technician_id: request.assigned_technician_id,
+        method: method.to_string(),
+    };
+    if request.assigned_technician_id == Some(technician_id) {
+        return Ok(assignment);
+    }
+
+    sqlx::query!(
+        "UPDATE maintenance_assignments SET unassigned_at = now() WHERE maintenance_id = $1 AND unassigned_at IS NULL",
+        maintenance_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO maintenance_assignments (maintenance_id, technician_id, assigned_by, method) VALUES ($1, $2, $3, $4)",
+        maintenance_id,
+        technician_id,
+        assigned_by,
+        method,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE maintenance_requests SET assigned_technician_id = $1, updated_at = now() WHERE maintenance_id = $2",
+        technician_id,
+        maintenance_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(assignment)
+}
+
+/// Auto-dispatches a freshly filed request. Failing to find a technician is
+/// not an error; the request simply waits in the unassigned pool.
+pub async fn auto_dispatch_new_request(conn: &mut PgConnection, maintenance_id: i64) -> Option<i64> {
+    match assign_maintenance_request_in_database(conn, maintenance_id, None, None, None).await {
+        Ok(assignment) => Some(assignment.technician_id),
+        Err(DispatchError::Database(e)) => {
+            error!("Failed to auto-dispatch maintenance request {}: {:?}", maintenance_id, e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+async fn unassign_maintenance_request_in_database(
+    conn: &mut PgConnection,
+    maintenance_id: i64,
+    company: Option<&str>,
+) -> Result<Option<i64>, DispatchError> {
+    let mut tx = conn.begin().await?;
+
+    let previous = sqlx::query!(
+        "SELECT assigned_technician_id FROM maintenance_requests WHERE maintenance_id = $1 AND ($2::text IS NULL OR company_name = $2) FOR UPDATE",
+        maintenance_id,
+        company,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(DispatchError::NotFound)?
+    .assigned_technician_id;
+
+    sqlx::query!(
+        "UPDATE maintenance_assignments SET unassigned_at = now() WHERE maintenance_id = $1 AND unassigned_at IS NULL",
+        maintenance_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE maintenance_requests SET assigned_technician_id = NULL, updated_at = now() WHERE maintenance_id = $1",
+        maintenance_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(previous)
+}
+
+/// Assigns the request as [`assign_maintenance_request_in_database`] does and
+/// records who did it in the same transaction.
+async fn assign_and_record(
+    pool: &PgPool,
+    req: &HttpRequest,
+    claims: &Claims,
+    maintenance_id: i64,
+    technician_id: Option<i64>,
+    company: Option<&str>,
+) -> Result<Assignment, DispatchError> {
+    let mut tx = pool.begin().await?;
+    let assignment = assign_maintenance_request_in_database(&mut tx, maintenance_id, technician_id, Some(claims.email()), company).await?;
+    record(
+        &mut tx,
+        &AuditActor::new(req, claims),
+        "assign",
+        "maintenance_request",
+        &maintenance_id.to_string(),
+        Some(serde_json::json!({ "assigned_technician_id": assignment.previous_technician_id })),
+        Some(serde_json::json!({ "assigned_technician_id": assignment.technician_id, "method": assignment.method })),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(assignment)
+}
+
+async fn fetch_unassigned_open_requests(pool: &PgPool, company: Option<&str>) -> Result<Vec<i64>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT maintenance_id FROM maintenance_requests
+        WHERE assigned_technician_id IS NULL AND status NOT IN ('Resolved', 'Closed', 'Rejected')
+        AND ($1::text IS NULL OR company_name = $1)
+        ORDER BY priority DESC, created_at",
+        company
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| row.maintenance_id).collect())
+}
+
+async fn fetch_technician_queue(
+    pool: &PgPool,
+    technician_id: i64,
+    query: &QueueQuery,
+    company: Option<&str>,
+) -> Result<Vec<MaintenanceRequest>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceRequest,
+        r#"SELECT maintenance_id, reported_by_sub_admin_id, reported_by_staff_id, device_name, title, description, status as "status: MaintenanceStatus", priority as "priority: MaintenancePriority", created_at, updated_at, assigned_technician_id, required_skills, device_id, company_name, first_response_at, resolved_at, response_due_at, resolve_due_at, schedule_id
+        FROM maintenance_requests
+        WHERE assigned_technician_id = $1
+        AND ($2::maintenance_status IS NULL OR status = $2)
+        AND ($3::maintenance_priority IS NULL OR priority = $3)
+        AND ($4 OR status NOT IN ('Resolved', 'Closed', 'Rejected'))
+        AND ($5::text IS NULL OR company_name = $5)
+        ORDER BY priority DESC, created_at"#,
+        technician_id,
+        query.status as Option<MaintenanceStatus>,
+        query.priority as Option<MaintenancePriority>,
+        query.include_closed.unwrap_or(false),
+        company,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_assignment_history(pool: &PgPool, maintenance_id: i64) -> Result<Vec<AssignmentHistoryEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AssignmentHistoryEntry,
+        "SELECT id, maintenance_id, technician_id, assigned_by, method, assigned_at, unassigned_at
+        FROM maintenance_assignments WHERE maintenance_id = $1 ORDER BY assigned_at, id",
+        maintenance_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Returns the technician's email and previous skills, or None if there is no such technician
+async fn update_technician_skills_in_database(
+    conn: &mut PgConnection,
+    technician_id: i64,
+    skills: &[String],
+    company_name: Option<&str>,
+) -> Result<Option<(String, Vec<String>)>, sqlx::Error> {
+    let previous = sqlx::query!(
+        "SELECT email, skills FROM technician WHERE id = $1 AND ($2::text IS NULL OR company_name = $2) FOR UPDATE",
+        technician_id,
+        company_name
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if previous.is_none() {
+        return Ok(None);
+    }
+
+    sqlx::query!(
+        "UPDATE technician SET skills = $1, updated_at = now() WHERE id = $2",
+        skills,
+        technician_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(previous.map(|row| (row.email, row.skills)))
+}
+
+pub async fn assign_maintenance_request(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+    assign: web::Json<AssignTechnician>,
+) -> impl Responder {
+    if !can_dispatch(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can assign maintenance requests");
+    }
+    let company_name = match resolve_company_scope(&pool, &claims, None).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    let maintenance_id = maintenance_id.into_inner();
+    match assign_and_record(&pool, &req, &claims, maintenance_id, assign.technician_id, company_name.as_deref()).await {
+        Ok(assignment) => HttpResponse::Ok().json(assignment),
+        Err(e) => e.into_response(maintenance_id),
+    }
+}
+
+pub async fn unassign_maintenance_request(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    if !can_dispatch(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can unassign maintenance requests");
+    }
+    let company_name = match resolve_company_scope(&pool, &claims, None).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    let maintenance_id = maintenance_id.into_inner();
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let previous = unassign_maintenance_request_in_database(&mut tx, maintenance_id, company_name.as_deref()).await?;
+        record(
+            &mut tx,
+            &AuditActor::new(&req, &claims),
+            "unassign",
+            "maintenance_request",
+            &maintenance_id.to_string(),
+            Some(serde_json::json!({ "assigned_technician_id": previous })),
+            Some(serde_json::json!({ "assigned_technician_id": null })),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, DispatchError>(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("Maintenance request unassigned"),
+        Err(DispatchError::Database(e)) => {
+            error!("Failed to unassign maintenance request {}: {:?}", maintenance_id, e);
+            HttpResponse::InternalServerError().body("Failed to unassign maintenance request")
+        }
+        Err(e) => e.into_response(maintenance_id),
+    }
+}
+
+/// Auto-dispatches every open request that has no technician, most urgent first.
+pub async fn dispatch_unassigned_requests(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+) -> impl Responder {
+    if !can_dispatch(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can dispatch maintenance requests");
+    }
+    let company_name = match resolve_company_scope(&pool, &claims, None).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    let pending = match fetch_unassigned_open_requests(&pool, company_name.as_deref()).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Failed to fetch unassigned maintenance requests: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch unassigned maintenance requests");
+        }
+    };
+
+    let mut summary = DispatchSummary { assigned: Vec::new(), unassigned: Vec::new() };
+    for maintenance_id in pending {
+        match assign_and_record(&pool, &req, &claims, maintenance_id, None, company_name.as_deref()).await {
+            Ok(assignment) => summary.assigned.push(assignment),
+            Err(DispatchError::Database(e)) => {
+                error!("Failed to dispatch maintenance request {}: {:?}", maintenance_id, e);
+                return HttpResponse::InternalServerError().body("Failed to dispatch maintenance requests");
+            }
+            // Picked up or closed by someone else meanwhile, or nobody qualifies
+            Err(_) => summary.unassigned.push(maintenance_id),
+        }
+    }
+
+    HttpResponse::Ok().json(summary)
+}
+
+pub async fn get_technician_queue(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    technician_id: web::Path<i64>,
+    query: web::Query<QueueQuery>,
+) -> impl Responder {
+    let technician_id = technician_id.into_inner();
+
+    // Sub-admins only see the part of the queue that belongs to their company
+    let company_name = match claims.role() {
+        UserRole::SubAdmin => match resolve_company_scope(&pool, &claims, None).await {
+            Ok(company_name) => company_name,
+            Err(response) => return response,
+        },
+        _ => None,
+    };
+
+    let allowed = match claims.role() {
+        UserRole::SuperAdmin | UserRole::SubAdmin => true,
+        UserRole::Technician => matches!(
+            sqlx::query!("SELECT id FROM technician WHERE email = $1", claims.email())
+                .fetch_optional(pool.get_ref())
+                .await,
+            Ok(Some(row)) if row.id == technician_id
+        ),
+        UserRole::Staff => false,
+    };
+    if !allowed {
+        return HttpResponse::Forbidden().body("Not allowed to view this technician's queue");
+    }
+
+    match fetch_technician_queue(&pool, technician_id, &query, company_name.as_deref()).await {
+        Ok(queue) => HttpResponse::Ok().json(queue),
+        Err(e) => {
+            error!("Failed to fetch technician queue: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch technician queue")
+        }
+    }
+}
+
+pub async fn get_assignment_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    match fetch_assignment_history(&pool, maintenance_id).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch assignment history: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch assignment history")
+        }
+    }
+}
+
+pub async fn update_technician_skills(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    technician_id: web::Path<i64>,
+    update: web::Json<UpdateTechnicianSkills>,
+) -> impl Responder {
+    if !can_dispatch(&claims) {
+        return HttpResponse::Forbidden().body("Only admins can change technician skills");
+    }
+    let company_name = match resolve_company_scope(&pool, &claims, None).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    let technician_id = technician_id.into_inner();
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let updated = update_technician_skills_in_database(&mut tx, technician_id, &update.skills, company_name.as_deref()).await?;
+        if let Some((email, previous)) = &updated {
+            record(
+                &mut tx,
+                &AuditActor::new(&req, &claims),
+                "update",
+                "technician",
+                email,
+                Some(serde_json::json!({ "skills": previous })),
+                Some(serde_json::json!({ "skills": update.skills })),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(updated)
+    }
+    .await;
+
+    match result {
+        Ok(Some(_)) => HttpResponse::Ok().body("Technician skills updated"),
+        Ok(None) => HttpResponse::NotFound().body("Technician not found"),
+        Err(e) => {
+            error!("Failed to update technician skills: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to update technician skills")
+        }
+    }
+}
@@ -1,83 +1,293 @@
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{Connection, FromRow, PgConnection, PgPool};
 use chrono::Utc;
-use actix_web::{web, HttpResponse, Responder};
-use sysinfo::System;
-use rand::Rng;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::error;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::device::devices::{fetch_current_device_for_owner, load_reportable_device, Device};
+use crate::device::snapshot::capture_device_snapshot;
+use crate::functionalities::attachments::fetch_attachment_keys;
+use crate::functionalities::sla::{apply_status_change, refresh_sla_due_dates};
+use crate::functionalities::dispatch::{auto_dispatch_enabled, auto_dispatch_new_request};
+use crate::storage::blob::BlobStore;
+use crate::user::users::{fetch_user_company, UserRole};
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "maintenance_status")]
+pub enum MaintenanceStatus {
+    Pending,
+    Triaged,
+    Ongoing,
+    OnHold,
+    Resolved,
+    Closed,
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "maintenance_priority")]
+pub enum MaintenancePriority {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl MaintenanceStatus {
+    /// Statuses a request may move to next, regardless of who is asking.
+    pub fn next_statuses(self) -> &'static [MaintenanceStatus] {
+        use MaintenanceStatus::*;
+        match self {
+            Pending => &[Triaged, Rejected, Closed],
+            Triaged => &[Ongoing, OnHold, Rejected],
+            Ongoing => &[OnHold, Resolved],
+            OnHold => &[Ongoing, Triaged],
+            Resolved => &[Closed, Ongoing],
+            Closed | Rejected => &[],
+        }
+    }
+
+    /// Whether `role` may move a request from this status to `to`. Admins drive
+    /// the whole workflow, technicians work the request, and reporters can only
+    /// withdraw it, confirm the fix or reopen it.
+    pub fn can_transition(self, to: MaintenanceStatus, role: UserRole) -> bool {
+        use MaintenanceStatus::*;
+        if !self.next_statuses().contains(&to) {
+            return false;
+        }
+        match role {
+            UserRole::SuperAdmin | UserRole::SubAdmin => true,
+            UserRole::Technician => matches!(
+                (self, to),
+                (Triaged, Ongoing) | (Ongoing, OnHold) | (OnHold, Ongoing) | (Ongoing, Resolved)
+            ),
+            UserRole::Staff => matches!(
+                (self, to),
+                (Pending, Closed) | (Resolved, Closed) | (Resolved, Ongoing)
+            ),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct MaintenanceRequest {
-    pub maintenance_id: Option<i32>,
-    pub reported_by_sub_admin_id: Option<i32>,
-    pub reported_by_staff_id: Option<i32>,
+    pub maintenance_id: Option<i64>,
+    // Taken from the signed-in reporter, never from the request body
+    pub reported_by_sub_admin_id: Option<i64>,
+    pub reported_by_staff_id: Option<i64>,
     pub device_name: Option<String>,
     pub title: Option<String>,
     pub description: Option<String>,
-    pub status: Option<String>, // Changed to Option<String>
-    pub priority: Option<String>, // Changed to Option<String>
+    pub status: Option<MaintenanceStatus>,
+    pub priority: Option<MaintenancePriority>,
     pub created_at: Option<chrono::DateTime<Utc>>,
     pub updated_at: Option<chrono::DateTime<Utc>>,
+    pub assigned_technician_id: Option<i64>,
+    pub required_skills: Option<Vec<String>>,
+    // Defaults to the reporter's current device
+    pub device_id: Option<i64>,
+    pub company_name: Option<String>,
+    pub first_response_at: Option<chrono::DateTime<Utc>>,
+    pub resolved_at: Option<chrono::DateTime<Utc>>,
+    pub response_due_at: Option<chrono::DateTime<Utc>>,
+    pub resolve_due_at: Option<chrono::DateTime<Utc>>,
+    // Set on requests raised by a preventive maintenance schedule
+    pub schedule_id: Option<i64>,
 }
 
-fn generate_id() -> i32 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(1..=i32::MAX)
+impl MaintenanceRequest {
+    /// A pending request about `device` raised by the system rather than a reporter.
+    pub fn raised_for_device(
+        device: &Device,
+        title: String,
+        description: Option<String>,
+        priority: MaintenancePriority,
+        required_skills: Vec<String>,
+    ) -> Self {
+        Self {
+            maintenance_id: None,
+            reported_by_sub_admin_id: None,
+            reported_by_staff_id: None,
+            device_name: device.hostname.clone(),
+            title: Some(title),
+            description,
+            status: Some(MaintenanceStatus::Pending),
+            priority: Some(priority),
+            created_at: Some(Utc::now()),
+            updated_at: None,
+            assigned_technician_id: None,
+            required_skills: Some(required_skills),
+            device_id: Some(device.device_id),
+            company_name: device.company_name.clone(),
+            first_response_at: None,
+            resolved_at: None,
+            response_due_at: None,
+            resolve_due_at: None,
+            schedule_id: None,
+        }
+    }
 }
 
 pub async fn create_maintenance_request(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
+    claims: Claims,
     request: web::Json<MaintenanceRequest>,
 ) -> impl Responder {
     let new_request = request.into_inner();
 
-    let device_name = System::host_name().unwrap_or_else(|| "Unknown".to_string());
-    let maintenance_id = new_request.maintenance_id.unwrap_or_else(generate_id);
+    let device = match new_request.device_id {
+        Some(device_id) => match load_reportable_device(&pool, &claims, device_id).await {
+            Ok(device) => Some(device),
+            Err(response) => return response,
+        },
+        None => fetch_current_device_for_owner(&pool, claims.email())
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to look up device of {}: {:?}", claims.email(), e);
+                None
+            }),
+    };
+
+    // SLAs follow the company the machine belongs to, else the reporter's
+    let company_name = match device.as_ref().and_then(|device| device.company_name.clone()) {
+        Some(company_name) => Some(company_name),
+        None => fetch_user_company(&pool, &claims).await.unwrap_or_else(|e| {
+            error!("Failed to fetch company of {}: {:?}", claims.email(), e);
+            None
+        }),
+    };
+
+    let (reported_by_sub_admin_id, reported_by_staff_id) = match fetch_reporter_ids(pool.get_ref(), &claims).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("Failed to look up reporter {}: {:?}", claims.email(), e);
+            return HttpResponse::InternalServerError().body("Failed to create maintenance request");
+        }
+    };
+
+    // Freeze the machine's state as it was when the problem was reported
+    let device_snapshot = match &device {
+        Some(device) => snapshot_device_for_request(&pool, device.device_id).await,
+        None => None,
+    };
 
-    let maintenance_request = MaintenanceRequest {
-        maintenance_id: Some(maintenance_id),
-        reported_by_sub_admin_id: new_request.reported_by_sub_admin_id,
-        reported_by_staff_id: new_request.reported_by_staff_id,
-        device_name: Some(device_name),
+    let mut maintenance_request = MaintenanceRequest {
+        maintenance_id: None,
+        reported_by_sub_admin_id,
+        reported_by_staff_id,
+        device_name: device.as_ref().and_then(|device| device.hostname.clone()).or(new_request.device_name),
         title: new_request.title,
         description: new_request.description,
-        status: Some("Pending".to_string()), // Default status as string
-        priority: Some("Medium".to_string()), // Default priority as string
+        status: Some(MaintenanceStatus::Pending),
+        priority: Some(new_request.priority.unwrap_or(MaintenancePriority::Medium)),
         created_at: Some(Utc::now()),
         updated_at: None,
+        assigned_technician_id: None,
+        required_skills: Some(new_request.required_skills.unwrap_or_default()),
+        device_id: device.as_ref().map(|device| device.device_id),
+        company_name,
+        first_response_at: None,
+        resolved_at: None,
+        response_due_at: None,
+        resolve_due_at: None,
+        schedule_id: None,
     };
 
-    match save_maintenance_request_to_database(&pool, &maintenance_request).await {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let maintenance_id = open_maintenance_request(&mut tx, &mut maintenance_request, device_snapshot, Some(&claims), None).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "maintenance_request", &maintenance_id.to_string(), None, snapshot(&maintenance_request)).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
         Ok(_) => HttpResponse::Created().json(maintenance_request),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to create maintenance request"),
+        Err(e) => {
+            error!("Failed to create maintenance request: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to create maintenance request")
+        }
     }
 }
 
-async fn save_maintenance_request_to_database(
-    pool: &PgPool,
+/// The device's current metrics as stored with a new request. Failing to take
+/// one does not stop the request from being filed.
+pub async fn snapshot_device_for_request(pool: &PgPool, device_id: i64) -> Option<serde_json::Value> {
+    match capture_device_snapshot(pool, device_id).await {
+        Ok(snapshot) => serde_json::to_value(snapshot).ok(),
+        Err(e) => {
+            error!("Failed to capture snapshot of device {}: {:?}", device_id, e);
+            None
+        }
+    }
+}
+
+/// Saves a new request and starts its workflow: the initial history entry,
+/// SLA due dates and, when enabled, auto-dispatch. `request` is updated with
+/// what was filled in along the way. `reason` explains requests nobody filed.
+/// Run it in a transaction so a request is never left half opened.
+pub async fn open_maintenance_request(
+    conn: &mut PgConnection,
+    request: &mut MaintenanceRequest,
+    device_snapshot: Option<serde_json::Value>,
+    actor: Option<&Claims>,
+    reason: Option<String>,
+) -> Result<i64, sqlx::Error> {
+    let maintenance_id = save_maintenance_request_to_database(&mut *conn, request, device_snapshot).await?;
+    request.maintenance_id = Some(maintenance_id);
+
+    let entry = StatusChange {
+        maintenance_id,
+        from_status: None,
+        to_status: MaintenanceStatus::Pending,
+        actor_email: actor.map(|c| c.email().to_string()),
+        actor_role: actor.map(|c| format!("{:?}", c.role())),
+        reason,
+    };
+    save_status_change_to_database(&mut *conn, &entry).await?;
+    (request.response_due_at, request.resolve_due_at) = refresh_sla_due_dates(&mut *conn, maintenance_id).await?;
+    if auto_dispatch_enabled() {
+        request.assigned_technician_id = auto_dispatch_new_request(conn, maintenance_id).await;
+    }
+    Ok(maintenance_id)
+}
+
+async fn save_maintenance_request_to_database<'e, E>(
+    executor: E,
     request: &MaintenanceRequest,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "INSERT INTO maintenance_requests (maintenance_id, reported_by_sub_admin_id, reported_by_staff_id, device_name, title, description, status, priority, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
-        request.maintenance_id,
+    device_snapshot: Option<serde_json::Value>,
+) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        "INSERT INTO maintenance_requests (reported_by_sub_admin_id, reported_by_staff_id, device_name, title, description, status, priority, created_at, updated_at, required_skills, device_id, device_snapshot, company_name, schedule_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, COALESCE($10, '{}'::text[]), $11, $12, $13, $14) RETURNING maintenance_id",
         request.reported_by_sub_admin_id,
         request.reported_by_staff_id,
         request.device_name,
         request.title,
         request.description,
-        request.status, 
-        request.priority,
+        request.status as Option<MaintenanceStatus>,
+        request.priority as Option<MaintenancePriority>,
         request.created_at,
         request.updated_at,
+        request.required_skills.as_deref(),
+        request.device_id,
+        device_snapshot,
+        request.company_name,
+        request.schedule_id,
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await?;
 
-    Ok(())
+    Ok(row.maintenance_id)
 }
 
 pub async fn get_user_maintenance_requests(
     pool: web::Data<PgPool>,
-    reported_by_id: web::Path<i32>,
+    reported_by_id: web::Path<i64>,
 ) -> impl Responder {
     let reported_by_id = reported_by_id.into_inner();
     match fetch_maintenance_requests_by_user(&pool, reported_by_id).await {
@@ -88,11 +298,11 @@ pub async fn get_user_maintenance_requests(
 
 pub async fn fetch_maintenance_requests_by_user(
     pool: &PgPool,
-    reported_by_id: i32,
+    reported_by_id: i64,
 ) -> Result<Vec<MaintenanceRequest>, sqlx::Error> {
     let requests = sqlx::query_as!(
         MaintenanceRequest,
-        "SELECT * FROM maintenance_requests WHERE reported_by_sub_admin_id = $1 OR reported_by_staff_id = $1",
+        r#"SELECT maintenance_id, reported_by_sub_admin_id, reported_by_staff_id, device_name, title, description, status as "status: MaintenanceStatus", priority as "priority: MaintenancePriority", created_at, updated_at, assigned_technician_id, required_skills, device_id, company_name, first_response_at, resolved_at, response_due_at, resolve_due_at, schedule_id FROM maintenance_requests WHERE reported_by_sub_admin_id = $1 OR reported_by_staff_id = $1"#,
         reported_by_id
     )
     .fetch_all(pool)
@@ -103,27 +313,30 @@ pub async fn fetch_maintenance_requests_by_user(
 
 pub async fn get_user_specific_maintenance_request(
     pool: web::Data<PgPool>,
-    path: web::Path<(i32, i32)>,
+    path: web::Path<(i64, i64)>,
 ) -> impl Responder {
     let (reported_by_id, maintenance_id) = path.into_inner();
-    match fetch_specific_maintenance_request_by_user(&pool, reported_by_id, maintenance_id).await {
+    match fetch_specific_maintenance_request_by_user(pool.get_ref(), reported_by_id, maintenance_id).await {
         Ok(request) => HttpResponse::Ok().json(request),
         Err(_) => HttpResponse::NotFound().body("Maintenance request not found"),
     }
 }
 
-async fn fetch_specific_maintenance_request_by_user(
-    pool: &PgPool,
-    reported_by_id: i32,
-    maintenance_id: i32,
-) -> Result<MaintenanceRequest, sqlx::Error> {
+async fn fetch_specific_maintenance_request_by_user<'e, E>(
+    executor: E,
+    reported_by_id: i64,
+    maintenance_id: i64,
+) -> Result<MaintenanceRequest, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     let request = sqlx::query_as!(
         MaintenanceRequest,
-        "SELECT * FROM maintenance_requests WHERE (reported_by_sub_admin_id = $1 OR reported_by_staff_id = $1) AND maintenance_id = $2",
+        r#"SELECT maintenance_id, reported_by_sub_admin_id, reported_by_staff_id, device_name, title, description, status as "status: MaintenanceStatus", priority as "priority: MaintenancePriority", created_at, updated_at, assigned_technician_id, required_skills, device_id, company_name, first_response_at, resolved_at, response_due_at, resolve_due_at, schedule_id FROM maintenance_requests WHERE (reported_by_sub_admin_id = $1 OR reported_by_staff_id = $1) AND maintenance_id = $2"#,
         reported_by_id,
         maintenance_id
     )
-    .fetch_one(pool)
+    .fetch_one(executor)
     .await?;
 
     Ok(request)
@@ -133,72 +346,141 @@ async fn fetch_specific_maintenance_request_by_user(
 pub struct UpdateMaintenanceRequest {
     pub title: Option<String>,
     pub description: Option<String>,
-    pub status: Option<String>,
-    pub priority: Option<String>,
+    // Rejected here; status only changes through the transition endpoint
+    pub status: Option<MaintenanceStatus>,
+    pub priority: Option<MaintenancePriority>,
     pub updated_at: Option<chrono::DateTime<Utc>>,
 }
 
 pub async fn update_maintenance_request(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
-    path: web::Path<(i32, i32)>,
+    claims: Claims,
+    path: web::Path<(i64, i64)>,
     update_request: web::Json<UpdateMaintenanceRequest>,
 ) -> impl Responder {
     let (reported_by_id, maintenance_id) = path.into_inner();
     let update_request = update_request.into_inner();
+    if update_request.status.is_some() {
+        return HttpResponse::BadRequest().body("Use /maintenance/{maintenance_id}/transition to change the status");
+    }
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
     let updated_at = Some(Utc::now());
 
-    match update_maintenance_request_in_database(&pool, reported_by_id, maintenance_id, &update_request, updated_at).await {
-        Ok(_) => HttpResponse::Ok().body("Maintenance request updated"),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to update maintenance request"),
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let before = match fetch_specific_maintenance_request_by_user(&mut *tx, reported_by_id, maintenance_id).await {
+            Ok(before) => before,
+            Err(sqlx::Error::RowNotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        update_maintenance_request_in_database(&mut *tx, reported_by_id, maintenance_id, &update_request, updated_at).await?;
+        if update_request.priority.is_some() {
+            refresh_sla_due_dates(&mut tx, maintenance_id).await?;
+        }
+        let after = fetch_specific_maintenance_request_by_user(&mut *tx, reported_by_id, maintenance_id).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "update", "maintenance_request", &maintenance_id.to_string(), snapshot(&before), snapshot(&after)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().body("Maintenance request updated"),
+        Ok(false) => HttpResponse::NotFound().body("Maintenance request not found"),
+        Err(e) => {
+            error!("Failed to update maintenance request {}: {:?}", maintenance_id, e);
+            HttpResponse::InternalServerError().body("Failed to update maintenance request")
+        }
     }
 }
 
-async fn update_maintenance_request_in_database(
-    pool: &PgPool,
-    reported_by_id: i32,
-    maintenance_id: i32,
+async fn update_maintenance_request_in_database<'e, E>(
+    executor: E,
+    reported_by_id: i64,
+    maintenance_id: i64,
     update_request: &UpdateMaintenanceRequest,
     updated_at: Option<chrono::DateTime<Utc>>,
-) -> Result<(), sqlx::Error> {
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     sqlx::query!(
-        "UPDATE maintenance_requests SET title = COALESCE($1, title), description = COALESCE($2, description), status = COALESCE($3, status), priority = COALESCE($4, priority), updated_at = $5 WHERE (reported_by_sub_admin_id = $6 OR reported_by_staff_id = $6) AND maintenance_id = $7",
+        "UPDATE maintenance_requests SET title = COALESCE($1, title), description = COALESCE($2, description), priority = COALESCE($3, priority), updated_at = $4 WHERE (reported_by_sub_admin_id = $5 OR reported_by_staff_id = $5) AND maintenance_id = $6",
         update_request.title,
         update_request.description,
-        update_request.status,
-        update_request.priority,
+        update_request.priority as Option<MaintenancePriority>,
         updated_at,
         reported_by_id,
         maintenance_id,
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
 pub async fn delete_maintenance_request(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
-    path: web::Path<(i32, i32)>,
+    store: web::Data<dyn BlobStore>,
+    claims: Claims,
+    path: web::Path<(i64, i64)>,
 ) -> impl Responder {
     let (reported_by_id, maintenance_id) = path.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let before = match fetch_specific_maintenance_request_by_user(&mut *tx, reported_by_id, maintenance_id).await {
+            Ok(before) => before,
+            Err(sqlx::Error::RowNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // Attachment rows go with the request; their blobs have to be removed by hand
+        let attachment_keys = fetch_attachment_keys(&mut *tx, maintenance_id).await?;
+        delete_maintenance_request_from_database(&mut *tx, reported_by_id, maintenance_id).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "delete", "maintenance_request", &maintenance_id.to_string(), snapshot(&before), None).await?;
+        tx.commit().await?;
+        Ok(Some(attachment_keys))
+    }
+    .await;
 
-    match delete_maintenance_request_from_database(&pool, reported_by_id, maintenance_id).await {
-        Ok(_) => HttpResponse::Ok().body("Maintenance request deleted"),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to delete maintenance request"),
+    match result {
+        Ok(Some(attachment_keys)) => {
+            for key in attachment_keys {
+                if let Err(e) = store.delete(&key).await {
+                    error!("Failed to remove attachment {}: {:?}", key, e);
+                }
+            }
+            HttpResponse::Ok().body("Maintenance request deleted")
+        }
+        Ok(None) => HttpResponse::NotFound().body("Maintenance request not found"),
+        Err(e) => {
+            error!("Failed to delete maintenance request {}: {:?}", maintenance_id, e);
+            HttpResponse::InternalServerError().body("Failed to delete maintenance request")
+        }
     }
 }
 
-async fn delete_maintenance_request_from_database(
-    pool: &PgPool,
-    reported_by_id: i32,
-    maintenance_id: i32,
-) -> Result<(), sqlx::Error> {
+async fn delete_maintenance_request_from_database<'e, E>(
+    executor: E,
+    reported_by_id: i64,
+    maintenance_id: i64,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     sqlx::query!(
         "DELETE FROM maintenance_requests WHERE (reported_by_sub_admin_id = $1 OR reported_by_staff_id = $1) AND maintenance_id = $2",
         reported_by_id,
         maintenance_id
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(())
@@ -206,7 +488,7 @@ async fn delete_maintenance_request_from_database(
 
 pub async fn count_ongoing_maintenance_requests(
     pool: &PgPool,
-    reported_by_id: i32,
+    reported_by_id: i64,
 ) -> Result<i64, sqlx::Error> {
     let count = sqlx::query!(
         "SELECT COUNT(*) as count FROM maintenance_requests WHERE (reported_by_sub_admin_id = $1 OR reported_by_staff_id = $1) AND status = 'Ongoing'",
@@ -221,7 +503,7 @@ pub async fn count_ongoing_maintenance_requests(
 
 pub async fn get_ongoing_maintenance_count(
     pool: web::Data<PgPool>,
-    reported_by_id: web::Path<i32>,
+    reported_by_id: web::Path<i64>,
 ) -> impl Responder {
     let reported_by_id = reported_by_id.into_inner();
 
@@ -230,3 +512,357 @@ pub async fn get_ongoing_maintenance_count(
         Err(_) => HttpResponse::InternalServerError().body("Failed to count ongoing maintenance requests"),
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct TransitionRequest {
+    pub status: MaintenanceStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct StatusHistoryEntry {
+    pub id: i64,
+    pub maintenance_id: i64,
+    pub from_status: Option<MaintenanceStatus>,
+    pub to_status: MaintenanceStatus,
+    pub actor_email: Option<String>,
+    pub actor_role: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+struct StatusChange {
+    maintenance_id: i64,
+    from_status: Option<MaintenanceStatus>,
+    to_status: MaintenanceStatus,
+    actor_email: Option<String>,
+    actor_role: Option<String>,
+    reason: Option<String>,
+}
+
+enum TransitionError {
+    NotFound,
+    // The caller may not act on this request at all
+    Forbidden,
+    NotAllowed(MaintenanceStatus),
+    Database(sqlx::Error),
+}
+
+// The people a request belongs to, for checking who may see or act on it
+struct RequestParties {
+    company_name: Option<String>,
+    reported_by_sub_admin_id: Option<i64>,
+    reported_by_staff_id: Option<i64>,
+    assigned_technician_id: Option<i64>,
+}
+
+/// Super admins may see and act on every request, sub-admins on those of
+/// their company or that they reported, staff on those they reported and
+/// technicians on those assigned to them.
+async fn may_access_request<'e, E>(executor: E, claims: &Claims, request: &RequestParties) -> Result<bool, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    match claims.role() {
+        UserRole::SuperAdmin => Ok(true),
+        UserRole::SubAdmin => Ok(sqlx::query!("SELECT id, company_name FROM sub_admin WHERE email = $1", claims.email())
+            .fetch_optional(executor)
+            .await?
+            .is_some_and(|sub_admin| {
+                request.reported_by_sub_admin_id == Some(sub_admin.id)
+                    || (sub_admin.company_name.is_some() && sub_admin.company_name == request.company_name)
+            })),
+        UserRole::Staff => Ok(sqlx::query!("SELECT id FROM staff WHERE email = $1", claims.email())
+            .fetch_optional(executor)
+            .await?
+            .is_some_and(|staff| request.reported_by_staff_id == Some(staff.id))),
+        UserRole::Technician => Ok(sqlx::query!("SELECT id FROM technician WHERE email = $1", claims.email())
+            .fetch_optional(executor)
+            .await?
+            .is_some_and(|technician| request.assigned_technician_id == Some(technician.id))),
+    }
+}
+
+/// The sub-admin or staff row of the caller, recorded as a request's reporter.
+async fn fetch_reporter_ids<'e, E>(executor: E, claims: &Claims) -> Result<(Option<i64>, Option<i64>), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    match claims.role() {
+        UserRole::SubAdmin => Ok((
+            sqlx::query_scalar!("SELECT id FROM sub_admin WHERE email = $1", claims.email())
+                .fetch_optional(executor)
+                .await?,
+            None,
+        )),
+        UserRole::Staff => Ok((
+            None,
+            sqlx::query_scalar!("SELECT id FROM staff WHERE email = $1", claims.email())
+                .fetch_optional(executor)
+                .await?,
+        )),
+        UserRole::SuperAdmin | UserRole::Technician => Ok((None, None)),
+    }
+}
+
+impl From<sqlx::Error> for TransitionError {
+    fn from(e: sqlx::Error) -> Self {
+        TransitionError::Database(e)
+    }
+}
+
+async fn save_status_change_to_database<'e, E>(executor: E, change: &StatusChange) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "INSERT INTO maintenance_status_history (maintenance_id, from_status, to_status, actor_email, actor_role, reason) VALUES ($1, $2, $3, $4, $5, $6)",
+        change.maintenance_id,
+        change.from_status as Option<MaintenanceStatus>,
+        change.to_status as MaintenanceStatus,
+        change.actor_email,
+        change.actor_role,
+        change.reason,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Moves a request to `change.to_status` if the actor may act on it and the
+/// graph and their role allow the move, returning the status it left. No
+/// actor means the system, which may do what a super admin can. The row is
+/// locked so concurrent transitions are checked against the request as it is
+/// when they replace it.
+async fn transition_maintenance_request_in_database(
+    conn: &mut PgConnection,
+    mut change: StatusChange,
+    actor: Option<&Claims>,
+) -> Result<MaintenanceStatus, TransitionError> {
+    let mut tx = conn.begin().await?;
+
+    let request = sqlx::query!(
+        r#"SELECT status as "status: MaintenanceStatus", company_name, reported_by_sub_admin_id, reported_by_staff_id, assigned_technician_id
+        FROM maintenance_requests WHERE maintenance_id = $1 FOR UPDATE"#,
+        change.maintenance_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(TransitionError::NotFound)?;
+    let current = request.status;
+
+    if let Some(claims) = actor {
+        let parties = RequestParties {
+            company_name: request.company_name,
+            reported_by_sub_admin_id: request.reported_by_sub_admin_id,
+            reported_by_staff_id: request.reported_by_staff_id,
+            assigned_technician_id: request.assigned_technician_id,
+        };
+        if !may_access_request(&mut *tx, claims, &parties).await? {
+            return Err(TransitionError::Forbidden);
+        }
+    }
+
+    let role = actor.map_or(UserRole::SuperAdmin, |claims| claims.role());
+    if !current.can_transition(change.to_status, role) {
+        return Err(TransitionError::NotAllowed(current));
+    }
+
+    sqlx::query!(
+        "UPDATE maintenance_requests SET status = $1, updated_at = now() WHERE maintenance_id = $2",
+        change.to_status as MaintenanceStatus,
+        change.maintenance_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    change.from_status = Some(current);
+    save_status_change_to_database(&mut *tx, &change).await?;
+    tx.commit().await?;
+
+    Ok(current)
+}
+
+/// Wraps up a request the system raised once its cause has gone away: work in
+/// progress is resolved and untouched requests are closed. Requests someone has
+/// triaged or put on hold are left for a person to finish. Returns the status
+/// the request moved to, if any.
+pub async fn settle_maintenance_request(pool: &PgPool, maintenance_id: i64, reason: String) -> Result<Option<MaintenanceStatus>, sqlx::Error> {
+    let current = sqlx::query!(
+        r#"SELECT status as "status: MaintenanceStatus" FROM maintenance_requests WHERE maintenance_id = $1"#,
+        maintenance_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let to = match current.map(|row| row.status) {
+        Some(MaintenanceStatus::Ongoing) => MaintenanceStatus::Resolved,
+        Some(MaintenanceStatus::Pending) => MaintenanceStatus::Closed,
+        _ => return Ok(None),
+    };
+    let change = StatusChange {
+        maintenance_id,
+        from_status: None,
+        to_status: to,
+        actor_email: None,
+        actor_role: None,
+        reason: Some(reason),
+    };
+
+    let mut tx = pool.begin().await?;
+    match transition_maintenance_request_in_database(&mut tx, change, None).await {
+        Ok(from) => {
+            apply_status_change(&mut tx, maintenance_id, from, to, None).await?;
+            tx.commit().await?;
+            Ok(Some(to))
+        }
+        // Someone moved it in the meantime
+        Err(TransitionError::NotFound) | Err(TransitionError::Forbidden) | Err(TransitionError::NotAllowed(_)) => Ok(None),
+        Err(TransitionError::Database(e)) => Err(e),
+    }
+}
+
+pub async fn transition_maintenance_request(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+    transition: web::Json<TransitionRequest>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    let transition = transition.into_inner();
+    let change = StatusChange {
+        maintenance_id,
+        from_status: None,
+        to_status: transition.status,
+        actor_email: Some(claims.email().to_string()),
+        actor_role: Some(format!("{:?}", claims.role())),
+        reason: transition.reason,
+    };
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let from = transition_maintenance_request_in_database(&mut tx, change, Some(&claims)).await?;
+        apply_status_change(&mut tx, maintenance_id, from, transition.status, Some(claims.role())).await?;
+        record(
+            &mut tx,
+            &AuditActor::new(&req, &claims),
+            "transition",
+            "maintenance_request",
+            &maintenance_id.to_string(),
+            Some(serde_json::json!({ "status": from })),
+            Some(serde_json::json!({ "status": transition.status })),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, TransitionError>(from)
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("Maintenance request status updated"),
+        Err(TransitionError::NotFound) => HttpResponse::NotFound().body("Maintenance request not found"),
+        Err(TransitionError::Forbidden) => HttpResponse::Forbidden().body("Not allowed to act on this maintenance request"),
+        Err(TransitionError::NotAllowed(from)) => HttpResponse::Conflict().body(format!(
+            "A {:?} cannot move a maintenance request from {:?} to {:?}",
+            claims.role(),
+            from,
+            transition.status
+        )),
+        Err(TransitionError::Database(e)) => {
+            error!("Failed to transition maintenance request {}: {:?}", maintenance_id, e);
+            HttpResponse::InternalServerError().body("Failed to update maintenance request status")
+        }
+    }
+}
+
+async fn fetch_status_history(pool: &PgPool, maintenance_id: i64) -> Result<Vec<StatusHistoryEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        StatusHistoryEntry,
+        r#"SELECT id, maintenance_id, from_status as "from_status: MaintenanceStatus", to_status as "to_status: MaintenanceStatus", actor_email, actor_role, reason, created_at
+        FROM maintenance_status_history WHERE maintenance_id = $1 ORDER BY created_at, id"#,
+        maintenance_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_maintenance_status_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    match fetch_status_history(&pool, maintenance_id).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch maintenance status history: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance status history")
+        }
+    }
+}
+
+/// Lets handlers for things hanging off a request (comments, attachments)
+/// check the caller may see it, as [`may_access_request`] decides.
+pub async fn load_visible_maintenance_request(
+    pool: &PgPool,
+    claims: &Claims,
+    maintenance_id: i64,
+) -> Result<(), HttpResponse> {
+    let request = sqlx::query_as!(
+        RequestParties,
+        "SELECT company_name, reported_by_sub_admin_id, reported_by_staff_id, assigned_technician_id FROM maintenance_requests WHERE maintenance_id = $1",
+        maintenance_id
+    )
+    .fetch_optional(pool)
+    .await;
+
+    let request = match request {
+        Ok(Some(request)) => request,
+        Ok(None) => return Err(HttpResponse::NotFound().body("Maintenance request not found")),
+        Err(e) => {
+            error!("Failed to fetch maintenance request {}: {:?}", maintenance_id, e);
+            return Err(HttpResponse::InternalServerError().body("Failed to fetch maintenance request"));
+        }
+    };
+
+    match may_access_request(pool, claims, &request).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(HttpResponse::Forbidden().body("Not allowed to view this maintenance request")),
+        Err(e) => {
+            error!("Failed to check access of {} to maintenance request {}: {:?}", claims.email(), maintenance_id, e);
+            Err(HttpResponse::InternalServerError().body("Failed to fetch maintenance request"))
+        }
+    }
+}
+
+pub async fn get_maintenance_device_snapshot(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    maintenance_id: web::Path<i64>,
+) -> impl Responder {
+    let maintenance_id = maintenance_id.into_inner();
+    if let Err(response) = load_visible_maintenance_request(&pool, &claims, maintenance_id).await {
+        return response;
+    }
+
+    match sqlx::query_scalar!(
+        "SELECT device_snapshot FROM maintenance_requests WHERE maintenance_id = $1",
+        maintenance_id
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    {
+        Ok(Some(snapshot)) => HttpResponse::Ok().json(snapshot),
+        Ok(None) => HttpResponse::NotFound().body("No device snapshot was taken for this request"),
+        Err(e) => {
+            error!("Failed to fetch device snapshot of maintenance request {}: {:?}", maintenance_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch device snapshot")
+        }
+    }
+}
@@ -1,2 +1,12 @@
 pub mod maintenance;
-pub mod assign;
\ No newline at end of file
+pub mod assign;
+pub mod dispatch;
+pub mod comments;
+pub mod attachments;
+pub mod sla;
+pub mod analytics;
+pub mod checklist;
+pub mod schedules;
+pub mod alerts;
+pub mod acknowledgements;
+pub mod process_policies;
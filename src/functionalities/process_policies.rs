@@ -0,0 +1,560 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use sha2::{Digest, Sha256};
+use log::{error, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::device::devices::fetch_device_by_id;
+use crate::functionalities::maintenance::{
+    open_maintenance_request, snapshot_device_for_request, MaintenancePriority, MaintenanceRequest,
+};
+use crate::metrics::software::process::{ProcessMetrics, ProcessSnapshot};
+use crate::user::users::resolve_company_scope;
+
+const MATCH_FIELDS: [&str; 3] = ["name", "exe", "sha256"];
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ProcessPolicy {
+    pub id: i64,
+    pub company_name: String,
+    pub name: String,
+    pub effect: String,
+    pub match_field: String,
+    pub pattern: String,
+    pub raise_request: bool,
+    pub priority: MaintenancePriority,
+    pub required_skills: Vec<String>,
+    pub active: bool,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewProcessPolicy {
+    pub company_name: Option<String>,
+    pub name: String,
+    pub effect: String,
+    pub match_field: String,
+    pub pattern: String,
+    pub raise_request: Option<bool>,
+    pub priority: Option<MaintenancePriority>,
+    #[serde(default)]
+    pub required_skills: Vec<String>,
+    pub active: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ProcessPolicyViolation {
+    pub id: i64,
+    pub policy_id: i64,
+    pub device_id: i64,
+    pub process_name: String,
+    pub exe: String,
+    pub sha256: Option<String>,
+    pub pid: Option<i32>,
+    pub user_name: Option<String>,
+    pub cmdline: Option<String>,
+    pub first_snapshot_id: Option<i64>,
+    pub last_snapshot_id: Option<i64>,
+    pub detected_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub maintenance_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProcessPolicyQuery {
+    pub company_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViolationQuery {
+    pub company_name: Option<String>,
+    pub device_id: Option<i64>,
+    pub policy_id: Option<i64>,
+    /// Only violations still running (true) or already gone (false)
+    pub open: Option<bool>,
+}
+
+/// Case-insensitive glob over the whole text: `*` is any run, `?` one character.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and how much text it has swallowed so far
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    t = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl ProcessPolicy {
+    fn matches(&self, process: &ProcessMetrics, sha256: Option<&str>) -> bool {
+        match self.match_field.as_str() {
+            "name" => process.name.as_deref().is_some_and(|name| glob_matches(&self.pattern, name)),
+            "exe" => process.exe.as_deref().is_some_and(|exe| glob_matches(&self.pattern, exe)),
+            "sha256" => sha256.is_some_and(|sha256| sha256.eq_ignore_ascii_case(&self.pattern)),
+            _ => false,
+        }
+    }
+}
+
+fn validate_process_policy(policy: &NewProcessPolicy) -> Result<(), String> {
+    if policy.name.trim().is_empty() {
+        return Err("Process policies need a name".to_string());
+    }
+    if !matches!(policy.effect.as_str(), "allow" | "deny") {
+        return Err("effect must be allow or deny".to_string());
+    }
+    if !MATCH_FIELDS.contains(&policy.match_field.as_str()) {
+        return Err(format!("match_field must be one of {}", MATCH_FIELDS.join(", ")));
+    }
+    if policy.pattern.trim().is_empty() {
+        return Err("pattern is required".to_string());
+    }
+    if policy.match_field == "sha256" && !(policy.pattern.len() == 64 && policy.pattern.chars().all(|c| c.is_ascii_hexdigit())) {
+        return Err("sha256 patterns must be a 64 character hex digest".to_string());
+    }
+    if policy.effect == "allow" && policy.raise_request == Some(true) {
+        return Err("Only deny policies can raise maintenance requests".to_string());
+    }
+    Ok(())
+}
+
+async fn fetch_process_policy(pool: &PgPool, policy_id: i64) -> Result<Option<ProcessPolicy>, sqlx::Error> {
+    sqlx::query_as!(
+        ProcessPolicy,
+        r#"SELECT id, company_name, name, effect, match_field, pattern, raise_request,
+            priority as "priority: MaintenancePriority", required_skills, active, created_by, created_at, updated_at
+        FROM process_policies WHERE id = $1"#,
+        policy_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_process_policies(pool: &PgPool, company_name: Option<&str>, active_only: bool) -> Result<Vec<ProcessPolicy>, sqlx::Error> {
+    sqlx::query_as!(
+        ProcessPolicy,
+        r#"SELECT id, company_name, name, effect, match_field, pattern, raise_request,
+            priority as "priority: MaintenancePriority", required_skills, active, created_by, created_at, updated_at
+        FROM process_policies
+        WHERE ($1::text IS NULL OR company_name = $1) AND (active OR NOT $2)
+        ORDER BY company_name, id"#,
+        company_name,
+        active_only,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn save_process_policy_to_database<'e, E>(
+    executor: E,
+    company_name: &str,
+    policy: &NewProcessPolicy,
+    created_by: &str,
+) -> Result<ProcessPolicy, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        ProcessPolicy,
+        r#"INSERT INTO process_policies
+            (company_name, name, effect, match_field, pattern, raise_request, priority, required_skills, active, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING id, company_name, name, effect, match_field, pattern, raise_request,
+            priority as "priority: MaintenancePriority", required_skills, active, created_by, created_at, updated_at"#,
+        company_name,
+        policy.name.trim(),
+        policy.effect,
+        policy.match_field,
+        policy.pattern.trim(),
+        policy.raise_request.unwrap_or(false),
+        policy.priority.unwrap_or(MaintenancePriority::High) as MaintenancePriority,
+        &policy.required_skills,
+        policy.active.unwrap_or(true),
+        created_by,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+async fn update_process_policy_in_database<'e, E>(executor: E, policy_id: i64, policy: &NewProcessPolicy) -> Result<Option<ProcessPolicy>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        ProcessPolicy,
+        r#"UPDATE process_policies SET
+            name = $2, effect = $3, match_field = $4, pattern = $5, raise_request = $6, priority = $7,
+            required_skills = $8, active = $9, updated_at = now()
+        WHERE id = $1
+        RETURNING id, company_name, name, effect, match_field, pattern, raise_request,
+            priority as "priority: MaintenancePriority", required_skills, active, created_by, created_at, updated_at"#,
+        policy_id,
+        policy.name.trim(),
+        policy.effect,
+        policy.match_field,
+        policy.pattern.trim(),
+        policy.raise_request.unwrap_or(false),
+        policy.priority.unwrap_or(MaintenancePriority::High) as MaintenancePriority,
+        &policy.required_skills,
+        policy.active.unwrap_or(true),
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Executable path -> (size, modified, sha256). A binary is read again only
+// once its size or modification time changes.
+type HashCache = Mutex<HashMap<String, (u64, SystemTime, String)>>;
+static EXECUTABLE_HASHES: OnceLock<HashCache> = OnceLock::new();
+
+async fn hash_executable(exe: &str) -> Option<String> {
+    let metadata = tokio::fs::metadata(exe).await.ok()?;
+    let (size, modified) = (metadata.len(), metadata.modified().ok()?);
+    let cache = EXECUTABLE_HASHES.get_or_init(Default::default);
+    if let Some((_, _, sha256)) = cache
+        .lock()
+        .ok()?
+        .get(exe)
+        .filter(|(cached_size, cached_modified, _)| (*cached_size, *cached_modified) == (size, modified))
+    {
+        return Some(sha256.clone());
+    }
+
+    let path = exe.to_string();
+    let sha256 = tokio::task::spawn_blocking(move || -> std::io::Result<String> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .ok()?
+    .ok()?;
+    cache.lock().ok()?.insert(exe.to_string(), (size, modified, sha256.clone()));
+    Some(sha256)
+}
+
+/// Records that `process` breaks `policy`. Returns the violation when this
+/// snapshot is the first to show it.
+async fn record_violation(
+    pool: &PgPool,
+    policy: &ProcessPolicy,
+    snapshot: &ProcessSnapshot,
+    process: &ProcessMetrics,
+    sha256: Option<&str>,
+) -> Result<Option<ProcessPolicyViolation>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"INSERT INTO process_policy_violations
+            (policy_id, device_id, process_name, exe, sha256, pid, user_name, cmdline, first_snapshot_id, last_snapshot_id, detected_at, last_seen_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9, $10, $10)
+        ON CONFLICT (policy_id, device_id, process_name, exe) WHERE resolved_at IS NULL
+        DO UPDATE SET last_snapshot_id = EXCLUDED.last_snapshot_id, last_seen_at = EXCLUDED.last_seen_at, pid = EXCLUDED.pid,
+            sha256 = COALESCE(EXCLUDED.sha256, process_policy_violations.sha256)
+        RETURNING id, (xmax = 0) as "inserted!""#,
+        policy.id,
+        snapshot.device_id,
+        process.name.as_deref().unwrap_or_default(),
+        process.exe.as_deref().unwrap_or_default(),
+        sha256,
+        process.pid,
+        process.user_name,
+        process.cmdline,
+        snapshot.id,
+        snapshot.captured_at,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if !row.inserted {
+        return Ok(None);
+    }
+    sqlx::query_as!(
+        ProcessPolicyViolation,
+        "SELECT id, policy_id, device_id, process_name, exe, sha256, pid, user_name, cmdline, first_snapshot_id, last_snapshot_id,
+            detected_at, last_seen_at, resolved_at, maintenance_id
+        FROM process_policy_violations WHERE id = $1",
+        row.id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn raise_violation_request(pool: &PgPool, policy: &ProcessPolicy, violation: &ProcessPolicyViolation) -> Result<(), sqlx::Error> {
+    let device = fetch_device_by_id(pool, violation.device_id).await?;
+    let host = device.hostname.clone().unwrap_or_else(|| "unknown host".to_string());
+    let description = format!(
+        "{} (pid {}, {}) matches process policy \"{}\" on {}.",
+        violation.process_name,
+        violation.pid.map(|pid| pid.to_string()).unwrap_or_else(|| "?".to_string()),
+        if violation.exe.is_empty() { "unknown executable" } else { &violation.exe },
+        policy.name,
+        host,
+    );
+    let mut request = MaintenanceRequest::raised_for_device(
+        &device,
+        format!("Disallowed process {} on {}", violation.process_name, host),
+        Some(description),
+        policy.priority,
+        policy.required_skills.clone(),
+    );
+    let device_snapshot = snapshot_device_for_request(pool, device.device_id).await;
+    let mut tx = pool.begin().await?;
+    let maintenance_id = open_maintenance_request(&mut tx, &mut request, device_snapshot, None, Some(format!("Raised by process policy {}", policy.id))).await?;
+
+    sqlx::query!(
+        "UPDATE process_policy_violations SET maintenance_id = $2 WHERE id = $1",
+        violation.id,
+        maintenance_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Checks a freshly stored snapshot against the device company's policies.
+/// Violations the snapshot no longer shows are marked resolved.
+pub async fn evaluate_process_snapshot(pool: &PgPool, snapshot: &ProcessSnapshot, processes: &[ProcessMetrics]) -> Result<(), sqlx::Error> {
+    let device = fetch_device_by_id(pool, snapshot.device_id).await?;
+    let policies = match device.company_name.as_deref() {
+        Some(company_name) => fetch_process_policies(pool, Some(company_name), true).await?,
+        None => Vec::new(),
+    };
+    let (allow, deny): (Vec<&ProcessPolicy>, Vec<&ProcessPolicy>) = policies.iter().partition(|policy| policy.effect == "allow");
+    let needs_hash = policies.iter().any(|policy| policy.match_field == "sha256");
+
+    // Exited processes waiting to be reaped run nothing and have no executable left
+    for process in processes.iter().filter(|process| !matches!(process.state.as_deref(), Some("Zombie" | "Dead"))) {
+        let sha256 = match (&process.exe, needs_hash) {
+            (Some(exe), true) => hash_executable(exe).await,
+            _ => None,
+        };
+        let sha256 = sha256.as_deref();
+        if allow.iter().any(|policy| policy.matches(process, sha256)) {
+            continue;
+        }
+        for policy in deny.iter().filter(|policy| policy.matches(process, sha256)) {
+            let Some(violation) = record_violation(pool, policy, snapshot, process, sha256).await? else {
+                continue;
+            };
+            warn!(
+                "Process {} on device {} violates process policy {}",
+                violation.process_name, violation.device_id, policy.id
+            );
+            if policy.raise_request {
+                raise_violation_request(pool, policy, &violation).await?;
+            }
+        }
+    }
+
+    sqlx::query!(
+        "UPDATE process_policy_violations SET resolved_at = $3
+        WHERE device_id = $1 AND resolved_at IS NULL AND last_snapshot_id IS DISTINCT FROM $2",
+        snapshot.device_id,
+        snapshot.id,
+        snapshot.captured_at,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn load_managed_process_policy(pool: &PgPool, claims: &Claims, policy_id: i64) -> Result<ProcessPolicy, HttpResponse> {
+    let policy = match fetch_process_policy(pool, policy_id).await {
+        Ok(Some(policy)) => policy,
+        Ok(None) => return Err(HttpResponse::NotFound().body("Process policy not found")),
+        Err(e) => {
+            error!("Failed to fetch process policy {}: {:?}", policy_id, e);
+            return Err(HttpResponse::InternalServerError().body("Failed to fetch process policy"));
+        }
+    };
+    resolve_company_scope(pool, claims, Some(&policy.company_name)).await?;
+    Ok(policy)
+}
+
+pub async fn get_process_policies(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<ProcessPolicyQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_process_policies(&pool, company_name.as_deref(), false).await {
+        Ok(policies) => HttpResponse::Ok().json(policies),
+        Err(e) => {
+            error!("Failed to fetch process policies: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch process policies")
+        }
+    }
+}
+
+pub async fn create_process_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    policy: web::Json<NewProcessPolicy>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, policy.company_name.as_deref()).await {
+        Ok(Some(company_name)) => company_name,
+        Ok(None) => return HttpResponse::BadRequest().body("company_name is required"),
+        Err(response) => return response,
+    };
+    if let Err(message) = validate_process_policy(&policy) {
+        return HttpResponse::BadRequest().body(message);
+    }
+
+    let saved = async {
+        let mut tx = pool.begin().await?;
+        let saved = save_process_policy_to_database(&mut *tx, &company_name, &policy, claims.email()).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "process_policy", &saved.id.to_string(), None, snapshot(&saved)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match saved {
+        Ok(saved) => HttpResponse::Created().json(saved),
+        Err(e) => {
+            error!("Failed to save process policy: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save process policy")
+        }
+    }
+}
+
+pub async fn update_process_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    policy_id: web::Path<i64>,
+    policy: web::Json<NewProcessPolicy>,
+) -> impl Responder {
+    let policy_id = policy_id.into_inner();
+    let before = match load_managed_process_policy(&pool, &claims, policy_id).await {
+        Ok(before) => before,
+        Err(response) => return response,
+    };
+    if policy.company_name.as_deref().is_some_and(|company_name| company_name != before.company_name) {
+        return HttpResponse::BadRequest().body("Process policies cannot move between companies");
+    }
+    if let Err(message) = validate_process_policy(&policy) {
+        return HttpResponse::BadRequest().body(message);
+    }
+
+    let saved = async {
+        let mut tx = pool.begin().await?;
+        let saved = update_process_policy_in_database(&mut *tx, policy_id, &policy).await?;
+        if let Some(saved) = &saved {
+            record(&mut tx, &AuditActor::new(&req, &claims), "update", "process_policy", &policy_id.to_string(), snapshot(&before), snapshot(saved)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match saved {
+        Ok(Some(saved)) => HttpResponse::Ok().json(saved),
+        Ok(None) => HttpResponse::NotFound().body("Process policy not found"),
+        Err(e) => {
+            error!("Failed to update process policy {}: {:?}", policy_id, e);
+            HttpResponse::InternalServerError().body("Failed to update process policy")
+        }
+    }
+}
+
+pub async fn delete_process_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    policy_id: web::Path<i64>,
+) -> impl Responder {
+    let policy_id = policy_id.into_inner();
+    let policy = match load_managed_process_policy(&pool, &claims, policy_id).await {
+        Ok(policy) => policy,
+        Err(response) => return response,
+    };
+
+    // Its violations go with it; requests it opened stay open for a person to close
+    let deleted = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM process_policies WHERE id = $1", policy_id).execute(&mut *tx).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "delete", "process_policy", &policy_id.to_string(), snapshot(&policy), None).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match deleted {
+        Ok(_) => HttpResponse::Ok().body("Process policy deleted"),
+        Err(e) => {
+            error!("Failed to delete process policy {}: {:?}", policy_id, e);
+            HttpResponse::InternalServerError().body("Failed to delete process policy")
+        }
+    }
+}
+
+pub async fn get_process_policy_violations(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<ViolationQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as!(
+        ProcessPolicyViolation,
+        "SELECT v.id, v.policy_id, v.device_id, v.process_name, v.exe, v.sha256, v.pid, v.user_name, v.cmdline,
+            v.first_snapshot_id, v.last_snapshot_id, v.detected_at, v.last_seen_at, v.resolved_at, v.maintenance_id
+        FROM process_policy_violations v
+        JOIN process_policies p ON p.id = v.policy_id
+        WHERE ($1::text IS NULL OR p.company_name = $1)
+        AND ($2::bigint IS NULL OR v.device_id = $2)
+        AND ($3::bigint IS NULL OR v.policy_id = $3)
+        AND ($4::bool IS NULL OR (v.resolved_at IS NULL) = $4)
+        ORDER BY v.detected_at DESC, v.id DESC
+        LIMIT 500",
+        company_name,
+        query.device_id,
+        query.policy_id,
+        query.open,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    {
+        Ok(violations) => HttpResponse::Ok().json(violations),
+        Err(e) => {
+            error!("Failed to fetch process policy violations: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch process policy violations")
+        }
+    }
+}
@@ -0,0 +1,898 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, Timelike, Utc};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use dotenvy::dotenv;
+use log::error;
+use std::env;
+use tokio::time::interval;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::device::devices::Device;
+use crate::functionalities::checklist::save_checklist_to_database;
+use crate::functionalities::maintenance::{open_maintenance_request, snapshot_device_for_request, MaintenancePriority, MaintenanceRequest};
+use crate::user::users::resolve_company_scope;
+
+// Cron expressions that match nothing within five years are treated as never due
+const MAX_CRON_DAYS: i64 = 5 * 366;
+// Upper bounds on what a calendar query may expand to
+const MAX_CALENDAR_RANGE_DAYS: i64 = 366;
+const MAX_OCCURRENCES_PER_SCHEDULE: usize = 500;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct MaintenanceSchedule {
+    pub id: i64,
+    pub company_name: String,
+    pub name: String,
+    pub scope: String,
+    pub device_ids: Vec<i64>,
+    pub cron: Option<String>,
+    pub interval_days: Option<i32>,
+    pub starts_at: DateTime<Utc>,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub active: bool,
+    pub title: String,
+    pub description: Option<String>,
+    pub priority: MaintenancePriority,
+    pub required_skills: Vec<String>,
+    pub checklist: Vec<String>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewSchedule {
+    pub company_name: Option<String>,
+    pub name: String,
+    // "device", "group" or "company"
+    pub scope: String,
+    #[serde(default)]
+    pub device_ids: Vec<i64>,
+    pub cron: Option<String>,
+    pub interval_days: Option<i32>,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub active: Option<bool>,
+    pub title: String,
+    pub description: Option<String>,
+    pub priority: Option<MaintenancePriority>,
+    #[serde(default)]
+    pub required_skills: Vec<String>,
+    #[serde(default)]
+    pub checklist: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleQuery {
+    pub company_name: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ScheduleRun {
+    pub id: i64,
+    pub schedule_id: i64,
+    pub device_id: Option<i64>,
+    pub due_at: DateTime<Utc>,
+    pub outcome: String,
+    pub maintenance_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduleOccurrence {
+    pub schedule_id: i64,
+    pub name: String,
+    pub due_at: DateTime<Utc>,
+    pub scope: String,
+    pub device_ids: Vec<i64>,
+    pub title: String,
+    pub priority: MaintenancePriority,
+}
+
+/// A five-field cron expression (minute, hour, day of month, month, day of
+/// week with 0 or 7 for Sunday) supporting `*`, lists, ranges and steps.
+pub struct CronExpression {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("Invalid step in '{}'", part)),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            match (start.parse::<u32>(), end.parse::<u32>()) {
+                (Ok(start), Ok(end)) => (start, end),
+                _ => return Err(format!("Invalid range '{}'", range)),
+            }
+        } else {
+            match range.parse::<u32>() {
+                // "5/15" means every 15 starting at 5
+                Ok(start) if part.contains('/') => (start, max),
+                Ok(start) => (start, start),
+                Err(_) => return Err(format!("Invalid value '{}'", range)),
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronExpression {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err("Cron expressions have five fields: minute hour day-of-month month day-of-week".to_string());
+        }
+        let mut days_of_week = parse_cron_field(fields[4], 0, 7)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days_of_month: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            days_of_week,
+            any_day_of_month: fields[2].starts_with('*'),
+            any_day_of_week: fields[4].starts_with('*'),
+        })
+    }
+
+    // As in cron, a date matches either day field when both are restricted
+    fn matches_date(&self, date: chrono::NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day_of_month = self.days_of_month & (1 << date.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// The first matching minute at or after `from`.
+    pub fn next_on_or_after(&self, from: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut start = from.with_second(0)?.with_nanosecond(0)?;
+        if start < from {
+            start += Duration::minutes(1);
+        }
+
+        for day in 0..MAX_CRON_DAYS {
+            let date = start.date() + Duration::days(day);
+            if !self.matches_date(date) {
+                continue;
+            }
+            let earliest = if day == 0 { start.time() } else { NaiveTime::MIN };
+            for hour in earliest.hour()..24 {
+                if self.hours & (1 << hour) == 0 {
+                    continue;
+                }
+                let first_minute = if hour == earliest.hour() { earliest.minute() } else { 0 };
+                for minute in first_minute..60 {
+                    if self.minutes & (1 << minute) != 0 {
+                        return date.and_hms_opt(hour, minute, 0);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+pub enum Recurrence {
+    Cron(CronExpression),
+    EveryDays { starts_at: DateTime<Utc>, days: i32 },
+}
+
+impl Recurrence {
+    pub fn new(cron: Option<&str>, interval_days: Option<i32>, starts_at: DateTime<Utc>) -> Result<Self, String> {
+        match (cron, interval_days) {
+            (Some(cron), None) => CronExpression::parse(cron).map(Recurrence::Cron),
+            (None, Some(days)) if days > 0 => Ok(Recurrence::EveryDays { starts_at, days }),
+            (None, Some(_)) => Err("interval_days must be positive".to_string()),
+            _ => Err("Give either cron or interval_days".to_string()),
+        }
+    }
+
+    fn of(schedule: &MaintenanceSchedule) -> Result<Self, String> {
+        Self::new(schedule.cron.as_deref(), schedule.interval_days, schedule.starts_at)
+    }
+
+    /// The first occurrence at or after `from`. Cron expressions are read in
+    /// local time, `utc_offset` ahead of UTC.
+    pub fn next_on_or_after(&self, from: DateTime<Utc>, utc_offset: Duration) -> Option<DateTime<Utc>> {
+        match self {
+            Recurrence::Cron(expression) => expression
+                .next_on_or_after(from.naive_utc() + utc_offset)
+                .map(|local| (local - utc_offset).and_utc()),
+            Recurrence::EveryDays { starts_at, days } => {
+                if from <= *starts_at {
+                    return Some(*starts_at);
+                }
+                let period = Duration::days(*days as i64);
+                let elapsed = (from - *starts_at).num_seconds();
+                let periods = (elapsed + period.num_seconds() - 1) / period.num_seconds();
+                Some(*starts_at + period * periods as i32)
+            }
+        }
+    }
+}
+
+// Schedules run on the company's local time when it has an SLA calendar
+async fn fetch_company_utc_offset(pool: &PgPool, company_name: &str) -> Result<Duration, sqlx::Error> {
+    let offset = sqlx::query_scalar!(
+        "SELECT utc_offset_minutes FROM sla_calendars WHERE company_name = $1",
+        company_name
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(Duration::minutes(offset.unwrap_or(0) as i64))
+}
+
+async fn fetch_schedule(pool: &PgPool, schedule_id: i64) -> Result<Option<MaintenanceSchedule>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceSchedule,
+        r#"SELECT id, company_name, name, scope, device_ids, cron, interval_days, starts_at, next_run_at, last_run_at, active,
+            title, description, priority as "priority: MaintenancePriority", required_skills, checklist, created_by, created_at, updated_at
+        FROM maintenance_schedules WHERE id = $1"#,
+        schedule_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_schedules(pool: &PgPool, company_name: Option<&str>) -> Result<Vec<MaintenanceSchedule>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceSchedule,
+        r#"SELECT id, company_name, name, scope, device_ids, cron, interval_days, starts_at, next_run_at, last_run_at, active,
+            title, description, priority as "priority: MaintenancePriority", required_skills, checklist, created_by, created_at, updated_at
+        FROM maintenance_schedules
+        WHERE ($1::text IS NULL OR company_name = $1)
+        ORDER BY company_name, id"#,
+        company_name
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_due_schedules(pool: &PgPool) -> Result<Vec<MaintenanceSchedule>, sqlx::Error> {
+    sqlx::query_as!(
+        MaintenanceSchedule,
+        r#"SELECT id, company_name, name, scope, device_ids, cron, interval_days, starts_at, next_run_at, last_run_at, active,
+            title, description, priority as "priority: MaintenancePriority", required_skills, checklist, created_by, created_at, updated_at
+        FROM maintenance_schedules
+        WHERE active AND next_run_at <= now()
+        ORDER BY next_run_at"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn save_schedule_to_database<'e, E>(
+    executor: E,
+    company_name: &str,
+    schedule: &NewSchedule,
+    starts_at: DateTime<Utc>,
+    next_run_at: DateTime<Utc>,
+    created_by: &str,
+) -> Result<MaintenanceSchedule, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        MaintenanceSchedule,
+        r#"INSERT INTO maintenance_schedules
+            (company_name, name, scope, device_ids, cron, interval_days, starts_at, next_run_at, active, title, description, priority, required_skills, checklist, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+        RETURNING id, company_name, name, scope, device_ids, cron, interval_days, starts_at, next_run_at, last_run_at, active,
+            title, description, priority as "priority: MaintenancePriority", required_skills, checklist, created_by, created_at, updated_at"#,
+        company_name,
+        schedule.name.trim(),
+        schedule.scope,
+        &schedule.device_ids,
+        schedule.cron.as_deref().map(str::trim),
+        schedule.interval_days,
+        starts_at,
+        next_run_at,
+        schedule.active.unwrap_or(true),
+        schedule.title.trim(),
+        schedule.description,
+        schedule.priority.unwrap_or(MaintenancePriority::Medium) as MaintenancePriority,
+        &schedule.required_skills,
+        &schedule.checklist,
+        created_by,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+async fn update_schedule_in_database<'e, E>(
+    executor: E,
+    schedule_id: i64,
+    schedule: &NewSchedule,
+    starts_at: DateTime<Utc>,
+    next_run_at: DateTime<Utc>,
+) -> Result<Option<MaintenanceSchedule>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        MaintenanceSchedule,
+        r#"UPDATE maintenance_schedules SET
+            name = $2, scope = $3, device_ids = $4, cron = $5, interval_days = $6, starts_at = $7, next_run_at = $8, active = $9,
+            title = $10, description = $11, priority = $12, required_skills = $13, checklist = $14, updated_at = now()
+        WHERE id = $1
+        RETURNING id, company_name, name, scope, device_ids, cron, interval_days, starts_at, next_run_at, last_run_at, active,
+            title, description, priority as "priority: MaintenancePriority", required_skills, checklist, created_by, created_at, updated_at"#,
+        schedule_id,
+        schedule.name.trim(),
+        schedule.scope,
+        &schedule.device_ids,
+        schedule.cron.as_deref().map(str::trim),
+        schedule.interval_days,
+        starts_at,
+        next_run_at,
+        schedule.active.unwrap_or(true),
+        schedule.title.trim(),
+        schedule.description,
+        schedule.priority.unwrap_or(MaintenancePriority::Medium) as MaintenancePriority,
+        &schedule.required_skills,
+        &schedule.checklist,
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+async fn fetch_schedule_runs(pool: &PgPool, schedule_id: i64) -> Result<Vec<ScheduleRun>, sqlx::Error> {
+    sqlx::query_as!(
+        ScheduleRun,
+        "SELECT id, schedule_id, device_id, due_at, outcome, maintenance_id, created_at
+        FROM maintenance_schedule_runs WHERE schedule_id = $1
+        ORDER BY due_at DESC, device_id
+        LIMIT 500",
+        schedule_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Checks a schedule definition against the company it belongs to and
+/// returns its first due date.
+async fn validate_schedule(pool: &PgPool, company_name: &str, schedule: &NewSchedule, starts_at: DateTime<Utc>) -> Result<DateTime<Utc>, HttpResponse> {
+    if schedule.name.trim().is_empty() || schedule.title.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().body("Schedules need a name and a title"));
+    }
+
+    let devices_ok = match schedule.scope.as_str() {
+        "device" => schedule.device_ids.len() == 1,
+        "group" => !schedule.device_ids.is_empty(),
+        "company" => schedule.device_ids.is_empty(),
+        _ => return Err(HttpResponse::BadRequest().body("scope must be device, group or company")),
+    };
+    if !devices_ok {
+        return Err(HttpResponse::BadRequest().body("device scope takes one device, group scope at least one and company scope none"));
+    }
+
+    if !schedule.device_ids.is_empty() {
+        let known = sqlx::query_scalar!(
+            r#"SELECT COUNT(DISTINCT device_id) as "count!" FROM devices WHERE device_id = ANY($1) AND company_name = $2"#,
+            &schedule.device_ids,
+            company_name,
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to check devices of schedule: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save maintenance schedule")
+        })?;
+        let mut wanted = schedule.device_ids.clone();
+        wanted.sort_unstable();
+        wanted.dedup();
+        if known != wanted.len() as i64 {
+            return Err(HttpResponse::BadRequest().body("Every device must exist and belong to the schedule's company"));
+        }
+    }
+
+    let recurrence = Recurrence::new(schedule.cron.as_deref().map(str::trim), schedule.interval_days, starts_at)
+        .map_err(|e| HttpResponse::BadRequest().body(e))?;
+    let utc_offset = fetch_company_utc_offset(pool, company_name).await.map_err(|e| {
+        error!("Failed to fetch UTC offset of {}: {:?}", company_name, e);
+        HttpResponse::InternalServerError().body("Failed to save maintenance schedule")
+    })?;
+
+    recurrence
+        .next_on_or_after(starts_at.max(Utc::now()), utc_offset)
+        .ok_or_else(|| HttpResponse::BadRequest().body("Schedule never comes due"))
+}
+
+async fn record_schedule_run<'e, E>(
+    executor: E,
+    schedule_id: i64,
+    device_id: i64,
+    due_at: DateTime<Utc>,
+    outcome: &str,
+    maintenance_id: Option<i64>,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "INSERT INTO maintenance_schedule_runs (schedule_id, device_id, due_at, outcome, maintenance_id)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (schedule_id, device_id, due_at) DO NOTHING",
+        schedule_id,
+        device_id,
+        due_at,
+        outcome,
+        maintenance_id,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Raises the schedule's request on every device it covers, except those
+/// still working through the request from an earlier occurrence.
+async fn raise_scheduled_requests(
+    pool: &PgPool,
+    conn: &mut PgConnection,
+    schedule: &MaintenanceSchedule,
+    due_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let devices = sqlx::query_as!(
+        Device,
+        "SELECT device_id, machine_id, company_name, hostname, mac_address, system_id, created_at, updated_at
+        FROM devices
+        WHERE company_name = $1 AND ($2 = 'company' OR device_id = ANY($3))
+        ORDER BY device_id",
+        schedule.company_name,
+        schedule.scope,
+        &schedule.device_ids,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for device in devices {
+        let open = sqlx::query_scalar!(
+            "SELECT maintenance_id FROM maintenance_requests
+            WHERE schedule_id = $1 AND device_id = $2 AND status NOT IN ('Resolved', 'Closed', 'Rejected')
+            ORDER BY maintenance_id LIMIT 1",
+            schedule.id,
+            device.device_id,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(maintenance_id) = open {
+            record_schedule_run(&mut *conn, schedule.id, device.device_id, due_at, "skipped_open", Some(maintenance_id)).await?;
+            continue;
+        }
+
+        let mut request = MaintenanceRequest::raised_for_device(
+            &device,
+            schedule.title.clone(),
+            schedule.description.clone(),
+            schedule.priority,
+            schedule.required_skills.clone(),
+        );
+        request.schedule_id = Some(schedule.id);
+
+        let device_snapshot = snapshot_device_for_request(pool, device.device_id).await;
+        let maintenance_id = open_maintenance_request(
+            &mut *conn,
+            &mut request,
+            device_snapshot,
+            None,
+            Some(format!("Raised by maintenance schedule {}", schedule.id)),
+        )
+        .await?;
+        save_checklist_to_database(&mut *conn, maintenance_id, &schedule.checklist).await?;
+        record_schedule_run(&mut *conn, schedule.id, device.device_id, due_at, "created", Some(maintenance_id)).await?;
+    }
+    Ok(())
+}
+
+/// Runs every schedule that has come due once, however many occurrences were
+/// missed while the server was down, and moves it to its next due date. The
+/// move and the requests it raises commit together, so a schedule that fails
+/// to raise them stays due and is tried again on the next run.
+async fn run_due_schedules(pool: &PgPool) -> Result<(), sqlx::Error> {
+    for schedule in fetch_due_schedules(pool).await? {
+        let due_at = match schedule.next_run_at {
+            Some(due_at) => due_at,
+            None => continue,
+        };
+
+        let next_run_at = match Recurrence::of(&schedule) {
+            Ok(recurrence) => {
+                let utc_offset = fetch_company_utc_offset(pool, &schedule.company_name).await?;
+                recurrence.next_on_or_after(Utc::now().max(due_at) + Duration::seconds(1), utc_offset)
+            }
+            Err(e) => {
+                error!("Maintenance schedule {} has an invalid recurrence: {}", schedule.id, e);
+                None
+            }
+        };
+
+        // Only the instance that moves the due date on raises the requests
+        let mut tx = pool.begin().await?;
+        let claimed = sqlx::query!(
+            "UPDATE maintenance_schedules SET next_run_at = $2, last_run_at = $3
+            WHERE id = $1 AND active AND next_run_at = $3",
+            schedule.id,
+            next_run_at,
+            due_at,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() == 1;
+        if !claimed {
+            continue;
+        }
+
+        match raise_scheduled_requests(pool, &mut tx, &schedule, due_at).await {
+            Ok(()) => tx.commit().await?,
+            Err(e) => error!("Failed to raise requests of maintenance schedule {}: {:?}", schedule.id, e),
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_maintenance_scheduler(pool: PgPool) {
+    dotenv().ok();
+    let seconds = env::var("MAINTENANCE_SCHEDULE_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60);
+    let mut interval = interval(std::time::Duration::from_secs(seconds));
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_due_schedules(&pool).await {
+            error!("Failed to run maintenance schedules: {:?}", e);
+        }
+    }
+}
+
+// Loads a schedule and checks the caller may manage its company
+async fn load_managed_schedule(pool: &PgPool, claims: &Claims, schedule_id: i64) -> Result<MaintenanceSchedule, HttpResponse> {
+    let schedule = match fetch_schedule(pool, schedule_id).await {
+        Ok(Some(schedule)) => schedule,
+        Ok(None) => return Err(HttpResponse::NotFound().body("Maintenance schedule not found")),
+        Err(e) => {
+            error!("Failed to fetch maintenance schedule {}: {:?}", schedule_id, e);
+            return Err(HttpResponse::InternalServerError().body("Failed to fetch maintenance schedule"));
+        }
+    };
+    resolve_company_scope(pool, claims, Some(&schedule.company_name)).await?;
+    Ok(schedule)
+}
+
+pub async fn create_schedule(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    schedule: web::Json<NewSchedule>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, schedule.company_name.as_deref()).await {
+        Ok(Some(company_name)) => company_name,
+        Ok(None) => return HttpResponse::BadRequest().body("company_name is required"),
+        Err(response) => return response,
+    };
+
+    let starts_at = schedule.starts_at.unwrap_or_else(Utc::now);
+    let next_run_at = match validate_schedule(&pool, &company_name, &schedule, starts_at).await {
+        Ok(next_run_at) => next_run_at,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let saved = save_schedule_to_database(&mut *tx, &company_name, &schedule, starts_at, next_run_at, claims.email()).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "maintenance_schedule", &saved.id.to_string(), None, snapshot(&saved)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match result {
+        Ok(saved) => HttpResponse::Created().json(saved),
+        Err(e) => {
+            error!("Failed to save maintenance schedule: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save maintenance schedule")
+        }
+    }
+}
+
+pub async fn get_schedules(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<ScheduleQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_schedules(&pool, company_name.as_deref()).await {
+        Ok(schedules) => HttpResponse::Ok().json(schedules),
+        Err(e) => {
+            error!("Failed to fetch maintenance schedules: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance schedules")
+        }
+    }
+}
+
+pub async fn update_schedule(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    schedule_id: web::Path<i64>,
+    schedule: web::Json<NewSchedule>,
+) -> impl Responder {
+    let schedule_id = schedule_id.into_inner();
+    let before = match load_managed_schedule(&pool, &claims, schedule_id).await {
+        Ok(before) => before,
+        Err(response) => return response,
+    };
+    if schedule.company_name.as_deref().is_some_and(|company_name| company_name != before.company_name) {
+        return HttpResponse::BadRequest().body("Schedules cannot move between companies");
+    }
+
+    let starts_at = schedule.starts_at.unwrap_or(before.starts_at);
+    let next_run_at = match validate_schedule(&pool, &before.company_name, &schedule, starts_at).await {
+        Ok(next_run_at) => next_run_at,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let saved = update_schedule_in_database(&mut *tx, schedule_id, &schedule, starts_at, next_run_at).await?;
+        if let Some(saved) = &saved {
+            record(&mut tx, &AuditActor::new(&req, &claims), "update", "maintenance_schedule", &schedule_id.to_string(), snapshot(&before), snapshot(saved)).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match result {
+        Ok(Some(saved)) => HttpResponse::Ok().json(saved),
+        Ok(None) => HttpResponse::NotFound().body("Maintenance schedule not found"),
+        Err(e) => {
+            error!("Failed to update maintenance schedule {}: {:?}", schedule_id, e);
+            HttpResponse::InternalServerError().body("Failed to update maintenance schedule")
+        }
+    }
+}
+
+pub async fn delete_schedule(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    schedule_id: web::Path<i64>,
+) -> impl Responder {
+    let schedule_id = schedule_id.into_inner();
+    let schedule = match load_managed_schedule(&pool, &claims, schedule_id).await {
+        Ok(schedule) => schedule,
+        Err(response) => return response,
+    };
+
+    // Requests already raised stay, they just lose the link to the schedule
+    let result = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM maintenance_schedules WHERE id = $1", schedule_id).execute(&mut *tx).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "delete", "maintenance_schedule", &schedule_id.to_string(), snapshot(&schedule), None).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("Maintenance schedule deleted"),
+        Err(e) => {
+            error!("Failed to delete maintenance schedule {}: {:?}", schedule_id, e);
+            HttpResponse::InternalServerError().body("Failed to delete maintenance schedule")
+        }
+    }
+}
+
+pub async fn get_schedule_runs(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    schedule_id: web::Path<i64>,
+) -> impl Responder {
+    let schedule_id = schedule_id.into_inner();
+    if let Err(response) = load_managed_schedule(&pool, &claims, schedule_id).await {
+        return response;
+    }
+
+    match fetch_schedule_runs(&pool, schedule_id).await {
+        Ok(runs) => HttpResponse::Ok().json(runs),
+        Err(e) => {
+            error!("Failed to fetch runs of maintenance schedule {}: {:?}", schedule_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch maintenance schedule runs")
+        }
+    }
+}
+
+/// Upcoming occurrences of every active schedule between `from` (default now)
+/// and `to` (default 30 days later).
+pub async fn get_schedule_calendar(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<ScheduleQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    let from = query.from.unwrap_or_else(Utc::now);
+    let to = query.to.unwrap_or(from + Duration::days(30));
+    if to <= from || to - from > Duration::days(MAX_CALENDAR_RANGE_DAYS) {
+        return HttpResponse::BadRequest().body(format!("The calendar covers at most {} days", MAX_CALENDAR_RANGE_DAYS));
+    }
+
+    let schedules = match fetch_schedules(&pool, company_name.as_deref()).await {
+        Ok(schedules) => schedules,
+        Err(e) => {
+            error!("Failed to fetch maintenance schedules: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to fetch maintenance calendar");
+        }
+    };
+
+    let mut occurrences = Vec::new();
+    for schedule in schedules.into_iter().filter(|schedule| schedule.active) {
+        let recurrence = match Recurrence::of(&schedule) {
+            Ok(recurrence) => recurrence,
+            Err(_) => continue,
+        };
+        let utc_offset = match fetch_company_utc_offset(&pool, &schedule.company_name).await {
+            Ok(utc_offset) => utc_offset,
+            Err(e) => {
+                error!("Failed to fetch UTC offset of {}: {:?}", schedule.company_name, e);
+                return HttpResponse::InternalServerError().body("Failed to fetch maintenance calendar");
+            }
+        };
+
+        // Cron expressions know nothing of when the schedule starts
+        let mut cursor = from.max(schedule.starts_at);
+        for _ in 0..MAX_OCCURRENCES_PER_SCHEDULE {
+            match recurrence.next_on_or_after(cursor, utc_offset) {
+                Some(due_at) if due_at < to => {
+                    occurrences.push(ScheduleOccurrence {
+                        schedule_id: schedule.id,
+                        name: schedule.name.clone(),
+                        due_at,
+                        scope: schedule.scope.clone(),
+                        device_ids: schedule.device_ids.clone(),
+                        title: schedule.title.clone(),
+                        priority: schedule.priority,
+                    });
+                    cursor = due_at + Duration::seconds(1);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    occurrences.sort_by_key(|occurrence| (occurrence.due_at, occurrence.schedule_id));
+    HttpResponse::Ok().json(occurrences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(bits: u64) -> Vec<u32> {
+        (0..64).filter(|value| bits & (1 << value) != 0).collect()
+    }
+
+    fn at(instant: &str) -> NaiveDateTime {
+        instant.parse().unwrap()
+    }
+
+    fn next(expression: &str, from: &str) -> Option<NaiveDateTime> {
+        CronExpression::parse(expression).unwrap().next_on_or_after(at(from))
+    }
+
+    #[test]
+    fn parses_wildcards() {
+        assert_eq!(values(parse_cron_field("*", 0, 59).unwrap()), (0..=59).collect::<Vec<_>>());
+        assert_eq!(values(parse_cron_field("*", 1, 12).unwrap()), (1..=12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parses_ranges_and_lists() {
+        assert_eq!(values(parse_cron_field("1-5", 0, 59).unwrap()), vec![1, 2, 3, 4, 5]);
+        assert_eq!(values(parse_cron_field("7", 0, 59).unwrap()), vec![7]);
+        assert_eq!(values(parse_cron_field("1,3,10-12", 0, 59).unwrap()), vec![1, 3, 10, 11, 12]);
+    }
+
+    #[test]
+    fn parses_steps() {
+        assert_eq!(values(parse_cron_field("*/15", 0, 59).unwrap()), vec![0, 15, 30, 45]);
+        assert_eq!(values(parse_cron_field("5/20", 0, 59).unwrap()), vec![5, 25, 45]);
+        assert_eq!(values(parse_cron_field("10-20/5", 0, 59).unwrap()), vec![10, 15, 20]);
+        assert_eq!(values(parse_cron_field("*/5", 1, 12).unwrap()), vec![1, 6, 11]);
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert!(parse_cron_field("60", 0, 59).is_err());
+        assert!(parse_cron_field("0", 1, 31).is_err());
+        assert!(parse_cron_field("20-24", 0, 23).is_err());
+        assert!(parse_cron_field("5-1", 0, 59).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_fields() {
+        assert!(parse_cron_field("*/0", 0, 59).is_err());
+        assert!(parse_cron_field("a", 0, 59).is_err());
+        assert!(parse_cron_field("1-", 0, 59).is_err());
+        assert!(parse_cron_field("", 0, 59).is_err());
+        assert!(CronExpression::parse("0 9 * *").is_err());
+        assert!(CronExpression::parse("0 9 * 13 *").is_err());
+    }
+
+    #[test]
+    fn finds_the_next_minute_on_the_same_day() {
+        assert_eq!(next("*/15 * * * *", "2024-03-01T10:07:30"), Some(at("2024-03-01T10:15:00")));
+        assert_eq!(next("0 9 * * *", "2024-03-01T09:00:00"), Some(at("2024-03-01T09:00:00")));
+        assert_eq!(next("0 9 * * *", "2024-03-01T09:00:01"), Some(at("2024-03-02T09:00:00")));
+    }
+
+    #[test]
+    fn crosses_month_boundaries() {
+        assert_eq!(next("0 9 1 * *", "2024-01-31T10:00:00"), Some(at("2024-02-01T09:00:00")));
+        assert_eq!(next("0 0 31 * *", "2024-04-01T00:00:00"), Some(at("2024-05-31T00:00:00")));
+        assert_eq!(next("0 0 29 2 *", "2023-03-01T00:00:00"), Some(at("2024-02-29T00:00:00")));
+    }
+
+    #[test]
+    fn crosses_year_boundaries() {
+        assert_eq!(next("30 6 * * *", "2024-12-31T07:00:00"), Some(at("2025-01-01T06:30:00")));
+        assert_eq!(next("0 0 1 1 *", "2024-01-01T00:01:00"), Some(at("2025-01-01T00:00:00")));
+    }
+
+    #[test]
+    fn reads_seven_as_sunday() {
+        // 2024-03-01 is a Friday
+        assert_eq!(next("0 12 * * 7", "2024-03-01T00:00:00"), Some(at("2024-03-03T12:00:00")));
+        assert_eq!(next("0 12 * * 0", "2024-03-01T00:00:00"), Some(at("2024-03-03T12:00:00")));
+    }
+
+    #[test]
+    fn matches_either_day_field_when_both_are_restricted() {
+        assert_eq!(next("0 0 13 * 5", "2024-03-01T00:01:00"), Some(at("2024-03-08T00:00:00")));
+        assert_eq!(next("0 0 13 * 5", "2024-03-09T00:00:00"), Some(at("2024-03-13T00:00:00")));
+    }
+
+    #[test]
+    fn never_matches_impossible_dates() {
+        assert_eq!(next("0 0 30 2 *", "2024-01-01T00:00:00"), None);
+    }
+}
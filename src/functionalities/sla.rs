@@ -0,0 +1,794 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use dotenvy::dotenv;
+use log::{error, warn};
+use std::collections::HashSet;
+use std::env;
+use tokio::time::interval;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
+use crate::functionalities::maintenance::{MaintenancePriority, MaintenanceStatus};
+use crate::user::users::{resolve_company_scope, UserRole};
+
+// Far enough to cover any sane target without looping forever on a bad calendar
+const MAX_CALENDAR_DAYS: i64 = 3660;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct SlaPolicy {
+    pub id: i64,
+    pub company_name: String,
+    pub priority: MaintenancePriority,
+    pub response_minutes: i32,
+    pub resolve_minutes: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpsertSlaPolicy {
+    pub company_name: String,
+    pub priority: MaintenancePriority,
+    pub response_minutes: i32,
+    pub resolve_minutes: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlaCalendar {
+    pub company_name: String,
+    pub utc_offset_minutes: i32,
+    pub workdays: Vec<i16>,
+    pub day_start: NaiveTime,
+    pub day_end: NaiveTime,
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SlaQuery {
+    pub company_name: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SlaBreach {
+    pub id: i64,
+    pub maintenance_id: i64,
+    pub company_name: Option<String>,
+    pub kind: String,
+    pub due_at: DateTime<Utc>,
+    pub detected_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SlaComplianceRow {
+    pub priority: MaintenancePriority,
+    pub total: i64,
+    pub response_met: i64,
+    pub response_breached: i64,
+    pub resolve_met: i64,
+    pub resolve_breached: i64,
+    // Share of requests with a decided outcome that met the target, 0-100
+    pub response_compliance: Option<f64>,
+    pub resolve_compliance: Option<f64>,
+}
+
+/// Working hours of a company. Without one, SLA clocks run around the clock.
+pub struct BusinessCalendar {
+    offset: Duration,
+    workdays: Vec<u32>,
+    day_start: NaiveTime,
+    day_end: NaiveTime,
+    holidays: HashSet<NaiveDate>,
+}
+
+impl BusinessCalendar {
+    fn from_calendar(calendar: &SlaCalendar) -> Self {
+        Self {
+            offset: Duration::minutes(calendar.utc_offset_minutes as i64),
+            workdays: calendar.workdays.iter().map(|day| *day as u32).collect(),
+            day_start: calendar.day_start,
+            day_end: calendar.day_end,
+            holidays: calendar.holidays.iter().copied().collect(),
+        }
+    }
+
+    fn is_workday(&self, date: NaiveDate) -> bool {
+        self.workdays.contains(&date.weekday().number_from_monday()) && !self.holidays.contains(&date)
+    }
+
+    // Working window of a local date, if it is a workday
+    fn window(&self, date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        self.is_workday(date)
+            .then(|| (date.and_time(self.day_start), date.and_time(self.day_end)))
+    }
+
+    /// The instant `minutes` of working time after `start`.
+    pub fn add_business_minutes(&self, start: DateTime<Utc>, minutes: i64) -> DateTime<Utc> {
+        let mut remaining = Duration::minutes(minutes);
+        let mut cursor = start.naive_utc() + self.offset;
+
+        for _ in 0..MAX_CALENDAR_DAYS {
+            let date = cursor.date();
+            if let Some((window_start, window_end)) = self.window(date) {
+                cursor = cursor.max(window_start);
+                if cursor < window_end {
+                    let available = window_end - cursor;
+                    if remaining <= available {
+                        return (cursor + remaining - self.offset).and_utc();
+                    }
+                    remaining -= available;
+                }
+            }
+            cursor = (date + Duration::days(1)).and_time(NaiveTime::MIN);
+        }
+
+        // No working time in range: the calendar is effectively empty
+        start + Duration::minutes(minutes)
+    }
+
+    /// Working minutes between two instants.
+    pub fn business_minutes_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> i64 {
+        if to <= from {
+            return 0;
+        }
+        let from = from.naive_utc() + self.offset;
+        let to = to.naive_utc() + self.offset;
+
+        let mut total = Duration::zero();
+        let mut date = from.date();
+        while date <= to.date() {
+            if let Some((window_start, window_end)) = self.window(date) {
+                let start = window_start.max(from);
+                let end = window_end.min(to);
+                if start < end {
+                    total += end - start;
+                }
+            }
+            date += Duration::days(1);
+        }
+        total.num_minutes()
+    }
+}
+
+fn minutes_between(calendar: Option<&BusinessCalendar>, from: DateTime<Utc>, to: DateTime<Utc>) -> i64 {
+    match calendar {
+        Some(calendar) => calendar.business_minutes_between(from, to),
+        None => (to - from).num_minutes().max(0),
+    }
+}
+
+fn add_minutes(calendar: Option<&BusinessCalendar>, start: DateTime<Utc>, minutes: i64) -> DateTime<Utc> {
+    match calendar {
+        Some(calendar) => calendar.add_business_minutes(start, minutes),
+        None => start + Duration::minutes(minutes),
+    }
+}
+
+pub async fn fetch_business_calendar(conn: &mut PgConnection, company_name: &str) -> Result<Option<BusinessCalendar>, sqlx::Error> {
+    Ok(fetch_sla_calendar(&mut *conn, company_name).await?.as_ref().map(BusinessCalendar::from_calendar))
+}
+
+async fn fetch_sla_calendar(conn: &mut PgConnection, company_name: &str) -> Result<Option<SlaCalendar>, sqlx::Error> {
+    let calendar = sqlx::query!(
+        "SELECT company_name, utc_offset_minutes, workdays, day_start, day_end FROM sla_calendars WHERE company_name = $1",
+        company_name
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let calendar = match calendar {
+        Some(calendar) => calendar,
+        None => return Ok(None),
+    };
+
+    let holidays = sqlx::query_scalar!(
+        "SELECT holiday FROM sla_holidays WHERE company_name = $1 ORDER BY holiday",
+        company_name
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Some(SlaCalendar {
+        company_name: calendar.company_name,
+        utc_offset_minutes: calendar.utc_offset_minutes,
+        workdays: calendar.workdays,
+        day_start: calendar.day_start,
+        day_end: calendar.day_end,
+        holidays,
+    }))
+}
+
+/// Recomputes the due dates of a request from its company's policy for its
+/// priority and returns them. Time spent on hold is added to the resolution target.
+pub async fn refresh_sla_due_dates(conn: &mut PgConnection, maintenance_id: i64) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), sqlx::Error> {
+    let request = sqlx::query!(
+        r#"SELECT company_name, priority as "priority: MaintenancePriority", created_at, sla_paused_minutes
+        FROM maintenance_requests WHERE maintenance_id = $1"#,
+        maintenance_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let request = match request {
+        Some(request) => request,
+        None => return Ok((None, None)),
+    };
+
+    let mut due = (None, None);
+    if let Some(company_name) = &request.company_name {
+        let policy = sqlx::query!(
+            "SELECT response_minutes, resolve_minutes FROM sla_policies WHERE company_name = $1 AND priority = $2",
+            company_name,
+            request.priority as MaintenancePriority,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(policy) = policy {
+            let calendar = fetch_business_calendar(&mut *conn, company_name).await?;
+            let created_at = request.created_at.unwrap_or_else(Utc::now);
+            due = (
+                Some(add_minutes(calendar.as_ref(), created_at, policy.response_minutes as i64)),
+                Some(add_minutes(calendar.as_ref(), created_at, policy.resolve_minutes as i64 + request.sla_paused_minutes)),
+            );
+        }
+    }
+
+    sqlx::query!(
+        "UPDATE maintenance_requests SET response_due_at = $1, resolve_due_at = $2 WHERE maintenance_id = $3",
+        due.0,
+        due.1,
+        maintenance_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(due)
+}
+
+async fn refresh_open_requests_of_company(conn: &mut PgConnection, company_name: &str) -> Result<(), sqlx::Error> {
+    let open = sqlx::query_scalar!(
+        "SELECT maintenance_id FROM maintenance_requests
+        WHERE company_name = $1 AND status NOT IN ('Resolved', 'Closed', 'Rejected')",
+        company_name
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for maintenance_id in open {
+        refresh_sla_due_dates(&mut *conn, maintenance_id).await?;
+    }
+    Ok(())
+}
+
+/// Marks the request as responded to, unless it already was.
+pub async fn record_first_response<'e, E>(executor: E, maintenance_id: i64) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "UPDATE maintenance_requests SET first_response_at = now() WHERE maintenance_id = $1 AND first_response_at IS NULL",
+        maintenance_id
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Keeps the SLA clocks in step with a status change: anyone other than the
+/// reporter acting on the request counts as a response (changes made by the
+/// system, with no role, do not), putting it on hold
+/// stops the resolution clock and taking it off hold pushes the due date out
+/// by the working time it spent there.
+pub async fn apply_status_change(
+    conn: &mut PgConnection,
+    maintenance_id: i64,
+    from: MaintenanceStatus,
+    to: MaintenanceStatus,
+    role: Option<UserRole>,
+) -> Result<(), sqlx::Error> {
+    if role.is_some_and(|role| role != UserRole::Staff) {
+        record_first_response(&mut *conn, maintenance_id).await?;
+    }
+
+    if to == MaintenanceStatus::OnHold {
+        sqlx::query!(
+            "UPDATE maintenance_requests SET sla_paused_at = now() WHERE maintenance_id = $1 AND sla_paused_at IS NULL",
+            maintenance_id
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    if from == MaintenanceStatus::OnHold {
+        let paused = sqlx::query!(
+            "SELECT company_name, sla_paused_at FROM maintenance_requests WHERE maintenance_id = $1",
+            maintenance_id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if let Some(paused_at) = paused.sla_paused_at {
+            let calendar = match &paused.company_name {
+                Some(company_name) => fetch_business_calendar(&mut *conn, company_name).await?,
+                None => None,
+            };
+            let minutes = minutes_between(calendar.as_ref(), paused_at, Utc::now());
+            sqlx::query!(
+                "UPDATE maintenance_requests SET sla_paused_at = NULL, sla_paused_minutes = sla_paused_minutes + $1 WHERE maintenance_id = $2",
+                minutes,
+                maintenance_id
+            )
+            .execute(&mut *conn)
+            .await?;
+            refresh_sla_due_dates(&mut *conn, maintenance_id).await?;
+        }
+    }
+
+    match to {
+        MaintenanceStatus::Resolved | MaintenanceStatus::Closed | MaintenanceStatus::Rejected => {
+            sqlx::query!(
+                "UPDATE maintenance_requests SET resolved_at = COALESCE(resolved_at, now()) WHERE maintenance_id = $1",
+                maintenance_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        // Reopened
+        _ if from == MaintenanceStatus::Resolved => {
+            sqlx::query!(
+                "UPDATE maintenance_requests SET resolved_at = NULL WHERE maintenance_id = $1",
+                maintenance_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Records every open request that has passed a due date it has not met.
+/// Requests on hold are skipped; their resolution clock is stopped.
+async fn detect_sla_breaches(pool: &PgPool) -> Result<Vec<(i64, String)>, sqlx::Error> {
+    let breaches = sqlx::query!(
+        "INSERT INTO sla_breaches (maintenance_id, kind, due_at)
+        SELECT maintenance_id, 'response', response_due_at FROM maintenance_requests
+        WHERE first_response_at IS NULL AND response_due_at < now()
+        AND status NOT IN ('Resolved', 'Closed', 'Rejected')
+        UNION ALL
+        SELECT maintenance_id, 'resolve', resolve_due_at FROM maintenance_requests
+        WHERE resolved_at IS NULL AND sla_paused_at IS NULL AND resolve_due_at < now()
+        AND status NOT IN ('Resolved', 'Closed', 'Rejected')
+        ON CONFLICT (maintenance_id, kind) DO NOTHING
+        RETURNING maintenance_id, kind"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(breaches.into_iter().map(|row| (row.maintenance_id, row.kind)).collect())
+}
+
+/// Background job checking for breaches every SLA_CHECK_INTERVAL_SECS (60 by default).
+pub async fn run_sla_breach_monitor(pool: PgPool) {
+    dotenv().ok();
+    let seconds = env::var("SLA_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60);
+    let mut interval = interval(std::time::Duration::from_secs(seconds));
+
+    loop {
+        interval.tick().await;
+        match detect_sla_breaches(&pool).await {
+            Ok(breaches) => {
+                for (maintenance_id, kind) in breaches {
+                    warn!("Maintenance request {} breached its {} SLA", maintenance_id, kind);
+                }
+            }
+            Err(e) => error!("Failed to check SLA breaches: {:?}", e),
+        }
+    }
+}
+
+async fn fetch_sla_policies(pool: &PgPool, company_name: Option<&str>) -> Result<Vec<SlaPolicy>, sqlx::Error> {
+    sqlx::query_as!(
+        SlaPolicy,
+        r#"SELECT id, company_name, priority as "priority: MaintenancePriority", response_minutes, resolve_minutes, created_at, updated_at
+        FROM sla_policies
+        WHERE ($1::text IS NULL OR company_name = $1)
+        ORDER BY company_name, priority"#,
+        company_name
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn upsert_sla_policy_in_database<'e, E>(executor: E, policy: &UpsertSlaPolicy) -> Result<SlaPolicy, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        SlaPolicy,
+        r#"INSERT INTO sla_policies (company_name, priority, response_minutes, resolve_minutes)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (company_name, priority) DO UPDATE
+        SET response_minutes = EXCLUDED.response_minutes, resolve_minutes = EXCLUDED.resolve_minutes, updated_at = now()
+        RETURNING id, company_name, priority as "priority: MaintenancePriority", response_minutes, resolve_minutes, created_at, updated_at"#,
+        policy.company_name,
+        policy.priority as MaintenancePriority,
+        policy.response_minutes,
+        policy.resolve_minutes,
+    )
+    .fetch_one(executor)
+    .await
+}
+
+async fn save_sla_calendar_to_database(conn: &mut PgConnection, calendar: &SlaCalendar) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO sla_calendars (company_name, utc_offset_minutes, workdays, day_start, day_end)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (company_name) DO UPDATE
+        SET utc_offset_minutes = EXCLUDED.utc_offset_minutes, workdays = EXCLUDED.workdays,
+            day_start = EXCLUDED.day_start, day_end = EXCLUDED.day_end, updated_at = now()",
+        calendar.company_name,
+        calendar.utc_offset_minutes,
+        &calendar.workdays,
+        calendar.day_start,
+        calendar.day_end,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!("DELETE FROM sla_holidays WHERE company_name = $1", calendar.company_name)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+        "INSERT INTO sla_holidays (company_name, holiday) SELECT $1, holiday FROM UNNEST($2::date[]) AS holiday ON CONFLICT DO NOTHING",
+        calendar.company_name,
+        &calendar.holidays,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_sla_breaches(pool: &PgPool, company_name: Option<&str>, query: &SlaQuery) -> Result<Vec<SlaBreach>, sqlx::Error> {
+    sqlx::query_as!(
+        SlaBreach,
+        "SELECT b.id, b.maintenance_id, m.company_name, b.kind, b.due_at, b.detected_at
+        FROM sla_breaches b
+        JOIN maintenance_requests m ON m.maintenance_id = b.maintenance_id
+        WHERE ($1::text IS NULL OR m.company_name = $1)
+        AND ($2::timestamptz IS NULL OR b.due_at >= $2)
+        AND ($3::timestamptz IS NULL OR b.due_at < $3)
+        ORDER BY b.due_at DESC",
+        company_name,
+        query.from,
+        query.to,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A target counts as met or breached once it is decided: the request was
+// responded to / resolved, or the due date passed without that happening.
+async fn fetch_sla_compliance(pool: &PgPool, company_name: Option<&str>, query: &SlaQuery) -> Result<Vec<SlaComplianceRow>, sqlx::Error> {
+    sqlx::query_as!(
+        SlaComplianceRow,
+        r#"SELECT
+            priority as "priority!: MaintenancePriority",
+            COUNT(*) as "total!",
+            COUNT(*) FILTER (WHERE first_response_at <= response_due_at) as "response_met!",
+            COUNT(*) FILTER (WHERE first_response_at > response_due_at OR (first_response_at IS NULL AND response_due_at < now())) as "response_breached!",
+            COUNT(*) FILTER (WHERE resolved_at <= resolve_due_at) as "resolve_met!",
+            COUNT(*) FILTER (WHERE resolved_at > resolve_due_at OR (resolved_at IS NULL AND sla_paused_at IS NULL AND resolve_due_at < now())) as "resolve_breached!",
+            NULL::float8 as response_compliance,
+            NULL::float8 as resolve_compliance
+        FROM maintenance_requests
+        WHERE response_due_at IS NOT NULL
+        AND ($1::text IS NULL OR company_name = $1)
+        AND ($2::timestamptz IS NULL OR created_at >= $2)
+        AND ($3::timestamptz IS NULL OR created_at < $3)
+        GROUP BY priority
+        ORDER BY priority DESC"#,
+        company_name,
+        query.from,
+        query.to,
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| {
+        rows.into_iter()
+            .map(|mut row| {
+                row.response_compliance = percentage(row.response_met, row.response_breached);
+                row.resolve_compliance = percentage(row.resolve_met, row.resolve_breached);
+                row
+            })
+            .collect()
+    })
+}
+
+fn percentage(met: i64, breached: i64) -> Option<f64> {
+    let decided = met + breached;
+    (decided > 0).then(|| met as f64 * 100.0 / decided as f64)
+}
+
+pub async fn get_sla_policies(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<SlaQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_sla_policies(&pool, company_name.as_deref()).await {
+        Ok(policies) => HttpResponse::Ok().json(policies),
+        Err(e) => {
+            error!("Failed to fetch SLA policies: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch SLA policies")
+        }
+    }
+}
+
+pub async fn upsert_sla_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    policy: web::Json<UpsertSlaPolicy>,
+) -> impl Responder {
+    if let Err(response) = resolve_company_scope(&pool, &claims, Some(&policy.company_name)).await {
+        return response;
+    }
+    if policy.response_minutes <= 0 || policy.resolve_minutes <= 0 {
+        return HttpResponse::BadRequest().body("SLA targets must be positive");
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let saved = upsert_sla_policy_in_database(&mut *tx, &policy).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "upsert", "sla_policy", &saved.id.to_string(), None, snapshot(&saved)).await?;
+        refresh_open_requests_of_company(&mut tx, &saved.company_name).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(saved)
+    }
+    .await;
+
+    match result {
+        Ok(saved) => HttpResponse::Ok().json(saved),
+        Err(e) => {
+            error!("Failed to save SLA policy: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save SLA policy")
+        }
+    }
+}
+
+pub async fn delete_sla_policy(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    policy_id: web::Path<i64>,
+) -> impl Responder {
+    let policy_id = policy_id.into_inner();
+    let policy = match sqlx::query_as!(
+        SlaPolicy,
+        r#"SELECT id, company_name, priority as "priority: MaintenancePriority", response_minutes, resolve_minutes, created_at, updated_at
+        FROM sla_policies WHERE id = $1"#,
+        policy_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    {
+        Ok(Some(policy)) => policy,
+        Ok(None) => return HttpResponse::NotFound().body("SLA policy not found"),
+        Err(e) => {
+            error!("Failed to fetch SLA policy {}: {:?}", policy_id, e);
+            return HttpResponse::InternalServerError().body("Failed to delete SLA policy");
+        }
+    };
+
+    if let Err(response) = resolve_company_scope(&pool, &claims, Some(&policy.company_name)).await {
+        return response;
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM sla_policies WHERE id = $1", policy_id).execute(&mut *tx).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "delete", "sla_policy", &policy_id.to_string(), snapshot(&policy), None).await?;
+        refresh_open_requests_of_company(&mut tx, &policy.company_name).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("SLA policy deleted"),
+        Err(e) => {
+            error!("Failed to delete SLA policy {}: {:?}", policy_id, e);
+            HttpResponse::InternalServerError().body("Failed to delete SLA policy")
+        }
+    }
+}
+
+pub async fn get_sla_calendar(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    company_name: web::Path<String>,
+) -> impl Responder {
+    let company_name = company_name.into_inner();
+    if let Err(response) = resolve_company_scope(&pool, &claims, Some(&company_name)).await {
+        return response;
+    }
+
+    let calendar = match pool.acquire().await {
+        Ok(mut conn) => fetch_sla_calendar(&mut conn, &company_name).await,
+        Err(e) => Err(e),
+    };
+    match calendar {
+        Ok(Some(calendar)) => HttpResponse::Ok().json(calendar),
+        Ok(None) => HttpResponse::NotFound().body("No business calendar; SLAs run around the clock"),
+        Err(e) => {
+            error!("Failed to fetch SLA calendar: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch SLA calendar")
+        }
+    }
+}
+
+pub async fn update_sla_calendar(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    company_name: web::Path<String>,
+    calendar: web::Json<SlaCalendar>,
+) -> impl Responder {
+    let company_name = company_name.into_inner();
+    if let Err(response) = resolve_company_scope(&pool, &claims, Some(&company_name)).await {
+        return response;
+    }
+
+    let mut calendar = calendar.into_inner();
+    calendar.company_name = company_name.clone();
+    if calendar.workdays.is_empty() || calendar.workdays.iter().any(|day| !(1..=7).contains(day)) {
+        return HttpResponse::BadRequest().body("Workdays must be ISO weekdays between 1 (Monday) and 7 (Sunday)");
+    }
+    if calendar.day_start >= calendar.day_end {
+        return HttpResponse::BadRequest().body("The working day must start before it ends");
+    }
+    if calendar.utc_offset_minutes.abs() > 14 * 60 {
+        return HttpResponse::BadRequest().body("UTC offset must be within +/-14 hours");
+    }
+
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let before = fetch_sla_calendar(&mut tx, &company_name).await?;
+        save_sla_calendar_to_database(&mut tx, &calendar).await?;
+        record(&mut tx, &AuditActor::new(&req, &claims), "update", "sla_calendar", &company_name, before.as_ref().and_then(snapshot), snapshot(&calendar)).await?;
+        refresh_open_requests_of_company(&mut tx, &company_name).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(calendar),
+        Err(e) => {
+            error!("Failed to save SLA calendar: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to save SLA calendar")
+        }
+    }
+}
+
+pub async fn get_sla_breaches(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<SlaQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_sla_breaches(&pool, company_name.as_deref(), &query).await {
+        Ok(breaches) => HttpResponse::Ok().json(breaches),
+        Err(e) => {
+            error!("Failed to fetch SLA breaches: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch SLA breaches")
+        }
+    }
+}
+
+pub async fn get_sla_compliance_report(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<SlaQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_sla_compliance(&pool, company_name.as_deref(), &query).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            error!("Failed to build SLA compliance report: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to build SLA compliance report")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Monday to Friday, 09:00-17:00
+    fn office_hours(utc_offset_minutes: i32, holidays: &[&str]) -> BusinessCalendar {
+        BusinessCalendar::from_calendar(&SlaCalendar {
+            company_name: "Acme".to_string(),
+            utc_offset_minutes,
+            workdays: vec![1, 2, 3, 4, 5],
+            day_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            day_end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            holidays: holidays.iter().map(|day| day.parse().unwrap()).collect(),
+        })
+    }
+
+    fn at(instant: &str) -> DateTime<Utc> {
+        format!("{}Z", instant).parse().unwrap()
+    }
+
+    #[test]
+    fn adds_minutes_within_a_working_day() {
+        let calendar = office_hours(0, &[]);
+        assert_eq!(calendar.add_business_minutes(at("2024-03-01T10:00:00"), 60), at("2024-03-01T11:00:00"));
+    }
+
+    #[test]
+    fn carries_minutes_over_the_night() {
+        let calendar = office_hours(0, &[]);
+        assert_eq!(calendar.add_business_minutes(at("2024-02-29T16:00:00"), 120), at("2024-03-01T10:00:00"));
+        assert_eq!(calendar.business_minutes_between(at("2024-02-29T16:00:00"), at("2024-03-01T10:00:00")), 120);
+    }
+
+    #[test]
+    fn skips_weekends() {
+        let calendar = office_hours(0, &[]);
+        assert_eq!(calendar.add_business_minutes(at("2024-03-01T16:30:00"), 60), at("2024-03-04T09:30:00"));
+        assert_eq!(calendar.add_business_minutes(at("2024-03-02T12:00:00"), 30), at("2024-03-04T09:30:00"));
+        assert_eq!(calendar.business_minutes_between(at("2024-03-01T16:30:00"), at("2024-03-04T09:30:00")), 60);
+    }
+
+    #[test]
+    fn skips_holidays() {
+        let calendar = office_hours(0, &["2024-03-04"]);
+        assert_eq!(calendar.add_business_minutes(at("2024-03-01T16:30:00"), 60), at("2024-03-05T09:30:00"));
+        assert_eq!(calendar.business_minutes_between(at("2024-03-01T16:00:00"), at("2024-03-05T10:00:00")), 120);
+    }
+
+    #[test]
+    fn works_in_the_company_time_zone() {
+        // 16:30 local on a Friday, two hours ahead of UTC
+        let calendar = office_hours(120, &[]);
+        assert_eq!(calendar.add_business_minutes(at("2024-03-01T14:30:00"), 60), at("2024-03-04T07:30:00"));
+    }
+
+    #[test]
+    fn adding_and_measuring_agree() {
+        let calendar = office_hours(0, &["2024-03-04"]);
+        let start = at("2024-02-28T13:17:00");
+        for minutes in [0, 1, 480, 1000, 5000] {
+            let due = calendar.add_business_minutes(start, minutes);
+            assert_eq!(calendar.business_minutes_between(start, due), minutes);
+        }
+    }
+
+    #[test]
+    fn nothing_elapses_backwards() {
+        let calendar = office_hours(0, &[]);
+        assert_eq!(calendar.business_minutes_between(at("2024-03-01T12:00:00"), at("2024-03-01T10:00:00")), 0);
+    }
+}
@@ -2,6 +2,8 @@ use std::error::Error;
 use sqlx::postgres::PgPoolOptions;
 use std::env;
 use dotenvy::dotenv;
+use std::sync::Arc;
+use crate::storage::blob::{BlobStore, LocalBlobStore};
 
 mod metrics;
 mod server;
@@ -9,6 +11,9 @@ mod user;
 mod error;
 mod auth;
 mod functionalities;
+mod audit;
+mod device;
+mod storage;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -22,7 +27,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .connect(&database_url)
         .await
         .expect("Failed to connect to PostgreSQL");
+
+    // Bring the schema up to date before serving requests
+    sqlx::migrate!("./migrations").run(&pool).await?;
+    tokio::spawn(functionalities::sla::run_sla_breach_monitor(pool.clone()));
+    tokio::spawn(functionalities::schedules::run_maintenance_scheduler(pool.clone()));
+    tokio::spawn(functionalities::alerts::run_alert_evaluator(pool.clone()));
+    tokio::spawn(functionalities::assign::run_system_return_monitor(pool.clone()));
+    tokio::spawn(device::heartbeat::run_device_status_monitor(pool.clone()));
+    let blob_store: Arc<dyn BlobStore> = Arc::new(LocalBlobStore::from_env());
     println!("Listening on port 8080");
-    server::run_server(pool).await;
+    server::run_server(pool, blob_store).await;
     Ok(())
 }
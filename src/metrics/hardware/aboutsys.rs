@@ -4,12 +4,12 @@ use serde::Serialize;
 use sqlx::PgPool;
 use chrono::Utc;
 use tokio::time::{interval, Duration};
+use crate::device::enrollment::AuthenticatedDevice;
 
 
 #[derive(Serialize, Clone)]
 pub struct SystemInfo {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub name: Option<String>,
     pub hostname: Option<String>,
     pub os_version: Option<String>,
@@ -20,8 +20,7 @@ pub struct SystemInfo {
 
 impl SystemInfo {
     pub fn new(
-        sub_admin_metrics_id: Option<i32>,
-        staff_metrics_id: Option<i32>,
+        device_id: Option<i64>,
         name: Option<String>,
         hostname: Option<String>,
         os_version: Option<String>,
@@ -29,8 +28,7 @@ impl SystemInfo {
     ) -> Self {
         let now = Utc::now();
         Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
+            device_id,
             name,
             hostname,
             os_version,
@@ -63,11 +61,11 @@ impl SystemInfo {
 
 pub async fn get_system_info_handler(
     pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
+    let device_id = device.device_id;
 
-    match get_system_info_for_user(&pool, user_id_value).await {
+    match get_system_info_for_device(&pool, device_id).await {
         Ok(mut system_info) => {
             let mut sys = System::new_all();
             sys.refresh_all();
@@ -85,7 +83,7 @@ pub async fn get_system_info_handler(
             HttpResponse::Ok().json(system_info)
         }
         Err(_) => {
-            let new_system_info = gather_system_info(Some(user_id_value), None).await;
+            let new_system_info = gather_system_info(Some(device_id)).await;
             if let Err(e) = save_systeminfo_metrics_to_database(&pool, &new_system_info).await {
                 eprintln!("Failed to save metrics to database: {}", e);
                 return HttpResponse::InternalServerError().body("Failed to save system info");
@@ -96,8 +94,7 @@ pub async fn get_system_info_handler(
 }
 
 pub async fn gather_system_info(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
+    device_id: Option<i64>,
 ) -> SystemInfo {
     let mut interval = interval(Duration::from_secs(3600)); // Update every hour
     loop {
@@ -111,8 +108,7 @@ pub async fn gather_system_info(
     let kernel_version = Some(System::kernel_version().unwrap_or_default());
 
     return SystemInfo::new(
-        sub_admin_metrics_id,
-        staff_metrics_id,
+        device_id,
         name,
         hostname,
         os_version,
@@ -125,9 +121,8 @@ pub async fn save_systeminfo_metrics_to_database(
     metrics: &SystemInfo,
 ) -> Result<(), Box<dyn std::error::Error>> {
     sqlx::query!(
-        "INSERT INTO systeminfo_metrics (sub_admin_metrics_id, staff_metrics_id, name, hostname, os_version, kernel_version, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-        metrics.sub_admin_metrics_id,
-        metrics.staff_metrics_id,
+        "INSERT INTO systeminfo_metrics (device_id, name, hostname, os_version, kernel_version, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        metrics.device_id,
         metrics.name,
         metrics.hostname,
         metrics.os_version,
@@ -140,16 +135,16 @@ pub async fn save_systeminfo_metrics_to_database(
     Ok(())
 }
 
-async fn get_system_info_for_user(
+async fn get_system_info_for_device(
     pool: &PgPool,
-    user_id: i32,
+    device_id: i64,
 ) -> Result<SystemInfo, sqlx::Error> {
     sqlx::query_as!(
         SystemInfo,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, name, hostname, os_version, kernel_version, created_at, updated_at 
+        "SELECT device_id, name, hostname, os_version, kernel_version, created_at, updated_at 
         FROM systeminfo_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        WHERE device_id = $1",
+        device_id
     )
     .fetch_one(pool)
     .await
@@ -158,7 +153,7 @@ async fn get_system_info_for_user(
 pub async fn get_system_info(pool: &PgPool) -> Result<Vec<SystemInfo>, sqlx::Error> {
     sqlx::query_as!(
         SystemInfo,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, name, hostname, os_version, kernel_version, created_at, updated_at FROM systeminfo_metrics"
+        "SELECT device_id, name, hostname, os_version, kernel_version, created_at, updated_at FROM systeminfo_metrics"
     )
     .fetch_all(pool)
     .await
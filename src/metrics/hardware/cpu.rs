@@ -4,23 +4,22 @@ use serde::Serialize;
 use sqlx::PgPool;
 use chrono::prelude::*;
 use tokio::time::{interval, Duration};
+use crate::device::enrollment::AuthenticatedDevice;
 
 #[derive(Serialize, Clone)]
 pub struct CpuMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub last_refresh: Option<NaiveDateTime>,
     pub cpu_info: Option<String>,
     pub usage_summary: Option<String>,
 }
 
 impl CpuMetrics {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, cpu_info: Option<String>, usage_summary: Option<String>) -> Self {
+    pub fn new(device_id: Option<i64>, cpu_info: Option<String>, usage_summary: Option<String>) -> Self {
         let now: DateTime<Utc> = Utc::now();
         let timestamp = now.naive_utc();
         Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
+            device_id,
             last_refresh: Some(timestamp),
             cpu_info,
             usage_summary,
@@ -36,11 +35,11 @@ impl CpuMetrics {
 
 pub async fn get_cpu_info_handler(
     pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
+    let device_id = device.device_id;
 
-    match get_cpu_info_for_user(&pool, user_id_value).await {
+    match get_cpu_info_for_device(&pool, device_id).await {
         Ok(mut cpu_metrics) => {
             let mut sys = System::new_all();
             sys.refresh_cpu();
@@ -64,7 +63,7 @@ pub async fn get_cpu_info_handler(
             HttpResponse::Ok().json(cpu_metrics)
         }
         Err(_) => {
-            let new_cpu_metrics = gather_cpu_metrics(Some(user_id_value), None).await;
+            let new_cpu_metrics = gather_cpu_metrics(Some(device_id)).await;
             if let Err(e) = save_cpu_metrics_to_database(&pool, &new_cpu_metrics).await {
                 eprintln!("Failed to save metrics to database: {}", e);
                 return HttpResponse::InternalServerError().body("Failed to save CPU info");
@@ -75,8 +74,7 @@ pub async fn get_cpu_info_handler(
 }
 
 pub async fn gather_cpu_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
+    device_id: Option<i64>,
 ) -> CpuMetrics {
     let mut interval = interval(Duration::from_secs(3600)); // Update every hour
     loop {
@@ -96,8 +94,7 @@ pub async fn gather_cpu_metrics(
     );
 
     return CpuMetrics::new(
-        sub_admin_metrics_id,
-        staff_metrics_id,
+        device_id,
         cpu_info,
         Some(usage_summary),
     )
@@ -108,13 +105,12 @@ pub async fn save_cpu_metrics_to_database(
     metrics: &CpuMetrics,
 ) -> Result<(), Box<dyn std::error::Error>> {
     sqlx::query!(
-        "INSERT INTO cpu_metrics (sub_admin_metrics_id, staff_metrics_id, last_refresh, cpu_info, usage_summary) VALUES ($1, $2, $3, $4, $5)
-        ON CONFLICT (sub_admin_metrics_id) DO UPDATE SET 
+        "INSERT INTO cpu_metrics (device_id, last_refresh, cpu_info, usage_summary) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (device_id) DO UPDATE SET 
             last_refresh = EXCLUDED.last_refresh, 
             cpu_info = EXCLUDED.cpu_info, 
             usage_summary = EXCLUDED.usage_summary",
-        metrics.sub_admin_metrics_id,
-        metrics.staff_metrics_id,
+        metrics.device_id,
         metrics.last_refresh,
         metrics.cpu_info,
         metrics.usage_summary
@@ -124,16 +120,16 @@ pub async fn save_cpu_metrics_to_database(
     Ok(())
 }
 
-async fn get_cpu_info_for_user(
+async fn get_cpu_info_for_device(
     pool: &PgPool,
-    user_id: i32,
+    device_id: i64,
 ) -> Result<CpuMetrics, sqlx::Error> {
     sqlx::query_as!(
         CpuMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, last_refresh, cpu_info, usage_summary 
+        "SELECT device_id, last_refresh, cpu_info, usage_summary 
         FROM cpu_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        WHERE device_id = $1",
+        device_id
     )
     .fetch_one(pool)
     .await
@@ -160,7 +156,7 @@ fn calculate_min_cpu_usage(_sub_admin_metrics_id: i32) -> String {
 pub async fn get_cpu_info(pool: &PgPool) -> Result<Vec<CpuMetrics>, sqlx::Error> {
     sqlx::query_as!(
         CpuMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, last_refresh, cpu_info, usage_summary FROM cpu_metrics"
+        "SELECT device_id, last_refresh, cpu_info, usage_summary FROM cpu_metrics"
     )
     .fetch_all(pool)
     .await
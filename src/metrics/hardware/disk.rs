@@ -1,112 +1,428 @@
 use sysinfo::Disks;
-use serde::Serialize;
-use sqlx::PgPool;
-// use std::sync::Arc;
-use tokio::time::{interval, Duration};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
 use actix_web::{web, HttpResponse, Responder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
 
+const DISKSTATS_PATH: &str = "/proc/diskstats";
+// diskstats counts 512-byte sectors whatever the disk's own sector size
+const SECTOR_BYTES: i64 = 512;
+const DEFAULT_HISTORY_LIMIT: i64 = 100;
+const MAX_HISTORY_LIMIT: i64 = 1000;
 
+/// Device-wide totals in MiB, kept for readers of `disk_metrics`.
 #[derive(Serialize, Clone)]
 pub struct DiskMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub total_space: Option<f64>,
     pub available_space: Option<f64>,
 }
 
-impl DiskMetrics {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, total_space: Option<f64>, available_space: Option<f64>) -> Self {
-        Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
-            total_space,
-            available_space,
-        }
-    }
+/// One mounted filesystem at one moment. I/O counters are those of its block
+/// device, cumulative since boot; rates are against the previous stored sample.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DiskVolumeMetrics {
+    pub device_id: Option<i64>,
+    pub name: String,
+    pub mount_point: String,
+    pub file_system: Option<String>,
+    pub kind: String,
+    pub removable: bool,
+    pub total_bytes: i64,
+    pub available_bytes: i64,
+    pub read_bytes: Option<i64>,
+    pub written_bytes: Option<i64>,
+    pub reads_completed: Option<i64>,
+    pub writes_completed: Option<i64>,
+    pub read_bytes_per_sec: Option<f64>,
+    pub write_bytes_per_sec: Option<f64>,
+    pub read_iops: Option<f64>,
+    pub write_iops: Option<f64>,
+    pub counter_reset: bool,
+    pub recorded_at: Option<DateTime<Utc>>,
+}
 
-    pub fn update(&mut self, new_total_space: Option<f64>, new_available_space: Option<f64>) {
-        self.total_space = new_total_space;
-        self.available_space = new_available_space;
+impl DiskVolumeMetrics {
+    fn counters(&self) -> [Option<i64>; 4] {
+        [self.read_bytes, self.written_bytes, self.reads_completed, self.writes_completed]
     }
-}
 
-pub async fn get_disk_info_handler(
-    pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
-) -> impl Responder {
-    let user_id_value = user_id.into_inner(); 
-    match get_cpu_info_for_user(&pool, user_id_value).await {
-        Ok(mut disk_metrics) => {
-        let disks = Disks::new_with_refreshed_list();
-        let total_space: Option<f64> = disks.iter().map(|d| Some(d.total_space() as f64)).sum::<Option<f64>>().map(|total| total / 1_048_576.0);
-        let available_space: Option<f64> = disks.iter().map(|d| Some(d.available_space() as f64)).sum::<Option<f64>>().map(|available| available / 1_048_576.0);
-
-
-        disk_metrics.update(total_space, available_space);
-        if let Err(e) = save_disk_metrics_to_database(&pool, &disk_metrics).await {
-            eprintln!("Failed to save metrics to database: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to save CPU info");
-        }   
-        HttpResponse::Ok().json(disk_metrics)
+    // Counters only grow until the machine reboots
+    fn counters_reset_since(&self, previous: &DiskVolumeMetrics) -> bool {
+        self.counters()
+            .iter()
+            .zip(previous.counters().iter())
+            .any(|pair| matches!(pair, (Some(current), Some(before)) if current < before))
     }
-    Err(_) => {
-        let new_disk_metrics = gather_disk_metrics(Some(user_id_value), None).await;
-        if let Err(e) = save_disk_metrics_to_database(&pool, &new_disk_metrics).await {
-            eprintln!("Failed to save metrics to database: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to save CPU info");
+
+    /// Fills in the rates against `previous`, or flags a counter reset.
+    fn apply_previous(&mut self, previous: &DiskVolumeMetrics, recorded_at: DateTime<Utc>) {
+        if self.counters_reset_since(previous) {
+            self.counter_reset = true;
+            return;
+        }
+        let Some(previous_at) = previous.recorded_at else {
+            return;
+        };
+        let elapsed = (recorded_at - previous_at).num_milliseconds() as f64 / 1000.0;
+        if elapsed <= 0.0 {
+            return;
         }
-        HttpResponse::Ok().json(new_disk_metrics)
+        let rate = |current: Option<i64>, before: Option<i64>| Some((current? - before?) as f64 / elapsed);
+        self.read_bytes_per_sec = rate(self.read_bytes, previous.read_bytes);
+        self.write_bytes_per_sec = rate(self.written_bytes, previous.written_bytes);
+        self.read_iops = rate(self.reads_completed, previous.reads_completed);
+        self.write_iops = rate(self.writes_completed, previous.writes_completed);
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct DiskHistoryQuery {
+    pub mount_point: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
 }
 
-pub async fn gather_disk_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
-) -> DiskMetrics {
-    let mut interval = interval(Duration::from_secs(3600)); // Update every hour
-    loop {
-    interval.tick().await;
-    let disks = Disks::new_with_refreshed_list();
-    let total_space: Option<f64> = disks.iter().map(|d| Some(d.total_space() as f64)).sum::<Option<f64>>().map(|total| total / 1_048_576.0);
-    let available_space: Option<f64> = disks.iter().map(|d| Some(d.available_space() as f64)).sum::<Option<f64>>().map(|available| available / 1_048_576.0);
-    return DiskMetrics::new(sub_admin_metrics_id, staff_metrics_id, total_space, available_space)
-}
-}
-pub async fn save_disk_metrics_to_database(pool: &PgPool, metrics: &DiskMetrics) -> Result<(), Box<dyn std::error::Error>> {
-        sqlx::query!(
-            "INSERT INTO disk_metrics (sub_admin_metrics_id, staff_metrics_id, total_space, available_space) VALUES ($1, $2, $3, $4)",
-            metrics.sub_admin_metrics_id,
-            metrics.staff_metrics_id,
-            metrics.total_space,
-            metrics.available_space,
-        )
-        .execute(pool)
-        .await?;
-    Ok(())
+/// Cumulative I/O of one block device, from a line of /proc/diskstats.
+#[derive(Debug, Clone, Copy)]
+struct BlockDeviceIo {
+    reads_completed: i64,
+    read_bytes: i64,
+    writes_completed: i64,
+    written_bytes: i64,
+}
+
+// Fields after the name: reads completed, reads merged, sectors read, time
+// reading, writes completed, writes merged, sectors written, ...
+fn parse_diskstats(contents: &str) -> HashMap<String, BlockDeviceIo> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let counter = |index: usize| fields.get(index).and_then(|field| field.parse::<i64>().ok());
+            Some((
+                fields.get(2)?.to_string(),
+                BlockDeviceIo {
+                    reads_completed: counter(3)?,
+                    read_bytes: counter(5)? * SECTOR_BYTES,
+                    writes_completed: counter(7)?,
+                    written_bytes: counter(9)? * SECTOR_BYTES,
+                },
+            ))
+        })
+        .collect()
+}
+
+// /dev/mapper and /dev/disk/by-* names are links to the kernel name diskstats uses
+fn block_device_name(name: &str) -> Option<String> {
+    block_device_name_under(Path::new("/dev"), name)
+}
+
+fn block_device_name_under(dev: &Path, name: &str) -> Option<String> {
+    let path = Path::new(name);
+    if !path.starts_with(dev) {
+        return None;
     }
-   
+    let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    resolved.file_name().map(|file_name| file_name.to_string_lossy().into_owned())
+}
 
-pub async fn get_disk_info(pool: &PgPool) -> Result<Vec<DiskMetrics>, sqlx::Error> {
+/// Reads capacity and cumulative I/O of every mounted filesystem.
+pub fn gather_disk_metrics(device_id: Option<i64>) -> Vec<DiskVolumeMetrics> {
+    let disks = Disks::new_with_refreshed_list();
+    let diskstats = match fs::read_to_string(DISKSTATS_PATH) {
+        Ok(contents) => parse_diskstats(&contents),
+        Err(e) => {
+            error!("Failed to read {}: {:?}", DISKSTATS_PATH, e);
+            HashMap::new()
+        }
+    };
+
+    let mut volumes: Vec<DiskVolumeMetrics> = disks
+        .iter()
+        .map(|disk| {
+            let name = disk.name().to_string_lossy().into_owned();
+            let io = block_device_name(&name).and_then(|block_device| diskstats.get(&block_device).copied());
+            DiskVolumeMetrics {
+                device_id,
+                mount_point: disk.mount_point().to_string_lossy().into_owned(),
+                file_system: Some(disk.file_system().to_string_lossy().into_owned()).filter(|file_system| !file_system.is_empty()),
+                kind: disk.kind().to_string(),
+                removable: disk.is_removable(),
+                total_bytes: disk.total_space() as i64,
+                available_bytes: disk.available_space() as i64,
+                read_bytes: io.map(|io| io.read_bytes),
+                written_bytes: io.map(|io| io.written_bytes),
+                reads_completed: io.map(|io| io.reads_completed),
+                writes_completed: io.map(|io| io.writes_completed),
+                read_bytes_per_sec: None,
+                write_bytes_per_sec: None,
+                read_iops: None,
+                write_iops: None,
+                counter_reset: false,
+                recorded_at: None,
+                name,
+            }
+        })
+        .collect();
+    volumes.sort_by(|a, b| a.mount_point.cmp(&b.mount_point));
+    volumes
+}
+
+async fn fetch_latest_volumes<'e, E>(executor: E, device_id: i64) -> Result<Vec<DiskVolumeMetrics>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
     sqlx::query_as!(
-        DiskMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, total_space, available_space FROM disk_metrics"
+        DiskVolumeMetrics,
+        "SELECT DISTINCT ON (mount_point) device_id, name, mount_point, file_system, kind, removable, total_bytes, available_bytes,
+            read_bytes, written_bytes, reads_completed, writes_completed, read_bytes_per_sec, write_bytes_per_sec, read_iops, write_iops,
+            counter_reset, recorded_at
+        FROM disk_volume_metrics WHERE device_id = $1
+        ORDER BY mount_point, recorded_at DESC, id DESC",
+        device_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await
 }
 
-async fn get_cpu_info_for_user(
+/// Stores one sample of every mounted filesystem, with rates against the
+/// previous sample, and the device totals alongside. Returns the stored volumes.
+pub async fn save_disk_metrics_to_database(
     pool: &PgPool,
-    user_id: i32,
-) -> Result<DiskMetrics, sqlx::Error> {
+    device_id: i64,
+    volumes: &[DiskVolumeMetrics],
+) -> Result<Vec<DiskVolumeMetrics>, sqlx::Error> {
+    let recorded_at = Utc::now();
+    let mut tx = pool.begin().await?;
+
+    let previous: HashMap<String, DiskVolumeMetrics> = fetch_latest_volumes(&mut *tx, device_id)
+        .await?
+        .into_iter()
+        .map(|volume| (volume.mount_point.clone(), volume))
+        .collect();
+
+    let volumes: Vec<DiskVolumeMetrics> = volumes
+        .iter()
+        .cloned()
+        .map(|mut volume| {
+            volume.device_id = Some(device_id);
+            if let Some(previous) = previous.get(&volume.mount_point) {
+                volume.apply_previous(previous, recorded_at);
+            }
+            volume.recorded_at = Some(recorded_at);
+            volume
+        })
+        .collect();
+
+    let names: Vec<String> = volumes.iter().map(|volume| volume.name.clone()).collect();
+    let mount_points: Vec<String> = volumes.iter().map(|volume| volume.mount_point.clone()).collect();
+    let file_systems: Vec<Option<String>> = volumes.iter().map(|volume| volume.file_system.clone()).collect();
+    let kinds: Vec<String> = volumes.iter().map(|volume| volume.kind.clone()).collect();
+    let removables: Vec<bool> = volumes.iter().map(|volume| volume.removable).collect();
+    let total_bytes: Vec<i64> = volumes.iter().map(|volume| volume.total_bytes).collect();
+    let available_bytes: Vec<i64> = volumes.iter().map(|volume| volume.available_bytes).collect();
+    let read_bytes: Vec<Option<i64>> = volumes.iter().map(|volume| volume.read_bytes).collect();
+    let written_bytes: Vec<Option<i64>> = volumes.iter().map(|volume| volume.written_bytes).collect();
+    let reads_completed: Vec<Option<i64>> = volumes.iter().map(|volume| volume.reads_completed).collect();
+    let writes_completed: Vec<Option<i64>> = volumes.iter().map(|volume| volume.writes_completed).collect();
+    let read_rates: Vec<Option<f64>> = volumes.iter().map(|volume| volume.read_bytes_per_sec).collect();
+    let write_rates: Vec<Option<f64>> = volumes.iter().map(|volume| volume.write_bytes_per_sec).collect();
+    let read_iops: Vec<Option<f64>> = volumes.iter().map(|volume| volume.read_iops).collect();
+    let write_iops: Vec<Option<f64>> = volumes.iter().map(|volume| volume.write_iops).collect();
+    let counter_resets: Vec<bool> = volumes.iter().map(|volume| volume.counter_reset).collect();
+
+    sqlx::query!(
+        "INSERT INTO disk_volume_metrics (device_id, recorded_at, name, mount_point, file_system, kind, removable, total_bytes, available_bytes,
+            read_bytes, written_bytes, reads_completed, writes_completed, read_bytes_per_sec, write_bytes_per_sec, read_iops, write_iops, counter_reset)
+        SELECT $1, $2, v.*
+        FROM unnest($3::text[], $4::text[], $5::text[], $6::text[], $7::bool[], $8::int8[], $9::int8[], $10::int8[], $11::int8[], $12::int8[], $13::int8[],
+            $14::float8[], $15::float8[], $16::float8[], $17::float8[], $18::bool[])
+            AS v(name, mount_point, file_system, kind, removable, total_bytes, available_bytes, read_bytes, written_bytes, reads_completed, writes_completed,
+                read_bytes_per_sec, write_bytes_per_sec, read_iops, write_iops, counter_reset)",
+        device_id,
+        recorded_at,
+        &names,
+        &mount_points,
+        &file_systems as &[Option<String>],
+        &kinds,
+        &removables,
+        &total_bytes,
+        &available_bytes,
+        &read_bytes as &[Option<i64>],
+        &written_bytes as &[Option<i64>],
+        &reads_completed as &[Option<i64>],
+        &writes_completed as &[Option<i64>],
+        &read_rates as &[Option<f64>],
+        &write_rates as &[Option<f64>],
+        &read_iops as &[Option<f64>],
+        &write_iops as &[Option<f64>],
+        &counter_resets,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let totals = DiskMetrics {
+        device_id: Some(device_id),
+        total_space: Some(volumes.iter().map(|volume| volume.total_bytes as f64).sum::<f64>() / 1_048_576.0),
+        available_space: Some(volumes.iter().map(|volume| volume.available_bytes as f64).sum::<f64>() / 1_048_576.0),
+    };
+    sqlx::query!(
+        "INSERT INTO disk_metrics (device_id, total_space, available_space, recorded_at) VALUES ($1, $2, $3, $4)",
+        totals.device_id,
+        totals.total_space,
+        totals.available_space,
+        recorded_at,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(volumes)
+}
+
+pub async fn get_disk_info_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let device_id = device.device_id;
+    let volumes = gather_disk_metrics(Some(device_id));
+
+    match save_disk_metrics_to_database(&pool, device_id, &volumes).await {
+        Ok(volumes) => HttpResponse::Ok().json(volumes),
+        Err(e) => {
+            error!("Failed to save disk metrics of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save disk info")
+        }
+    }
+}
+
+async fn fetch_volume_history(
+    pool: &PgPool,
+    device_id: i64,
+    query: &DiskHistoryQuery,
+) -> Result<Vec<DiskVolumeMetrics>, sqlx::Error> {
     sqlx::query_as!(
-        DiskMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, total_space, available_space 
-        FROM disk_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        DiskVolumeMetrics,
+        "SELECT device_id, name, mount_point, file_system, kind, removable, total_bytes, available_bytes,
+            read_bytes, written_bytes, reads_completed, writes_completed, read_bytes_per_sec, write_bytes_per_sec, read_iops, write_iops,
+            counter_reset, recorded_at
+        FROM disk_volume_metrics
+        WHERE device_id = $1
+            AND ($2::text IS NULL OR mount_point = $2)
+            AND ($3::timestamptz IS NULL OR recorded_at >= $3)
+        ORDER BY recorded_at DESC, mount_point
+        LIMIT $4",
+        device_id,
+        query.mount_point,
+        query.since,
+        query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT),
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await
 }
+
+/// The latest sample of each of the device's mounted filesystems.
+pub async fn get_device_disks(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_latest_volumes(pool.get_ref(), device_id).await {
+        Ok(volumes) => HttpResponse::Ok().json(volumes),
+        Err(e) => {
+            error!("Failed to fetch disks of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch disk info")
+        }
+    }
+}
+
+/// Past filesystem samples, newest first, optionally for one mount point.
+pub async fn get_device_disk_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<DiskHistoryQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_volume_history(&pool, device_id, &query).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch disk history of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch disk info")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From a laptop with an LVM volume group on an NVMe partition
+    const DISKSTATS: &str = "\
+ 259       0 nvme0n1 193640 61052 14113874 48761 423107 297412 29744136 517260 0 254440 618574 0 0 0 0 37462 52552
+ 259       1 nvme0n1p1 412 1420 18542 96 2 0 2 0 0 124 97 0 0 0 0 0 0
+ 259       2 nvme0n1p2 193118 59632 14091132 48641 423105 297412 29744134 517260 0 254244 565901 0 0 0 0 0 0
+ 253       0 dm-0 252503 0 14084130 89740 720517 0 29744134 1394160 0 262088 1483900 0 0 0 0 0 0
+   7       0 loop0 52 0 2134 9 0 0 0 0 0 40 9
+   8      16 sdb
+";
+
+    #[test]
+    fn reads_disks_partitions_and_mapped_devices() {
+        let diskstats = parse_diskstats(DISKSTATS);
+
+        let partition = diskstats["nvme0n1p2"];
+        assert_eq!(partition.reads_completed, 193118);
+        assert_eq!(partition.read_bytes, 14091132 * SECTOR_BYTES);
+        assert_eq!(partition.writes_completed, 423105);
+        assert_eq!(partition.written_bytes, 29744134 * SECTOR_BYTES);
+
+        let mapped = diskstats["dm-0"];
+        assert_eq!(mapped.reads_completed, 252503);
+        assert_eq!(mapped.writes_completed, 720517);
+        assert!(diskstats.contains_key("nvme0n1"));
+        assert!(diskstats.contains_key("nvme0n1p1"));
+    }
+
+    #[test]
+    fn keeps_old_kernel_lines_and_skips_short_ones() {
+        let diskstats = parse_diskstats(DISKSTATS);
+        // Kernels before 4.18 stop after the eleventh counter
+        assert_eq!(diskstats["loop0"].read_bytes, 2134 * SECTOR_BYTES);
+        assert!(!diskstats.contains_key("sdb"));
+        assert_eq!(diskstats.len(), 5);
+    }
+
+    #[test]
+    fn resolves_mapper_links_to_kernel_names() {
+        let dev = std::env::temp_dir().join(format!("disk-test-dev-{}", std::process::id()));
+        fs::create_dir_all(dev.join("mapper")).unwrap();
+        fs::write(dev.join("dm-0"), "").unwrap();
+        std::os::unix::fs::symlink("../dm-0", dev.join("mapper/vg-root")).unwrap();
+        let dev = fs::canonicalize(&dev).unwrap();
+
+        let mapped = dev.join("mapper/vg-root");
+        assert_eq!(block_device_name_under(&dev, mapped.to_str().unwrap()).as_deref(), Some("dm-0"));
+        // Names that don't resolve still match diskstats by their own name
+        let missing = dev.join("nvme0n1p2");
+        assert_eq!(block_device_name_under(&dev, missing.to_str().unwrap()).as_deref(), Some("nvme0n1p2"));
+        assert_eq!(block_device_name_under(&dev, "overlay"), None);
+
+        fs::remove_dir_all(&dev).unwrap();
+    }
+}
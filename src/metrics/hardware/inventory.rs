@@ -0,0 +1,302 @@
+use actix_web::{web, HttpResponse, Responder};
+use sysinfo::System;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::Path;
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
+
+const DMI_DIR: &str = "/sys/class/dmi/id";
+const BLOCK_DIR: &str = "/sys/block";
+const NET_DIR: &str = "/sys/class/net";
+
+/// What a device is built from. Fields sit at the top level so a change
+/// record can name exactly what moved (say `bios_version`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HardwareInventory {
+    pub cpu_model: Option<String>,
+    pub cpu_vendor: Option<String>,
+    pub physical_cores: Option<usize>,
+    pub logical_cores: usize,
+    pub total_memory_bytes: u64,
+    pub disks: Vec<DiskInventory>,
+    pub network_interfaces: Vec<NetworkInterfaceInventory>,
+    pub system_vendor: Option<String>,
+    pub product_name: Option<String>,
+    pub product_serial: Option<String>,
+    pub bios_vendor: Option<String>,
+    pub bios_version: Option<String>,
+    pub bios_date: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DiskInventory {
+    pub name: String,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub size_bytes: u64,
+    pub rotational: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NetworkInterfaceInventory {
+    pub name: String,
+    pub mac_address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct InventoryRecord {
+    pub id: i64,
+    pub device_id: i64,
+    pub version: i32,
+    pub document: Value,
+    pub document_hash: String,
+    pub changes: Value,
+    pub collected_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+// sysfs values end in a newline and unreadable or blank ones mean "unknown"
+fn read_sys_value(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Only block devices backed by hardware have a `device` link; loop, ram and
+// device-mapper entries do not.
+fn collect_disks() -> Vec<DiskInventory> {
+    let Ok(entries) = fs::read_dir(BLOCK_DIR) else {
+        return Vec::new();
+    };
+    let mut disks: Vec<DiskInventory> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("device").exists())
+        .map(|entry| {
+            let path = entry.path();
+            // `size` counts 512-byte sectors whatever the disk's own sector size
+            let sectors: u64 = read_sys_value(path.join("size")).and_then(|size| size.parse().ok()).unwrap_or(0);
+            DiskInventory {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                model: read_sys_value(path.join("device/model")),
+                serial: read_sys_value(path.join("device/serial")),
+                size_bytes: sectors * 512,
+                rotational: read_sys_value(path.join("queue/rotational")).map(|rotational| rotational == "1"),
+            }
+        })
+        .collect();
+    disks.sort_by(|a, b| a.name.cmp(&b.name));
+    disks
+}
+
+// Physical interfaces only; bridges, veths and loopback have no `device` link
+fn collect_network_interfaces() -> Vec<NetworkInterfaceInventory> {
+    let Ok(entries) = fs::read_dir(NET_DIR) else {
+        return Vec::new();
+    };
+    let mut interfaces: Vec<NetworkInterfaceInventory> = entries
+        .flatten()
+        .filter(|entry| entry.path().join("device").exists())
+        .map(|entry| NetworkInterfaceInventory {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            mac_address: read_sys_value(entry.path().join("address")).filter(|mac| mac != "00:00:00:00:00:00"),
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces
+}
+
+pub fn gather_hardware_inventory() -> HardwareInventory {
+    let mut sys = System::new();
+    sys.refresh_cpu();
+    sys.refresh_memory();
+    let dmi = Path::new(DMI_DIR);
+    let cpu = sys.cpus().first();
+
+    HardwareInventory {
+        cpu_model: cpu.map(|cpu| cpu.brand().trim().to_string()).filter(|brand| !brand.is_empty()),
+        cpu_vendor: cpu.map(|cpu| cpu.vendor_id().to_string()).filter(|vendor| !vendor.is_empty()),
+        physical_cores: sys.physical_core_count(),
+        logical_cores: sys.cpus().len(),
+        total_memory_bytes: sys.total_memory(),
+        disks: collect_disks(),
+        network_interfaces: collect_network_interfaces(),
+        system_vendor: read_sys_value(dmi.join("sys_vendor")),
+        product_name: read_sys_value(dmi.join("product_name")),
+        // Readable by root only on most distributions
+        product_serial: read_sys_value(dmi.join("product_serial")),
+        bios_vendor: read_sys_value(dmi.join("bios_vendor")),
+        bios_version: read_sys_value(dmi.join("bios_version")),
+        bios_date: read_sys_value(dmi.join("bios_date")),
+    }
+}
+
+/// Top-level fields that differ between two inventory documents.
+fn diff_documents(before: &Value, after: &Value) -> Value {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    let changes: Vec<Value> = fields
+        .into_iter()
+        .filter(|field| before.get(*field) != after.get(*field))
+        .map(|field| {
+            json!({
+                "field": field,
+                "before": before.get(field).cloned().unwrap_or(Value::Null),
+                "after": after.get(field).cloned().unwrap_or(Value::Null),
+            })
+        })
+        .collect();
+    Value::Array(changes)
+}
+
+/// Stores `document` as the device's next inventory version, or only marks
+/// the latest version as seen again when nothing changed.
+async fn save_inventory_to_database(pool: &PgPool, device_id: i64, document: Value) -> Result<InventoryRecord, sqlx::Error> {
+    let document_hash = format!("{:x}", Sha256::digest(document.to_string().as_bytes()));
+    let mut tx = pool.begin().await?;
+
+    let latest = sqlx::query_as!(
+        InventoryRecord,
+        "SELECT id, device_id, version, document, document_hash, changes, collected_at, last_seen_at
+        FROM hardware_inventories WHERE device_id = $1
+        ORDER BY version DESC
+        LIMIT 1
+        FOR UPDATE",
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let record = match latest {
+        Some(latest) if latest.document_hash == document_hash => {
+            sqlx::query_as!(
+                InventoryRecord,
+                "UPDATE hardware_inventories SET last_seen_at = now() WHERE id = $1
+                RETURNING id, device_id, version, document, document_hash, changes, collected_at, last_seen_at",
+                latest.id
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+        latest => {
+            let (version, changes) = match latest {
+                Some(latest) => (latest.version + 1, diff_documents(&latest.document, &document)),
+                None => (1, Value::Array(Vec::new())),
+            };
+            sqlx::query_as!(
+                InventoryRecord,
+                "INSERT INTO hardware_inventories (device_id, version, document, document_hash, changes)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING id, device_id, version, document, document_hash, changes, collected_at, last_seen_at",
+                device_id,
+                version,
+                document,
+                document_hash,
+                changes,
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+
+    tx.commit().await?;
+    Ok(record)
+}
+
+pub async fn get_inventory_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let inventory = gather_hardware_inventory();
+    let document = match serde_json::to_value(&inventory) {
+        Ok(document) => document,
+        Err(e) => {
+            error!("Failed to serialize hardware inventory: {:?}", e);
+            return HttpResponse::InternalServerError().body("Failed to save hardware inventory");
+        }
+    };
+
+    match save_inventory_to_database(&pool, device.device_id, document).await {
+        Ok(record) => HttpResponse::Ok().json(record),
+        Err(e) => {
+            error!("Failed to save hardware inventory of device {}: {:?}", device.device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save hardware inventory")
+        }
+    }
+}
+
+async fn fetch_latest_inventory(pool: &PgPool, device_id: i64) -> Result<Option<InventoryRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        InventoryRecord,
+        "SELECT id, device_id, version, document, document_hash, changes, collected_at, last_seen_at
+        FROM hardware_inventories WHERE device_id = $1
+        ORDER BY version DESC
+        LIMIT 1",
+        device_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_inventory_history(pool: &PgPool, device_id: i64) -> Result<Vec<InventoryRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        InventoryRecord,
+        "SELECT id, device_id, version, document, document_hash, changes, collected_at, last_seen_at
+        FROM hardware_inventories WHERE device_id = $1
+        ORDER BY version DESC",
+        device_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_device_inventory(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_latest_inventory(&pool, device_id).await {
+        Ok(Some(latest)) => HttpResponse::Ok().json(latest),
+        Ok(None) => HttpResponse::NotFound().body("No inventory collected for this device"),
+        Err(e) => {
+            error!("Failed to fetch inventory of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch hardware inventory")
+        }
+    }
+}
+
+/// Every inventory version of a device, newest first, with what changed.
+pub async fn get_device_inventory_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_inventory_history(&pool, device_id).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch inventory history of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch hardware inventory")
+        }
+    }
+}
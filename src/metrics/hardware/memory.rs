@@ -1,114 +1,285 @@
 use sysinfo::System;
-use serde::Serialize;
-use sqlx::PgPool;
-use tokio::time::{interval, Duration};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
 use actix_web::{web, HttpResponse, Responder};
+use std::fs;
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
 
-#[derive(Serialize, Clone)]
+const MEMINFO_PATH: &str = "/proc/meminfo";
+const PRESSURE_PATH: &str = "/proc/pressure/memory";
+const DEFAULT_HISTORY_LIMIT: i64 = 100;
+const MAX_HISTORY_LIMIT: i64 = 1000;
+
+/// Memory of a device at one moment. `total_memory` and `used_memory` are
+/// MiB for existing readers; every `_bytes` field is bytes. Used memory
+/// leaves out page cache, so compare `available_bytes` to tell a machine
+/// short on RAM from one with a large cache.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct MemoryMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub total_memory: Option<f64>,
     pub used_memory: Option<f64>,
+    pub total_bytes: Option<i64>,
+    pub used_bytes: Option<i64>,
+    pub available_bytes: Option<i64>,
+    pub free_bytes: Option<i64>,
+    pub cached_bytes: Option<i64>,
+    pub buffers_bytes: Option<i64>,
+    pub swap_total_bytes: Option<i64>,
+    pub swap_used_bytes: Option<i64>,
+    /// Percent of time some tasks stalled on memory, over 10s, 60s and 300s
+    pub pressure_some_avg10: Option<f64>,
+    pub pressure_some_avg60: Option<f64>,
+    pub pressure_some_avg300: Option<f64>,
+    /// Percent of time every non-idle task stalled on memory at once
+    pub pressure_full_avg10: Option<f64>,
+    pub pressure_full_avg60: Option<f64>,
+    pub pressure_full_avg300: Option<f64>,
+    pub recorded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemoryHistoryQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+/// Stall averages from one line of a PSI file.
+#[derive(Debug, Default, Clone, Copy)]
+struct PressureAverages {
+    avg10: Option<f64>,
+    avg60: Option<f64>,
+    avg300: Option<f64>,
+}
+
+// Values in /proc/meminfo are kB
+fn meminfo_bytes(meminfo: &str, field: &str) -> Option<i64> {
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kilobytes: i64 = value.split_whitespace().next()?.parse().ok()?;
+        Some(kilobytes * 1024)
+    })
 }
 
-impl MemoryMetrics {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, total_memory: Option<f64>, used_memory: Option<f64>) -> Self {
-        Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
-            total_memory,
-            used_memory,
+// Lines look like `some avg10=0.00 avg60=0.00 avg300=0.00 total=1109842`
+fn parse_pressure(contents: &str, kind: &str) -> Option<PressureAverages> {
+    let line = contents.lines().find(|line| line.split_whitespace().next() == Some(kind))?;
+    let mut averages = PressureAverages::default();
+    for pair in line.split_whitespace().skip(1) {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.parse().ok();
+        match key {
+            "avg10" => averages.avg10 = value,
+            "avg60" => averages.avg60 = value,
+            "avg300" => averages.avg300 = value,
+            _ => {}
         }
     }
+    Some(averages)
+}
+
+pub fn gather_memory_metrics(device_id: Option<i64>) -> MemoryMetrics {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    let meminfo = fs::read_to_string(MEMINFO_PATH).unwrap_or_default();
+    // Missing before Linux 4.20 or when the kernel is booted with psi=0
+    let pressure = fs::read_to_string(PRESSURE_PATH).ok();
+    let some = pressure.as_deref().and_then(|contents| parse_pressure(contents, "some")).unwrap_or_default();
+    let full = pressure.as_deref().and_then(|contents| parse_pressure(contents, "full")).unwrap_or_default();
 
-    pub fn update(&mut self, new_total_memory: Option<f64>, new_used_memory: Option<f64>) {
-        self.total_memory = new_total_memory;
-        self.used_memory = new_used_memory;
+    MemoryMetrics {
+        device_id,
+        total_memory: Some(sys.total_memory() as f64 / 1_048_576.0),
+        used_memory: Some(sys.used_memory() as f64 / 1_048_576.0),
+        total_bytes: Some(sys.total_memory() as i64),
+        used_bytes: Some(sys.used_memory() as i64),
+        available_bytes: Some(sys.available_memory() as i64),
+        free_bytes: Some(sys.free_memory() as i64),
+        cached_bytes: meminfo_bytes(&meminfo, "Cached"),
+        buffers_bytes: meminfo_bytes(&meminfo, "Buffers"),
+        swap_total_bytes: Some(sys.total_swap() as i64),
+        swap_used_bytes: Some(sys.used_swap() as i64),
+        pressure_some_avg10: some.avg10,
+        pressure_some_avg60: some.avg60,
+        pressure_some_avg300: some.avg300,
+        pressure_full_avg10: full.avg10,
+        pressure_full_avg60: full.avg60,
+        pressure_full_avg300: full.avg300,
+        recorded_at: None,
     }
 }
 
-pub async fn get_memory_info_handler(pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+pub async fn save_memory_metrics_to_database(pool: &PgPool, metrics: &MemoryMetrics) -> Result<MemoryMetrics, sqlx::Error> {
+    sqlx::query_as!(
+        MemoryMetrics,
+        "INSERT INTO memory_metrics (device_id, total_memory, used_memory, total_bytes, used_bytes, available_bytes, free_bytes, cached_bytes,
+            buffers_bytes, swap_total_bytes, swap_used_bytes, pressure_some_avg10, pressure_some_avg60, pressure_some_avg300,
+            pressure_full_avg10, pressure_full_avg60, pressure_full_avg300)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+        RETURNING device_id, total_memory, used_memory, total_bytes, used_bytes, available_bytes, free_bytes, cached_bytes,
+            buffers_bytes, swap_total_bytes, swap_used_bytes, pressure_some_avg10, pressure_some_avg60, pressure_some_avg300,
+            pressure_full_avg10, pressure_full_avg60, pressure_full_avg300, recorded_at",
+        metrics.device_id,
+        metrics.total_memory,
+        metrics.used_memory,
+        metrics.total_bytes,
+        metrics.used_bytes,
+        metrics.available_bytes,
+        metrics.free_bytes,
+        metrics.cached_bytes,
+        metrics.buffers_bytes,
+        metrics.swap_total_bytes,
+        metrics.swap_used_bytes,
+        metrics.pressure_some_avg10,
+        metrics.pressure_some_avg60,
+        metrics.pressure_some_avg300,
+        metrics.pressure_full_avg10,
+        metrics.pressure_full_avg60,
+        metrics.pressure_full_avg300,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_memory_info_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
-    match get_memory_info_for_user(&pool, user_id_value).await {
-    Ok(mut memory_metrics) => {
-    let mut sys = System::new_all();
-    sys.refresh_all();
-
-    let total_memory = Some(sys.total_memory() as f64).map(|total| total / 1_048_576.0);
-    let used_memory = Some(sys.used_memory() as f64).map(|used| used / 1_048_576.0);
-    memory_metrics.update(total_memory, used_memory);
-    if let Err(e) = save_memory_metrics_to_database(&pool, &memory_metrics).await {
-        eprintln!("Failed to save metrics to database: {}", e);
-        return HttpResponse::InternalServerError().body("Failed to save Memory info");
-    }   
-    HttpResponse::Ok().json(memory_metrics)
-    }
-    Err(_) => {
-        let new_memory_metrics = gather_memory_metrics(Some(user_id_value), None).await;
-        if let Err(e) = save_memory_metrics_to_database(&pool, &new_memory_metrics).await {
-            eprintln!("Failed to save metrics to database: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to save CPU info");
+    let device_id = device.device_id;
+    let memory_metrics = gather_memory_metrics(Some(device_id));
+
+    match save_memory_metrics_to_database(&pool, &memory_metrics).await {
+        Ok(memory_metrics) => HttpResponse::Ok().json(memory_metrics),
+        Err(e) => {
+            error!("Failed to save memory metrics of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save Memory info")
         }
-        HttpResponse::Ok().json(new_memory_metrics)
     }
 }
+
+async fn fetch_memory_history(
+    pool: &PgPool,
+    device_id: i64,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> Result<Vec<MemoryMetrics>, sqlx::Error> {
+    sqlx::query_as!(
+        MemoryMetrics,
+        "SELECT device_id, total_memory, used_memory, total_bytes, used_bytes, available_bytes, free_bytes, cached_bytes,
+            buffers_bytes, swap_total_bytes, swap_used_bytes, pressure_some_avg10, pressure_some_avg60, pressure_some_avg300,
+            pressure_full_avg10, pressure_full_avg60, pressure_full_avg300, recorded_at
+        FROM memory_metrics
+        WHERE device_id = $1 AND ($2::timestamptz IS NULL OR recorded_at >= $2)
+        ORDER BY recorded_at DESC
+        LIMIT $3",
+        device_id,
+        since,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
 }
 
-pub async fn gather_memory_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
-) -> MemoryMetrics {
-    let mut interval = interval(Duration::from_secs(3600)); // Update every hour
-    loop {
-    interval.tick().await;
-    let mut sys = System::new_all();
-    sys.refresh_all();
-    let total_memory = Some(sys.total_memory() as f64).map(|total| total / 1_048_576.0);
-    let used_memory = Some(sys.used_memory() as f64).map(|used| used / 1_048_576.0);
-    return MemoryMetrics::new(sub_admin_metrics_id, staff_metrics_id, total_memory, used_memory)
+/// The device's latest memory sample.
+pub async fn get_device_memory(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_memory_history(&pool, device_id, None, 1).await {
+        Ok(mut history) if !history.is_empty() => HttpResponse::Ok().json(history.remove(0)),
+        Ok(_) => HttpResponse::NotFound().body("No memory metrics collected for this device"),
+        Err(e) => {
+            error!("Failed to fetch memory metrics of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch memory info")
+        }
+    }
 }
+
+/// Past memory samples, newest first.
+pub async fn get_device_memory_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<MemoryHistoryQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+    match fetch_memory_history(&pool, device_id, query.since, limit).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch memory history of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch memory info")
+        }
+    }
 }
 
-pub async fn save_memory_metrics_to_database(pool: &PgPool, metrics: &MemoryMetrics) -> Result<(), Box<dyn std::error::Error>> {
-    
-        sqlx::query!(
-            "INSERT INTO memory_metrics (sub_admin_metrics_id, staff_metrics_id, total_memory, used_memory) VALUES ($1, $2, $3, $4)",
-            metrics.sub_admin_metrics_id,
-            metrics.staff_metrics_id,
-            metrics.total_memory,
-            metrics.used_memory,
-        )
-        .execute(pool)
-        .await?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Head of /proc/meminfo on a 16 GiB machine
+    const MEMINFO: &str = "\
+MemTotal:       16318540 kB
+MemFree:         1203312 kB
+MemAvailable:    9874204 kB
+Buffers:          412876 kB
+Cached:          8120448 kB
+SwapCached:        18224 kB
+Active:          7410028 kB
+Inactive:        6121964 kB
+";
+
+    const PRESSURE: &str = "\
+some avg10=0.10 avg60=0.08 avg300=0.08 total=5564956
+full avg10=0.02 avg60=0.02 avg300=0.03 total=3178144
+";
 
+    #[test]
+    fn reads_meminfo_fields_in_bytes() {
+        assert_eq!(meminfo_bytes(MEMINFO, "Cached"), Some(8120448 * 1024));
+        assert_eq!(meminfo_bytes(MEMINFO, "Buffers"), Some(412876 * 1024));
+        assert_eq!(meminfo_bytes(MEMINFO, "SwapCached"), Some(18224 * 1024));
     }
 
+    #[test]
+    fn missing_meminfo_field_is_none() {
+        assert_eq!(meminfo_bytes(MEMINFO, "Shmem"), None);
+        assert_eq!(meminfo_bytes("", "Cached"), None);
+    }
 
-pub async fn get_memory_info(pool: &PgPool) -> Result<Vec<MemoryMetrics>, sqlx::Error> {
-    let rows = sqlx::query_as!(
-        MemoryMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, total_memory, used_memory FROM memory_metrics"
-    )
-    .fetch_all(pool)
-    .await?;
-    Ok(rows)
-}
+    #[test]
+    fn reads_some_and_full_pressure() {
+        let some = parse_pressure(PRESSURE, "some").unwrap();
+        assert_eq!((some.avg10, some.avg60, some.avg300), (Some(0.10), Some(0.08), Some(0.08)));
+        let full = parse_pressure(PRESSURE, "full").unwrap();
+        assert_eq!((full.avg10, full.avg60, full.avg300), (Some(0.02), Some(0.02), Some(0.03)));
+    }
 
-async fn get_memory_info_for_user(
-    pool: &PgPool,
-    user_id: i32,
-) -> Result<MemoryMetrics, sqlx::Error> {
-    sqlx::query_as!(
-        MemoryMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, total_memory, used_memory 
-        FROM memory_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
-    )
-    .fetch_one(pool)
-    .await
-}
\ No newline at end of file
+    #[test]
+    fn pressure_without_full_line() {
+        // Laid out like /proc/pressure/cpu before Linux 5.13, with no `full` line
+        let contents = "some avg10=1.50 avg60=0.75 avg300=0.20 total=88211\n";
+        assert_eq!(parse_pressure(contents, "some").and_then(|some| some.avg60), Some(0.75));
+        assert!(parse_pressure(contents, "full").is_none());
+    }
+
+    #[test]
+    fn pressure_with_missing_average() {
+        let some = parse_pressure("some avg10=0.40 avg300=0.10 total=12\n", "some").unwrap();
+        assert_eq!((some.avg10, some.avg60, some.avg300), (Some(0.40), None, Some(0.10)));
+    }
+}
@@ -3,3 +3,5 @@ pub mod memory;
 pub mod disk;
 pub mod network;
 pub mod aboutsys;
+pub mod inventory;
+pub mod sensors;
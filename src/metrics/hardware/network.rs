@@ -1,113 +1,348 @@
 use sysinfo::Networks;
-use serde::Serialize;
-use sqlx::PgPool;
-use tokio::time::{interval, Duration};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
 use actix_web::{web, HttpResponse, Responder};
+use std::collections::HashMap;
+use std::fs;
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
 
+const NET_DIR: &str = "/sys/class/net";
+// ARPHRD_LOOPBACK in /sys/class/net/<interface>/type
+const LOOPBACK_TYPE: &str = "772";
+const DEFAULT_HISTORY_LIMIT: i64 = 100;
+const MAX_HISTORY_LIMIT: i64 = 1000;
 
+/// Device-wide totals, kept for readers of `network_metrics`.
 #[derive(Serialize, Clone)]
 pub struct NetworkMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
-    pub total_received: Option<i32>,
-    pub total_transmitted: Option<i32>,
+    pub device_id: Option<i64>,
+    pub total_received: Option<i64>,
+    pub total_transmitted: Option<i64>,
 }
 
-impl NetworkMetrics {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, total_received: Option<i32>, total_transmitted: Option<i32>) -> Self {
-        Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
-            total_received,
-            total_transmitted,
-        }
+/// One interface at one moment. Counters are cumulative since the interface
+/// came up; the rates are worked out against the previous stored sample.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct NetworkInterfaceMetrics {
+    pub device_id: Option<i64>,
+    pub interface_name: String,
+    pub mac_address: Option<String>,
+    pub addresses: Vec<String>,
+    pub bytes_received: i64,
+    pub bytes_transmitted: i64,
+    pub packets_received: i64,
+    pub packets_transmitted: i64,
+    pub errors_received: i64,
+    pub errors_transmitted: i64,
+    pub drops_received: Option<i64>,
+    pub drops_transmitted: Option<i64>,
+    pub receive_bytes_per_sec: Option<f64>,
+    pub transmit_bytes_per_sec: Option<f64>,
+    pub counter_reset: bool,
+    pub recorded_at: Option<DateTime<Utc>>,
+}
+
+impl NetworkInterfaceMetrics {
+    fn counters(&self) -> [Option<i64>; 8] {
+        [
+            Some(self.bytes_received),
+            Some(self.bytes_transmitted),
+            Some(self.packets_received),
+            Some(self.packets_transmitted),
+            Some(self.errors_received),
+            Some(self.errors_transmitted),
+            self.drops_received,
+            self.drops_transmitted,
+        ]
     }
 
-    pub fn update(&mut self, new_total_received: Option<i32>, new_total_transmitted: Option<i32>) {
-        self.total_received = new_total_received;
-        self.total_transmitted = new_total_transmitted;
+    // Counters only grow while an interface stays up
+    fn counters_reset_since(&self, previous: &NetworkInterfaceMetrics) -> bool {
+        self.counters()
+            .iter()
+            .zip(previous.counters().iter())
+            .any(|pair| matches!(pair, (Some(current), Some(before)) if current < before))
     }
-}
 
-pub async fn get_network_info_handler(pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
-) -> impl Responder {
-    let user_id_value = user_id.into_inner();
-    match get_network_info_for_user(&pool, user_id_value).await {
-        Ok(mut network_metrics) => {
-        let networks = Networks::new_with_refreshed_list();
-        let total_received = networks.values().map(|n| n.received() as i32).sum();
-        let total_transmitted = networks.values().map(|n| n.transmitted() as i32).sum();
-
-        let total_received = Some(total_received);
-        let total_transmitted = Some(total_transmitted);
-        network_metrics.update(total_received, total_transmitted);
-        if let Err(e) = save_network_metrics_to_database(&pool, &network_metrics).await {
-            eprintln!("Failed to save metrics to database: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to save Memory info");
-        }   
-        HttpResponse::Ok().json(network_metrics)
-    }    
-    Err(_) => {
-        let new_network_metrics = gather_network_metrics(Some(user_id_value), None).await;
-        if let Err(e) = save_network_metrics_to_database(&pool, &new_network_metrics).await {
-            eprintln!("Failed to save metrics to database: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to save CPU info");
+    /// Fills in the rates against `previous`, or flags a counter reset.
+    fn apply_previous(&mut self, previous: &NetworkInterfaceMetrics, recorded_at: DateTime<Utc>) {
+        if self.counters_reset_since(previous) {
+            self.counter_reset = true;
+            return;
+        }
+        let Some(previous_at) = previous.recorded_at else {
+            return;
+        };
+        let elapsed = (recorded_at - previous_at).num_milliseconds() as f64 / 1000.0;
+        if elapsed <= 0.0 {
+            return;
         }
-        HttpResponse::Ok().json(new_network_metrics)
+        self.receive_bytes_per_sec = Some((self.bytes_received - previous.bytes_received) as f64 / elapsed);
+        self.transmit_bytes_per_sec = Some((self.bytes_transmitted - previous.bytes_transmitted) as f64 / elapsed);
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct NetworkHistoryQuery {
+    pub interface_name: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
 }
-pub async fn gather_network_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
-) -> NetworkMetrics {
-    let mut interval = interval(Duration::from_secs(3600)); // Update every hour
-    loop {
-    interval.tick().await;
-    let networks = Networks::new_with_refreshed_list();
-    let total_received = networks.values().map(|n| n.received() as i32).sum();
-    let total_transmitted = networks.values().map(|n| n.transmitted() as i32).sum();
-    let total_received = Some(total_received);
-    let total_transmitted = Some(total_transmitted);
-    return NetworkMetrics::new(sub_admin_metrics_id, staff_metrics_id, total_received, total_transmitted)
+
+// sysinfo has no drop counters, the kernel keeps them next to the others
+fn read_statistic(interface_name: &str, statistic: &str) -> Option<i64> {
+    fs::read_to_string(format!("{}/{}/statistics/{}", NET_DIR, interface_name, statistic))
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
 }
+
+fn is_loopback(interface_name: &str) -> bool {
+    fs::read_to_string(format!("{}/{}/type", NET_DIR, interface_name))
+        .map(|value| value.trim() == LOOPBACK_TYPE)
+        .unwrap_or(interface_name == "lo")
+}
+
+fn interface_addresses() -> HashMap<String, Vec<String>> {
+    let mut addresses: HashMap<String, Vec<String>> = HashMap::new();
+    match local_ip_address::list_afinet_netifas() {
+        Ok(interfaces) => {
+            for (name, address) in interfaces {
+                addresses.entry(name).or_default().push(address.to_string());
+            }
+        }
+        Err(e) => error!("Failed to list interface addresses: {:?}", e),
+    }
+    addresses
 }
 
-pub async fn save_network_metrics_to_database(pool: &PgPool, metrics: &NetworkMetrics) -> Result<(), Box<dyn std::error::Error>> {
-        sqlx::query!(
-            "INSERT INTO network_metrics (sub_admin_metrics_id, staff_metrics_id, total_received, total_transmitted) VALUES ($1, $2, $3, $4)",
-            metrics.sub_admin_metrics_id,
-            metrics.staff_metrics_id,
-            metrics.total_received,
-            metrics.total_transmitted,
-        )
-        .execute(pool)
-        .await?;
-    Ok(())
+/// Reads the cumulative counters of every interface on this machine.
+pub fn gather_network_metrics(device_id: Option<i64>) -> Vec<NetworkInterfaceMetrics> {
+    let networks = Networks::new_with_refreshed_list();
+    let mut addresses = interface_addresses();
+
+    let mut interfaces: Vec<NetworkInterfaceMetrics> = networks
+        .iter()
+        .map(|(name, data)| {
+            let mut interface_addresses = addresses.remove(name).unwrap_or_default();
+            interface_addresses.sort();
+            let mac_address = data.mac_address();
+            NetworkInterfaceMetrics {
+                device_id,
+                interface_name: name.clone(),
+                mac_address: Some(mac_address.to_string()).filter(|_| !mac_address.is_unspecified()),
+                addresses: interface_addresses,
+                bytes_received: data.total_received() as i64,
+                bytes_transmitted: data.total_transmitted() as i64,
+                packets_received: data.total_packets_received() as i64,
+                packets_transmitted: data.total_packets_transmitted() as i64,
+                errors_received: data.total_errors_on_received() as i64,
+                errors_transmitted: data.total_errors_on_transmitted() as i64,
+                drops_received: read_statistic(name, "rx_dropped"),
+                drops_transmitted: read_statistic(name, "tx_dropped"),
+                receive_bytes_per_sec: None,
+                transmit_bytes_per_sec: None,
+                counter_reset: false,
+                recorded_at: None,
+            }
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.interface_name.cmp(&b.interface_name));
+    interfaces
 }
 
-pub async fn get_network_info(pool: &PgPool) -> Result<Vec<NetworkMetrics>, sqlx::Error> {
-sqlx::query_as!(
-        NetworkMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, total_received, total_transmitted 
-        FROM network_metrics"
+async fn fetch_latest_interfaces<'e, E>(executor: E, device_id: i64) -> Result<Vec<NetworkInterfaceMetrics>, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        NetworkInterfaceMetrics,
+        "SELECT DISTINCT ON (interface_name) device_id, interface_name, mac_address, addresses, bytes_received, bytes_transmitted,
+            packets_received, packets_transmitted, errors_received, errors_transmitted, drops_received, drops_transmitted,
+            receive_bytes_per_sec, transmit_bytes_per_sec, counter_reset, recorded_at
+        FROM network_interface_metrics WHERE device_id = $1
+        ORDER BY interface_name, recorded_at DESC, id DESC",
+        device_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await
 }
 
-async fn get_network_info_for_user(
+/// Stores one sample of every interface, with rates against the previous
+/// sample, and the device totals alongside. Returns the stored interfaces.
+pub async fn save_network_metrics_to_database(
+    pool: &PgPool,
+    device_id: i64,
+    interfaces: &[NetworkInterfaceMetrics],
+) -> Result<Vec<NetworkInterfaceMetrics>, sqlx::Error> {
+    let recorded_at = Utc::now();
+    let mut tx = pool.begin().await?;
+
+    let previous: HashMap<String, NetworkInterfaceMetrics> = fetch_latest_interfaces(&mut *tx, device_id)
+        .await?
+        .into_iter()
+        .map(|interface| (interface.interface_name.clone(), interface))
+        .collect();
+
+    let interfaces: Vec<NetworkInterfaceMetrics> = interfaces
+        .iter()
+        .cloned()
+        .map(|mut interface| {
+            interface.device_id = Some(device_id);
+            if let Some(previous) = previous.get(&interface.interface_name) {
+                interface.apply_previous(previous, recorded_at);
+            }
+            interface.recorded_at = Some(recorded_at);
+            interface
+        })
+        .collect();
+
+    let names: Vec<String> = interfaces.iter().map(|interface| interface.interface_name.clone()).collect();
+    let mac_addresses: Vec<Option<String>> = interfaces.iter().map(|interface| interface.mac_address.clone()).collect();
+    // Postgres arrays cannot nest unevenly, so addresses travel as one string each
+    let addresses: Vec<String> = interfaces.iter().map(|interface| interface.addresses.join(",")).collect();
+    let bytes_received: Vec<i64> = interfaces.iter().map(|interface| interface.bytes_received).collect();
+    let bytes_transmitted: Vec<i64> = interfaces.iter().map(|interface| interface.bytes_transmitted).collect();
+    let packets_received: Vec<i64> = interfaces.iter().map(|interface| interface.packets_received).collect();
+    let packets_transmitted: Vec<i64> = interfaces.iter().map(|interface| interface.packets_transmitted).collect();
+    let errors_received: Vec<i64> = interfaces.iter().map(|interface| interface.errors_received).collect();
+    let errors_transmitted: Vec<i64> = interfaces.iter().map(|interface| interface.errors_transmitted).collect();
+    let drops_received: Vec<Option<i64>> = interfaces.iter().map(|interface| interface.drops_received).collect();
+    let drops_transmitted: Vec<Option<i64>> = interfaces.iter().map(|interface| interface.drops_transmitted).collect();
+    let receive_rates: Vec<Option<f64>> = interfaces.iter().map(|interface| interface.receive_bytes_per_sec).collect();
+    let transmit_rates: Vec<Option<f64>> = interfaces.iter().map(|interface| interface.transmit_bytes_per_sec).collect();
+    let counter_resets: Vec<bool> = interfaces.iter().map(|interface| interface.counter_reset).collect();
+
+    sqlx::query!(
+        "INSERT INTO network_interface_metrics (device_id, recorded_at, interface_name, mac_address, addresses, bytes_received, bytes_transmitted,
+            packets_received, packets_transmitted, errors_received, errors_transmitted, drops_received, drops_transmitted,
+            receive_bytes_per_sec, transmit_bytes_per_sec, counter_reset)
+        SELECT $1, $2, i.interface_name, i.mac_address, COALESCE(string_to_array(NULLIF(i.addresses, ''), ','), '{}'), i.bytes_received, i.bytes_transmitted,
+            i.packets_received, i.packets_transmitted, i.errors_received, i.errors_transmitted, i.drops_received, i.drops_transmitted,
+            i.receive_bytes_per_sec, i.transmit_bytes_per_sec, i.counter_reset
+        FROM unnest($3::text[], $4::text[], $5::text[], $6::int8[], $7::int8[], $8::int8[], $9::int8[], $10::int8[], $11::int8[], $12::int8[], $13::int8[], $14::float8[], $15::float8[], $16::bool[])
+            AS i(interface_name, mac_address, addresses, bytes_received, bytes_transmitted, packets_received, packets_transmitted,
+                errors_received, errors_transmitted, drops_received, drops_transmitted, receive_bytes_per_sec, transmit_bytes_per_sec, counter_reset)",
+        device_id,
+        recorded_at,
+        &names,
+        &mac_addresses as &[Option<String>],
+        &addresses,
+        &bytes_received,
+        &bytes_transmitted,
+        &packets_received,
+        &packets_transmitted,
+        &errors_received,
+        &errors_transmitted,
+        &drops_received as &[Option<i64>],
+        &drops_transmitted as &[Option<i64>],
+        &receive_rates as &[Option<f64>],
+        &transmit_rates as &[Option<f64>],
+        &counter_resets,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Loopback traffic never leaves the machine
+    let external: Vec<&NetworkInterfaceMetrics> = interfaces.iter().filter(|interface| !is_loopback(&interface.interface_name)).collect();
+    let totals = NetworkMetrics {
+        device_id: Some(device_id),
+        total_received: Some(external.iter().map(|interface| interface.bytes_received).sum()),
+        total_transmitted: Some(external.iter().map(|interface| interface.bytes_transmitted).sum()),
+    };
+    sqlx::query!(
+        "INSERT INTO network_metrics (device_id, total_received, total_transmitted, recorded_at) VALUES ($1, $2, $3, $4)",
+        totals.device_id,
+        totals.total_received,
+        totals.total_transmitted,
+        recorded_at,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(interfaces)
+}
+
+pub async fn get_network_info_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let device_id = device.device_id;
+    let interfaces = gather_network_metrics(Some(device_id));
+
+    match save_network_metrics_to_database(&pool, device_id, &interfaces).await {
+        Ok(interfaces) => HttpResponse::Ok().json(interfaces),
+        Err(e) => {
+            error!("Failed to save network metrics of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save network info")
+        }
+    }
+}
+
+async fn fetch_interface_history(
     pool: &PgPool,
-    user_id: i32,
-) -> Result<NetworkMetrics, sqlx::Error> {
+    device_id: i64,
+    query: &NetworkHistoryQuery,
+) -> Result<Vec<NetworkInterfaceMetrics>, sqlx::Error> {
     sqlx::query_as!(
-        NetworkMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, total_received, total_transmitted 
-        FROM network_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        NetworkInterfaceMetrics,
+        "SELECT device_id, interface_name, mac_address, addresses, bytes_received, bytes_transmitted,
+            packets_received, packets_transmitted, errors_received, errors_transmitted, drops_received, drops_transmitted,
+            receive_bytes_per_sec, transmit_bytes_per_sec, counter_reset, recorded_at
+        FROM network_interface_metrics
+        WHERE device_id = $1
+            AND ($2::text IS NULL OR interface_name = $2)
+            AND ($3::timestamptz IS NULL OR recorded_at >= $3)
+        ORDER BY recorded_at DESC, interface_name
+        LIMIT $4",
+        device_id,
+        query.interface_name,
+        query.since,
+        query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT),
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await
 }
+
+/// The latest sample of each of the device's interfaces.
+pub async fn get_device_network_interfaces(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_latest_interfaces(pool.get_ref(), device_id).await {
+        Ok(interfaces) => HttpResponse::Ok().json(interfaces),
+        Err(e) => {
+            error!("Failed to fetch network interfaces of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch network info")
+        }
+    }
+}
+
+/// Past interface samples, newest first, optionally for one interface.
+pub async fn get_device_network_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<NetworkHistoryQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_interface_history(&pool, device_id, &query).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch network history of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch network info")
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use actix_web::{web, HttpResponse, Responder};
+use sysinfo::Components;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::Path;
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
+
+const HWMON_DIR: &str = "/sys/class/hwmon";
+const DEFAULT_HISTORY_LIMIT: i64 = 100;
+const MAX_HISTORY_LIMIT: i64 = 1000;
+
+/// One temperature sensor at one moment, in degrees Celsius.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct TemperatureSensorMetrics {
+    pub device_id: Option<i64>,
+    pub label: String,
+    pub source: String,
+    pub temperature_celsius: f64,
+    pub max_celsius: Option<f64>,
+    pub critical_celsius: Option<f64>,
+    pub recorded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TemperatureHistoryQuery {
+    pub label: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+// sysinfo reports unreadable values as NaN
+fn celsius(value: f32) -> Option<f64> {
+    Some(value as f64).filter(|value| value.is_finite())
+}
+
+fn sensors_from_components(device_id: Option<i64>) -> Vec<TemperatureSensorMetrics> {
+    let components = Components::new_with_refreshed_list();
+    components
+        .iter()
+        .filter_map(|component| {
+            Some(TemperatureSensorMetrics {
+                device_id,
+                label: component.label().to_string(),
+                source: "sysinfo".to_string(),
+                temperature_celsius: celsius(component.temperature())?,
+                max_celsius: celsius(component.max()),
+                critical_celsius: component.critical().and_then(celsius),
+                recorded_at: None,
+            })
+        })
+        .collect()
+}
+
+// hwmon values are millidegrees Celsius
+fn read_millidegrees(path: impl AsRef<Path>) -> Option<f64> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .map(|millidegrees| millidegrees / 1000.0)
+}
+
+/// Reads every `tempN_input` under /sys/class/hwmon, labelled as
+/// `<chip> <tempN_label>` or `<chip> tempN` when the chip gives no label.
+fn sensors_from_hwmon(device_id: Option<i64>) -> Vec<TemperatureSensorMetrics> {
+    let Ok(chips) = fs::read_dir(HWMON_DIR) else {
+        return Vec::new();
+    };
+    let mut sensors = Vec::new();
+    for chip in chips.flatten() {
+        let chip_path = chip.path();
+        let chip_name = fs::read_to_string(chip_path.join("name"))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_else(|_| chip.file_name().to_string_lossy().into_owned());
+        let Ok(files) = fs::read_dir(&chip_path) else {
+            continue;
+        };
+        for file in files.flatten() {
+            let file_name = file.file_name().to_string_lossy().into_owned();
+            let Some(sensor) = file_name.strip_suffix("_input").filter(|sensor| sensor.starts_with("temp")) else {
+                continue;
+            };
+            let Some(temperature_celsius) = read_millidegrees(file.path()) else {
+                continue;
+            };
+            let label = fs::read_to_string(chip_path.join(format!("{}_label", sensor)))
+                .map(|label| label.trim().to_string())
+                .unwrap_or_else(|_| sensor.to_string());
+            sensors.push(TemperatureSensorMetrics {
+                device_id,
+                label: format!("{} {}", chip_name, label),
+                source: "hwmon".to_string(),
+                temperature_celsius,
+                max_celsius: read_millidegrees(chip_path.join(format!("{}_highest", sensor))),
+                critical_celsius: read_millidegrees(chip_path.join(format!("{}_crit", sensor))),
+                recorded_at: None,
+            });
+        }
+    }
+    sensors
+}
+
+/// Reads every temperature sensor on this machine. Falls back to hwmon when
+/// sysinfo finds nothing readable; machines without sensors get an empty list.
+pub fn gather_temperature_metrics(device_id: Option<i64>) -> Vec<TemperatureSensorMetrics> {
+    let mut sensors = sensors_from_components(device_id);
+    if sensors.is_empty() {
+        sensors = sensors_from_hwmon(device_id);
+    }
+    sensors.sort_by(|a, b| a.label.cmp(&b.label));
+    sensors
+}
+
+pub async fn save_temperature_metrics_to_database(
+    pool: &PgPool,
+    device_id: i64,
+    sensors: &[TemperatureSensorMetrics],
+) -> Result<Vec<TemperatureSensorMetrics>, sqlx::Error> {
+    let labels: Vec<String> = sensors.iter().map(|sensor| sensor.label.clone()).collect();
+    let sources: Vec<String> = sensors.iter().map(|sensor| sensor.source.clone()).collect();
+    let temperatures: Vec<f64> = sensors.iter().map(|sensor| sensor.temperature_celsius).collect();
+    let maxes: Vec<Option<f64>> = sensors.iter().map(|sensor| sensor.max_celsius).collect();
+    let criticals: Vec<Option<f64>> = sensors.iter().map(|sensor| sensor.critical_celsius).collect();
+
+    sqlx::query_as!(
+        TemperatureSensorMetrics,
+        "INSERT INTO temperature_sensor_metrics (device_id, recorded_at, label, source, temperature_celsius, max_celsius, critical_celsius)
+        SELECT $1, now(), s.*
+        FROM unnest($2::text[], $3::text[], $4::float8[], $5::float8[], $6::float8[])
+            AS s(label, source, temperature_celsius, max_celsius, critical_celsius)
+        RETURNING device_id, label, source, temperature_celsius, max_celsius, critical_celsius, recorded_at",
+        device_id,
+        &labels,
+        &sources,
+        &temperatures,
+        &maxes as &[Option<f64>],
+        &criticals as &[Option<f64>],
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_temperature_info_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let device_id = device.device_id;
+    let sensors = gather_temperature_metrics(Some(device_id));
+
+    match save_temperature_metrics_to_database(&pool, device_id, &sensors).await {
+        Ok(sensors) => HttpResponse::Ok().json(sensors),
+        Err(e) => {
+            error!("Failed to save temperature metrics of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save temperature info")
+        }
+    }
+}
+
+async fn fetch_latest_temperatures(pool: &PgPool, device_id: i64) -> Result<Vec<TemperatureSensorMetrics>, sqlx::Error> {
+    sqlx::query_as!(
+        TemperatureSensorMetrics,
+        "SELECT device_id, label, source, temperature_celsius, max_celsius, critical_celsius, recorded_at
+        FROM temperature_sensor_metrics
+        WHERE device_id = $1
+            AND recorded_at = (SELECT max(recorded_at) FROM temperature_sensor_metrics WHERE device_id = $1)
+        ORDER BY label",
+        device_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_temperature_history(
+    pool: &PgPool,
+    device_id: i64,
+    query: &TemperatureHistoryQuery,
+) -> Result<Vec<TemperatureSensorMetrics>, sqlx::Error> {
+    sqlx::query_as!(
+        TemperatureSensorMetrics,
+        "SELECT device_id, label, source, temperature_celsius, max_celsius, critical_celsius, recorded_at
+        FROM temperature_sensor_metrics
+        WHERE device_id = $1
+            AND ($2::text IS NULL OR label = $2)
+            AND ($3::timestamptz IS NULL OR recorded_at >= $3)
+        ORDER BY recorded_at DESC, label
+        LIMIT $4",
+        device_id,
+        query.label,
+        query.since,
+        query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT),
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Every sensor of the device's latest collection.
+pub async fn get_device_temperatures(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_latest_temperatures(&pool, device_id).await {
+        Ok(sensors) => HttpResponse::Ok().json(sensors),
+        Err(e) => {
+            error!("Failed to fetch temperatures of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch temperature info")
+        }
+    }
+}
+
+/// Past sensor readings, newest first, optionally for one sensor label.
+pub async fn get_device_temperature_history(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<TemperatureHistoryQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_temperature_history(&pool, device_id, &query).await {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(e) => {
+            error!("Failed to fetch temperature history of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch temperature info")
+        }
+    }
+}
@@ -4,20 +4,19 @@ use std::process::Command;
 // use std::sync::{Arc, RwLock};
 use tokio::time::{interval, Duration};
 use actix_web::{web, HttpResponse, Responder};
+use crate::device::enrollment::AuthenticatedDevice;
 
 #[derive(Serialize, Clone)]
 pub struct FileSystemMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub filesystem: Option<String>,
     pub status: Option<String>,
 }
 
 impl FileSystemMetrics {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, filesystem: Option<String>, status: Option<String>) -> Self {
+    pub fn new(device_id: Option<i64>, filesystem: Option<String>, status: Option<String>) -> Self {
         Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
+            device_id,
             filesystem,
             status,
         }
@@ -30,11 +29,11 @@ impl FileSystemMetrics {
 }
 
 pub async fn get_filesystem_info_handler(pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
+    let device_id = device.device_id;
 
-    match get_filesystem_info_for_user(&pool, user_id_value).await {
+    match get_filesystem_info_for_device(&pool, device_id).await {
         Ok(mut filesystem_metrics) => {
         let filesystems = vec!["C:", "D:"];
         for filesystem in &filesystems {
@@ -55,7 +54,7 @@ pub async fn get_filesystem_info_handler(pool: web::Data<PgPool>,
 
 }
     Err(_) => {
-    let new_filesystem_metrics = gather_filesystem_metrics(Some(user_id_value), None).await;
+    let new_filesystem_metrics = gather_filesystem_metrics(Some(device_id)).await;
     if let Err(e) = save_filesystem_metrics_to_database(&pool, &new_filesystem_metrics).await {
         eprintln!("Failed to save metrics to database: {}", e);
         return HttpResponse::InternalServerError().body("Failed to save Filesystem info");
@@ -66,8 +65,7 @@ pub async fn get_filesystem_info_handler(pool: web::Data<PgPool>,
 }
 
 pub async fn gather_filesystem_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
+    device_id: Option<i64>,
 ) -> FileSystemMetrics {
     let mut interval = interval(Duration::from_secs(3600)); // Update every hour
     loop {
@@ -82,16 +80,15 @@ pub async fn gather_filesystem_metrics(
             .expect("Failed to check filesystem status");
 
         let status = String::from_utf8_lossy(&output.stdout).to_string();
-    return FileSystemMetrics::new(sub_admin_metrics_id, staff_metrics_id, Some(filesystem.to_string()), Some(status))
+    return FileSystemMetrics::new(device_id, Some(filesystem.to_string()), Some(status))
 }
 }
 }
 
 pub async fn save_filesystem_metrics_to_database(pool: &PgPool, metrics: &FileSystemMetrics) -> Result<(), Box<dyn std::error::Error>> {
         sqlx::query!(
-            "INSERT INTO filesystem_metrics (sub_admin_metrics_id, staff_metrics_id, filesystem, status) VALUES ($1, $2, $3, $4)",
-            metrics.sub_admin_metrics_id,
-            metrics.staff_metrics_id,
+            "INSERT INTO filesystem_metrics (device_id, filesystem, status) VALUES ($1, $2, $3)",
+            metrics.device_id,
             metrics.filesystem,
             metrics.status,
         )
@@ -103,22 +100,22 @@ pub async fn save_filesystem_metrics_to_database(pool: &PgPool, metrics: &FileSy
 pub async fn get_filesystem_info(pool: &PgPool) -> Result<Vec<FileSystemMetrics>, sqlx::Error> {
     sqlx::query_as!(
         FileSystemMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, filesystem, status FROM filesystem_metrics"
+        "SELECT device_id, filesystem, status FROM filesystem_metrics"
     )
     .fetch_all(pool)
     .await
 }
 
-async fn get_filesystem_info_for_user(
+async fn get_filesystem_info_for_device(
     pool: &PgPool,
-    user_id: i32,
+    device_id: i64,
 ) -> Result<FileSystemMetrics, sqlx::Error> {
     sqlx::query_as!(
         FileSystemMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, filesystem, status 
+        "SELECT device_id, filesystem, status 
         FROM filesystem_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        WHERE device_id = $1",
+        device_id
     )
     .fetch_one(pool)
     .await
@@ -2,11 +2,11 @@ use actix_web::{web, HttpResponse, Responder};
 use serde::Serialize;
 use sqlx::PgPool;
 use tokio::time::{interval, Duration};
+use crate::device::enrollment::AuthenticatedDevice;
 
 #[derive(Serialize, Clone)]
 pub struct IpLocation {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub ip: Option<String>,
     pub city: Option<String>,
     pub region: Option<String>,
@@ -18,8 +18,7 @@ pub struct IpLocation {
 
 impl IpLocation {
     pub fn new(
-        sub_admin_metrics_id: Option<i32>,
-        staff_metrics_id: Option<i32>,
+        device_id: Option<i64>,
         ip: Option<String>,
         city: Option<String>,
         region: Option<String>,
@@ -29,8 +28,7 @@ impl IpLocation {
         isp: Option<String>,
     ) -> Self {
         Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
+            device_id,
             ip,
             city,
             region,
@@ -69,8 +67,7 @@ async fn fetch_ip_location() -> Result<IpLocation, Box<dyn std::error::Error>> {
     let response: serde_json::Value = reqwest::get(&url).await?.json().await?;
 
     Ok(IpLocation {
-        sub_admin_metrics_id: None,
-        staff_metrics_id: None,
+        device_id: None,
         ip: Some(ip_str),
         city: response.get("city").and_then(|v| v.as_str()).map(String::from),
         region: response.get("regionName").and_then(|v| v.as_str()).map(String::from),
@@ -82,18 +79,17 @@ async fn fetch_ip_location() -> Result<IpLocation, Box<dyn std::error::Error>> {
 }
 
 pub async fn gather_ip_location(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
+    device_id: Option<i64>,
 ) -> IpLocation {
     let mut interval = interval(Duration::from_secs(3600)); // Update every hour
 
     loop {
         interval.tick().await;
     let ip = match fetch_ip_location().await {
-        Ok(location) => location,
+        Ok(location) => IpLocation { device_id, ..location },
         Err(e) => {
             eprintln!("Failed to fetch IP location: {}", e);
-            IpLocation::new(sub_admin_metrics_id, staff_metrics_id, None, None, None, None, None, None, None)
+            IpLocation::new(device_id, None, None, None, None, None, None, None)
         }
     };
     return ip
@@ -102,16 +98,16 @@ pub async fn gather_ip_location(
 
 pub async fn get_ip_location_info_handler(
     pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
+    let device_id = device.device_id;
 
-    match get_ip_location_info_for_user(&pool, user_id_value).await {
+    match get_ip_location_info_for_device(&pool, device_id).await {
         Ok(ip_location_metrics) => {
             HttpResponse::Ok().json(ip_location_metrics)
         }
         Err(_) => {
-            let new_ip_location_metrics = gather_ip_location(Some(user_id_value), None).await;
+            let new_ip_location_metrics = gather_ip_location(Some(device_id)).await;
             if let Err(e) = save_ip_location_to_database(&pool, &new_ip_location_metrics).await {
                 eprintln!("Failed to save metrics to database: {}", e);
                 return HttpResponse::InternalServerError().body("Failed to save IP info");
@@ -123,9 +119,8 @@ pub async fn get_ip_location_info_handler(
 
 pub async fn save_ip_location_to_database(pool: &PgPool, metrics: &IpLocation) -> Result<(), Box<dyn std::error::Error>> {
     sqlx::query!(
-        "INSERT INTO ip_location_metrics (sub_admin_metrics_id, staff_metrics_id, ip, city, region, country, latitude, longitude, isp) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
-        metrics.sub_admin_metrics_id,
-        metrics.staff_metrics_id,
+        "INSERT INTO ip_location_metrics (device_id, ip, city, region, country, latitude, longitude, isp) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        metrics.device_id,
         metrics.ip,
         metrics.city,
         metrics.region,
@@ -142,22 +137,22 @@ pub async fn save_ip_location_to_database(pool: &PgPool, metrics: &IpLocation) -
 pub async fn get_ip_location_info(pool: &PgPool) -> Result<Vec<IpLocation>, sqlx::Error> {
     sqlx::query_as!(
         IpLocation,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, ip, city, region, country, latitude, longitude, isp FROM ip_location_metrics"
+        "SELECT device_id, ip, city, region, country, latitude, longitude, isp FROM ip_location_metrics"
     )
     .fetch_all(pool)
     .await
 }
 
-async fn get_ip_location_info_for_user(
+async fn get_ip_location_info_for_device(
     pool: &PgPool,
-    user_id: i32,
+    device_id: i64,
 ) -> Result<IpLocation, sqlx::Error> {
     sqlx::query_as!(
         IpLocation,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, ip, city, region, country, latitude, longitude, isp 
+        "SELECT device_id, ip, city, region, country, latitude, longitude, isp 
         FROM ip_location_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        WHERE device_id = $1",
+        device_id
     )
     .fetch_one(pool)
     .await
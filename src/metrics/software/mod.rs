@@ -3,3 +3,4 @@ pub mod uptime;
 pub mod services;
 pub mod filesystem;
 pub mod  ip_location;
+pub mod packages;
@@ -0,0 +1,514 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use tokio::process::Command;
+use log::{error, warn};
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
+use crate::user::users::resolve_company_scope;
+
+const DPKG_STATUS_FILE: &str = "/var/lib/dpkg/status";
+const RPM_QUERY_FORMAT: &str = "%{NAME}\\t%{EPOCHNUM}:%{VERSION}-%{RELEASE}\\t%{ARCH}\\n";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    pub architecture: Option<String>,
+}
+
+impl InstalledPackage {
+    fn new(source: &str, name: &str, version: &str, architecture: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            source: source.to_string(),
+            architecture: architecture.filter(|arch| !arch.is_empty()).map(str::to_string),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct SoftwarePackage {
+    pub device_id: i64,
+    pub source: String,
+    pub name: String,
+    pub architecture: String,
+    pub version: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// A package found on a device by the fleet search.
+#[derive(Debug, Serialize, FromRow)]
+pub struct PackageInstallation {
+    pub device_id: i64,
+    pub hostname: Option<String>,
+    pub company_name: Option<String>,
+    pub source: String,
+    pub name: String,
+    pub architecture: String,
+    pub version: String,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// `min_version` and `max_version` are both inclusive and compared the way
+/// dpkg and rpm order versions, so `1.10` sorts after `1.9`.
+#[derive(Debug, Deserialize)]
+pub struct PackageSearchQuery {
+    pub name: String,
+    pub source: Option<String>,
+    pub version: Option<String>,
+    pub min_version: Option<String>,
+    pub max_version: Option<String>,
+    pub company_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SoftwareCollectionSummary {
+    pub device_id: i64,
+    pub packages: usize,
+    pub sources: Vec<String>,
+}
+
+/// Installed packages from a dpkg status file. Stanzas are separated by blank
+/// lines; only packages whose status ends in `installed` count.
+pub fn parse_dpkg_status(status: &str) -> Vec<InstalledPackage> {
+    status
+        .split("\n\n")
+        .filter_map(|stanza| {
+            let (mut name, mut version, mut arch, mut installed) = (None, None, None, false);
+            // Continuation lines start with whitespace and belong to the previous field
+            for line in stanza.lines().filter(|line| !line.starts_with([' ', '\t'])) {
+                let Some((field, value)) = line.split_once(':') else { continue };
+                let value = value.trim();
+                match field {
+                    "Package" => name = Some(value),
+                    "Version" => version = Some(value),
+                    "Architecture" => arch = Some(value),
+                    "Status" => installed = value.split_whitespace().last() == Some("installed"),
+                    _ => {}
+                }
+            }
+            match (name, version, installed) {
+                (Some(name), Some(version), true) => Some(InstalledPackage::new("dpkg", name, version, arch)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Output of `rpm -qa --queryformat` with `RPM_QUERY_FORMAT`. The epoch is
+/// dropped when it is 0, as rpm itself prints versions.
+pub fn parse_rpm_query(output: &str) -> Vec<InstalledPackage> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let (name, version, arch) = (fields.next()?.trim(), fields.next()?.trim(), fields.next().map(str::trim));
+            if name.is_empty() || version.is_empty() {
+                return None;
+            }
+            let version = version.strip_prefix("0:").unwrap_or(version);
+            // gpg-pubkey entries are keys imported into the database, not software
+            (name != "gpg-pubkey").then(|| InstalledPackage::new("rpm", name, version, arch.filter(|arch| *arch != "(none)")))
+        })
+        .collect()
+}
+
+/// Output of `flatpak list --columns=application,version,arch`, tab separated.
+pub fn parse_flatpak_list(output: &str) -> Vec<InstalledPackage> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t').map(str::trim);
+            let name = fields.next().filter(|name| !name.is_empty())?;
+            let version = fields.next().unwrap_or_default();
+            Some(InstalledPackage::new("flatpak", name, version, fields.next()))
+        })
+        .collect()
+}
+
+/// Output of `snap list`: a header line, then name and version first in each
+/// whitespace separated row.
+pub fn parse_snap_list(output: &str) -> Vec<InstalledPackage> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Name"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (name, version) = (fields.next()?, fields.next()?);
+            Some(InstalledPackage::new("snap", name, version, None))
+        })
+        .collect()
+}
+
+// Sort weight of a character inside a non-digit run, as in dpkg: `~` before
+// anything, even the end of the string, and letters before other symbols.
+fn version_char_order(c: Option<char>) -> i32 {
+    match c {
+        None => 0,
+        Some('~') => -1,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+// dpkg's verrevcmp: alternate non-digit runs, compared character by character,
+// with digit runs, compared numerically.
+fn compare_version_part(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    while a.peek().is_some() || b.peek().is_some() {
+        while a.peek().is_some_and(|c| !c.is_ascii_digit()) || b.peek().is_some_and(|c| !c.is_ascii_digit()) {
+            let (ac, bc) = (version_char_order(a.peek().copied()), version_char_order(b.peek().copied()));
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            a.next();
+            b.next();
+        }
+        while a.peek() == Some(&'0') {
+            a.next();
+        }
+        while b.peek() == Some(&'0') {
+            b.next();
+        }
+        let mut first_difference = Ordering::Equal;
+        while let (Some(ac), Some(bc)) = (a.peek().filter(|c| c.is_ascii_digit()), b.peek().filter(|c| c.is_ascii_digit())) {
+            if first_difference == Ordering::Equal {
+                first_difference = ac.cmp(bc);
+            }
+            a.next();
+            b.next();
+        }
+        if a.peek().is_some_and(|c| c.is_ascii_digit()) {
+            return Ordering::Greater;
+        }
+        if b.peek().is_some_and(|c| c.is_ascii_digit()) {
+            return Ordering::Less;
+        }
+        if first_difference != Ordering::Equal {
+            return first_difference;
+        }
+    }
+    Ordering::Equal
+}
+
+// `[epoch:]upstream[-revision]`
+fn split_version(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => (epoch.parse().unwrap_or(0), rest),
+        _ => (0, version),
+    };
+    match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (epoch, upstream, revision),
+        None => (epoch, rest, ""),
+    }
+}
+
+/// Orders two package versions by epoch, then upstream version, then revision.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_upstream, a_revision) = split_version(a.trim());
+    let (b_epoch, b_upstream, b_revision) = split_version(b.trim());
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_version_part(a_upstream, b_upstream))
+        .then_with(|| compare_version_part(a_revision, b_revision))
+}
+
+// A missing tool just means the device does not use that packaging system
+async fn run_lister(program: &str, args: &[&str]) -> Option<String> {
+    match Command::new(program).args(args).output().await {
+        Ok(output) if output.status.success() => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Ok(output) => {
+            warn!("{} exited with {}", program, output.status);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+pub async fn gather_installed_packages() -> (Vec<InstalledPackage>, Vec<String>) {
+    let mut packages = Vec::new();
+    let mut sources = Vec::new();
+
+    if let Ok(status) = tokio::fs::read_to_string(DPKG_STATUS_FILE).await {
+        packages.extend(parse_dpkg_status(&status));
+        sources.push("dpkg".to_string());
+    }
+    if let Some(output) = run_lister("rpm", &["-qa", "--queryformat", RPM_QUERY_FORMAT]).await {
+        packages.extend(parse_rpm_query(&output));
+        sources.push("rpm".to_string());
+    }
+    if let Some(output) = run_lister("flatpak", &["list", "--columns=application,version,arch"]).await {
+        packages.extend(parse_flatpak_list(&output));
+        sources.push("flatpak".to_string());
+    }
+    if let Some(output) = run_lister("snap", &["list"]).await {
+        packages.extend(parse_snap_list(&output));
+        sources.push("snap".to_string());
+    }
+
+    (packages, sources)
+}
+
+/// Replaces what `sources` reported for the device with `packages`. Sources
+/// that were not read this time keep their previous rows.
+async fn save_packages_to_database(
+    pool: &PgPool,
+    device_id: i64,
+    packages: &[InstalledPackage],
+    sources: &[String],
+) -> Result<(), sqlx::Error> {
+    let names: Vec<String> = packages.iter().map(|package| package.name.clone()).collect();
+    let versions: Vec<String> = packages.iter().map(|package| package.version.clone()).collect();
+    let package_sources: Vec<String> = packages.iter().map(|package| package.source.clone()).collect();
+    let architectures: Vec<String> = packages.iter().map(|package| package.architecture.clone().unwrap_or_default()).collect();
+
+    let mut tx = pool.begin().await?;
+    let collected_at = sqlx::query_scalar!(r#"SELECT now() as "now!""#).fetch_one(&mut *tx).await?;
+
+    // A package can appear twice in one source for multiarch installs; the
+    // architecture keeps those apart, DISTINCT ON drops true duplicates
+    sqlx::query!(
+        "INSERT INTO software_packages (device_id, source, name, architecture, version, first_seen_at, last_seen_at)
+        SELECT DISTINCT ON (source, name, architecture) $1, source, name, architecture, version, $6, $6
+        FROM unnest($2::text[], $3::text[], $4::text[], $5::text[]) AS p(source, name, architecture, version)
+        ON CONFLICT (device_id, source, name, architecture)
+        DO UPDATE SET version = EXCLUDED.version, last_seen_at = EXCLUDED.last_seen_at",
+        device_id,
+        &package_sources,
+        &names,
+        &architectures,
+        &versions,
+        collected_at,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM software_packages WHERE device_id = $1 AND source = ANY($2) AND last_seen_at < $3",
+        device_id,
+        sources,
+        collected_at,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_software_info_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let (packages, sources) = gather_installed_packages().await;
+
+    match save_packages_to_database(&pool, device.device_id, &packages, &sources).await {
+        Ok(()) => HttpResponse::Ok().json(SoftwareCollectionSummary {
+            device_id: device.device_id,
+            packages: packages.len(),
+            sources,
+        }),
+        Err(e) => {
+            error!("Failed to save software inventory of device {}: {:?}", device.device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save software inventory")
+        }
+    }
+}
+
+async fn fetch_device_packages(pool: &PgPool, device_id: i64) -> Result<Vec<SoftwarePackage>, sqlx::Error> {
+    sqlx::query_as!(
+        SoftwarePackage,
+        "SELECT device_id, source, name, architecture, version, first_seen_at, last_seen_at
+        FROM software_packages WHERE device_id = $1
+        ORDER BY source, name, architecture",
+        device_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+async fn fetch_package_installations(
+    pool: &PgPool,
+    name: &str,
+    source: Option<&str>,
+    company_name: Option<&str>,
+) -> Result<Vec<PackageInstallation>, sqlx::Error> {
+    sqlx::query_as!(
+        PackageInstallation,
+        "SELECT p.device_id, d.hostname, d.company_name, p.source, p.name, p.architecture, p.version, p.last_seen_at
+        FROM software_packages p
+        JOIN devices d ON d.device_id = p.device_id
+        WHERE p.name = $1
+        AND ($2::text IS NULL OR p.source = $2)
+        AND ($3::text IS NULL OR d.company_name = $3)
+        ORDER BY p.device_id, p.source, p.architecture",
+        name,
+        source,
+        company_name,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_device_software(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    match fetch_device_packages(&pool, device_id).await {
+        Ok(packages) => HttpResponse::Ok().json(packages),
+        Err(e) => {
+            error!("Failed to fetch software of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch software inventory")
+        }
+    }
+}
+
+/// Devices that have a package, optionally within a version range. Versions
+/// are compared here rather than in SQL, which cannot order them.
+pub async fn search_packages(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<PackageSearchQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+    if query.name.trim().is_empty() {
+        return HttpResponse::BadRequest().body("name is required");
+    }
+
+    let installations = match fetch_package_installations(&pool, query.name.trim(), query.source.as_deref(), company_name.as_deref()).await {
+        Ok(installations) => installations,
+        Err(e) => {
+            error!("Failed to search package {}: {:?}", query.name, e);
+            return HttpResponse::InternalServerError().body("Failed to search packages");
+        }
+    };
+
+    let matching: Vec<PackageInstallation> = installations
+        .into_iter()
+        .filter(|installation| {
+            query.version.as_deref().is_none_or(|version| compare_versions(&installation.version, version) == Ordering::Equal)
+                && query.min_version.as_deref().is_none_or(|min| compare_versions(&installation.version, min) != Ordering::Less)
+                && query.max_version.as_deref().is_none_or(|max| compare_versions(&installation.version, max) != Ordering::Greater)
+        })
+        .collect();
+    HttpResponse::Ok().json(matching)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(source: &str, name: &str, version: &str, architecture: Option<&str>) -> InstalledPackage {
+        InstalledPackage::new(source, name, version, architecture)
+    }
+
+    #[test]
+    fn reads_installed_packages_from_dpkg_status() {
+        let packages = parse_dpkg_status(include_str!("../../../tests/fixtures/packages/dpkg_status"));
+        assert_eq!(
+            packages,
+            vec![
+                package("dpkg", "libc6", "2.36-9+deb12u14", Some("amd64")),
+                package("dpkg", "libssl3", "3.0.19-1~deb12u2", Some("amd64")),
+                package("dpkg", "tzdata", "2025b-0+deb12u2", Some("all")),
+                package("dpkg", "zlib1g", "1:1.2.13.dfsg-1", Some("amd64")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_rpm_query_output() {
+        let packages = parse_rpm_query(include_str!("../../../tests/fixtures/packages/rpm_query"));
+        assert_eq!(
+            packages,
+            vec![
+                package("rpm", "bash", "5.2.26-3.fc40", Some("x86_64")),
+                package("rpm", "grub2-efi-x64", "1:2.06-121.fc40", Some("x86_64")),
+                package("rpm", "fedora-release-common", "40-39", Some("noarch")),
+                package("rpm", "kernel-core", "6.8.5-301.fc40", Some("x86_64")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_flatpak_list_output() {
+        let packages = parse_flatpak_list(include_str!("../../../tests/fixtures/packages/flatpak_list"));
+        assert_eq!(
+            packages,
+            vec![
+                package("flatpak", "org.mozilla.firefox", "125.0.3", Some("x86_64")),
+                package("flatpak", "org.freedesktop.Platform", "23.08.16", Some("x86_64")),
+                package("flatpak", "org.gnome.Platform", "", Some("x86_64")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_snap_list_output() {
+        let packages = parse_snap_list(include_str!("../../../tests/fixtures/packages/snap_list"));
+        assert_eq!(
+            packages,
+            vec![
+                package("snap", "core22", "20240408", None),
+                package("snap", "firefox", "125.0.3-1", None),
+                package("snap", "snapd", "2.62", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_epochs_first() {
+        assert_eq!(compare_versions("1:1.0", "2.0"), Ordering::Greater);
+        assert_eq!(compare_versions("0:1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1:1.2.13.dfsg-1", "2:1.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn sorts_tildes_before_everything() {
+        assert_eq!(compare_versions("1.0~rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0~~", "1.0~"), Ordering::Less);
+        assert_eq!(compare_versions("1.0~rc1", "1.0~rc2"), Ordering::Less);
+        assert_eq!(compare_versions("3.0.19-1~deb12u2", "3.0.19-1"), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_digit_runs_numerically() {
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.010", "1.10"), Ordering::Equal);
+        assert_eq!(compare_versions("2.36-9+deb12u14", "2.36-9+deb12u9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0", "1.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_other_runs_lexically() {
+        assert_eq!(compare_versions("1.0a", "1.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0a", "1.0b"), Ordering::Less);
+        // Letters sort before symbols
+        assert_eq!(compare_versions("1.0a", "1.0+"), Ordering::Less);
+    }
+
+    #[test]
+    fn compares_revisions_after_upstream_versions() {
+        assert_eq!(compare_versions("1.0-1", "1.0-2"), Ordering::Less);
+        assert_eq!(compare_versions("1.1-1", "1.0-9"), Ordering::Greater);
+        // Only the last hyphen starts the revision
+        assert_eq!(compare_versions("1.2-3-4", "1.2-3-10"), Ordering::Less);
+    }
+}
@@ -1,142 +1,392 @@
 use actix_web::{web, HttpResponse, Responder};
-use serde::Serialize;
-use sqlx::PgPool;
-use sysinfo::System;
-use tokio::time::{interval, Duration};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use sysinfo::{ProcessRefreshKind, System, ThreadKind, Users, MINIMUM_CPU_UPDATE_INTERVAL};
+use chrono::{DateTime, Utc};
+use dotenvy::dotenv;
+use std::env;
+use tokio::time::{sleep, Duration};
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
+use crate::functionalities::process_policies::evaluate_process_snapshot;
+use crate::user::users::{resolve_company_scope, UserRole};
 
-#[derive(Serialize, Clone)]
+const DEFAULT_TOP_PROCESSES: i64 = 10;
+const MAX_TOP_PROCESSES: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct ProcessMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
+    pub snapshot_id: Option<i64>,
     pub pid: Option<i32>,
+    pub ppid: Option<i32>,
     pub name: Option<String>,
     pub exe: Option<String>,
+    pub cmdline: Option<String>,
+    pub user_name: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    /// Percent of one core over the sample interval, so it can pass 100
     pub cpu_usage: Option<f64>,
+    /// Resident memory in MiB, kept for readers of the original column
     pub memory: Option<f64>,
+    pub rss_bytes: Option<i64>,
+    pub virtual_bytes: Option<i64>,
+    pub state: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ProcessSnapshot {
+    pub id: i64,
+    pub device_id: i64,
+    pub captured_at: DateTime<Utc>,
+    pub sample_interval_ms: i32,
+    pub process_count: i32,
+}
+
+/// Every process running on a device at one moment.
+#[derive(Debug, Serialize)]
+pub struct ProcessSnapshotDetail {
+    #[serde(flatten)]
+    pub snapshot: ProcessSnapshot,
+    pub processes: Vec<ProcessMetrics>,
 }
 
-impl ProcessMetrics {
-    pub fn new(
-        sub_admin_metrics_id: Option<i32>,
-        staff_metrics_id: Option<i32>,
-        pid: Option<i32>,
-        name: Option<String>,
-        exe: Option<String>,
-        cpu_usage: Option<f64>,
-        memory: Option<f64>,
-    ) -> Self {
-        Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
-            pid,
-            name,
-            exe,
-            cpu_usage,
-            memory,
+/// A process from the fleet-wide top list, with the device it runs on.
+#[derive(Debug, Serialize, FromRow)]
+pub struct FleetProcess {
+    pub device_id: i64,
+    pub hostname: Option<String>,
+    pub captured_at: DateTime<Utc>,
+    pub pid: Option<i32>,
+    pub name: Option<String>,
+    pub exe: Option<String>,
+    pub user_name: Option<String>,
+    pub cpu_usage: Option<f64>,
+    pub rss_bytes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessRanking {
+    #[default]
+    Cpu,
+    Memory,
+}
+
+impl ProcessRanking {
+    fn as_str(self) -> &'static str {
+        match self {
+            ProcessRanking::Cpu => "cpu",
+            ProcessRanking::Memory => "memory",
         }
     }
+}
 
-    pub fn update(
-        &mut self,
-        new_pid: Option<i32>,
-        new_name: Option<String>,
-        new_exe: Option<String>,
-        new_cpu_usage: Option<f64>,
-        new_memory: Option<f64>,
-    ) {
-        self.pid = new_pid;
-        self.name = new_name;
-        self.exe = new_exe;
-        self.cpu_usage = new_cpu_usage;
-        self.memory = new_memory;
+#[derive(Debug, Deserialize)]
+pub struct TopProcessesQuery {
+    #[serde(default)]
+    pub by: ProcessRanking,
+    pub limit: Option<i64>,
+    pub company_name: Option<String>,
+}
+
+impl TopProcessesQuery {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_TOP_PROCESSES).clamp(1, MAX_TOP_PROCESSES)
     }
 }
 
-pub async fn gather_process_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
-) -> ProcessMetrics {
-    let mut interval = interval(Duration::from_secs(3600)); // Update every hour
-
-    loop {
-        interval.tick().await;
-
-        let mut sys = System::new_all();
-        sys.refresh_all();
-
-        let process_metrics = sys.processes().iter().map(|(&pid, process)| {
-            ProcessMetrics::new(
-                sub_admin_metrics_id,
-                staff_metrics_id,
-                Some(pid.as_u32() as i32),
-                Some(process.name().to_string()),
-                process.exe().map(|path| path.to_string_lossy().to_string()),
-                Some(process.cpu_usage() as f64),
-                Some(process.memory() as f64 / 1024.0 / 1024.0),
-            )
-        }).collect::<Vec<ProcessMetrics>>();
-
-        if let Some(metric) = process_metrics.into_iter().next() {
-            return metric;
-        }
+// CPU usage is the CPU time used between two refreshes, so the collector has
+// to wait between them. PROCESS_CPU_SAMPLE_MS sets how long (1000 by default).
+fn sample_interval() -> Duration {
+    dotenv().ok();
+    let millis = env::var("PROCESS_CPU_SAMPLE_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(millis).max(MINIMUM_CPU_UPDATE_INTERVAL)
+}
+
+/// Samples every running process, measuring CPU over `sample_interval()`.
+pub async fn gather_process_metrics(device_id: Option<i64>) -> (Vec<ProcessMetrics>, Duration) {
+    let interval = sample_interval();
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(ProcessRefreshKind::everything());
+    sleep(interval).await;
+    sys.refresh_processes_specifics(ProcessRefreshKind::everything());
+    let users = Users::new_with_refreshed_list();
+
+    let processes = sys
+        .processes()
+        .iter()
+        // Threads of a process show up as tasks of their own on Linux
+        .filter(|(_, process)| process.thread_kind() != Some(ThreadKind::Userland))
+        .map(|(&pid, process)| ProcessMetrics {
+            device_id,
+            snapshot_id: None,
+            pid: Some(pid.as_u32() as i32),
+            ppid: process.parent().map(|parent| parent.as_u32() as i32),
+            name: Some(process.name().to_string()),
+            exe: process.exe().map(|path| path.to_string_lossy().to_string()),
+            cmdline: Some(process.cmd().join(" ")).filter(|cmdline| !cmdline.is_empty()),
+            user_name: process
+                .user_id()
+                .and_then(|uid| users.get_user_by_id(uid))
+                .map(|user| user.name().to_string()),
+            started_at: DateTime::from_timestamp(process.start_time() as i64, 0),
+            cpu_usage: Some(process.cpu_usage() as f64),
+            memory: Some(process.memory() as f64 / 1024.0 / 1024.0),
+            rss_bytes: Some(process.memory() as i64),
+            virtual_bytes: Some(process.virtual_memory() as i64),
+            state: Some(process.status().to_string()),
+        })
+        .collect();
+
+    (processes, interval)
+}
+
+/// Stores a snapshot header and its processes in one transaction.
+async fn save_process_metrics_to_database(
+    pool: &PgPool,
+    device_id: i64,
+    processes: &[ProcessMetrics],
+    sample_interval: Duration,
+) -> Result<ProcessSnapshot, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let snapshot = sqlx::query_as!(
+        ProcessSnapshot,
+        "INSERT INTO process_snapshots (device_id, sample_interval_ms, process_count) VALUES ($1, $2, $3)
+        RETURNING id, device_id, captured_at, sample_interval_ms, process_count",
+        device_id,
+        sample_interval.as_millis() as i32,
+        processes.len() as i32,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let pids: Vec<Option<i32>> = processes.iter().map(|process| process.pid).collect();
+    let ppids: Vec<Option<i32>> = processes.iter().map(|process| process.ppid).collect();
+    let names: Vec<Option<String>> = processes.iter().map(|process| process.name.clone()).collect();
+    let exes: Vec<Option<String>> = processes.iter().map(|process| process.exe.clone()).collect();
+    let cmdlines: Vec<Option<String>> = processes.iter().map(|process| process.cmdline.clone()).collect();
+    let user_names: Vec<Option<String>> = processes.iter().map(|process| process.user_name.clone()).collect();
+    let started_ats: Vec<Option<DateTime<Utc>>> = processes.iter().map(|process| process.started_at).collect();
+    let cpu_usages: Vec<Option<f64>> = processes.iter().map(|process| process.cpu_usage).collect();
+    let memories: Vec<Option<f64>> = processes.iter().map(|process| process.memory).collect();
+    let rss_bytes: Vec<Option<i64>> = processes.iter().map(|process| process.rss_bytes).collect();
+    let virtual_bytes: Vec<Option<i64>> = processes.iter().map(|process| process.virtual_bytes).collect();
+    let states: Vec<Option<String>> = processes.iter().map(|process| process.state.clone()).collect();
+
+    sqlx::query!(
+        "INSERT INTO process_metrics (device_id, snapshot_id, recorded_at, pid, ppid, name, exe, cmdline, user_name, started_at, cpu_usage, memory, rss_bytes, virtual_bytes, state)
+        SELECT $1, $2, $3, p.*
+        FROM unnest($4::int[], $5::int[], $6::text[], $7::text[], $8::text[], $9::text[], $10::timestamptz[], $11::float8[], $12::float8[], $13::int8[], $14::int8[], $15::text[])
+            AS p(pid, ppid, name, exe, cmdline, user_name, started_at, cpu_usage, memory, rss_bytes, virtual_bytes, state)",
+        device_id,
+        snapshot.id,
+        snapshot.captured_at,
+        &pids as &[Option<i32>],
+        &ppids as &[Option<i32>],
+        &names as &[Option<String>],
+        &exes as &[Option<String>],
+        &cmdlines as &[Option<String>],
+        &user_names as &[Option<String>],
+        &started_ats as &[Option<DateTime<Utc>>],
+        &cpu_usages as &[Option<f64>],
+        &memories as &[Option<f64>],
+        &rss_bytes as &[Option<i64>],
+        &virtual_bytes as &[Option<i64>],
+        &states as &[Option<String>],
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(snapshot)
+}
+
+/// Stores a snapshot and checks it against the company's process policies.
+/// A failed policy check is logged; the snapshot is kept either way.
+pub async fn store_process_snapshot(
+    pool: &PgPool,
+    device_id: i64,
+    processes: &[ProcessMetrics],
+    sample_interval: Duration,
+) -> Result<ProcessSnapshot, sqlx::Error> {
+    let snapshot = save_process_metrics_to_database(pool, device_id, processes, sample_interval).await?;
+    if let Err(e) = evaluate_process_snapshot(pool, &snapshot, processes).await {
+        error!("Failed to check process snapshot {} against policies: {:?}", snapshot.id, e);
     }
+    Ok(snapshot)
 }
 
 pub async fn get_process_info_handler(
     pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
-
-    match get_process_info_for_user(&pool, user_id_value).await {
-        Ok(process_metrics) => HttpResponse::Ok().json(process_metrics),
-        Err(_) => {
-            let new_process_metrics = gather_process_metrics(Some(user_id_value), None).await;
-            if let Err(e) = save_process_metrics_to_database(&pool, &new_process_metrics).await {
-                eprintln!("Failed to save metrics to database: {}", e);
-                return HttpResponse::InternalServerError().body("Failed to save process info");
-            }
-            HttpResponse::Ok().json(new_process_metrics)
+    let device_id = device.device_id;
+    let (processes, sample_interval) = gather_process_metrics(Some(device_id)).await;
+
+    match store_process_snapshot(&pool, device_id, &processes, sample_interval).await {
+        Ok(snapshot) => HttpResponse::Ok().json(snapshot),
+        Err(e) => {
+            error!("Failed to save process snapshot of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save process info")
         }
     }
 }
 
-pub async fn save_process_metrics_to_database(pool: &PgPool, metrics: &ProcessMetrics) -> Result<(), Box<dyn std::error::Error>> {
-    sqlx::query!(
-        "INSERT INTO process_metrics (sub_admin_metrics_id, staff_metrics_id, pid, name, exe, cpu_usage, memory) VALUES ($1, $2, $3, $4, $5, $6, $7)",
-        metrics.sub_admin_metrics_id,
-        metrics.staff_metrics_id,
-        metrics.pid,
-        metrics.name,
-        metrics.exe,
-        metrics.cpu_usage,
-        metrics.memory,
+// Command lines can carry passwords and tokens passed as arguments, so only
+// super admins see them
+fn redact_for(claims: &Claims, mut processes: Vec<ProcessMetrics>) -> Vec<ProcessMetrics> {
+    if claims.role() != UserRole::SuperAdmin {
+        for process in &mut processes {
+            process.cmdline = None;
+        }
+    }
+    processes
+}
+
+async fn fetch_latest_snapshot(pool: &PgPool, device_id: i64) -> Result<Option<ProcessSnapshot>, sqlx::Error> {
+    sqlx::query_as!(
+        ProcessSnapshot,
+        "SELECT id, device_id, captured_at, sample_interval_ms, process_count
+        FROM process_snapshots WHERE device_id = $1
+        ORDER BY captured_at DESC, id DESC
+        LIMIT 1",
+        device_id
     )
-    .execute(pool)
-    .await?;
-    Ok(())
+    .fetch_optional(pool)
+    .await
 }
 
-pub async fn get_process_info(pool: &PgPool) -> Result<Vec<ProcessMetrics>, sqlx::Error> {
+// A limit of None returns the whole snapshot
+async fn fetch_snapshot_processes(
+    pool: &PgPool,
+    snapshot_id: i64,
+    ranking: ProcessRanking,
+    limit: Option<i64>,
+) -> Result<Vec<ProcessMetrics>, sqlx::Error> {
     sqlx::query_as!(
         ProcessMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, pid, name, exe, cpu_usage, memory FROM process_metrics"
+        "SELECT device_id, snapshot_id, pid, ppid, name, exe, cmdline, user_name, started_at, cpu_usage, memory, rss_bytes, virtual_bytes, state
+        FROM process_metrics WHERE snapshot_id = $1
+        ORDER BY CASE WHEN $2 = 'memory' THEN rss_bytes::float8 ELSE cpu_usage END DESC NULLS LAST, pid
+        LIMIT $3",
+        snapshot_id,
+        ranking.as_str(),
+        limit,
     )
     .fetch_all(pool)
     .await
 }
 
-async fn get_process_info_for_user(
+// Ranks the processes of every device's latest snapshot together
+async fn fetch_fleet_top_processes(
     pool: &PgPool,
-    user_id: i32,
-) -> Result<ProcessMetrics, sqlx::Error> {
+    company_name: Option<&str>,
+    ranking: ProcessRanking,
+    limit: i64,
+) -> Result<Vec<FleetProcess>, sqlx::Error> {
     sqlx::query_as!(
-        ProcessMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, pid, name, exe, cpu_usage, memory 
-        FROM process_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        FleetProcess,
+        r#"WITH latest AS (
+            SELECT DISTINCT ON (s.device_id) s.id, s.device_id, s.captured_at
+            FROM process_snapshots s
+            JOIN devices d ON d.device_id = s.device_id
+            WHERE ($1::text IS NULL OR d.company_name = $1)
+            ORDER BY s.device_id, s.captured_at DESC, s.id DESC
+        )
+        SELECT l.device_id as "device_id!", d.hostname, l.captured_at as "captured_at!", p.pid, p.name, p.exe, p.user_name, p.cpu_usage, p.rss_bytes
+        FROM latest l
+        JOIN process_metrics p ON p.snapshot_id = l.id
+        JOIN devices d ON d.device_id = l.device_id
+        ORDER BY CASE WHEN $2 = 'memory' THEN p.rss_bytes::float8 ELSE p.cpu_usage END DESC NULLS LAST, l.device_id, p.pid
+        LIMIT $3"#,
+        company_name,
+        ranking.as_str(),
+        limit,
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await
 }
+
+/// The device's latest snapshot with every process in it.
+pub async fn get_device_processes(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    let snapshot = match fetch_latest_snapshot(&pool, device_id).await {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => return HttpResponse::NotFound().body("No processes collected for this device"),
+        Err(e) => {
+            error!("Failed to fetch process snapshot of device {}: {:?}", device_id, e);
+            return HttpResponse::InternalServerError().body("Failed to fetch processes");
+        }
+    };
+
+    match fetch_snapshot_processes(&pool, snapshot.id, ProcessRanking::Cpu, None).await {
+        Ok(processes) => HttpResponse::Ok().json(ProcessSnapshotDetail { snapshot, processes: redact_for(&claims, processes) }),
+        Err(e) => {
+            error!("Failed to fetch processes of snapshot {}: {:?}", snapshot.id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch processes")
+        }
+    }
+}
+
+/// Busiest processes of the device's latest snapshot, `?by=cpu` or `?by=memory`.
+pub async fn get_device_top_processes(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<TopProcessesQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    let snapshot = match fetch_latest_snapshot(&pool, device_id).await {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => return HttpResponse::NotFound().body("No processes collected for this device"),
+        Err(e) => {
+            error!("Failed to fetch process snapshot of device {}: {:?}", device_id, e);
+            return HttpResponse::InternalServerError().body("Failed to fetch processes");
+        }
+    };
+
+    match fetch_snapshot_processes(&pool, snapshot.id, query.by, Some(query.limit())).await {
+        Ok(processes) => HttpResponse::Ok().json(ProcessSnapshotDetail { snapshot, processes: redact_for(&claims, processes) }),
+        Err(e) => {
+            error!("Failed to fetch processes of snapshot {}: {:?}", snapshot.id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch processes")
+        }
+    }
+}
+
+/// Busiest processes across the fleet, from each device's latest snapshot.
+pub async fn get_fleet_top_processes(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    query: web::Query<TopProcessesQuery>,
+) -> impl Responder {
+    let company_name = match resolve_company_scope(&pool, &claims, query.company_name.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
+
+    match fetch_fleet_top_processes(&pool, company_name.as_deref(), query.by, query.limit()).await {
+        Ok(processes) => HttpResponse::Ok().json(processes),
+        Err(e) => {
+            error!("Failed to fetch fleet top processes: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to fetch processes")
+        }
+    }
+}
@@ -4,21 +4,20 @@ use std::process::Command;
 // use std::sync::{Arc, RwLock};
 use tokio::time::{interval, Duration};
 use actix_web::{web, HttpResponse, Responder};
+use crate::device::enrollment::AuthenticatedDevice;
 
 
 #[derive(Serialize, Clone)]
 pub struct ServiceStatus {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
     pub service_name: Option<String>,
     pub status: Option<String>,
 }
 
 impl ServiceStatus {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, service_name: Option<String>, status: Option<String>) -> Self {
+    pub fn new(device_id: Option<i64>, service_name: Option<String>, status: Option<String>) -> Self {
         Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
+            device_id,
             service_name,
             status,
         }
@@ -30,8 +29,7 @@ impl ServiceStatus {
 }
 
 pub async fn gather_services_status_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
+    device_id: Option<i64>,
 ) -> ServiceStatus {
     let mut interval = interval(Duration::from_secs(3600)); // Update every hour
     loop {
@@ -46,16 +44,16 @@ pub async fn gather_services_status_metrics(
             }
         };
 
-    return ServiceStatus::new(sub_admin_metrics_id, staff_metrics_id, Some(service.to_string()), status)
+    return ServiceStatus::new(device_id, Some(service.to_string()), status)
 }
 }
 }
 
 pub async fn get_services_status_info_handler(pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
+    device: AuthenticatedDevice,
 ) -> impl Responder {
-    let user_id_value = user_id.into_inner();
-    match get_services_status_info_for_user(&pool, user_id_value).await{
+    let device_id = device.device_id;
+    match get_services_status_info_for_device(&pool, device_id).await{
     Ok(mut services_metrics) => {
     let services = vec!["wuauserv", "WinDefend"];
     for service in &services {
@@ -74,7 +72,7 @@ pub async fn get_services_status_info_handler(pool: web::Data<PgPool>,
     HttpResponse::Ok().json(services_metrics)
 }
     Err(_) => {
-    let new_services_metrics = gather_services_status_metrics(Some(user_id_value), None).await;
+    let new_services_metrics = gather_services_status_metrics(Some(device_id)).await;
     if let Err(e) = save_service_status_to_database(&pool, &new_services_metrics).await {
         eprintln!("Failed to save metrics to database: {}", e);
         return HttpResponse::InternalServerError().body("Failed to save Service Status info");
@@ -96,11 +94,10 @@ async fn fetch_service_status(service_name: &str) -> Result<Option<String>, Box<
 
 pub async fn save_service_status_to_database(pool: &PgPool, metrics: &ServiceStatus) -> Result<(), Box<dyn std::error::Error>> {
         sqlx::query!(
-            "INSERT INTO service_status_metrics (sub_admin_metrics_id, staff_metrics_id, service_name, status) VALUES ($1, $2, $3, $4)
-             ON CONFLICT (sub_admin_metrics_id, service_name) DO UPDATE
-             SET status = EXCLUDED.status",
-            metrics.sub_admin_metrics_id,
-            metrics.staff_metrics_id,
+            "INSERT INTO service_status_metrics (device_id, service_name, status) VALUES ($1, $2, $3)
+             ON CONFLICT (device_id, service_name) DO UPDATE
+             SET status = EXCLUDED.status, recorded_at = now()",
+            metrics.device_id,
             metrics.service_name,
             metrics.status,
         )
@@ -112,22 +109,22 @@ pub async fn save_service_status_to_database(pool: &PgPool, metrics: &ServiceSta
 pub async fn get_service_status_info(pool: &PgPool) -> Result<Vec<ServiceStatus>, sqlx::Error> {
  sqlx::query_as!(
         ServiceStatus,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, service_name, status FROM service_status_metrics"
+        "SELECT device_id, service_name, status FROM service_status_metrics"
     )
     .fetch_all(pool)
     .await
 }
 
-async fn get_services_status_info_for_user(
+async fn get_services_status_info_for_device(
     pool: &PgPool,
-    user_id: i32,
+    device_id: i64,
 ) -> Result<ServiceStatus, sqlx::Error> {
     sqlx::query_as!(
         ServiceStatus,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, service_name, status 
+        "SELECT device_id, service_name, status 
         FROM service_status_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+        WHERE device_id = $1",
+        device_id
     )
     .fetch_one(pool)
     .await
@@ -1,132 +1,573 @@
-use serde::Serialize;
-use std::error::Error;
-// use std::sync::{Arc, RwLock};
-use tokio::time::{interval, Duration};
+use serde::{Deserialize, Serialize};
 use sysinfo::System;
-use sqlx::PgPool;
-use chrono::Utc;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use chrono::{DateTime, Duration, Utc};
 use actix_web::{web, HttpResponse, Responder};
+use dotenvy::dotenv;
+use std::env;
+use std::fs;
+use log::error;
+use crate::auth::claims::Claims;
+use crate::device::devices::load_managed_device;
+use crate::device::enrollment::AuthenticatedDevice;
+use crate::device::heartbeat::record_heartbeat;
 
-#[derive(Serialize, Clone, Copy)]
+const WTMP_PATH: &str = "/var/log/wtmp";
+// struct utmp on Linux: ut_type at 0, ut_user at 44..76, ut_tv.tv_sec at 340
+const UTMP_RECORD_SIZE: usize = 384;
+const UTMP_USER: std::ops::Range<usize> = 44..76;
+const UTMP_SECONDS: std::ops::Range<usize> = 340..344;
+const UTMP_RUN_LVL: i16 = 1;
+const UTMP_BOOT_TIME: i16 = 2;
+// The kernel's boot time can move by a second or two when the clock is stepped
+const BOOT_TIME_TOLERANCE_SECS: i64 = 5;
+// wtmp's reboot record is written once userspace is up, well after the kernel booted
+const WTMP_BOOT_TOLERANCE_SECS: i64 = 300;
+const DEFAULT_REPORT_DAYS: i64 = 7;
+const DEFAULT_BOOT_HISTORY_LIMIT: i64 = 50;
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct UptimeMetrics {
-    pub sub_admin_metrics_id: Option<i32>,
-    pub staff_metrics_id: Option<i32>,
+    pub device_id: Option<i64>,
+    /// Hours since `boot_time`
     pub uptime: Option<f64>,
-    pub downtime: Option<f64>,
-    pub created_at: Option<chrono::DateTime<Utc>>,
-    pub updated_at: Option<chrono::DateTime<Utc>>,
-}
-
-impl UptimeMetrics {
-    pub fn new(sub_admin_metrics_id: Option<i32>, staff_metrics_id: Option<i32>, uptime: Option<f64>, downtime: Option<f64>) -> Self {
-        let now = Utc::now();
-        Self {
-            sub_admin_metrics_id,
-            staff_metrics_id,
-            uptime,
-            downtime,
-            created_at: Some(now),
-            updated_at: Some(now),
-        }
+    pub boot_time: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A stored uptime sample and whether the device booted since its last one.
+#[derive(Debug, Serialize)]
+pub struct UptimeReport {
+    #[serde(flatten)]
+    pub metrics: UptimeMetrics,
+    pub rebooted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DeviceBoot {
+    pub id: i64,
+    pub device_id: i64,
+    pub boot_time: DateTime<Utc>,
+    pub source: String,
+    pub first_seen_at: Option<DateTime<Utc>>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub shutdown_at: Option<DateTime<Utc>>,
+    pub shutdown_source: Option<String>,
+}
+
+/// A boot or shutdown recorded in wtmp.
+#[derive(Debug, Clone, Copy)]
+pub enum BootEvent {
+    Boot(DateTime<Utc>),
+    Shutdown(DateTime<Utc>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct DowntimeGap {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub seconds: i64,
+}
+
+/// Downtime of a device over a period, from gaps between its heartbeats.
+#[derive(Debug, Serialize)]
+pub struct AvailabilityReport {
+    pub device_id: i64,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Where accounting starts: `from`, or the first heartbeat when the device
+    /// has none before the period. Unset when there are no heartbeats at all.
+    pub counted_from: Option<DateTime<Utc>>,
+    pub gap_threshold_secs: i64,
+    pub downtime_seconds: i64,
+    pub uptime_percent: Option<f64>,
+    pub gaps: Vec<DowntimeGap>,
+    pub boots: Vec<DeviceBoot>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvailabilityQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BootHistoryQuery {
+    pub limit: Option<i64>,
+}
+
+pub fn gather_uptime_metrics(device_id: Option<i64>) -> UptimeMetrics {
+    let now = Utc::now();
+    let boot_time = DateTime::from_timestamp(System::boot_time() as i64, 0).filter(|boot_time| boot_time.timestamp() > 0);
+    let uptime_seconds = match boot_time {
+        Some(boot_time) => (now - boot_time).num_seconds(),
+        None => System::uptime() as i64,
+    };
+    UptimeMetrics {
+        device_id,
+        uptime: Some(uptime_seconds as f64 / 3600.0),
+        boot_time,
+        created_at: Some(now),
+        updated_at: Some(now),
     }
+}
 
-    pub fn update(&mut self, new_uptime: Option<f64>, new_downtime: Option<f64>) {
-        if let Some(new_uptime) = new_uptime {
-            if let Some(uptime) = self.uptime {
-                if new_uptime > uptime {
-                    self.downtime = Some(self.downtime.unwrap_or(0.0) + new_uptime - uptime);
-                }
+fn parse_wtmp(contents: &[u8]) -> Vec<BootEvent> {
+    contents
+        .chunks_exact(UTMP_RECORD_SIZE)
+        .filter_map(|record| {
+            let kind = i16::from_ne_bytes([record[0], record[1]]);
+            let user = &record[UTMP_USER];
+            let user = &user[..user.iter().position(|&byte| byte == 0).unwrap_or(user.len())];
+            let seconds = i32::from_ne_bytes(record[UTMP_SECONDS].try_into().ok()?);
+            let at = DateTime::from_timestamp(seconds as i64, 0)?;
+            match kind {
+                UTMP_BOOT_TIME => Some(BootEvent::Boot(at)),
+                UTMP_RUN_LVL if user == b"shutdown" => Some(BootEvent::Shutdown(at)),
+                _ => None,
             }
-            self.uptime = Some(new_uptime);
+        })
+        .collect()
+}
+
+/// Boots and shutdowns this machine recorded in wtmp. Empty on systems
+/// without wtmp or when it is unreadable.
+pub fn gather_boot_events() -> Vec<BootEvent> {
+    fs::read(WTMP_PATH).map(|contents| parse_wtmp(&contents)).unwrap_or_default()
+}
+
+/// Tracks the boot the device reports, closing the previous one when the
+/// boot time changed. Returns whether the device rebooted.
+async fn record_boot(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: i64,
+    boot_time: DateTime<Utc>,
+    seen_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let tolerance = Duration::seconds(BOOT_TIME_TOLERANCE_SECS);
+    let previous = sqlx::query_scalar!(
+        "SELECT boot_time FROM device_boots WHERE device_id = $1 AND last_seen_at IS NOT NULL ORDER BY last_seen_at DESC LIMIT 1",
+        device_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+    let rebooted = previous.is_some_and(|previous| (previous - boot_time).abs() > tolerance);
+
+    if rebooted {
+        // Without a shutdown record the last report is the best estimate
+        sqlx::query!(
+            "UPDATE device_boots SET shutdown_at = last_seen_at, shutdown_source = 'last_seen'
+            WHERE device_id = $1 AND boot_time < $2 AND shutdown_at IS NULL AND last_seen_at IS NOT NULL",
+            device_id,
+            boot_time - tolerance,
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    let current = sqlx::query_scalar!(
+        "SELECT id FROM device_boots
+        WHERE device_id = $1 AND boot_time BETWEEN $2 AND $3
+        ORDER BY abs(extract(epoch FROM boot_time - $4))
+        LIMIT 1",
+        device_id,
+        boot_time - tolerance,
+        boot_time + tolerance,
+        boot_time,
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    match current {
+        Some(id) => {
+            sqlx::query!(
+                "UPDATE device_boots SET first_seen_at = COALESCE(first_seen_at, $2), last_seen_at = $2 WHERE id = $1",
+                id,
+                seen_at,
+            )
+            .execute(&mut **tx)
+            .await?;
         }
-        if let Some(new_downtime) = new_downtime {
-            self.downtime = Some(new_downtime);
+        None => {
+            sqlx::query!(
+                "INSERT INTO device_boots (device_id, boot_time, source, first_seen_at, last_seen_at) VALUES ($1, $2, 'agent', $3, $3)",
+                device_id,
+                boot_time,
+                seen_at,
+            )
+            .execute(&mut **tx)
+            .await?;
         }
-        self.updated_at = Some(Utc::now());
     }
+    Ok(rebooted)
 }
 
-pub async fn get_uptime_info_handler(pool: web::Data<PgPool>,
-    user_id: web::Path<i32>,
-) -> impl Responder {
-    let user_id_value = user_id.into_inner();
-
-    match get_uptime_info_for_user(&pool, user_id_value).await {
-        Ok(mut uptime_metrics) => {
-            let mut system = System::new_all();
-    system.refresh_all();
-    let uptime_hours = Some(System::uptime() as f64 / 3600.0);
-    let downtime_hours = uptime_hours.map(|u| u - 1.0);
-            uptime_metrics.update(uptime_hours, downtime_hours);
-            if let Err(e) = save_uptime_metrics_to_database(&pool, &uptime_metrics).await {
-                eprintln!("Failed to save metrics to database: {}", e);
-                return HttpResponse::InternalServerError().body("Failed to save Memory info");
-            }  
-    HttpResponse::Ok().json(uptime_metrics)
+/// Adds wtmp boots the device's history is missing and records wtmp
+/// shutdowns against the boot they ended.
+async fn import_boot_events(tx: &mut Transaction<'_, Postgres>, device_id: i64, events: &[BootEvent]) -> Result<(), sqlx::Error> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut boots = sqlx::query_as!(
+        DeviceBoot,
+        "SELECT id, device_id, boot_time, source, first_seen_at, last_seen_at, shutdown_at, shutdown_source
+        FROM device_boots WHERE device_id = $1",
+        device_id
+    )
+    .fetch_all(&mut **tx)
+    .await?;
 
+    let tolerance = Duration::seconds(WTMP_BOOT_TOLERANCE_SECS);
+    for event in events {
+        let BootEvent::Boot(boot_time) = *event else {
+            continue;
+        };
+        if boots.iter().any(|boot| (boot.boot_time - boot_time).abs() <= tolerance) {
+            continue;
+        }
+        let boot = sqlx::query_as!(
+            DeviceBoot,
+            "INSERT INTO device_boots (device_id, boot_time, source) VALUES ($1, $2, 'wtmp')
+            ON CONFLICT (device_id, boot_time) DO UPDATE SET source = device_boots.source
+            RETURNING id, device_id, boot_time, source, first_seen_at, last_seen_at, shutdown_at, shutdown_source",
+            device_id,
+            boot_time,
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+        boots.push(boot);
     }
-    Err(_) => {
-    let new_uptime_metrics = gather_uptime_metrics(Some(user_id_value), None).await;
-    if let Err(e) = save_uptime_metrics_to_database(&pool, &new_uptime_metrics).await {
-        eprintln!("Failed to save metrics to database: {}", e);
-        return HttpResponse::InternalServerError().body("Failed to save Uptime info");
+
+    for event in events {
+        let BootEvent::Shutdown(shutdown_at) = *event else {
+            continue;
+        };
+        let Some(boot) = boots
+            .iter_mut()
+            .filter(|boot| boot.boot_time < shutdown_at)
+            .max_by_key(|boot| boot.boot_time)
+        else {
+            continue;
+        };
+        if boot.shutdown_source.as_deref() == Some("wtmp") {
+            continue;
+        }
+        sqlx::query!(
+            "UPDATE device_boots SET shutdown_at = $2, shutdown_source = 'wtmp' WHERE id = $1",
+            boot.id,
+            shutdown_at,
+        )
+        .execute(&mut **tx)
+        .await?;
+        boot.shutdown_at = Some(shutdown_at);
+        boot.shutdown_source = Some("wtmp".to_string());
     }
-    HttpResponse::Ok().json(new_uptime_metrics)
-}
+    Ok(())
 }
+
+/// Stores an uptime sample, counts it as a heartbeat, and updates the
+/// device's boot history.
+pub async fn store_uptime_metrics(
+    pool: &PgPool,
+    device_id: i64,
+    metrics: &UptimeMetrics,
+    boot_events: &[BootEvent],
+) -> Result<UptimeReport, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let metrics = sqlx::query_as!(
+        UptimeMetrics,
+        "INSERT INTO uptime_metrics (device_id, uptime, boot_time, created_at, updated_at) VALUES ($1, $2, $3, $4, $5)
+        RETURNING device_id, uptime, boot_time, created_at, updated_at",
+        device_id,
+        metrics.uptime,
+        metrics.boot_time,
+        metrics.created_at,
+        metrics.updated_at,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let seen_at = metrics.updated_at.unwrap_or_else(Utc::now);
+    record_heartbeat(&mut tx, device_id, metrics.boot_time, "uptime", seen_at).await?;
+
+    let rebooted = match metrics.boot_time {
+        Some(boot_time) => record_boot(&mut tx, device_id, boot_time, seen_at).await?,
+        None => false,
+    };
+    import_boot_events(&mut tx, device_id, boot_events).await?;
+
+    tx.commit().await?;
+    Ok(UptimeReport { metrics, rebooted })
 }
 
-pub async fn gather_uptime_metrics(
-    sub_admin_metrics_id: Option<i32>,
-    staff_metrics_id: Option<i32>,
-) -> UptimeMetrics {
-    let mut interval = interval(Duration::from_secs(3600)); // Update every hour
-    loop {
-    interval.tick().await;
-    let mut system = System::new_all();
-    system.refresh_all();
-    let uptime_hours = Some(System::uptime() as f64 / 3600.0);
-    let downtime_hours = uptime_hours.map(|u| u - 1.0);
-    return UptimeMetrics::new(sub_admin_metrics_id, staff_metrics_id, uptime_hours, downtime_hours)
+pub async fn get_uptime_info_handler(
+    pool: web::Data<PgPool>,
+    device: AuthenticatedDevice,
+) -> impl Responder {
+    let device_id = device.device_id;
+    let metrics = gather_uptime_metrics(Some(device_id));
+
+    match store_uptime_metrics(&pool, device_id, &metrics, &gather_boot_events()).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            error!("Failed to save uptime of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to save Uptime info")
+        }
+    }
 }
+
+// A silence longer than DOWNTIME_GAP_SECS (two hours by default) counts as downtime
+fn downtime_gap_threshold() -> Duration {
+    dotenv().ok();
+    let seconds = env::var("DOWNTIME_GAP_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(7200);
+    Duration::seconds(seconds)
 }
 
+/// Gaps longer than `threshold` between heartbeats, clipped to `from..end`.
+/// `anchor` is the last heartbeat before `from`, if any.
+fn find_gaps(
+    anchor: Option<DateTime<Utc>>,
+    heartbeats: &[DateTime<Utc>],
+    from: DateTime<Utc>,
+    end: DateTime<Utc>,
+    threshold: Duration,
+) -> (Option<DateTime<Utc>>, Vec<DowntimeGap>) {
+    let Some(mut previous) = anchor.or_else(|| heartbeats.first().copied()) else {
+        return (None, Vec::new());
+    };
+    let counted_from = previous.max(from);
+    let mut gaps = Vec::new();
+    let mut push_gap = |start: DateTime<Utc>, stop: DateTime<Utc>| {
+        let start = start.max(from);
+        if stop > start {
+            gaps.push(DowntimeGap { start, end: stop, seconds: (stop - start).num_seconds() });
+        }
+    };
 
-pub async fn save_uptime_metrics_to_database(pool: &PgPool, metrics: &UptimeMetrics) -> Result<(), Box<dyn Error>> {
-        sqlx::query!(
-            "INSERT INTO uptime_metrics (sub_admin_metrics_id, staff_metrics_id, uptime, downtime, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
-            metrics.sub_admin_metrics_id,
-            metrics.staff_metrics_id,
-            metrics.uptime,
-            metrics.downtime,
-            metrics.created_at,
-            metrics.updated_at,
-        )
-        .execute(pool)
-        .await?;
-    Ok(())
+    for &heartbeat in heartbeats {
+        if heartbeat - previous > threshold {
+            push_gap(previous, heartbeat);
+        }
+        previous = previous.max(heartbeat);
+    }
+    if end - previous > threshold {
+        push_gap(previous, end);
+    }
+    (Some(counted_from), gaps)
 }
 
-pub async fn get_uptime_info(pool: &PgPool) -> Result<Vec<UptimeMetrics>, sqlx::Error> {
+async fn fetch_boots_between(
+    pool: &PgPool,
+    device_id: i64,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<DeviceBoot>, sqlx::Error> {
     sqlx::query_as!(
-        UptimeMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, uptime, downtime, created_at, updated_at FROM uptime_metrics"
+        DeviceBoot,
+        "SELECT id, device_id, boot_time, source, first_seen_at, last_seen_at, shutdown_at, shutdown_source
+        FROM device_boots
+        WHERE device_id = $1 AND boot_time <= $3 AND COALESCE(shutdown_at, last_seen_at, boot_time) >= $2
+        ORDER BY boot_time",
+        device_id,
+        from,
+        to,
     )
     .fetch_all(pool)
     .await
 }
 
-async fn get_uptime_info_for_user(
+async fn build_availability_report(
     pool: &PgPool,
-    user_id: i32,
-) -> Result<UptimeMetrics, sqlx::Error> {
-    sqlx::query_as!(
-        UptimeMetrics,
-        "SELECT sub_admin_metrics_id, staff_metrics_id, uptime, downtime, created_at, updated_at 
-        FROM uptime_metrics 
-        WHERE sub_admin_metrics_id = $1 OR staff_metrics_id = $1",
-        user_id
+    device_id: i64,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<AvailabilityReport, sqlx::Error> {
+    let anchor = sqlx::query_scalar!(
+        "SELECT max(received_at) FROM device_heartbeats WHERE device_id = $1 AND received_at < $2",
+        device_id,
+        from,
     )
     .fetch_one(pool)
-    .await
-}
\ No newline at end of file
+    .await?;
+    let heartbeats = sqlx::query_scalar!(
+        "SELECT received_at FROM device_heartbeats WHERE device_id = $1 AND received_at BETWEEN $2 AND $3 ORDER BY received_at",
+        device_id,
+        from,
+        to,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let threshold = downtime_gap_threshold();
+    // The future is not downtime yet
+    let end = to.min(Utc::now());
+    let (counted_from, gaps) = find_gaps(anchor, &heartbeats, from, end, threshold);
+    let downtime_seconds: i64 = gaps.iter().map(|gap| gap.seconds).sum();
+    let uptime_percent = counted_from
+        .map(|counted_from| (end - counted_from).num_seconds())
+        .filter(|&span| span > 0)
+        .map(|span| 100.0 * (span - downtime_seconds) as f64 / span as f64);
+
+    Ok(AvailabilityReport {
+        device_id,
+        from,
+        to,
+        counted_from,
+        gap_threshold_secs: threshold.num_seconds(),
+        downtime_seconds,
+        uptime_percent,
+        gaps,
+        boots: fetch_boots_between(pool, device_id, from, to).await?,
+    })
+}
+
+/// Downtime over `from..to` (the last seven days by default), with the
+/// heartbeat gaps it is made of and the boots in the period.
+pub async fn get_device_availability(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<AvailabilityQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(DEFAULT_REPORT_DAYS));
+    if from >= to {
+        return HttpResponse::BadRequest().body("from must be before to");
+    }
+
+    match build_availability_report(&pool, device_id, from, to).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            error!("Failed to build availability report of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch availability")
+        }
+    }
+}
+
+/// The device's boots, newest first.
+pub async fn get_device_boots(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    device_id: web::Path<i64>,
+    query: web::Query<BootHistoryQuery>,
+) -> impl Responder {
+    let device_id = device_id.into_inner();
+    if let Err(response) = load_managed_device(&pool, &claims, device_id).await {
+        return response;
+    }
+    let boots = sqlx::query_as!(
+        DeviceBoot,
+        "SELECT id, device_id, boot_time, source, first_seen_at, last_seen_at, shutdown_at, shutdown_source
+        FROM device_boots WHERE device_id = $1
+        ORDER BY boot_time DESC
+        LIMIT $2",
+        device_id,
+        query.limit.unwrap_or(DEFAULT_BOOT_HISTORY_LIMIT).clamp(1, 1000),
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match boots {
+        Ok(boots) => HttpResponse::Ok().json(boots),
+        Err(e) => {
+            error!("Failed to fetch boots of device {}: {:?}", device_id, e);
+            HttpResponse::InternalServerError().body("Failed to fetch boot history")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTMP_USER_PROCESS: i16 = 7;
+
+    fn utmp_record(kind: i16, user: &str, seconds: i32) -> Vec<u8> {
+        let mut record = vec![0u8; UTMP_RECORD_SIZE];
+        record[0..2].copy_from_slice(&kind.to_ne_bytes());
+        record[UTMP_USER.start..UTMP_USER.start + user.len()].copy_from_slice(user.as_bytes());
+        record[UTMP_SECONDS].copy_from_slice(&seconds.to_ne_bytes());
+        record
+    }
+
+    fn at(instant: &str) -> DateTime<Utc> {
+        format!("{}Z", instant).parse().unwrap()
+    }
+
+    #[test]
+    fn reads_boots_and_shutdowns_from_wtmp() {
+        let mut wtmp = utmp_record(UTMP_BOOT_TIME, "reboot", 1_709_280_000);
+        wtmp.extend(utmp_record(UTMP_USER_PROCESS, "alice", 1_709_280_060));
+        wtmp.extend(utmp_record(UTMP_RUN_LVL, "runlevel", 1_709_280_010));
+        wtmp.extend(utmp_record(UTMP_RUN_LVL, "shutdown", 1_709_308_800));
+        // A record still being written when the file was read
+        wtmp.extend(&utmp_record(UTMP_BOOT_TIME, "reboot", 1_709_309_000)[..100]);
+
+        let events = parse_wtmp(&wtmp);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], BootEvent::Boot(boot) if boot == at("2024-03-01T08:00:00")));
+        assert!(matches!(events[1], BootEvent::Shutdown(shutdown) if shutdown == at("2024-03-01T16:00:00")));
+    }
+
+    #[test]
+    fn no_heartbeats_means_nothing_to_count() {
+        let (counted_from, gaps) = find_gaps(None, &[], at("2024-03-01T00:00:00"), at("2024-03-02T00:00:00"), Duration::hours(2));
+        assert_eq!(counted_from, None);
+        assert!(gaps.is_empty());
+    }
+
+    #[test]
+    fn anchor_before_period_counts_from_its_start() {
+        let heartbeats = [at("2024-03-01T00:30:00"), at("2024-03-01T01:00:00"), at("2024-03-01T23:30:00")];
+        let (counted_from, gaps) = find_gaps(
+            Some(at("2024-02-29T23:45:00")),
+            &heartbeats,
+            at("2024-03-01T00:00:00"),
+            at("2024-03-02T00:00:00"),
+            Duration::hours(2),
+        );
+        assert_eq!(counted_from, Some(at("2024-03-01T00:00:00")));
+        assert_eq!(gaps.len(), 1);
+        assert_eq!((gaps[0].start, gaps[0].end), (at("2024-03-01T01:00:00"), at("2024-03-01T23:30:00")));
+        assert_eq!(gaps[0].seconds, 22 * 3600 + 1800);
+    }
+
+    #[test]
+    fn clips_gaps_crossing_the_period() {
+        let heartbeats = [at("2024-03-01T06:00:00"), at("2024-03-01T12:00:00")];
+        let (counted_from, gaps) = find_gaps(
+            Some(at("2024-02-29T20:00:00")),
+            &heartbeats,
+            at("2024-03-01T00:00:00"),
+            at("2024-03-01T18:00:00"),
+            Duration::hours(2),
+        );
+        assert_eq!(counted_from, Some(at("2024-03-01T00:00:00")));
+        let spans: Vec<_> = gaps.iter().map(|gap| (gap.start, gap.end)).collect();
+        assert_eq!(
+            spans,
+            vec![
+                (at("2024-03-01T00:00:00"), at("2024-03-01T06:00:00")),
+                (at("2024-03-01T06:00:00"), at("2024-03-01T12:00:00")),
+                (at("2024-03-01T12:00:00"), at("2024-03-01T18:00:00")),
+            ]
+        );
+    }
+
+    #[test]
+    fn without_anchor_counts_from_first_heartbeat() {
+        let heartbeats = [at("2024-03-01T10:00:00"), at("2024-03-01T10:30:00")];
+        let (counted_from, gaps) = find_gaps(None, &heartbeats, at("2024-03-01T00:00:00"), at("2024-03-01T11:00:00"), Duration::hours(2));
+        assert_eq!(counted_from, Some(at("2024-03-01T10:00:00")));
+        assert!(gaps.is_empty());
+    }
+}
@@ -2,46 +2,62 @@ use actix_web::{web, App, HttpServer};
 use crate::functionalities::maintenance::count_ongoing_maintenance_requests;
 use crate::metrics::hardware::{
     cpu::get_cpu_info_handler,
-    memory::get_memory_info_handler,
-    disk::get_disk_info_handler,
-    network::get_network_info_handler,
+    memory::{self, get_memory_info_handler},
+    disk::{self, get_disk_info_handler},
+    network::{self, get_network_info_handler},
     aboutsys::get_system_info_handler,
+    inventory,
+    sensors::{self, get_temperature_info_handler},
 };
 use crate::metrics::software::{
     ip_location::get_ip_location_info_handler,
-    uptime::get_uptime_info_handler,
+    uptime::{self, get_uptime_info_handler},
     services::get_services_status_info_handler,
     filesystem::get_filesystem_info_handler,
-    process::get_process_info_handler
+    process::{self, get_process_info_handler},
+    packages,
 };
 use crate::user::users::{self, get_all_staffs_by_company, get_all_sub_admins, count_staffs_by_company, count_sub_admins};
 use crate::user::login::login;
 use sqlx::PgPool;
+use std::sync::Arc;
+use crate::storage::blob::BlobStore;
 use crate::auth::middleware::AuthMiddleware;
+use crate::audit::trail::{get_audit_log, export_audit_log, verify_audit_chain};
+use crate::device::devices::{list_devices, get_device, update_device, get_device_owners, transfer_device_owner};
+use crate::device::heartbeat;
+use crate::device::enrollment::{create_enrollment_token, enroll_device, get_device_credentials, rotate_device_credential, revoke_device_credential};
 use crate::functionalities::{
-maintenance::{create_maintenance_request, get_user_maintenance_requests, get_user_specific_maintenance_request, update_maintenance_request, delete_maintenance_request, get_ongoing_maintenance_count}, 
-assign::{create_system_assignment, get_system_assignment, update_system_assignment, delete_system_assignment, get_system_assignment_count}};
+maintenance::{create_maintenance_request, get_user_maintenance_requests, get_user_specific_maintenance_request, update_maintenance_request, delete_maintenance_request, get_ongoing_maintenance_count, transition_maintenance_request, get_maintenance_status_history, get_maintenance_device_snapshot}, 
+assign::{create_system_assignment, get_system_assignment, update_system_assignment, delete_system_assignment, get_system_assignment_count},
+assign, acknowledgements, dispatch, comments, attachments, sla, analytics, checklist, schedules, alerts, process_policies};
 
-pub async fn run_server(pool: PgPool) {
+pub async fn run_server(pool: PgPool, blob_store: Arc<dyn BlobStore>) {
         HttpServer::new(move|| {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(blob_store.clone()))
+            .app_data(web::PayloadConfig::new(attachments::max_attachment_bytes()))
             .route("/login", web::post().to(login))
             .route("/seeallsubadmin", web::get().to(get_all_sub_admins))
             .route("/countallsubadmin", web::get().to(count_sub_admins))
             .route("/seeallmystaffs", web::get().to(get_all_staffs_by_company))
             .route("/countallmystaffs", web::get().to(count_staffs_by_company))
             .route("/countongoingmaintenancereq", web::get().to(get_ongoing_maintenance_count))
-            .route("/systeminfo/{user_id}", web::get().to(get_system_info_handler))
-            .route("/cpu", web::get().to(get_cpu_info_handler))
-            .route("/disk", web::get().to(get_disk_info_handler))
-            .route("/memory", web::get().to(get_memory_info_handler))
-            .route("/network", web::get().to(get_network_info_handler))
-            .route("/filesystem", web::get().to(get_filesystem_info_handler))
-            .route("/iplocation", web::get().to(get_ip_location_info_handler))
-            .route("/process", web::get().to(get_process_info_handler))
-            .route("/services", web::get().to(get_services_status_info_handler))
-            .route("/uptime", web::get().to(get_uptime_info_handler))
+            .route("/ingest/systeminfo", web::get().to(get_system_info_handler))
+            .route("/ingest/cpu", web::get().to(get_cpu_info_handler))
+            .route("/ingest/disk", web::get().to(get_disk_info_handler))
+            .route("/ingest/memory", web::get().to(get_memory_info_handler))
+            .route("/ingest/network", web::get().to(get_network_info_handler))
+            .route("/ingest/heartbeat", web::get().to(heartbeat::get_heartbeat_handler))
+            .route("/ingest/temperature", web::get().to(get_temperature_info_handler))
+            .route("/ingest/filesystem", web::get().to(get_filesystem_info_handler))
+            .route("/ingest/iplocation", web::get().to(get_ip_location_info_handler))
+            .route("/ingest/process", web::get().to(get_process_info_handler))
+            .route("/ingest/services", web::get().to(get_services_status_info_handler))
+            .route("/ingest/uptime", web::get().to(get_uptime_info_handler))
+            .route("/ingest/inventory", web::get().to(inventory::get_inventory_handler))
+            .route("/ingest/software", web::get().to(packages::get_software_info_handler))
             .route("/createsub", web::post().to(users::createsub))
             .route("/createsuper", web::post().to(users::createsuper))
             .route("/createstaff", web::post().to(users::createstaff))
@@ -51,12 +67,106 @@ pub async fn run_server(pool: PgPool) {
             .route("/maintenance/user/{reported_by_id}/{maintenance_id}", web::get().to(get_user_specific_maintenance_request))
             .route("/maintenance/user/{reported_by_id}/{maintenance_id}", web::patch().to(update_maintenance_request))
             .route("/maintenance/user/{reported_by_id}/{maintenance_id}", web::delete().to(delete_maintenance_request))
+            .route("/maintenance/{maintenance_id}/transition", web::post().to(transition_maintenance_request))
+            .route("/maintenance/{maintenance_id}/history", web::get().to(get_maintenance_status_history))
+            .route("/maintenance/{maintenance_id}/snapshot", web::get().to(get_maintenance_device_snapshot))
+            .route("/maintenance/{maintenance_id}/assign", web::post().to(dispatch::assign_maintenance_request))
+            .route("/maintenance/{maintenance_id}/assign", web::delete().to(dispatch::unassign_maintenance_request))
+            .route("/maintenance/{maintenance_id}/assignments", web::get().to(dispatch::get_assignment_history))
+            .route("/maintenance/dispatch", web::post().to(dispatch::dispatch_unassigned_requests))
+            .route("/maintenance/schedules", web::get().to(schedules::get_schedules))
+            .route("/maintenance/schedules", web::post().to(schedules::create_schedule))
+            .route("/maintenance/schedules/calendar", web::get().to(schedules::get_schedule_calendar))
+            .route("/maintenance/schedules/{schedule_id}", web::put().to(schedules::update_schedule))
+            .route("/maintenance/schedules/{schedule_id}", web::delete().to(schedules::delete_schedule))
+            .route("/maintenance/schedules/{schedule_id}/runs", web::get().to(schedules::get_schedule_runs))
+            .route("/maintenance/{maintenance_id}/checklist", web::get().to(checklist::get_checklist))
+            .route("/maintenance/{maintenance_id}/checklist/{item_id}", web::patch().to(checklist::update_checklist_item))
+            .route("/maintenance/{maintenance_id}/comments", web::post().to(comments::create_comment))
+            .route("/maintenance/{maintenance_id}/comments", web::get().to(comments::get_comments))
+            .route("/maintenance/{maintenance_id}/attachments", web::post().to(attachments::upload_attachment))
+            .route("/maintenance/{maintenance_id}/attachments", web::get().to(attachments::get_attachments))
+            .route("/maintenance/{maintenance_id}/attachments/{attachment_id}", web::get().to(attachments::download_attachment))
+            .route("/maintenance/{maintenance_id}/attachments/{attachment_id}", web::delete().to(attachments::delete_attachment))
+            .route("/sla/policies", web::get().to(sla::get_sla_policies))
+            .route("/sla/policies", web::put().to(sla::upsert_sla_policy))
+            .route("/sla/policies/{policy_id}", web::delete().to(sla::delete_sla_policy))
+            .route("/sla/calendars/{company_name}", web::get().to(sla::get_sla_calendar))
+            .route("/sla/calendars/{company_name}", web::put().to(sla::update_sla_calendar))
+            .route("/sla/breaches", web::get().to(sla::get_sla_breaches))
+            .route("/sla/report", web::get().to(sla::get_sla_compliance_report))
+            .route("/analytics/maintenance/backlog", web::get().to(analytics::get_maintenance_backlog))
+            .route("/analytics/maintenance/response-times", web::get().to(analytics::get_maintenance_response_times))
+            .route("/analytics/maintenance/devices", web::get().to(analytics::get_maintenance_by_device))
+            .route("/analytics/maintenance/departments", web::get().to(analytics::get_maintenance_by_department))
+            .route("/analytics/maintenance/trend", web::get().to(analytics::get_maintenance_weekly_trend))
+            .route("/analytics/maintenance/top-titles", web::get().to(analytics::get_maintenance_top_titles))
+            .route("/alerts/rules", web::get().to(alerts::get_alert_rules))
+            .route("/alerts/rules", web::post().to(alerts::create_alert_rule))
+            .route("/alerts/rules/{rule_id}", web::put().to(alerts::update_alert_rule))
+            .route("/alerts/rules/{rule_id}", web::delete().to(alerts::delete_alert_rule))
+            .route("/alerts/rules/{rule_id}/events", web::get().to(alerts::get_alert_rule_events))
+            .route("/alerts/active", web::get().to(alerts::get_active_alerts))
+            .route("/technicians/{technician_id}/queue", web::get().to(dispatch::get_technician_queue))
+            .route("/technicians/{technician_id}/skills", web::put().to(dispatch::update_technician_skills))
             .route("/ongoing_maintenance/{reported_by_id}", web::get().to(get_ongoing_maintenance_count))
+            .route("/systemassign", web::get().to(assign::list_system_assignments))
             .route("/systemassign", web::post().to(create_system_assignment))
+            .route("/systemassign/counts", web::get().to(assign::get_system_assignment_counts))
+            .route("/systemassign/overdue", web::get().to(assign::get_overdue_systems))
+            .route("/systemassign/terms", web::get().to(acknowledgements::get_assignment_terms))
+            .route("/systemassign/terms", web::post().to(acknowledgements::publish_assignment_terms))
+            .route("/systemassign/terms/current", web::get().to(acknowledgements::get_current_assignment_terms))
+            .route("/systemassign/mine", web::get().to(acknowledgements::get_my_systems))
+            .route("/systemassign/acknowledgements/pending", web::get().to(acknowledgements::get_pending_acknowledgements))
             .route("/systemassign/{new_system_id}", web::get().to(get_system_assignment))
             .route("/systemassign/{new_system_id}", web::patch().to(update_system_assignment))
             .route("/systemassign/{new_system_id}", web::delete().to(delete_system_assignment))
-            .route("/systemassigncount/{new_system_id}", web::delete().to(get_system_assignment_count))
+            .route("/systemassign/{new_system_id}/checkout", web::post().to(assign::check_out_system))
+            .route("/systemassign/{new_system_id}/return", web::post().to(assign::return_system))
+            .route("/systemassign/{new_system_id}/history", web::get().to(assign::get_system_checkout_history))
+            .route("/systemassign/{new_system_id}/holder", web::get().to(assign::get_system_holder_at))
+            .route("/systemassign/{new_system_id}/acknowledge", web::post().to(acknowledgements::acknowledge_system))
+            .route("/systemassign/{new_system_id}/acknowledgements", web::get().to(acknowledgements::get_system_acknowledgements))
+            .route("/systemassigncount/{email}", web::get().to(get_system_assignment_count))
+            .route("/devices", web::get().to(list_devices))
+            .route("/devices/status", web::get().to(heartbeat::get_fleet_status))
+            .route("/devices/{device_id}", web::get().to(get_device))
+            .route("/devices/{device_id}", web::patch().to(update_device))
+            .route("/devices/{device_id}/inventory", web::get().to(inventory::get_device_inventory))
+            .route("/devices/{device_id}/inventory/history", web::get().to(inventory::get_device_inventory_history))
+            .route("/devices/{device_id}/software", web::get().to(packages::get_device_software))
+            .route("/devices/{device_id}/disks", web::get().to(disk::get_device_disks))
+            .route("/devices/{device_id}/disks/history", web::get().to(disk::get_device_disk_history))
+            .route("/devices/{device_id}/memory", web::get().to(memory::get_device_memory))
+            .route("/devices/{device_id}/memory/history", web::get().to(memory::get_device_memory_history))
+            .route("/devices/{device_id}/network", web::get().to(network::get_device_network_interfaces))
+            .route("/devices/{device_id}/network/history", web::get().to(network::get_device_network_history))
+            .route("/devices/{device_id}/temperatures", web::get().to(sensors::get_device_temperatures))
+            .route("/devices/{device_id}/temperatures/history", web::get().to(sensors::get_device_temperature_history))
+            .route("/devices/{device_id}/status", web::get().to(heartbeat::get_device_status))
+            .route("/devices/{device_id}/status/events", web::get().to(heartbeat::get_device_status_events))
+            .route("/devices/{device_id}/boots", web::get().to(uptime::get_device_boots))
+            .route("/devices/{device_id}/availability", web::get().to(uptime::get_device_availability))
+            .route("/devices/{device_id}/processes", web::get().to(process::get_device_processes))
+            .route("/devices/{device_id}/processes/top", web::get().to(process::get_device_top_processes))
+            .route("/devices/{device_id}/owners", web::get().to(get_device_owners))
+            .route("/devices/{device_id}/owners", web::post().to(transfer_device_owner))
+            .route("/devices/{device_id}/credentials", web::get().to(get_device_credentials))
+            .route("/devices/{device_id}/credentials/rotate", web::post().to(rotate_device_credential))
+            .route("/devices/{device_id}/credentials/{credential_id}", web::delete().to(revoke_device_credential))
+            .route("/software/packages", web::get().to(packages::search_packages))
+            .route("/processes/top", web::get().to(process::get_fleet_top_processes))
+            .route("/processes/policies", web::get().to(process_policies::get_process_policies))
+            .route("/processes/policies", web::post().to(process_policies::create_process_policy))
+            .route("/processes/policies/{policy_id}", web::put().to(process_policies::update_process_policy))
+            .route("/processes/policies/{policy_id}", web::delete().to(process_policies::delete_process_policy))
+            .route("/processes/violations", web::get().to(process_policies::get_process_policy_violations))
+            .route("/enrollment-tokens", web::post().to(create_enrollment_token))
+            .route("/enroll", web::post().to(enroll_device))
+            .route("/audit", web::get().to(get_audit_log))
+            .route("/audit/export", web::get().to(export_audit_log))
+            .route("/audit/verify", web::get().to(verify_audit_chain))
             .wrap(AuthMiddleware)
     })
     .bind("127.0.0.1:8080")
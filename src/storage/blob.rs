@@ -0,0 +1,69 @@
+use futures::future::BoxFuture;
+use std::env;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use dotenvy::dotenv;
+
+/// Where uploaded files live. Keys are `/`-separated relative paths chosen by
+/// the caller; backends only need to store, fetch and remove opaque bytes.
+pub trait BlobStore: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>>;
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Stores blobs as files under a root directory.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Uses BLOB_STORE_DIR, falling back to ./data/blobs.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        Self::new(env::var("BLOB_STORE_DIR").unwrap_or_else(|_| "data/blobs".to_string()))
+    }
+
+    // Keys must stay inside the root: no absolute paths, `..` or empty segments
+    fn path_for(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(key);
+        let is_plain = !key.is_empty()
+            && relative.components().all(|component| matches!(component, Component::Normal(_)));
+        if !is_plain {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid blob key: {}", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put<'a>(&'a self, key: &'a str, data: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.path_for(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // Write beside the target and rename so readers never see a partial file
+            let partial = path.with_extension("partial");
+            tokio::fs::write(&partial, data).await?;
+            tokio::fs::rename(&partial, &path).await
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        Box::pin(async move { tokio::fs::read(self.path_for(key)?).await })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path_for(key)?).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        })
+    }
+}
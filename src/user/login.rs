@@ -2,11 +2,9 @@ use actix_web::{web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use jsonwebtoken::{encode, Header, EncodingKey};
-use crate::user::users::{SuperAdmin, SubAdmin, Staff, Technician, verify_password} ;
+use crate::user::users::{SuperAdmin, SubAdmin, Staff, Technician, UserRole, verify_password} ;
 use crate::error::CustomError;
-use crate::auth::claims::Claims;
-use dotenvy::dotenv;
-use std::env;
+use crate::auth::claims::{load_secret, Claims};
 use log::error;
 
 
@@ -26,10 +24,10 @@ pub async fn login(pool: web::Data<PgPool>, user: web::Json<LoginRequest>) -> im
     
     let result = match find_user_by_email(&pool, &user_info.email).await {
         Ok(user) => match user {
-            User::SuperAdmin(super_admin) => verify_user(super_admin.password, user_info.password, user_info.email),
-            User::SubAdmin(sub_admin) => verify_user(sub_admin.password, user_info.password, user_info.email),
-            User::Staff(staff) => verify_user(staff.password, user_info.password, user_info.email),
-            User::Technician(technician) => verify_user(technician.password, user_info.password, user_info.email),
+            User::SuperAdmin(super_admin) => verify_user(super_admin.password, user_info.password, user_info.email, UserRole::SuperAdmin),
+            User::SubAdmin(sub_admin) => verify_user(sub_admin.password, user_info.password, user_info.email, UserRole::SubAdmin),
+            User::Staff(staff) => verify_user(staff.password, user_info.password, user_info.email, UserRole::Staff),
+            User::Technician(technician) => verify_user(technician.password, user_info.password, user_info.email, UserRole::Technician),
         },
         Err(e) => {
             error!("Invalid request: {:?}", e);
@@ -57,9 +55,9 @@ async fn find_user_by_email(pool: &PgPool, email: &str) -> Result<User, CustomEr
     Err(CustomError::OtherError("Not Found".to_string()))
 }
 
-fn verify_user(stored_password: String, input_password: String, email: String) -> HttpResponse {
+fn verify_user(stored_password: String, input_password: String, email: String, role: UserRole) -> HttpResponse {
     if verify_password(&stored_password, &input_password).is_ok() {
-        let claims = Claims::with_email(&email);
+        let claims = Claims::for_user(&email, role);
         let secret = load_secret();
         let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
         HttpResponse::Ok().json(LoginResponse { token })
@@ -113,12 +111,6 @@ async fn find_technician_by_email(pool: &PgPool, email: &str) -> Result<Technici
 }
 
 
-fn load_secret() -> String {
-    dotenv().ok();
-    env::var("JWT_SECRET_KEY")
-        .expect("JWT_SECRET_KEY must be set in the environment")
-}
-
 enum User {
     SuperAdmin(SuperAdmin),
     SubAdmin(SubAdmin),
@@ -4,32 +4,18 @@ use argon2::{
 };
 use sha2::{Sha512, Digest};
 use std::error::Error;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use crate::error::CustomError;
+use crate::audit::trail::{record, snapshot, AuditActor};
+use crate::auth::claims::Claims;
 use chrono::Utc;
 use log::error;
-use rand::Rng;
-use crate::metrics::hardware::{
-aboutsys::{gather_system_info, save_systeminfo_metrics_to_database}, 
-cpu::{gather_cpu_metrics, save_cpu_metrics_to_database},
-disk::{gather_disk_metrics, save_disk_metrics_to_database},
-memory::{gather_memory_metrics, save_memory_metrics_to_database},
-network::{gather_network_metrics, save_network_metrics_to_database},
-};
-
-use crate::metrics::software::{
-filesystem::{gather_filesystem_metrics, save_filesystem_metrics_to_database},
-ip_location::{gather_ip_location, save_ip_location_to_database},
-process::{gather_process_metrics, save_process_metrics_to_database},
-services::{gather_services_status_metrics, save_service_status_to_database},
-uptime::{gather_uptime_metrics, save_uptime_metrics_to_database},
-};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SuperAdmin {
-    pub id: Option<i32>,
+    pub id: Option<i64>,
     pub name: String,
     pub email: String,
     pub password: String,
@@ -40,8 +26,7 @@ pub struct SuperAdmin {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Staff {
-    pub id: Option<i32>,
-    pub metrics_id: Option<i32>,
+    pub id: Option<i64>,
     pub name: String,
     pub email: String,
     pub password: String,
@@ -52,18 +37,23 @@ pub struct Staff {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Technician {
-    pub id: Option<i32>,
+    pub id: Option<i64>,
     pub name: String,
     pub email: String,
     pub password: String,
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
     pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    // Matched against a maintenance request's required_skills when dispatching
+    #[serde(default)]
+    pub skills: Vec<String>,
+    // Only dispatched to this company's requests; None works for every company
+    #[serde(default)]
+    pub company_name: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SubAdmin {
-    pub id: Option<i32>,
-    pub metrics_id: Option<i32>,
+    pub id: Option<i64>,
     pub company_name: Option<String>,
     pub email: String,
     pub phone: String,
@@ -73,7 +63,7 @@ pub struct SubAdmin {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum UserRole {
     SuperAdmin,
     #[serde(rename = "Subadmin")]
@@ -82,14 +72,23 @@ pub enum UserRole {
     Technician,
 }
 
-fn generate_id() -> i32 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(1..=i32::MAX)
+/// Returned from the create endpoints; ids are issued by the database.
+#[derive(Debug, Serialize)]
+pub struct CreatedUser {
+    pub id: i64,
+    pub email: String,
 }
 
-pub async fn createsuper(pool: web::Data<PgPool>, user: web::Json<SuperAdmin>) -> impl Responder {
+pub async fn createsuper(req: HttpRequest, pool: web::Data<PgPool>, claims: Option<Claims>, user: web::Json<SuperAdmin>) -> impl Responder {
     let new_user = user.into_inner();
 
+    // Without a session this is the first super admin signing themselves up
+    let actor = match &claims {
+        Some(claims) if claims.role() == UserRole::SuperAdmin => AuditActor::new(&req, claims),
+        Some(_) => return HttpResponse::Forbidden().body("Only super admins can create super admins"),
+        None => AuditActor::named(&req, &new_user.email, "SuperAdmin"),
+    };
+
     if !is_email_valid(&new_user.email) {
         return HttpResponse::BadRequest().body("Invalid email format");
     }
@@ -99,8 +98,8 @@ pub async fn createsuper(pool: web::Data<PgPool>, user: web::Json<SuperAdmin>) -
         Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
     };
 
-    let super_admin = SuperAdmin {
-        id: new_user.id,
+    let mut super_admin = SuperAdmin {
+        id: None,
         name: new_user.name,
         email: new_user.email,
         password: hashed_password,
@@ -108,8 +107,29 @@ pub async fn createsuper(pool: web::Data<PgPool>, user: web::Json<SuperAdmin>) -
         updated_at: None,
     };
 
-    match save_superadmin_to_database(&pool, &super_admin).await {
-        Ok(_) => HttpResponse::Created().body("Super admin created successfully"),
+    let result = async {
+        let mut tx = pool.begin().await?;
+        if claims.is_none() {
+            // Locked before checking so two sign-ups cannot both become the first
+            sqlx::query!("LOCK TABLE super_admin IN EXCLUSIVE MODE").execute(&mut *tx).await?;
+            let exists = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM super_admin) as "exists!""#)
+                .fetch_one(&mut *tx)
+                .await?;
+            if exists {
+                return Ok(None);
+            }
+        }
+        let id = save_superadmin_to_database(&mut *tx, &super_admin).await?;
+        super_admin.id = Some(id);
+        record(&mut tx, &actor, "create", "super_admin", &super_admin.email, None, snapshot(&super_admin)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(id))
+    }
+    .await;
+
+    match result {
+        Ok(Some(id)) => HttpResponse::Created().json(CreatedUser { id, email: super_admin.email }),
+        Ok(None) => HttpResponse::Unauthorized().body("Sign in as a super admin to create super admins"),
         Err(e) => {
             error!("Failed to create super admin: {:?}", e);
             HttpResponse::InternalServerError().body(format!("Failed to create super admin: {:?}", e))
@@ -117,7 +137,10 @@ pub async fn createsuper(pool: web::Data<PgPool>, user: web::Json<SuperAdmin>) -
     }
 }
 
-pub async fn createsub(pool: web::Data<PgPool>, user: web::Json<SubAdmin>) -> impl Responder {
+pub async fn createsub(req: HttpRequest, pool: web::Data<PgPool>, claims: Claims, user: web::Json<SubAdmin>) -> impl Responder {
+    if claims.role() != UserRole::SuperAdmin {
+        return HttpResponse::Forbidden().body("Only super admins can create sub admins");
+    }
     let new_user = user.into_inner();
 
     if !is_email_valid(&new_user.email) {
@@ -129,9 +152,8 @@ pub async fn createsub(pool: web::Data<PgPool>, user: web::Json<SubAdmin>) -> im
         Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
     };
 
-    let sub_admin = SubAdmin {
-        id: Some(new_user.id.unwrap_or_else(generate_id)),
-        metrics_id: Some(new_user.metrics_id.unwrap_or_else(generate_id)),
+    let mut sub_admin = SubAdmin {
+        id: None,
         company_name: new_user.company_name,
         email: new_user.email,
         phone: new_user.phone,
@@ -140,77 +162,18 @@ pub async fn createsub(pool: web::Data<PgPool>, user: web::Json<SubAdmin>) -> im
         updated_at: None,
     };
 
-    match save_subadmin_to_database(&pool, &sub_admin).await {
-        Ok(_) => {
-            // Gather and save system info
-            let system_info = gather_system_info(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_systeminfo_metrics_to_database(&pool, &system_info).await {
-                error!("Failed to save system info: {:?}", e);
-                return HttpResponse::InternalServerError().body(format!("Failed to save system info: {:?}", e));
-            }
-
-            // Gather and save CPU info
-            let cpu_info = gather_cpu_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_cpu_metrics_to_database(&pool, &cpu_info).await {
-            error!("Failed to save CPU info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save CPU info: {:?}", e));
-            }
-            
-        // Gather and save Disk info
-        let disk_info = gather_disk_metrics(sub_admin.metrics_id, None).await;
-        if let Err(e) = save_disk_metrics_to_database(&pool, &disk_info).await {
-        error!("Failed to save CPU info: {:?}", e);
-        return HttpResponse::InternalServerError().body(format!("Failed to save Disk info: {:?}", e));
-        }
-
-            // Gather and save Disk info
-            let memory_info = gather_memory_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_memory_metrics_to_database(&pool, &memory_info).await {
-            error!("Failed to save CPU info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Memory info: {:?}", e));
-            }
-
-            // Gather and save Network info
-            let disk_info = gather_network_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_network_metrics_to_database(&pool, &disk_info).await {
-            error!("Failed to save disk info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Memory info: {:?}", e));
-            }
-
-            // Gather and save Filesystem info
-            let filesystem_info = gather_filesystem_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_filesystem_metrics_to_database(&pool, &filesystem_info).await {
-            error!("Failed to save filesystem info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Memory info: {:?}", e));
-            }
-
-            let ip_info = gather_ip_location(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_ip_location_to_database(&pool, &ip_info).await {
-            error!("Failed to save ip info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save ip info: {:?}", e));
-            }
-
-            let process_info = gather_process_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_process_metrics_to_database(&pool, &process_info).await {
-            error!("Failed to save process info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save process info: {:?}", e));
-            }
-
-            let services_info = gather_services_status_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_service_status_to_database(&pool, &services_info).await {
-            error!("Failed to save services info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save services info: {:?}", e));
-            }
-
-            let uptime_info = gather_uptime_metrics(sub_admin.metrics_id, None).await;
-            if let Err(e) = save_uptime_metrics_to_database(&pool, &uptime_info).await {
-            error!("Failed to save process info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save process info: {:?}", e));
-            }
-
-        HttpResponse::Created().body("Sub admin created successfully")
-        },
+    let created = async {
+        let mut tx = pool.begin().await?;
+        let id = save_subadmin_to_database(&mut *tx, &sub_admin).await?;
+        sub_admin.id = Some(id);
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "sub_admin", &sub_admin.email, None, snapshot(&sub_admin)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(id)
+    }
+    .await;
 
+    match created {
+        Ok(id) => HttpResponse::Created().json(CreatedUser { id, email: sub_admin.email }),
         Err(e) => {
             error!("Failed to create sub admin: {:?}", e);
             HttpResponse::InternalServerError().body(format!("Failed to create sub admin: {:?}", e))
@@ -218,8 +181,13 @@ pub async fn createsub(pool: web::Data<PgPool>, user: web::Json<SubAdmin>) -> im
     }
 }
 
-pub async fn createstaff(pool: web::Data<PgPool>, user: web::Json<Staff>) -> impl Responder {
+pub async fn createstaff(req: HttpRequest, pool: web::Data<PgPool>, claims: Claims, user: web::Json<Staff>) -> impl Responder {
     let new_user = user.into_inner();
+    // Sub-admins add staff to their own company
+    let company_affiliated_to = match resolve_company_scope(&pool, &claims, new_user.company_affiliated_to.as_deref()).await {
+        Ok(company_name) => company_name,
+        Err(response) => return response,
+    };
 
     if !is_email_valid(&new_user.email) {
         return HttpResponse::BadRequest().body("Invalid email format");
@@ -230,88 +198,28 @@ pub async fn createstaff(pool: web::Data<PgPool>, user: web::Json<Staff>) -> imp
         Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
     };
 
-    let staff = Staff {
-        id: Some(new_user.id.unwrap_or_else(generate_id)),
-        metrics_id: Some(new_user.metrics_id.unwrap_or_else(generate_id)),
+    let mut staff = Staff {
+        id: None,
         name: new_user.name,
         email: new_user.email,
         password: hashed_password,
         created_at: Some(Utc::now()),
         updated_at: None,
-        company_affiliated_to: new_user.company_affiliated_to
+        company_affiliated_to,
     };
 
-    match save_staff_to_database(&pool, &staff).await {
-        Ok(_) => {
-            // Gather and save system info
-            let system_info = gather_system_info(None, staff.metrics_id).await;
-            if let Err(e) = save_systeminfo_metrics_to_database(&pool, &system_info).await {
-                error!("Failed to save system info: {:?}", e);
-                return HttpResponse::InternalServerError().body(format!("Failed to save system info: {:?}", e));
-            }
-    
-            // Gather and save CPU info
-            let cpu_info = gather_cpu_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_cpu_metrics_to_database(&pool, &cpu_info).await {
-                error!("Failed to save CPU info: {:?}", e);
-                return HttpResponse::InternalServerError().body(format!("Failed to save CPU info: {:?}", e));
-            }
-
-            // Gather and save Disk info
-            let disk_info = gather_disk_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_disk_metrics_to_database(&pool, &disk_info).await {
-            error!("Failed to save CPU info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Disk info: {:?}", e));
-            }
-
-            // Gather and save Memory info
-            let memory_info = gather_memory_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_memory_metrics_to_database(&pool, &memory_info).await {
-            error!("Failed to save CPU info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Memory info: {:?}", e));
-            }
-
-            // Gather and save Network info
-            let network_info = gather_network_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_network_metrics_to_database(&pool, &network_info).await {
-            error!("Failed to save CPU info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Memory info: {:?}", e));
-            }
-
-            // Gather and save Network info
-            let filesystem_info = gather_filesystem_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_filesystem_metrics_to_database(&pool, &filesystem_info).await {
-            error!("Failed to save CPU info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save Memory info: {:?}", e));
-            }
-
-            let ip_info = gather_ip_location(None, staff.metrics_id).await;
-            if let Err(e) = save_ip_location_to_database(&pool, &ip_info).await {
-            error!("Failed to save ip info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save ip info: {:?}", e));
-            }
-
-            let process_info = gather_process_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_process_metrics_to_database(&pool, &process_info).await {
-            error!("Failed to save process info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save process info: {:?}", e));
-            }
-
-            let services_info = gather_services_status_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_service_status_to_database(&pool, &services_info).await {
-            error!("Failed to save services info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save services info: {:?}", e));
-            }
-
-            let uptime_info = gather_uptime_metrics(None, staff.metrics_id).await;
-            if let Err(e) = save_uptime_metrics_to_database(&pool, &uptime_info).await {
-            error!("Failed to save process info: {:?}", e);
-            return HttpResponse::InternalServerError().body(format!("Failed to save process info: {:?}", e));
-            }
-    
-            HttpResponse::Created().body("Staff created successfully")
-        },
+    let created = async {
+        let mut tx = pool.begin().await?;
+        let id = save_staff_to_database(&mut *tx, &staff).await?;
+        staff.id = Some(id);
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "staff", &staff.email, None, snapshot(&staff)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(id)
+    }
+    .await;
 
+    match created {
+        Ok(id) => HttpResponse::Created().json(CreatedUser { id, email: staff.email }),
         Err(e) => {
             error!("Failed to create staff: {:?}", e);
             HttpResponse::InternalServerError().body(format!("Failed to create staff: {:?}", e))
@@ -320,7 +228,10 @@ pub async fn createstaff(pool: web::Data<PgPool>, user: web::Json<Staff>) -> imp
     
 }
 
-pub async fn createtechnician(pool: web::Data<PgPool>, user: web::Json<Technician>) -> impl Responder {
+pub async fn createtechnician(req: HttpRequest, pool: web::Data<PgPool>, claims: Claims, user: web::Json<Technician>) -> impl Responder {
+    if claims.role() != UserRole::SuperAdmin {
+        return HttpResponse::Forbidden().body("Only super admins can create technicians");
+    }
     let new_user = user.into_inner();
 
     if !is_email_valid(&new_user.email) {
@@ -332,17 +243,29 @@ pub async fn createtechnician(pool: web::Data<PgPool>, user: web::Json<Technicia
         Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
     };
 
-    let technician = Technician {
-        id: new_user.id,
+    let mut technician = Technician {
+        id: None,
         name: new_user.name,
         email: new_user.email,
         password: hashed_password,
         created_at: Some(Utc::now()),
         updated_at: None,
+        skills: new_user.skills,
+        company_name: new_user.company_name,
     };
 
-    match save_technician_to_database(&pool, &technician).await {
-        Ok(_) => HttpResponse::Created().body("Technician created successfully"),
+    let created = async {
+        let mut tx = pool.begin().await?;
+        let id = save_technician_to_database(&mut *tx, &technician).await?;
+        technician.id = Some(id);
+        record(&mut tx, &AuditActor::new(&req, &claims), "create", "technician", &technician.email, None, snapshot(&technician)).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(id)
+    }
+    .await;
+
+    match created {
+        Ok(id) => HttpResponse::Created().json(CreatedUser { id, email: technician.email }),
         Err(e) => {
             error!("Failed to create technician admin: {:?}", e);
             HttpResponse::InternalServerError().body(format!("Failed to create technician: {:?}", e))
@@ -389,44 +312,56 @@ pub fn verify_password(hash: &str, password: &str) -> Result<(), Box<dyn Error>>
     Ok(())
 }
 
-async fn save_subadmin_to_database(pool: &PgPool, user: &SubAdmin) -> Result<(), Box<dyn Error>> {
-    sqlx::query!(
-        "INSERT INTO sub_admin (id, metrics_id, company_name, email, phone, password, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-        user.id, user.metrics_id, user.company_name, user.email, user.phone, user.password, user.created_at, user.updated_at
+async fn save_subadmin_to_database<'e, E>(executor: E, user: &SubAdmin) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        "INSERT INTO sub_admin (company_name, email, phone, password, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        user.company_name, user.email, user.phone, user.password, user.created_at, user.updated_at
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await?;
-    Ok(())
+    Ok(row.id)
 }
 
-async fn save_staff_to_database(pool: &PgPool, user: &Staff) -> Result<(), Box<dyn Error>> {
-    sqlx::query!(
-        "INSERT INTO staff (id, metrics_id, name, email, password, created_at, updated_at, company_affiliated_to) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
-        user.id, user.metrics_id, user.name, user.email, user.password, user.created_at, user.updated_at, user.company_affiliated_to
+async fn save_staff_to_database<'e, E>(executor: E, user: &Staff) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        "INSERT INTO staff (name, email, password, created_at, updated_at, company_affiliated_to) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        user.name, user.email, user.password, user.created_at, user.updated_at, user.company_affiliated_to
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await?;
-    Ok(())
+    Ok(row.id)
 }
 
-async fn save_technician_to_database(pool: &PgPool, user: &Technician) -> Result<(), Box<dyn Error>> {
-    sqlx::query!(
-        "INSERT INTO technician (id, name, email, password, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
-        user.id, user.name, user.email, user.password, user.created_at, user.updated_at
+async fn save_technician_to_database<'e, E>(executor: E, user: &Technician) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        "INSERT INTO technician (name, email, password, created_at, updated_at, skills, company_name) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        user.name, user.email, user.password, user.created_at, user.updated_at, &user.skills, user.company_name
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await?;
-    Ok(())
+    Ok(row.id)
 }
 
-async fn save_superadmin_to_database(pool: &PgPool, user: &SuperAdmin) -> Result<(), Box<dyn Error>> {
-    sqlx::query!(
-        "INSERT INTO super_admin (id, name, email, password, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
-        user.id, user.name, user.email, user.password, user.created_at, user.updated_at
+async fn save_superadmin_to_database<'e, E>(executor: E, user: &SuperAdmin) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        "INSERT INTO super_admin (name, email, password, created_at, updated_at) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        user.name, user.email, user.password, user.created_at, user.updated_at
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await?;
-    Ok(())
+    Ok(row.id)
 }
 
 pub async fn get_all_staffs_by_company(
@@ -535,4 +470,72 @@ async fn count_all_sub_admins(pool: &PgPool) -> Result<i64, Box<dyn std::error::
     .unwrap_or(0);
 
     Ok(count)
-}
\ No newline at end of file
+}
+
+/// Company the caller belongs to: a sub-admin's own company or the one a staff
+/// member is affiliated to. Super admins and technicians have none.
+pub async fn fetch_user_company(pool: &PgPool, claims: &Claims) -> Result<Option<String>, sqlx::Error> {
+    let company = match claims.role() {
+        UserRole::SubAdmin => sqlx::query!(
+            "SELECT company_name FROM sub_admin WHERE email = $1",
+            claims.email()
+        )
+        .fetch_optional(pool)
+        .await?
+        .and_then(|row| row.company_name),
+        UserRole::Staff => sqlx::query!(
+            "SELECT company_affiliated_to FROM staff WHERE email = $1",
+            claims.email()
+        )
+        .fetch_optional(pool)
+        .await?
+        .and_then(|row| row.company_affiliated_to),
+        UserRole::SuperAdmin | UserRole::Technician => None,
+    };
+
+    Ok(company)
+}
+
+/// Company of the `role` account registered under `email`: `None` when there
+/// is no such account, `Some(None)` when the account belongs to no company.
+pub async fn fetch_company_of_user(pool: &PgPool, email: &str, role: UserRole) -> Result<Option<Option<String>>, sqlx::Error> {
+    let company = match role {
+        UserRole::SuperAdmin => sqlx::query!("SELECT id FROM super_admin WHERE email = $1", email)
+            .fetch_optional(pool)
+            .await?
+            .map(|_| None),
+        UserRole::SubAdmin => sqlx::query!("SELECT company_name FROM sub_admin WHERE email = $1", email)
+            .fetch_optional(pool)
+            .await?
+            .map(|row| row.company_name),
+        UserRole::Staff => sqlx::query!("SELECT company_affiliated_to FROM staff WHERE email = $1", email)
+            .fetch_optional(pool)
+            .await?
+            .map(|row| row.company_affiliated_to),
+        UserRole::Technician => sqlx::query!("SELECT company_name FROM technician WHERE email = $1", email)
+            .fetch_optional(pool)
+            .await?
+            .map(|row| row.company_name),
+    };
+
+    Ok(company)
+}
+
+/// Super admins see every company; sub-admins only their own. Returns the
+/// company to scope to, or the response to send back.
+pub async fn resolve_company_scope(pool: &PgPool, claims: &Claims, requested: Option<&str>) -> Result<Option<String>, HttpResponse> {
+    match claims.role() {
+        UserRole::SuperAdmin => Ok(requested.map(str::to_string)),
+        UserRole::SubAdmin => {
+            let company = fetch_user_company(pool, claims).await.map_err(|e| {
+                error!("Failed to fetch company of {}: {:?}", claims.email(), e);
+                HttpResponse::InternalServerError().body("Failed to fetch company")
+            })?;
+            match company {
+                Some(company) if requested.is_none_or(|requested| requested == company) => Ok(Some(company)),
+                _ => Err(HttpResponse::Forbidden().body("Not allowed to access data of this company")),
+            }
+        }
+        UserRole::Staff | UserRole::Technician => Err(HttpResponse::Forbidden().body("Only admins can access company data")),
+    }
+}